@@ -0,0 +1,1601 @@
+//! A small AML interpreter. The same code is used both to load a definition block into the
+//! namespace and to execute control methods, since definition blocks are really just a top-level
+//! term list which may contain conditionals.
+
+use super::namespace::{self, AmlObject, FieldKind, FieldUnit, NameString, Namespace};
+use super::region;
+use super::value::{AmlError, AmlValue};
+use alloc::{String, Vec};
+use alloc::boxed::Box;
+use device::pit;
+use core::cmp;
+
+/// The maximum depth of nested method invocations.
+const MAX_DEPTH: usize = 32;
+
+/// The maximum number of iterations of a single `While` loop.
+const MAX_LOOP_ITERATIONS: usize = 0xFFFF;
+
+/// The largest buffer, in bytes, and the most package elements AML may create. The sizes come
+/// from the firmware, and the kernel heap is small.
+const MAX_BUFFER_SIZE: usize = 0x4000;
+const MAX_PACKAGE_ELEMENTS: usize = 0x400;
+
+/// How control leaves a term list.
+enum Flow {
+    Normal,
+    Return(AmlValue),
+    Break,
+    Continue,
+}
+
+/// Somewhere a value can be stored.
+enum Target {
+    Null,
+    Debug,
+    Local(usize),
+    Arg(usize),
+    Name(String),
+    Index(Box<Target>, usize),
+}
+
+/// A single frame of execution, over either a whole definition block or a method body.
+pub struct Executor<'a> {
+    namespace: &'a mut Namespace,
+    code: &'static [u8],
+    pos: usize,
+    scope: String,
+    locals: [AmlValue; 8],
+    args: Vec<AmlValue>,
+    depth: usize,
+    /// Integers are only 32 bits wide in definition blocks with a revision below 2.
+    wide_integers: bool,
+    /// Terms which fail are skipped rather than ending execution, as a definition block is loaded.
+    loading: bool,
+}
+
+impl<'a> Executor<'a> {
+    pub fn new(
+        namespace: &'a mut Namespace,
+        code: &'static [u8],
+        scope: String,
+        args: Vec<AmlValue>,
+        depth: usize,
+    ) -> Self {
+        Executor {
+            namespace: namespace,
+            code: code,
+            pos: 0,
+            scope: scope,
+            locals: [
+                AmlValue::Uninitialized,
+                AmlValue::Uninitialized,
+                AmlValue::Uninitialized,
+                AmlValue::Uninitialized,
+                AmlValue::Uninitialized,
+                AmlValue::Uninitialized,
+                AmlValue::Uninitialized,
+                AmlValue::Uninitialized,
+            ],
+            args: args,
+            depth: depth,
+            wide_integers: true,
+            loading: false,
+        }
+    }
+
+    /// Execute the whole of this frame's code, returning whatever it returns.
+    pub fn run(&mut self) -> Result<AmlValue, AmlError> {
+        let end = self.code.len();
+
+        match self.term_list(end)? {
+            Flow::Return(value) => Ok(value),
+            _ => Ok(AmlValue::Uninitialized),
+        }
+    }
+
+    /// Use 32-bit integer semantics, as required by revision 1 definition blocks.
+    pub fn set_narrow_integers(&mut self) {
+        self.wide_integers = false;
+    }
+
+    /// Skip terms which fail, such as those using opcodes we don't support, instead of giving up
+    /// on the whole definition block. Anything else in the block is still loaded.
+    pub fn set_loading(&mut self) {
+        self.loading = true;
+    }
+
+    fn ones(&self) -> u64 {
+        if self.wide_integers {
+            !0
+        } else {
+            0xFFFF_FFFF
+        }
+    }
+
+    fn truncate(&self, value: u64) -> u64 {
+        value & self.ones()
+    }
+
+    /* Stream primitives. */
+
+    fn peek(&self) -> Result<u8, AmlError> {
+        self.code
+            .get(self.pos)
+            .cloned()
+            .ok_or(AmlError::UnexpectedEndOfStream)
+    }
+
+    fn byte(&mut self) -> Result<u8, AmlError> {
+        let b = self.peek()?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn word(&mut self) -> Result<u16, AmlError> {
+        Ok(self.byte()? as u16 | (self.byte()? as u16) << 8)
+    }
+
+    fn dword(&mut self) -> Result<u32, AmlError> {
+        Ok(self.word()? as u32 | (self.word()? as u32) << 16)
+    }
+
+    fn qword(&mut self) -> Result<u64, AmlError> {
+        Ok(self.dword()? as u64 | (self.dword()? as u64) << 32)
+    }
+
+    /// Parse a package length, and return the position in the stream at which the package ends.
+    fn pkg_length(&mut self) -> Result<usize, AmlError> {
+        let start = self.pos;
+        let lead = self.byte()?;
+        let follow = (lead >> 6) as usize;
+
+        let length = if follow == 0 {
+            (lead & 0x3F) as usize
+        } else {
+            let mut length = (lead & 0x0F) as usize;
+            for i in 0..follow {
+                length |= (self.byte()? as usize) << (4 + i * 8);
+            }
+            length
+        };
+
+        let end = start + length;
+        if end > self.code.len() {
+            Err(AmlError::UnexpectedEndOfStream)
+        } else {
+            Ok(end)
+        }
+    }
+
+    fn name_seg(&mut self) -> Result<[u8; 4], AmlError> {
+        let mut seg = [0; 4];
+
+        for i in 0..4 {
+            let c = self.byte()?;
+            match c {
+                b'A'...b'Z' | b'_' => {}
+                b'0'...b'9' if i > 0 => {}
+                _ => return Err(AmlError::InvalidNameString),
+            }
+            seg[i] = c;
+        }
+
+        Ok(seg)
+    }
+
+    fn name_string(&mut self) -> Result<NameString, AmlError> {
+        let mut name = NameString {
+            root: false,
+            parents: 0,
+            segments: Vec::new(),
+        };
+
+        if self.peek()? == b'\\' {
+            self.pos += 1;
+            name.root = true;
+        } else {
+            while self.peek()? == b'^' {
+                self.pos += 1;
+                name.parents += 1;
+            }
+        }
+
+        match self.peek()? {
+            0x00 => self.pos += 1,
+            0x2E => {
+                self.pos += 1;
+                name.segments.push(self.name_seg()?);
+                name.segments.push(self.name_seg()?);
+            }
+            0x2F => {
+                self.pos += 1;
+                let count = self.byte()?;
+                for _ in 0..count {
+                    name.segments.push(self.name_seg()?);
+                }
+            }
+            _ => name.segments.push(self.name_seg()?),
+        }
+
+        Ok(name)
+    }
+
+    /// Whether the next byte in the stream starts a name string.
+    fn at_name(&self) -> bool {
+        match self.peek() {
+            Ok(b'\\') | Ok(b'^') | Ok(b'A'...b'Z') | Ok(b'_') | Ok(0x2E) | Ok(0x2F) => true,
+            _ => false,
+        }
+    }
+
+    /* Term lists and statements. */
+
+    fn term_list(&mut self, end: usize) -> Result<Flow, AmlError> {
+        while self.pos < end {
+            let start = self.pos;
+
+            match self.term() {
+                Ok(Flow::Normal) => {}
+                Ok(flow) => {
+                    self.pos = end;
+                    return Ok(flow);
+                }
+                Err(e) => {
+                    if !self.loading {
+                        return Err(e);
+                    }
+
+                    self.skip_failed_term(start, end, e);
+                }
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    /// Skip a term which failed to load, using its package length to find where it ends. A term
+    /// without one can't be skipped on its own, so the rest of the term list is skipped with it.
+    fn skip_failed_term(&mut self, start: usize, end: usize, error: AmlError) {
+        let skipped_to = match self.term_end(start) {
+            Some(term_end) if term_end > start && term_end <= end => term_end,
+            _ => end,
+        };
+
+        println!(
+            "[ acpi ] Skipped AML from {:#x} to {:#x}, which could not be loaded: {:?}",
+            start, skipped_to, error
+        );
+        self.pos = skipped_to;
+    }
+
+    /// Where the term starting at `start` ends, if it is one which has a package length.
+    fn term_end(&mut self, start: usize) -> Option<usize> {
+        self.pos = start;
+
+        match self.byte().ok()? {
+            // Scope, Method, If, Else, While.
+            0x10 | 0x14 | 0xA0 | 0xA1 | 0xA2 => {}
+            // Name, whose value has a package length if it is a buffer or package.
+            0x08 => {
+                self.name_string().ok()?;
+
+                match self.byte().ok()? {
+                    0x11 | 0x12 | 0x13 => {}
+                    _ => return None,
+                }
+            }
+            // Field, Device, Processor, PowerResource, ThermalZone, IndexField, BankField.
+            0x5B => match self.byte().ok()? {
+                0x81...0x87 => {}
+                _ => return None,
+            },
+            _ => return None,
+        }
+
+        self.pkg_length().ok()
+    }
+
+    /// Execute a single term, which may be a definition, a statement or an expression whose
+    /// result is discarded.
+    fn term(&mut self) -> Result<Flow, AmlError> {
+        let op = self.peek()?;
+
+        match op {
+            // NoOp, BreakPoint.
+            0xA3 | 0xCC => {
+                self.pos += 1;
+                Ok(Flow::Normal)
+            }
+            // Alias.
+            0x06 => {
+                self.pos += 1;
+                let source = self.name_string()?;
+                let alias = self.name_string()?.resolve(&self.scope);
+                let source = self.namespace
+                    .search(&self.scope, &source)
+                    .unwrap_or_else(|| source.resolve(&self.scope));
+                self.namespace.add(alias, AmlObject::Alias(source));
+                Ok(Flow::Normal)
+            }
+            // Name.
+            0x08 => {
+                self.pos += 1;
+                let name = self.name_string()?.resolve(&self.scope);
+                let value = self.data_ref_object()?;
+                self.namespace.add(name, AmlObject::Value(value));
+                Ok(Flow::Normal)
+            }
+            // Scope.
+            0x10 => {
+                self.pos += 1;
+                let end = self.pkg_length()?;
+                let name = self.name_string()?.resolve(&self.scope);
+                self.namespace.add(name.clone(), AmlObject::Scope);
+                self.nested_scope(name, end)
+            }
+            // Method.
+            0x14 => {
+                self.pos += 1;
+                let end = self.pkg_length()?;
+                let name = self.name_string()?.resolve(&self.scope);
+                let flags = self.byte()?;
+                let table: &'static [u8] = self.code;
+                let code = table
+                    .get(self.pos..end)
+                    .ok_or(AmlError::UnexpectedEndOfStream)?;
+                self.pos = end;
+                self.namespace.add(
+                    name,
+                    AmlObject::Method {
+                        code: code,
+                        args: flags & 0x7,
+                        flags: flags,
+                    },
+                );
+                Ok(Flow::Normal)
+            }
+            // External.
+            0x15 => {
+                self.pos += 1;
+                self.name_string()?;
+                self.byte()?;
+                self.byte()?;
+                Ok(Flow::Normal)
+            }
+            // CreateDWordField, CreateWordField, CreateByteField, CreateBitField,
+            // CreateQWordField.
+            0x8A | 0x8B | 0x8C | 0x8D | 0x8F => {
+                self.pos += 1;
+                let source = self.buffer_source()?;
+                let index = self.term_arg()?.as_integer()? as usize;
+                let name = self.name_string()?.resolve(&self.scope);
+                let (offset, length) = match op {
+                    0x8A => (index * 8, 32),
+                    0x8B => (index * 8, 16),
+                    0x8C => (index * 8, 8),
+                    0x8D => (index, 1),
+                    _ => (index * 8, 64),
+                };
+                self.namespace.add(
+                    name,
+                    AmlObject::BufferField {
+                        source: source,
+                        offset: offset,
+                        length: length,
+                    },
+                );
+                Ok(Flow::Normal)
+            }
+            // Notify. We do not deliver notifications anywhere yet.
+            0x86 => {
+                self.pos += 1;
+                self.target()?;
+                self.term_arg()?;
+                Ok(Flow::Normal)
+            }
+            // If.
+            0xA0 => {
+                self.pos += 1;
+                let end = self.pkg_length()?;
+                let predicate = self.term_arg()?.as_integer()?;
+
+                let flow = if predicate != 0 {
+                    self.term_list(end)?
+                } else {
+                    self.pos = end;
+                    Flow::Normal
+                };
+
+                // An Else may directly follow the If.
+                if self.peek() == Ok(0xA1) {
+                    self.pos += 1;
+                    let else_end = self.pkg_length()?;
+
+                    if predicate == 0 {
+                        return self.term_list(else_end);
+                    }
+
+                    self.pos = else_end;
+                }
+
+                Ok(flow)
+            }
+            // A stray Else, whose If has already been handled.
+            0xA1 => {
+                self.pos += 1;
+                let end = self.pkg_length()?;
+                self.pos = end;
+                Ok(Flow::Normal)
+            }
+            // While.
+            0xA2 => {
+                self.pos += 1;
+                let end = self.pkg_length()?;
+                let predicate_start = self.pos;
+
+                for _ in 0..MAX_LOOP_ITERATIONS {
+                    self.pos = predicate_start;
+                    if self.term_arg()?.as_integer()? == 0 {
+                        self.pos = end;
+                        return Ok(Flow::Normal);
+                    }
+
+                    match self.term_list(end)? {
+                        Flow::Break => {
+                            self.pos = end;
+                            return Ok(Flow::Normal);
+                        }
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                }
+
+                Err(AmlError::LoopLimit)
+            }
+            // Continue.
+            0x9F => {
+                self.pos += 1;
+                Ok(Flow::Continue)
+            }
+            // Return.
+            0xA4 => {
+                self.pos += 1;
+                let value = self.term_arg()?;
+                Ok(Flow::Return(value))
+            }
+            // Break.
+            0xA5 => {
+                self.pos += 1;
+                Ok(Flow::Break)
+            }
+            0x5B => self.ext_term(),
+            _ => {
+                self.term_arg()?;
+                Ok(Flow::Normal)
+            }
+        }
+    }
+
+    /// Execute a term list in a new scope, and then restore the current scope.
+    fn nested_scope(&mut self, scope: String, end: usize) -> Result<Flow, AmlError> {
+        let old = ::core::mem::replace(&mut self.scope, scope);
+        let result = self.term_list(end);
+        self.scope = old;
+        result
+    }
+
+    /// Execute a term starting with the extended opcode prefix.
+    fn ext_term(&mut self) -> Result<Flow, AmlError> {
+        let op = *self.code
+            .get(self.pos + 1)
+            .ok_or(AmlError::UnexpectedEndOfStream)?;
+
+        match op {
+            // Mutex.
+            0x01 => {
+                self.pos += 2;
+                let name = self.name_string()?.resolve(&self.scope);
+                self.byte()?;
+                self.namespace.add(name, AmlObject::Mutex);
+            }
+            // Event.
+            0x02 => {
+                self.pos += 2;
+                let name = self.name_string()?.resolve(&self.scope);
+                self.namespace.add(name, AmlObject::Event);
+            }
+            // CreateField.
+            0x13 => {
+                self.pos += 2;
+                let source = self.buffer_source()?;
+                let offset = self.term_arg()?.as_integer()? as usize;
+                let length = self.term_arg()?.as_integer()? as usize;
+                let name = self.name_string()?.resolve(&self.scope);
+                self.namespace.add(
+                    name,
+                    AmlObject::BufferField {
+                        source: source,
+                        offset: offset,
+                        length: length,
+                    },
+                );
+            }
+            // Stall, Sleep. Firmware uses these to wait on hardware, which we do not need to do
+            // for the operations we support.
+            0x21 | 0x22 => {
+                self.pos += 2;
+                self.term_arg()?;
+            }
+            // Signal, Reset, Release.
+            0x24 | 0x26 | 0x27 => {
+                self.pos += 2;
+                self.target()?;
+            }
+            // Fatal.
+            0x32 => {
+                self.pos += 2;
+                let ty = self.byte()?;
+                let code = self.dword()?;
+                let arg = self.term_arg()?.as_integer()?;
+                println!(
+                    "[ acpi ] AML fatal error, type: {:#x}, code: {:#x}, argument: {:#x}",
+                    ty, code, arg
+                );
+                return Err(AmlError::Fatal);
+            }
+            // OperationRegion.
+            0x80 => {
+                self.pos += 2;
+                let name = self.name_string()?.resolve(&self.scope);
+                let space = self.byte()?;
+                let offset = self.term_arg()?.as_integer()?;
+                let length = self.term_arg()?.as_integer()?;
+                self.namespace.add(
+                    name,
+                    AmlObject::OpRegion {
+                        space: space,
+                        offset: offset,
+                        length: length,
+                    },
+                );
+            }
+            // Field.
+            0x81 => {
+                self.pos += 2;
+                let end = self.pkg_length()?;
+                let region = self.name_string()?;
+                let region = self.namespace
+                    .search(&self.scope, &region)
+                    .unwrap_or_else(|| region.resolve(&self.scope));
+                let flags = self.byte()?;
+                self.field_list(FieldKind::Region(region), flags, end)?;
+            }
+            // Device, PowerResource, ThermalZone.
+            0x82 | 0x84 | 0x85 => {
+                self.pos += 2;
+                let end = self.pkg_length()?;
+                let name = self.name_string()?.resolve(&self.scope);
+
+                let object = match op {
+                    0x82 => AmlObject::Device,
+                    0x84 => {
+                        // System level and resource order.
+                        self.byte()?;
+                        self.word()?;
+                        AmlObject::PowerResource
+                    }
+                    _ => AmlObject::ThermalZone,
+                };
+
+                self.namespace.add(name.clone(), object);
+                return self.nested_scope(name, end);
+            }
+            // Processor.
+            0x83 => {
+                self.pos += 2;
+                let end = self.pkg_length()?;
+                let name = self.name_string()?.resolve(&self.scope);
+                let id = self.byte()?;
+                let pblk_address = self.dword()?;
+                let pblk_len = self.byte()?;
+
+                self.namespace.add(
+                    name.clone(),
+                    AmlObject::Processor {
+                        id: id,
+                        pblk_address: pblk_address,
+                        pblk_len: pblk_len,
+                    },
+                );
+                return self.nested_scope(name, end);
+            }
+            // IndexField.
+            0x86 => {
+                self.pos += 2;
+                let end = self.pkg_length()?;
+                let index = self.name_string()?;
+                let data = self.name_string()?;
+                let index = self.namespace
+                    .search(&self.scope, &index)
+                    .unwrap_or_else(|| index.resolve(&self.scope));
+                let data = self.namespace
+                    .search(&self.scope, &data)
+                    .unwrap_or_else(|| data.resolve(&self.scope));
+                let flags = self.byte()?;
+                self.field_list(
+                    FieldKind::Index {
+                        index: index,
+                        data: data,
+                    },
+                    flags,
+                    end,
+                )?;
+            }
+            _ => {
+                self.term_arg()?;
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    /// Parse the field list of a `Field` or `IndexField`, adding each named field unit to the
+    /// namespace.
+    fn field_list(&mut self, kind: FieldKind, mut flags: u8, end: usize) -> Result<(), AmlError> {
+        let mut offset = 0;
+
+        while self.pos < end {
+            match self.peek()? {
+                // ReservedField.
+                0x00 => {
+                    self.pos += 1;
+                    offset += self.field_length()?;
+                }
+                // AccessField.
+                0x01 => {
+                    self.pos += 1;
+                    let access_type = self.byte()?;
+                    self.byte()?;
+                    flags = (flags & 0xF0) | (access_type & 0x0F);
+                }
+                // ConnectField.
+                0x02 => {
+                    self.pos += 1;
+                    if self.at_name() {
+                        self.name_string()?;
+                    } else {
+                        self.data_ref_object()?;
+                    }
+                }
+                // ExtendedAccessField.
+                0x03 => {
+                    self.pos += 1;
+                    let access_type = self.byte()?;
+                    self.byte()?;
+                    self.byte()?;
+                    flags = (flags & 0xF0) | (access_type & 0x0F);
+                }
+                _ => {
+                    let seg = self.name_seg()?;
+                    let length = self.field_length()?;
+                    let name = namespace::join(
+                        &self.scope,
+                        ::core::str::from_utf8(&seg).unwrap_or("____"),
+                    );
+
+                    self.namespace.add(
+                        name,
+                        AmlObject::Field(FieldUnit {
+                            kind: kind.clone(),
+                            offset: offset,
+                            length: length,
+                            flags: flags,
+                        }),
+                    );
+
+                    offset += length;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Field lengths are encoded like package lengths, but are not relative to the stream.
+    fn field_length(&mut self) -> Result<usize, AmlError> {
+        let start = self.pos;
+        let end = self.pkg_length_unchecked()?;
+        Ok(end - start)
+    }
+
+    fn pkg_length_unchecked(&mut self) -> Result<usize, AmlError> {
+        let start = self.pos;
+        let lead = self.byte()?;
+        let follow = (lead >> 6) as usize;
+
+        if follow == 0 {
+            return Ok(start + (lead & 0x3F) as usize);
+        }
+
+        let mut length = (lead & 0x0F) as usize;
+        for i in 0..follow {
+            length |= (self.byte()? as usize) << (4 + i * 8);
+        }
+
+        Ok(start + length)
+    }
+
+    /* Expressions. */
+
+    /// Parse a data object that may appear in a `Name` or package.
+    fn data_ref_object(&mut self) -> Result<AmlValue, AmlError> {
+        self.term_arg()
+    }
+
+    /// Evaluate a term argument, returning its value.
+    fn term_arg(&mut self) -> Result<AmlValue, AmlError> {
+        if self.at_name() {
+            return self.name_reference();
+        }
+
+        let op = self.byte()?;
+
+        match op {
+            0x00 => Ok(AmlValue::Integer(0)),
+            0x01 => Ok(AmlValue::Integer(1)),
+            0xFF => Ok(AmlValue::Integer(self.ones())),
+            0x0A => Ok(AmlValue::Integer(self.byte()? as u64)),
+            0x0B => Ok(AmlValue::Integer(self.word()? as u64)),
+            0x0C => Ok(AmlValue::Integer(self.dword()? as u64)),
+            0x0E => Ok(AmlValue::Integer(self.qword()?)),
+            0x0D => {
+                let mut s = String::new();
+                loop {
+                    match self.byte()? {
+                        0 => break,
+                        c => s.push(c as char),
+                    }
+                }
+                Ok(AmlValue::String(s))
+            }
+            // Buffer.
+            0x11 => {
+                let end = self.pkg_length()?;
+                let size = self.term_arg()?.as_integer()? as usize;
+                let table: &'static [u8] = self.code;
+                let initializer = table
+                    .get(self.pos..end)
+                    .ok_or(AmlError::UnexpectedEndOfStream)?;
+                self.pos = end;
+
+                let size = cmp::max(size, initializer.len());
+                if size > MAX_BUFFER_SIZE {
+                    return Err(AmlError::TooLarge);
+                }
+
+                let mut buffer = vec![0; size];
+                buffer[..initializer.len()].copy_from_slice(initializer);
+                Ok(AmlValue::Buffer(buffer))
+            }
+            // Package, VarPackage.
+            0x12 | 0x13 => {
+                let end = self.pkg_length()?;
+                let count = if op == 0x12 {
+                    self.byte()? as usize
+                } else {
+                    self.term_arg()?.as_integer()? as usize
+                };
+
+                if count > MAX_PACKAGE_ELEMENTS {
+                    return Err(AmlError::TooLarge);
+                }
+
+                let mut elements = Vec::new();
+                while self.pos < end {
+                    if self.at_name() {
+                        // Names in packages are references, which are not evaluated.
+                        let name = self.name_string()?;
+                        let path = self.namespace
+                            .search(&self.scope, &name)
+                            .unwrap_or_else(|| name.resolve(&self.scope));
+                        elements.push(AmlValue::Reference(path));
+                    } else {
+                        elements.push(self.term_arg()?);
+                    }
+                }
+
+                while elements.len() < count {
+                    elements.push(AmlValue::Uninitialized);
+                }
+
+                Ok(AmlValue::Package(elements))
+            }
+            0x60...0x67 => Ok(self.locals[(op - 0x60) as usize].clone()),
+            0x68...0x6E => Ok(self.args
+                .get((op - 0x68) as usize)
+                .cloned()
+                .unwrap_or(AmlValue::Uninitialized)),
+            // Store.
+            0x70 => {
+                let value = self.term_arg()?;
+                let target = self.target()?;
+                self.store(&target, value.clone())?;
+                Ok(value)
+            }
+            // RefOf. References are resolved eagerly, so this is just the object itself.
+            0x71 => {
+                let target = self.target()?;
+                self.load(&target)
+            }
+            // Add, Subtract, Multiply, ShiftLeft, ShiftRight, And, Nand, Or, Nor, Xor, Mod.
+            0x72 | 0x74 | 0x77 | 0x79 | 0x7A | 0x7B | 0x7C | 0x7D | 0x7E | 0x7F | 0x85 => {
+                let a = self.term_arg()?.as_integer()?;
+                let b = self.term_arg()?.as_integer()?;
+
+                let result = match op {
+                    0x72 => a.wrapping_add(b),
+                    0x74 => a.wrapping_sub(b),
+                    0x77 => a.wrapping_mul(b),
+                    0x79 => if b >= 64 { 0 } else { a << b },
+                    0x7A => if b >= 64 { 0 } else { a >> b },
+                    0x7B => a & b,
+                    0x7C => !(a & b),
+                    0x7D => a | b,
+                    0x7E => !(a | b),
+                    0x7F => a ^ b,
+                    _ => {
+                        if b == 0 {
+                            return Err(AmlError::DivideByZero);
+                        }
+                        a % b
+                    }
+                };
+
+                let result = AmlValue::Integer(self.truncate(result));
+                let target = self.target()?;
+                self.store(&target, result.clone())?;
+                Ok(result)
+            }
+            // Concat.
+            0x73 => {
+                let a = self.term_arg()?;
+                let b = self.term_arg()?;
+
+                let result = match a {
+                    AmlValue::String(ref s) => {
+                        let mut s = s.clone();
+                        s.push_str(&b.as_string()?);
+                        AmlValue::String(s)
+                    }
+                    _ => {
+                        let mut buffer = a.as_buffer()?;
+                        buffer.extend(b.as_buffer()?);
+                        AmlValue::Buffer(buffer)
+                    }
+                };
+
+                let target = self.target()?;
+                self.store(&target, result.clone())?;
+                Ok(result)
+            }
+            // Increment, Decrement.
+            0x75 | 0x76 => {
+                let target = self.target()?;
+                let value = self.load(&target)?.as_integer()?;
+                let value = if op == 0x75 {
+                    value.wrapping_add(1)
+                } else {
+                    value.wrapping_sub(1)
+                };
+                let result = AmlValue::Integer(self.truncate(value));
+                self.store(&target, result.clone())?;
+                Ok(result)
+            }
+            // Divide.
+            0x78 => {
+                let a = self.term_arg()?.as_integer()?;
+                let b = self.term_arg()?.as_integer()?;
+
+                if b == 0 {
+                    return Err(AmlError::DivideByZero);
+                }
+
+                let remainder_target = self.target()?;
+                let quotient_target = self.target()?;
+                self.store(&remainder_target, AmlValue::Integer(a % b))?;
+                self.store(&quotient_target, AmlValue::Integer(a / b))?;
+                Ok(AmlValue::Integer(a / b))
+            }
+            // Not.
+            0x80 => {
+                let value = self.term_arg()?.as_integer()?;
+                let result = AmlValue::Integer(self.truncate(!value));
+                let target = self.target()?;
+                self.store(&target, result.clone())?;
+                Ok(result)
+            }
+            // FindSetLeftBit, FindSetRightBit.
+            0x81 | 0x82 => {
+                let value = self.term_arg()?.as_integer()?;
+                let result = if value == 0 {
+                    0
+                } else if op == 0x81 {
+                    64 - value.leading_zeros() as u64
+                } else {
+                    value.trailing_zeros() as u64 + 1
+                };
+                let target = self.target()?;
+                self.store(&target, AmlValue::Integer(result))?;
+                Ok(AmlValue::Integer(result))
+            }
+            // DerefOf.
+            0x83 => match self.term_arg()? {
+                AmlValue::Reference(path) => self.read_object(&path),
+                value => Ok(value),
+            },
+            // SizeOf.
+            0x87 => {
+                let target = self.target()?;
+                let size = match self.load(&target)? {
+                    AmlValue::String(s) => s.len(),
+                    AmlValue::Buffer(b) => b.len(),
+                    AmlValue::Package(p) => p.len(),
+                    _ => return Err(AmlError::InvalidType),
+                };
+                Ok(AmlValue::Integer(size as u64))
+            }
+            // Index.
+            0x88 => {
+                let source = self.term_arg()?;
+                let index = self.term_arg()?.as_integer()? as usize;
+                let element = index_of(&source, index)?;
+                let target = self.target()?;
+                self.store(&target, element.clone())?;
+                Ok(element)
+            }
+            // ObjectType.
+            0x8E => {
+                let target = self.target()?;
+                Ok(AmlValue::Integer(self.load(&target)?.object_type()))
+            }
+            // LAnd, LOr.
+            0x90 | 0x91 => {
+                let a = self.term_arg()?.as_integer()? != 0;
+                let b = self.term_arg()?.as_integer()? != 0;
+                let result = if op == 0x90 { a && b } else { a || b };
+                Ok(self.boolean(result))
+            }
+            // LNot, which is also the prefix of LNotEqual, LLessEqual and LGreaterEqual.
+            0x92 => match self.peek()? {
+                0x93 | 0x94 | 0x95 => {
+                    let inner = self.byte()?;
+                    let ordering = self.compare()?;
+                    let result = match inner {
+                        0x93 => ordering != cmp::Ordering::Equal,
+                        0x94 => ordering != cmp::Ordering::Greater,
+                        _ => ordering != cmp::Ordering::Less,
+                    };
+                    Ok(self.boolean(result))
+                }
+                _ => {
+                    let value = self.term_arg()?.as_integer()?;
+                    Ok(self.boolean(value == 0))
+                }
+            },
+            // LEqual, LGreater, LLess.
+            0x93 | 0x94 | 0x95 => {
+                let ordering = self.compare()?;
+                let result = match op {
+                    0x93 => ordering == cmp::Ordering::Equal,
+                    0x94 => ordering == cmp::Ordering::Greater,
+                    _ => ordering == cmp::Ordering::Less,
+                };
+                Ok(self.boolean(result))
+            }
+            // ToBuffer.
+            0x96 => {
+                let result = AmlValue::Buffer(self.term_arg()?.as_buffer()?);
+                let target = self.target()?;
+                self.store(&target, result.clone())?;
+                Ok(result)
+            }
+            // ToDecimalString, ToHexString.
+            0x97 | 0x98 => {
+                let value = self.term_arg()?;
+                let result = match value {
+                    AmlValue::Integer(i) if op == 0x97 => format!("{}", i),
+                    AmlValue::Integer(i) => format!("0x{:X}", i),
+                    AmlValue::String(s) => s,
+                    AmlValue::Buffer(b) => {
+                        let mut s = String::new();
+                        for (i, byte) in b.iter().enumerate() {
+                            if i > 0 {
+                                s.push(',');
+                            }
+                            if op == 0x97 {
+                                s.push_str(&format!("{}", byte));
+                            } else {
+                                s.push_str(&format!("0x{:02X}", byte));
+                            }
+                        }
+                        s
+                    }
+                    _ => return Err(AmlError::InvalidType),
+                };
+                let result = AmlValue::String(result);
+                let target = self.target()?;
+                self.store(&target, result.clone())?;
+                Ok(result)
+            }
+            // ToInteger.
+            0x99 => {
+                let result = AmlValue::Integer(self.term_arg()?.as_integer()?);
+                let target = self.target()?;
+                self.store(&target, result.clone())?;
+                Ok(result)
+            }
+            // ToString.
+            0x9C => {
+                let buffer = self.term_arg()?.as_buffer()?;
+                let length = self.term_arg()?.as_integer()? as usize;
+                let result: String = buffer
+                    .iter()
+                    .take(length)
+                    .take_while(|&&c| c != 0)
+                    .map(|&c| c as char)
+                    .collect();
+                let result = AmlValue::String(result);
+                let target = self.target()?;
+                self.store(&target, result.clone())?;
+                Ok(result)
+            }
+            // CopyObject.
+            0x9D => {
+                let value = self.term_arg()?;
+                let target = self.target()?;
+                self.store(&target, value.clone())?;
+                Ok(value)
+            }
+            // Mid.
+            0x9E => {
+                let source = self.term_arg()?;
+                let index = self.term_arg()?.as_integer()? as usize;
+                let length = self.term_arg()?.as_integer()? as usize;
+
+                let result = match source {
+                    AmlValue::String(s) => {
+                        AmlValue::String(s.chars().skip(index).take(length).collect())
+                    }
+                    other => AmlValue::Buffer(other
+                        .as_buffer()?
+                        .into_iter()
+                        .skip(index)
+                        .take(length)
+                        .collect()),
+                };
+
+                let target = self.target()?;
+                self.store(&target, result.clone())?;
+                Ok(result)
+            }
+            0x5B => self.ext_term_arg(),
+            _ => Err(AmlError::UnsupportedOpcode(op as u16)),
+        }
+    }
+
+    /// Evaluate an expression starting with the extended opcode prefix.
+    fn ext_term_arg(&mut self) -> Result<AmlValue, AmlError> {
+        let op = self.byte()?;
+
+        match op {
+            // CondRefOf.
+            0x12 => {
+                let exists = if self.at_name() {
+                    let name = self.name_string()?;
+                    self.namespace.search(&self.scope, &name)
+                } else {
+                    self.target()?;
+                    None
+                };
+                let target = self.target()?;
+
+                match exists {
+                    Some(path) => {
+                        self.store(&target, AmlValue::Reference(path))?;
+                        Ok(self.boolean(true))
+                    }
+                    None => Ok(self.boolean(false)),
+                }
+            }
+            // Acquire. We are the only thread executing AML, so this always succeeds.
+            0x23 => {
+                self.target()?;
+                self.word()?;
+                Ok(AmlValue::Integer(0))
+            }
+            // Wait.
+            0x25 => {
+                self.target()?;
+                self.term_arg()?;
+                Ok(AmlValue::Integer(0))
+            }
+            // FromBCD, ToBCD.
+            0x28 | 0x29 => {
+                let mut value = self.term_arg()?.as_integer()?;
+                let mut result = 0;
+                let mut shift = 1;
+
+                if op == 0x28 {
+                    while value != 0 {
+                        result += (value & 0xF) * shift;
+                        value >>= 4;
+                        shift *= 10;
+                    }
+                } else {
+                    let mut nibble = 0;
+                    while value != 0 && nibble < 16 {
+                        result |= (value % 10) << (nibble * 4);
+                        value /= 10;
+                        nibble += 1;
+                    }
+                }
+
+                let result = AmlValue::Integer(result);
+                let target = self.target()?;
+                self.store(&target, result.clone())?;
+                Ok(result)
+            }
+            // Revision.
+            0x30 => Ok(AmlValue::Integer(1)),
+            // Timer, in 100ns units.
+            0x33 => Ok(AmlValue::Integer(pit::uptime_micros() * 10)),
+            _ => Err(AmlError::UnsupportedOpcode(0x5B00 | op as u16)),
+        }
+    }
+
+    fn boolean(&self, value: bool) -> AmlValue {
+        AmlValue::Integer(if value { self.ones() } else { 0 })
+    }
+
+    /// Compare two operands, using the type of the first to decide how.
+    fn compare(&mut self) -> Result<cmp::Ordering, AmlError> {
+        let a = self.term_arg()?;
+        let b = self.term_arg()?;
+
+        match a {
+            AmlValue::Integer(a) => Ok(a.cmp(&b.as_integer()?)),
+            AmlValue::String(ref s) => Ok(s.as_str().cmp(b.as_string()?.as_str())),
+            AmlValue::Buffer(ref buf) => Ok(buf.as_slice().cmp(b.as_buffer()?.as_slice())),
+            _ => Err(AmlError::InvalidType),
+        }
+    }
+
+    /// Evaluate a name appearing as a term argument. Methods are invoked, and fields read.
+    fn name_reference(&mut self) -> Result<AmlValue, AmlError> {
+        let name = self.name_string()?;
+
+        let path = match self.namespace.search(&self.scope, &name) {
+            Some(path) => path,
+            None => return Err(AmlError::ObjectNotFound(name.resolve(&self.scope))),
+        };
+
+        let arg_count = match self.namespace.get(&path) {
+            Some(&AmlObject::Method { args, .. }) => args,
+            Some(&AmlObject::NativeMethod { args, .. }) => args,
+            _ => return self.read_object(&path),
+        };
+
+        let mut args = Vec::new();
+        for _ in 0..arg_count {
+            args.push(self.term_arg()?);
+        }
+
+        self.invoke(&path, args)
+    }
+
+    /// Invoke the method at `path`. Evaluating anything other than a method returns its value.
+    pub fn invoke(&mut self, path: &str, args: Vec<AmlValue>) -> Result<AmlValue, AmlError> {
+        match self.namespace.get(path).cloned() {
+            Some(AmlObject::Method { code, .. }) => {
+                if self.depth >= MAX_DEPTH {
+                    return Err(AmlError::RecursionLimit);
+                }
+
+                let mut frame = Executor::new(
+                    self.namespace,
+                    code,
+                    String::from(path),
+                    args,
+                    self.depth + 1,
+                );
+                frame.wide_integers = self.wide_integers;
+                frame.run()
+            }
+            Some(AmlObject::NativeMethod { func, .. }) => func(&args),
+            Some(_) => self.read_object(path),
+            None => Err(AmlError::ObjectNotFound(String::from(path))),
+        }
+    }
+
+    /// Parse the source operand of a buffer field creation opcode, which must name a buffer.
+    fn buffer_source(&mut self) -> Result<String, AmlError> {
+        if !self.at_name() {
+            return Err(AmlError::InvalidType);
+        }
+
+        let name = self.name_string()?;
+        self.namespace
+            .search(&self.scope, &name)
+            .ok_or_else(|| AmlError::ObjectNotFound(name.resolve(&self.scope)))
+    }
+
+    /* Targets. */
+
+    fn target(&mut self) -> Result<Target, AmlError> {
+        let op = self.peek()?;
+
+        match op {
+            0x00 => {
+                self.pos += 1;
+                Ok(Target::Null)
+            }
+            0x60...0x67 => {
+                self.pos += 1;
+                Ok(Target::Local((op - 0x60) as usize))
+            }
+            0x68...0x6E => {
+                self.pos += 1;
+                Ok(Target::Arg((op - 0x68) as usize))
+            }
+            0x5B if self.code.get(self.pos + 1) == Some(&0x31) => {
+                self.pos += 2;
+                Ok(Target::Debug)
+            }
+            // Index.
+            0x88 => {
+                self.pos += 1;
+                let source = self.target()?;
+                let index = self.term_arg()?.as_integer()? as usize;
+                // The Index operation's own target is not needed when it is used as a target.
+                self.target()?;
+                Ok(Target::Index(Box::new(source), index))
+            }
+            // RefOf, DerefOf: we do not track references separately from objects.
+            0x71 | 0x83 => {
+                self.pos += 1;
+                self.target()
+            }
+            _ if self.at_name() => {
+                let name = self.name_string()?;
+                let path = self.namespace
+                    .search(&self.scope, &name)
+                    .unwrap_or_else(|| name.resolve(&self.scope));
+                Ok(Target::Name(path))
+            }
+            _ => Err(AmlError::UnsupportedOpcode(op as u16)),
+        }
+    }
+
+    fn load(&mut self, target: &Target) -> Result<AmlValue, AmlError> {
+        match *target {
+            Target::Null | Target::Debug => Ok(AmlValue::Uninitialized),
+            Target::Local(i) => Ok(self.locals[i].clone()),
+            Target::Arg(i) => Ok(self.args.get(i).cloned().unwrap_or(AmlValue::Uninitialized)),
+            Target::Name(ref path) => self.read_object(path),
+            Target::Index(ref source, index) => {
+                let source = self.load(source)?;
+                index_of(&source, index)
+            }
+        }
+    }
+
+    fn store(&mut self, target: &Target, value: AmlValue) -> Result<(), AmlError> {
+        match *target {
+            Target::Null => Ok(()),
+            Target::Debug => {
+                println!("[ acpi ] AML debug: {:?}", value);
+                Ok(())
+            }
+            Target::Local(i) => {
+                self.locals[i] = value;
+                Ok(())
+            }
+            Target::Arg(i) => {
+                // Arguments holding references write through to the referenced object.
+                match self.args.get(i).cloned() {
+                    Some(AmlValue::Reference(path)) => self.write_object(&path, value),
+                    Some(_) => {
+                        self.args[i] = value;
+                        Ok(())
+                    }
+                    None => Err(AmlError::IndexOutOfBounds),
+                }
+            }
+            Target::Name(ref path) => self.write_object(path, value),
+            Target::Index(ref source, index) => {
+                let mut container = self.load(source)?;
+
+                match container {
+                    AmlValue::Package(ref mut p) => {
+                        *p.get_mut(index).ok_or(AmlError::IndexOutOfBounds)? = value;
+                    }
+                    AmlValue::Buffer(ref mut b) => {
+                        *b.get_mut(index).ok_or(AmlError::IndexOutOfBounds)? =
+                            value.as_integer()? as u8;
+                    }
+                    _ => return Err(AmlError::InvalidType),
+                }
+
+                self.store(source, container)
+            }
+        }
+    }
+
+    /* Named object access. */
+
+    /// Read the current value of a named object.
+    fn read_object(&mut self, path: &str) -> Result<AmlValue, AmlError> {
+        match self.namespace.get(path).cloned() {
+            Some(AmlObject::Value(value)) => Ok(value),
+            Some(AmlObject::Field(field)) => self.read_field(&field),
+            Some(AmlObject::BufferField {
+                source,
+                offset,
+                length,
+            }) => {
+                if length > MAX_BUFFER_SIZE * 8 {
+                    return Err(AmlError::TooLarge);
+                }
+
+                let buffer = self.read_object(&source)?.as_buffer()?;
+                let mut result = vec![0u8; (length + 7) / 8];
+
+                for bit in 0..length {
+                    if get_bit(&buffer, offset + bit) {
+                        set_bit(&mut result, bit, true);
+                    }
+                }
+
+                Ok(bits_to_value(result, length))
+            }
+            Some(AmlObject::Method { .. }) | Some(AmlObject::NativeMethod { .. }) => {
+                self.invoke(path, Vec::new())
+            }
+            Some(_) => Ok(AmlValue::Reference(String::from(path))),
+            None => Err(AmlError::ObjectNotFound(String::from(path))),
+        }
+    }
+
+    /// Write a value to a named object, creating it if it does not exist.
+    fn write_object(&mut self, path: &str, value: AmlValue) -> Result<(), AmlError> {
+        match self.namespace.get(path).cloned() {
+            Some(AmlObject::Field(field)) => self.write_field(&field, &value),
+            Some(AmlObject::BufferField {
+                source,
+                offset,
+                length,
+            }) => {
+                let mut buffer = self.read_object(&source)?.as_buffer()?;
+                let bits = value.as_buffer()?;
+
+                for bit in 0..length {
+                    if offset + bit >= buffer.len() * 8 {
+                        return Err(AmlError::IndexOutOfBounds);
+                    }
+                    let set = get_bit(&bits, bit);
+                    set_bit(&mut buffer, offset + bit, set);
+                }
+
+                self.write_object(&source, AmlValue::Buffer(buffer))
+            }
+            _ => {
+                self.namespace
+                    .add(String::from(path), AmlObject::Value(value));
+                Ok(())
+            }
+        }
+    }
+
+    /* Field access. */
+
+    fn read_field(&mut self, field: &FieldUnit) -> Result<AmlValue, AmlError> {
+        let width = field.access_width();
+        let unit_bits = width * 8;
+
+        if field.length > MAX_BUFFER_SIZE * 8 {
+            return Err(AmlError::TooLarge);
+        }
+
+        let mut result = vec![0u8; (field.length + 7) / 8];
+
+        if field.length == 0 {
+            return Ok(AmlValue::Integer(0));
+        }
+
+        let first = field.offset / unit_bits;
+        let last = (field.offset + field.length - 1) / unit_bits;
+
+        for unit in first..last + 1 {
+            let raw = self.read_unit(&field.kind, unit * width, width)?;
+            let unit_start = unit * unit_bits;
+            let lo = cmp::max(field.offset, unit_start);
+            let hi = cmp::min(field.offset + field.length, unit_start + unit_bits);
+
+            for bit in lo..hi {
+                if raw & (1 << (bit - unit_start)) != 0 {
+                    set_bit(&mut result, bit - field.offset, true);
+                }
+            }
+        }
+
+        Ok(bits_to_value(result, field.length))
+    }
+
+    fn write_field(&mut self, field: &FieldUnit, value: &AmlValue) -> Result<(), AmlError> {
+        let width = field.access_width();
+        let unit_bits = width * 8;
+        let bits = value.as_buffer()?;
+
+        if field.length == 0 {
+            return Ok(());
+        }
+
+        let first = field.offset / unit_bits;
+        let last = (field.offset + field.length - 1) / unit_bits;
+
+        for unit in first..last + 1 {
+            let unit_start = unit * unit_bits;
+            let lo = cmp::max(field.offset, unit_start);
+            let hi = cmp::min(field.offset + field.length, unit_start + unit_bits);
+
+            // Partially covered units are read first, so that the other bits are preserved.
+            let mut raw = if hi - lo == unit_bits {
+                0
+            } else {
+                match (field.flags >> 5) & 0x3 {
+                    1 => !0,
+                    2 => 0,
+                    _ => self.read_unit(&field.kind, unit * width, width)?,
+                }
+            };
+
+            for bit in lo..hi {
+                let mask = 1 << (bit - unit_start);
+                if get_bit(&bits, bit - field.offset) {
+                    raw |= mask;
+                } else {
+                    raw &= !mask;
+                }
+            }
+
+            self.write_unit(&field.kind, unit * width, width, raw)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read a single access unit at byte `offset` from the start of a field's region.
+    fn read_unit(&mut self, kind: &FieldKind, offset: usize, width: usize) -> Result<u64, AmlError> {
+        match *kind {
+            FieldKind::Region(ref region) => {
+                let (space, address, pci) = self.region_address(region, offset)?;
+                region::read(space, address, width, pci)
+            }
+            FieldKind::Index {
+                ref index,
+                ref data,
+            } => {
+                self.write_object(index, AmlValue::Integer(offset as u64))?;
+                self.read_object(data)?.as_integer()
+            }
+        }
+    }
+
+    fn write_unit(
+        &mut self,
+        kind: &FieldKind,
+        offset: usize,
+        width: usize,
+        value: u64,
+    ) -> Result<(), AmlError> {
+        match *kind {
+            FieldKind::Region(ref region) => {
+                let (space, address, pci) = self.region_address(region, offset)?;
+                region::write(space, address, width, value, pci)
+            }
+            FieldKind::Index {
+                ref index,
+                ref data,
+            } => {
+                self.write_object(index, AmlValue::Integer(offset as u64))?;
+                self.write_object(data, AmlValue::Integer(value))
+            }
+        }
+    }
+
+    /// Return the address space, absolute address and PCI function (for PCI configuration
+    /// regions) of a byte offset into an operation region.
+    fn region_address(
+        &mut self,
+        region: &str,
+        offset: usize,
+    ) -> Result<(u8, u64, Option<(u8, u8, u8)>), AmlError> {
+        let (space, base) = match self.namespace.get(region) {
+            Some(&AmlObject::OpRegion {
+                space,
+                offset: base,
+                ..
+            }) => (space, base),
+            _ => return Err(AmlError::ObjectNotFound(String::from(region))),
+        };
+
+        let pci = if space == region::PCI_CONFIG {
+            // The function is given by the _ADR of the device the region is declared in.
+            let device = namespace::parent(region);
+            let adr = namespace::join(&device, "_ADR");
+            let adr = if self.namespace.contains(&adr) {
+                self.invoke(&adr, Vec::new())?.as_integer()?
+            } else {
+                0
+            };
+
+            let bbn = namespace::join(&namespace::parent(&device), "_BBN");
+            let bus = if self.namespace.contains(&bbn) {
+                self.invoke(&bbn, Vec::new())?.as_integer()?
+            } else {
+                0
+            };
+
+            Some((bus as u8, (adr >> 16) as u8, adr as u8))
+        } else {
+            None
+        };
+
+        Ok((space, base + offset as u64, pci))
+    }
+}
+
+/// Return the element at `index` in a string, buffer or package.
+fn index_of(source: &AmlValue, index: usize) -> Result<AmlValue, AmlError> {
+    match *source {
+        AmlValue::Package(ref p) => p.get(index).cloned().ok_or(AmlError::IndexOutOfBounds),
+        AmlValue::Buffer(ref b) => b.get(index)
+            .map(|&byte| AmlValue::Integer(byte as u64))
+            .ok_or(AmlError::IndexOutOfBounds),
+        AmlValue::String(ref s) => s.bytes()
+            .nth(index)
+            .map(|byte| AmlValue::Integer(byte as u64))
+            .ok_or(AmlError::IndexOutOfBounds),
+        _ => Err(AmlError::InvalidType),
+    }
+}
+
+fn get_bit(bytes: &[u8], bit: usize) -> bool {
+    bytes
+        .get(bit / 8)
+        .map(|&b| b & (1 << (bit % 8)) != 0)
+        .unwrap_or(false)
+}
+
+fn set_bit(bytes: &mut [u8], bit: usize, value: bool) {
+    if let Some(b) = bytes.get_mut(bit / 8) {
+        if value {
+            *b |= 1 << (bit % 8);
+        } else {
+            *b &= !(1 << (bit % 8));
+        }
+    }
+}
+
+/// Fields up to 64 bits wide are integers, wider ones are buffers.
+fn bits_to_value(bytes: Vec<u8>, length: usize) -> AmlValue {
+    if length <= 64 {
+        AmlValue::Buffer(bytes)
+            .as_integer()
+            .map(AmlValue::Integer)
+            .unwrap_or(AmlValue::Integer(0))
+    } else {
+        AmlValue::Buffer(bytes)
+    }
+}
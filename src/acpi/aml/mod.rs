@@ -0,0 +1,202 @@
+//! ACPI Machine Language support. The DSDT and SSDTs are loaded into a namespace, whose objects
+//! can then be evaluated to discover devices, interrupt routing and sleep states.
+
+use acpi::sdt::SdtHeader;
+use alloc::{String, Vec};
+use core::slice;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+pub mod interp;
+pub mod namespace;
+pub mod region;
+pub mod resource;
+pub mod value;
+
+pub use self::namespace::{AmlObject, Namespace};
+pub use self::value::{AmlError, AmlValue};
+use self::interp::Executor;
+
+lazy_static! {
+    /// The ACPI namespace, built from the DSDT and SSDTs.
+    pub static ref NAMESPACE: Mutex<Option<Namespace>> = Mutex::new(None);
+}
+
+/// Set while a method is evaluated, during which the namespace is taken out of `NAMESPACE`.
+static EVALUATING: AtomicBool = AtomicBool::new(false);
+
+/// Load a definition block into the namespace.
+fn load_table(namespace: &mut Namespace, sdt: &'static SdtHeader) -> Result<(), AmlError> {
+    let code: &'static [u8] =
+        unsafe { slice::from_raw_parts(sdt.data_address() as *const u8, sdt.data_len()) };
+
+    let mut executor = Executor::new(namespace, code, String::from("\\"), Vec::new(), 0);
+    if sdt.revision < 2 {
+        executor.set_narrow_integers();
+    }
+    executor.set_loading();
+
+    executor.run().map(|_| ())
+}
+
+/// Build the namespace from the DSDT and every SSDT.
+pub fn init() {
+    let mut namespace = Namespace::new();
+
+    let tables = super::find_sdt(b"DSDT")
+        .into_iter()
+        .chain(super::find_sdt(b"SSDT").into_iter());
+
+    for sdt in tables {
+        let signature = ::core::str::from_utf8(&sdt.signature).unwrap_or("????");
+
+        match load_table(&mut namespace, sdt) {
+            Ok(()) => println!(
+                "[ acpi ] Loaded {} at {:#x} into the namespace",
+                signature,
+                sdt as *const SdtHeader as usize
+            ),
+            Err(e) => println!("[ acpi ] Failed to load {}: {:?}", signature, e),
+        }
+    }
+
+    println!(
+        "[ acpi ] Namespace contains {} devices",
+        namespace.devices().len()
+    );
+
+    *NAMESPACE.lock() = Some(namespace);
+}
+
+/// Evaluate the object at `path`, invoking it with `args` if it is a method.
+///
+/// The namespace is taken out of its lock while the method runs, rather than the lock being held,
+/// so that anything the method reaches which evaluates AML in turn fails with `AmlError::Busy`
+/// instead of deadlocking.
+pub fn evaluate(path: &str, args: Vec<AmlValue>) -> Result<AmlValue, AmlError> {
+    let path = namespace::normalize(path);
+
+    let mut namespace = {
+        let mut slot = NAMESPACE.lock();
+
+        match slot.take() {
+            Some(namespace) => {
+                EVALUATING.store(true, Ordering::SeqCst);
+                namespace
+            }
+            None if EVALUATING.load(Ordering::SeqCst) => return Err(AmlError::Busy),
+            None => return Err(AmlError::ObjectNotFound(path)),
+        }
+    };
+
+    let result = if namespace.contains(&path) {
+        let mut executor = Executor::new(&mut namespace, &[], path.clone(), Vec::new(), 0);
+        executor.invoke(&path, args)
+    } else {
+        Err(AmlError::ObjectNotFound(path))
+    };
+
+    let mut slot = NAMESPACE.lock();
+    *slot = Some(namespace);
+    EVALUATING.store(false, Ordering::SeqCst);
+
+    result
+}
+
+/// Tell the firmware which interrupt model we use, via `\_PIC`. This changes the routing that
+/// `_PRT` returns. 0 is the 8259 PIC, 1 is the I/O APIC.
+pub fn set_interrupt_model(model: u64) {
+    match evaluate("\\_PIC", vec![AmlValue::Integer(model)]) {
+        Ok(_) | Err(AmlError::ObjectNotFound(_)) => {}
+        Err(e) => println!("[ acpi ] Failed to evaluate \\_PIC: {:?}", e),
+    }
+}
+
+/// Return the `SLP_TYPa` and `SLP_TYPb` values for a sleep state, from the `\_Sx` package.
+pub fn sleep_type(state: u8) -> Result<(u16, u16), AmlError> {
+    let package = evaluate(&format!("\\_S{}_", state), Vec::new())?;
+    let package = package.as_package()?;
+
+    let a = package.get(0).ok_or(AmlError::IndexOutOfBounds)?.as_integer()?;
+    // Some firmware packs both values into the first element.
+    let b = match package.get(1) {
+        Some(value) => value.as_integer()?,
+        None => a >> 8,
+    };
+
+    Ok((a as u16 & 0x7, b as u16 & 0x7))
+}
+
+/// Return the status of a device from its `_STA` method. Devices without one are assumed to be
+/// present and functioning.
+pub fn device_status(device: &str) -> Result<u64, AmlError> {
+    match evaluate(&format!("{}._STA", device), Vec::new()) {
+        Err(AmlError::ObjectNotFound(_)) => Ok(0x0F),
+        result => result?.as_integer(),
+    }
+}
+
+/// Evaluate the current resource settings of a device.
+pub fn current_resources(device: &str) -> Result<Vec<resource::Resource>, AmlError> {
+    let buffer = evaluate(&format!("{}._CRS", device), Vec::new())?.as_buffer()?;
+    Ok(resource::parse(&buffer))
+}
+
+/// A single entry of a PCI routing table.
+#[derive(Debug, Clone)]
+pub struct PrtEntry {
+    /// The PCI device number.
+    pub device: u8,
+    /// The interrupt pin, 0 for INTA# through 3 for INTD#.
+    pub pin: u8,
+    /// The global system interrupt this pin is wired to.
+    pub gsi: u32,
+}
+
+/// Evaluate the `_PRT` of a PCI root bridge or bridge, resolving link devices to their current
+/// interrupt.
+pub fn pci_routing(bridge: &str) -> Result<Vec<PrtEntry>, AmlError> {
+    let table = evaluate(&format!("{}._PRT", bridge), Vec::new())?;
+    let mut entries = Vec::new();
+
+    for entry in table.as_package()? {
+        let entry = entry.as_package()?;
+        if entry.len() < 4 {
+            return Err(AmlError::InvalidType);
+        }
+
+        let address = entry[0].as_integer()?;
+        let pin = entry[1].as_integer()? as u8;
+
+        // A source of zero means the index is the GSI itself, otherwise the source names a link
+        // device whose current resources contain the interrupt.
+        let gsi = match entry[2] {
+            AmlValue::Reference(ref link) => {
+                let resources = current_resources(link)?;
+                match resources.iter().filter_map(|r| r.irq()).next() {
+                    Some(irq) => irq,
+                    None => {
+                        println!(
+                            "[ acpi ] Ignoring the routing of device {:#x} pin {} in {}: {} has \
+                             no current interrupt",
+                            address >> 16,
+                            pin,
+                            bridge,
+                            link
+                        );
+                        continue;
+                    }
+                }
+            }
+            _ => entry[3].as_integer()? as u32,
+        };
+
+        entries.push(PrtEntry {
+            device: (address >> 16) as u8,
+            pin: pin,
+            gsi: gsi,
+        });
+    }
+
+    Ok(entries)
+}
@@ -0,0 +1,263 @@
+use super::value::{AmlError, AmlValue};
+use alloc::{String, Vec};
+use alloc::btree_map::BTreeMap;
+use core::str;
+
+/// A name string as it is encoded in AML, before it has been resolved against a scope.
+#[derive(Debug, Clone)]
+pub struct NameString {
+    /// Whether this name starts at the root, `\`.
+    pub root: bool,
+    /// The number of `^` parent prefixes.
+    pub parents: usize,
+    pub segments: Vec<[u8; 4]>,
+}
+
+impl NameString {
+    /// Whether the namespace search rules apply to this name - they only do for single segment
+    /// names with no prefixes.
+    pub fn is_search_name(&self) -> bool {
+        !self.root && self.parents == 0 && self.segments.len() == 1
+    }
+
+    /// Return the absolute path this name refers to when used in `scope`, without searching
+    /// parent scopes.
+    pub fn resolve(&self, scope: &str) -> String {
+        let mut path = if self.root {
+            String::from("\\")
+        } else {
+            let mut path = String::from(scope);
+            for _ in 0..self.parents {
+                path = parent(&path);
+            }
+            path
+        };
+
+        for segment in self.segments.iter() {
+            path = join(&path, str::from_utf8(segment).unwrap_or("____"));
+        }
+
+        path
+    }
+}
+
+/// Return the parent scope of an absolute path.
+pub fn parent(path: &str) -> String {
+    match path.rfind('.') {
+        Some(i) => String::from(&path[..i]),
+        None => String::from("\\"),
+    }
+}
+
+/// Append a name segment to an absolute path.
+pub fn join(path: &str, segment: &str) -> String {
+    if path == "\\" {
+        format!("\\{}", segment)
+    } else {
+        format!("{}.{}", path, segment)
+    }
+}
+
+/// Turn a human-written path such as `\_SB.PCI0._PRT` into the canonical form used by the
+/// namespace, where every segment is padded to four characters with underscores.
+pub fn normalize(path: &str) -> String {
+    let mut result = String::from("\\");
+
+    for segment in path.trim_left_matches('\\').split('.').filter(|s| !s.is_empty()) {
+        let mut padded = String::from(segment);
+        while padded.len() < 4 {
+            padded.push('_');
+        }
+        result = join(&result, &padded);
+    }
+
+    result
+}
+
+/// The type of a field, and the objects used to access it.
+#[derive(Debug, Clone)]
+pub enum FieldKind {
+    /// A field within an operation region.
+    Region(String),
+    /// A field accessed by writing its offset to the `index` field, and then accessing `data`.
+    Index { index: String, data: String },
+}
+
+/// A field unit, declared by the `Field` or `IndexField` opcodes.
+#[derive(Debug, Clone)]
+pub struct FieldUnit {
+    pub kind: FieldKind,
+    /// Offset of this field in bits.
+    pub offset: usize,
+    /// Length of this field in bits.
+    pub length: usize,
+    /// Access type, lock and update rule.
+    pub flags: u8,
+}
+
+impl FieldUnit {
+    /// The width of a single access to this field in bytes.
+    pub fn access_width(&self) -> usize {
+        match self.flags & 0xF {
+            2 => 2,
+            3 => 4,
+            4 => 8,
+            _ => 1,
+        }
+    }
+}
+
+/// An object in the ACPI namespace.
+#[derive(Clone)]
+pub enum AmlObject {
+    Scope,
+    Device,
+    Processor { id: u8, pblk_address: u32, pblk_len: u8 },
+    PowerResource,
+    ThermalZone,
+    Mutex,
+    Event,
+    /// A named data object, declared by `Name`.
+    Value(AmlValue),
+    /// A control method. The code is borrowed straight from the mapped table.
+    Method { code: &'static [u8], args: u8, flags: u8 },
+    /// A method implemented by the kernel, such as `_OSI`.
+    NativeMethod {
+        func: fn(&[AmlValue]) -> Result<AmlValue, AmlError>,
+        args: u8,
+    },
+    OpRegion { space: u8, offset: u64, length: u64 },
+    Field(FieldUnit),
+    /// A field within a named buffer, created by `CreateDWordField` and friends.
+    BufferField { source: String, offset: usize, length: usize },
+    Alias(String),
+}
+
+/// The ACPI namespace. Objects are keyed by their absolute path, so the children of a scope are
+/// stored directly after it.
+pub struct Namespace {
+    objects: BTreeMap<String, AmlObject>,
+}
+
+impl Namespace {
+    /// Create a namespace populated with the predefined root scopes and objects.
+    pub fn new() -> Self {
+        let mut namespace = Namespace {
+            objects: BTreeMap::new(),
+        };
+
+        namespace.add(String::from("\\"), AmlObject::Scope);
+
+        for scope in ["_GPE", "_PR_", "_SB_", "_SI_", "_TZ_"].iter() {
+            namespace.add(join("\\", scope), AmlObject::Scope);
+        }
+
+        namespace.add(String::from("\\_GL_"), AmlObject::Mutex);
+        namespace.add(
+            String::from("\\_OS_"),
+            AmlObject::Value(AmlValue::String(String::from("Microsoft Windows NT"))),
+        );
+        namespace.add(String::from("\\_REV"), AmlObject::Value(AmlValue::Integer(2)));
+        namespace.add(
+            String::from("\\_OSI"),
+            AmlObject::NativeMethod {
+                func: osi,
+                args: 1,
+            },
+        );
+
+        namespace
+    }
+
+    /// Add an object. Redefining an existing object replaces it, except that a scope never
+    /// replaces the device or processor that it extends.
+    pub fn add(&mut self, path: String, object: AmlObject) {
+        if let AmlObject::Scope = object {
+            if self.objects.contains_key(&path) {
+                return;
+            }
+        }
+
+        self.objects.insert(path, object);
+    }
+
+    pub fn get(&self, path: &str) -> Option<&AmlObject> {
+        match self.objects.get(path) {
+            Some(&AmlObject::Alias(ref target)) => self.objects.get(target),
+            object => object,
+        }
+    }
+
+    pub fn get_mut(&mut self, path: &str) -> Option<&mut AmlObject> {
+        self.objects.get_mut(path)
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.objects.contains_key(path)
+    }
+
+    /// Find the object referred to by `name` from within `scope`. Single segment names are looked
+    /// up in `scope` and then in each parent scope in turn.
+    pub fn search(&self, scope: &str, name: &NameString) -> Option<String> {
+        if !name.is_search_name() {
+            let path = name.resolve(scope);
+            return if self.contains(&path) { Some(path) } else { None };
+        }
+
+        let mut scope = String::from(scope);
+
+        loop {
+            let path = name.resolve(&scope);
+
+            if self.contains(&path) {
+                return Some(path);
+            } else if scope == "\\" {
+                return None;
+            }
+
+            scope = parent(&scope);
+        }
+    }
+
+    /// Return the paths of the direct children of `path`.
+    pub fn children(&self, path: &str) -> Vec<String> {
+        let prefix = if path == "\\" {
+            String::from("\\")
+        } else {
+            format!("{}.", path)
+        };
+
+        self.objects
+            .keys()
+            .filter(|k| k.as_str() != path && k.starts_with(&prefix))
+            .filter(|k| !k[prefix.len()..].contains('.'))
+            .cloned()
+            .collect()
+    }
+
+    /// Return the paths of every device in the namespace.
+    pub fn devices(&self) -> Vec<String> {
+        self.objects
+            .iter()
+            .filter(|&(_, o)| match *o {
+                AmlObject::Device => true,
+                _ => false,
+            })
+            .map(|(k, _)| k.clone())
+            .collect()
+    }
+}
+
+/// Our implementation of `\_OSI`. We claim to be the same operating systems that other hobby
+/// kernels do, because firmware is usually only tested against Windows.
+fn osi(args: &[AmlValue]) -> Result<AmlValue, AmlError> {
+    let interface = args[0].as_string()?;
+
+    let supported = match interface.as_str() {
+        "Windows 2000" | "Windows 2001" | "Windows 2001 SP1" | "Windows 2001.1"
+        | "Windows 2006" | "Windows 2009" | "Windows 2012" | "Windows 2015" => true,
+        _ => false,
+    };
+
+    Ok(AmlValue::Integer(if supported { !0 } else { 0 }))
+}
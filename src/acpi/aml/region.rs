@@ -0,0 +1,118 @@
+//! Access to the address spaces that operation regions can live in.
+
+use super::value::AmlError;
+use arch::memory::paging::ActivePageTable;
+use arch::memory::paging::entry::EntryFlags;
+use core::ptr;
+use device::io::cpuio::x86_io::{inb, inl, inw, outb, outl, outw};
+use device::pci;
+
+pub const SYSTEM_MEMORY: u8 = 0;
+pub const SYSTEM_IO: u8 = 1;
+pub const PCI_CONFIG: u8 = 2;
+
+/// Read `width` bytes from an address in the given address space.
+pub fn read(space: u8, address: u64, width: usize, pci: Option<(u8, u8, u8)>) -> Result<u64, AmlError> {
+    match space {
+        SYSTEM_MEMORY => {
+            map(address as usize, width);
+
+            unsafe {
+                Ok(match width {
+                    1 => ptr::read_volatile(address as *const u8) as u64,
+                    2 => ptr::read_volatile(address as *const u16) as u64,
+                    4 => ptr::read_volatile(address as *const u32) as u64,
+                    _ => ptr::read_volatile(address as *const u64),
+                })
+            }
+        }
+        SYSTEM_IO => unsafe {
+            let port = address as u16;
+            Ok(match width {
+                1 => inb(port) as u64,
+                2 => inw(port) as u64,
+                _ => inl(port) as u64,
+            })
+        },
+        PCI_CONFIG => {
+            let (bus, slot, func) = pci.unwrap_or((0, 0, 0));
+            let offset = address as u16;
+            let dword = unsafe { pci::read_config(bus, slot, func, offset) };
+            let shift = (offset & 0x3) * 8;
+
+            Ok(match width {
+                1 => (dword >> shift) & 0xFF,
+                2 => (dword >> shift) & 0xFFFF,
+                _ => dword,
+            } as u64)
+        }
+        _ => Err(AmlError::UnsupportedRegionSpace(space)),
+    }
+}
+
+/// Write `width` bytes to an address in the given address space.
+pub fn write(
+    space: u8,
+    address: u64,
+    width: usize,
+    value: u64,
+    pci: Option<(u8, u8, u8)>,
+) -> Result<(), AmlError> {
+    match space {
+        SYSTEM_MEMORY => {
+            map(address as usize, width);
+
+            unsafe {
+                match width {
+                    1 => ptr::write_volatile(address as *mut u8, value as u8),
+                    2 => ptr::write_volatile(address as *mut u16, value as u16),
+                    4 => ptr::write_volatile(address as *mut u32, value as u32),
+                    _ => ptr::write_volatile(address as *mut u64, value),
+                }
+            }
+
+            Ok(())
+        }
+        SYSTEM_IO => unsafe {
+            let port = address as u16;
+            match width {
+                1 => outb(value as u8, port),
+                2 => outw(value as u16, port),
+                _ => outl(value as u32, port),
+            }
+            Ok(())
+        },
+        PCI_CONFIG => {
+            let (bus, slot, func) = pci.unwrap_or((0, 0, 0));
+            let offset = address as u16;
+            let shift = (offset & 0x3) * 8;
+
+            unsafe {
+                let dword = match width {
+                    1 | 2 => {
+                        let mask: u32 = if width == 1 { 0xFF } else { 0xFFFF };
+                        let old = pci::read_config(bus, slot, func, offset);
+                        (old & !(mask << shift)) | ((value as u32 & mask) << shift)
+                    }
+                    _ => value as u32,
+                };
+
+                pci::write_config(bus, slot, func, offset, dword);
+            }
+
+            Ok(())
+        }
+        _ => Err(AmlError::UnsupportedRegionSpace(space)),
+    }
+}
+
+/// Identity map the memory backing a system memory region access.
+fn map(address: usize, width: usize) {
+    let mut active_table = unsafe { ActivePageTable::new() };
+    ::acpi::map_range(
+        address,
+        address + width - 1,
+        EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE,
+        &mut active_table,
+    );
+}
@@ -0,0 +1,154 @@
+//! Decoding of the resource templates returned by `_CRS`.
+
+use alloc::Vec;
+
+/// A decoded resource descriptor.
+#[derive(Debug, Clone)]
+pub enum Resource {
+    /// A set of legacy IRQs, as a bitmask.
+    Irq { mask: u16, flags: u8 },
+    /// A set of global system interrupts.
+    ExtendedIrq { interrupts: Vec<u32>, flags: u8 },
+    Io { min: u16, max: u16, alignment: u8, length: u8 },
+    FixedIo { base: u16, length: u8 },
+    Memory32 { base: u32, length: u32, writable: bool },
+    /// A memory, I/O or bus number range from a word, dword or qword address space descriptor.
+    AddressSpace {
+        kind: u8,
+        min: u64,
+        max: u64,
+        translation: u64,
+        length: u64,
+    },
+    Unknown(u8),
+}
+
+impl Resource {
+    /// Return the first interrupt described by this resource, if it describes any.
+    pub fn irq(&self) -> Option<u32> {
+        match *self {
+            Resource::Irq { mask, .. } if mask != 0 => Some(mask.trailing_zeros()),
+            Resource::ExtendedIrq { ref interrupts, .. } => interrupts.first().cloned(),
+            _ => None,
+        }
+    }
+}
+
+fn read_u16(bytes: &[u8], i: usize) -> u16 {
+    bytes[i] as u16 | (bytes[i + 1] as u16) << 8
+}
+
+fn read_u32(bytes: &[u8], i: usize) -> u32 {
+    read_u16(bytes, i) as u32 | (read_u16(bytes, i + 2) as u32) << 16
+}
+
+fn read_u64(bytes: &[u8], i: usize) -> u64 {
+    read_u32(bytes, i) as u64 | (read_u32(bytes, i + 4) as u64) << 32
+}
+
+/// Parse a resource template buffer into a list of resources. Parsing stops at the end tag, or
+/// at the first descriptor which would run past the end of the buffer.
+pub fn parse(buffer: &[u8]) -> Vec<Resource> {
+    let mut resources = Vec::new();
+    let mut i = 0;
+
+    while i < buffer.len() {
+        let tag = buffer[i];
+
+        if tag & 0x80 == 0 {
+            // Small resource descriptor.
+            let length = (tag & 0x7) as usize;
+            let data = match buffer.get(i + 1..i + 1 + length) {
+                Some(data) => data,
+                None => break,
+            };
+
+            match (tag >> 3) & 0xF {
+                // IRQ.
+                0x4 if length >= 2 => resources.push(Resource::Irq {
+                    mask: read_u16(data, 0),
+                    flags: if length > 2 { data[2] } else { 0x1 },
+                }),
+                // I/O port.
+                0x8 if length >= 7 => resources.push(Resource::Io {
+                    min: read_u16(data, 1),
+                    max: read_u16(data, 3),
+                    alignment: data[5],
+                    length: data[6],
+                }),
+                // Fixed I/O port.
+                0x9 if length >= 3 => resources.push(Resource::FixedIo {
+                    base: read_u16(data, 0) & 0x3FF,
+                    length: data[2],
+                }),
+                // End tag.
+                0xF => break,
+                kind => resources.push(Resource::Unknown(kind)),
+            }
+
+            i += 1 + length;
+        } else {
+            // Large resource descriptor.
+            if i + 3 > buffer.len() {
+                break;
+            }
+
+            let length = read_u16(buffer, i + 1) as usize;
+            let data = match buffer.get(i + 3..i + 3 + length) {
+                Some(data) => data,
+                None => break,
+            };
+
+            match tag {
+                // 32-bit fixed memory range.
+                0x86 if length >= 9 => resources.push(Resource::Memory32 {
+                    base: read_u32(data, 1),
+                    length: read_u32(data, 5),
+                    writable: data[0] & 1 != 0,
+                }),
+                // DWord address space.
+                0x87 if length >= 23 => resources.push(Resource::AddressSpace {
+                    kind: data[0],
+                    min: read_u32(data, 7) as u64,
+                    max: read_u32(data, 11) as u64,
+                    translation: read_u32(data, 15) as u64,
+                    length: read_u32(data, 19) as u64,
+                }),
+                // Word address space.
+                0x88 if length >= 13 => resources.push(Resource::AddressSpace {
+                    kind: data[0],
+                    min: read_u16(data, 5) as u64,
+                    max: read_u16(data, 7) as u64,
+                    translation: read_u16(data, 9) as u64,
+                    length: read_u16(data, 11) as u64,
+                }),
+                // Extended interrupt.
+                0x89 if length >= 2 => {
+                    let count = data[1] as usize;
+                    let interrupts = (0..count)
+                        .take_while(|n| 2 + n * 4 + 4 <= length)
+                        .map(|n| read_u32(data, 2 + n * 4))
+                        .collect();
+
+                    resources.push(Resource::ExtendedIrq {
+                        interrupts: interrupts,
+                        flags: data[0],
+                    });
+                }
+                // QWord address space.
+                0x8A if length >= 43 => resources.push(Resource::AddressSpace {
+                    kind: data[0],
+                    min: read_u64(data, 11),
+                    max: read_u64(data, 19),
+                    translation: read_u64(data, 27),
+                    length: read_u64(data, 35),
+                }),
+                _ => resources.push(Resource::Unknown(tag)),
+            }
+
+            i += 3 + length;
+        }
+    }
+
+    resources
+}
@@ -0,0 +1,118 @@
+use alloc::{String, Vec};
+
+/// Errors that can occur while parsing or evaluating AML.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AmlError {
+    /// We tried to read past the end of the current table or method.
+    UnexpectedEndOfStream,
+    /// The opcode (with the `0x5B` extended prefix in the high byte if present) is not known to
+    /// the interpreter.
+    UnsupportedOpcode(u16),
+    InvalidNameString,
+    ObjectNotFound(String),
+    /// An object had the wrong type for the operation performed on it.
+    InvalidType,
+    /// An index was out of range of the buffer, string or package it was applied to.
+    IndexOutOfBounds,
+    UnsupportedRegionSpace(u8),
+    DivideByZero,
+    /// A `While` loop ran for too long, so we assume firmware is waiting on hardware we do not
+    /// emulate.
+    LoopLimit,
+    /// Method invocations nested too deeply.
+    RecursionLimit,
+    /// The firmware executed a `Fatal` opcode.
+    Fatal,
+    /// The namespace is in use by another evaluation, as when evaluation is reentered.
+    Busy,
+    /// A buffer or package was declared larger than we are willing to allocate.
+    TooLarge,
+}
+
+/// A value produced or consumed by AML code.
+#[derive(Debug, Clone)]
+pub enum AmlValue {
+    Uninitialized,
+    Integer(u64),
+    String(String),
+    Buffer(Vec<u8>),
+    Package(Vec<AmlValue>),
+    /// A reference to a named object, by its absolute path.
+    Reference(String),
+}
+
+impl AmlValue {
+    /// Convert this value to an integer, using the implicit conversion rules in the ACPI
+    /// specification.
+    pub fn as_integer(&self) -> Result<u64, AmlError> {
+        match *self {
+            AmlValue::Integer(i) => Ok(i),
+            AmlValue::Buffer(ref b) => Ok(b.iter()
+                .take(8)
+                .enumerate()
+                .fold(0, |acc, (i, &byte)| acc | (byte as u64) << (i * 8))),
+            AmlValue::String(ref s) => {
+                // Strings are interpreted as hexadecimal, stopping at the first non-hex digit.
+                let s = s.trim_left_matches("0x").trim_left_matches("0X");
+                let mut value: u64 = 0;
+
+                for c in s.chars() {
+                    match c.to_digit(16) {
+                        Some(d) => value = (value << 4) | d as u64,
+                        None => break,
+                    }
+                }
+
+                Ok(value)
+            }
+            _ => Err(AmlError::InvalidType),
+        }
+    }
+
+    /// Convert this value to a buffer.
+    pub fn as_buffer(&self) -> Result<Vec<u8>, AmlError> {
+        match *self {
+            AmlValue::Integer(i) => Ok((0..8).map(|n| (i >> (n * 8)) as u8).collect()),
+            AmlValue::Buffer(ref b) => Ok(b.clone()),
+            AmlValue::String(ref s) => {
+                let mut bytes: Vec<u8> = s.bytes().collect();
+                bytes.push(0);
+                Ok(bytes)
+            }
+            _ => Err(AmlError::InvalidType),
+        }
+    }
+
+    /// Convert this value to a string.
+    pub fn as_string(&self) -> Result<String, AmlError> {
+        match *self {
+            AmlValue::Integer(i) => Ok(format!("{:X}", i)),
+            AmlValue::String(ref s) => Ok(s.clone()),
+            AmlValue::Buffer(ref b) => Ok(b.iter()
+                .take_while(|&&c| c != 0)
+                .map(|&c| c as char)
+                .collect()),
+            _ => Err(AmlError::InvalidType),
+        }
+    }
+
+    /// Return the elements of a package.
+    pub fn as_package(&self) -> Result<&[AmlValue], AmlError> {
+        match *self {
+            AmlValue::Package(ref p) => Ok(p),
+            _ => Err(AmlError::InvalidType),
+        }
+    }
+
+    /// The type of this value, as returned by the `ObjectType` opcode.
+    pub fn object_type(&self) -> u64 {
+        match *self {
+            AmlValue::Uninitialized => 0,
+            AmlValue::Integer(_) => 1,
+            AmlValue::String(_) => 2,
+            AmlValue::Buffer(_) => 3,
+            AmlValue::Package(_) => 4,
+            AmlValue::Reference(_) => 0,
+        }
+    }
+}
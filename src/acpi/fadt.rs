@@ -0,0 +1,72 @@
+use acpi::sdt::{GenericAddress, SdtHeader};
+
+/// The Fixed ACPI Description Table. This describes the fixed hardware registers used for power
+/// management, and points to the DSDT.
+#[derive(Copy, Clone, Debug)]
+#[repr(packed)]
+pub struct Fadt {
+    pub header: SdtHeader,
+    pub firmware_ctrl: u32,
+    /// Physical address of the DSDT.
+    pub dsdt: u32,
+    _reserved: u8,
+    pub preferred_power_profile: u8,
+    pub sci_interrupt: u16,
+    pub smi_command_port: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub s4bios_req: u8,
+    pub pstate_control: u8,
+    pub pm1a_event_block: u32,
+    pub pm1b_event_block: u32,
+    pub pm1a_control_block: u32,
+    pub pm1b_control_block: u32,
+    pub pm2_control_block: u32,
+    pub pm_timer_block: u32,
+    pub gpe0_block: u32,
+    pub gpe1_block: u32,
+    pub pm1_event_length: u8,
+    pub pm1_control_length: u8,
+    pub pm2_control_length: u8,
+    pub pm_timer_length: u8,
+    pub gpe0_length: u8,
+    pub gpe1_length: u8,
+    pub gpe1_base: u8,
+    pub cstate_control: u8,
+    pub worst_c2_latency: u16,
+    pub worst_c3_latency: u16,
+    pub flush_size: u16,
+    pub flush_stride: u16,
+    pub duty_offset: u8,
+    pub duty_width: u8,
+    pub day_alarm: u8,
+    pub month_alarm: u8,
+    pub century: u8,
+    pub boot_architecture_flags: u16,
+    _reserved2: u8,
+    pub flags: u32,
+    pub reset_reg: GenericAddress,
+    pub reset_value: u8,
+    _reserved3: [u8; 3],
+    /// 64-bit address of the DSDT, only valid on ACPI 2.0+.
+    pub x_firmware_control: u64,
+    pub x_dsdt: u64,
+}
+
+impl Fadt {
+    pub fn new(sdt: &'static SdtHeader) -> &'static Self {
+        unsafe { &*(sdt as *const SdtHeader as *const Fadt) }
+    }
+
+    /// Return the physical address of the DSDT, preferring the 64-bit field if this table is
+    /// large enough to contain it.
+    pub fn dsdt_address(&self) -> usize {
+        use core::mem;
+
+        if self.header.length as usize >= mem::size_of::<Fadt>() && self.x_dsdt != 0 {
+            self.x_dsdt as usize
+        } else {
+            self.dsdt as usize
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use acpi::sdt::{GenericAddress, SdtHeader};
+
+/// The High Precision Event Timer description table.
+#[derive(Copy, Clone, Debug)]
+#[repr(packed)]
+pub struct Hpet {
+    pub header: SdtHeader,
+    pub hardware_rev_id: u8,
+    /// Bits 0-4: number of comparators, bit 5: counter size, bit 7: legacy replacement capable.
+    pub comparator_info: u8,
+    pub pci_vendor_id: u16,
+    /// Location of the HPET register block.
+    pub address: GenericAddress,
+    pub hpet_number: u8,
+    pub minimum_tick: u16,
+    pub page_protection: u8,
+}
+
+impl Hpet {
+    pub fn new(sdt: &'static SdtHeader) -> &'static Self {
+        unsafe { &*(sdt as *const SdtHeader as *const Hpet) }
+    }
+}
@@ -0,0 +1,35 @@
+use acpi::sdt::SdtHeader;
+use core::{mem, slice};
+
+/// PCI Express memory mapped configuration space base address description table.
+#[derive(Debug, Clone, Copy)]
+pub struct Mcfg {
+    pub sdt: &'static SdtHeader,
+}
+
+/// A single ECAM region, covering the configuration space of a range of buses in one PCI segment.
+#[derive(Debug, Clone, Copy)]
+#[repr(packed)]
+pub struct McfgEntry {
+    /// Physical base address of the enhanced configuration mechanism.
+    pub base_address: u64,
+    /// PCI segment group number.
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+    _reserved: u32,
+}
+
+impl Mcfg {
+    pub fn new(sdt: &'static SdtHeader) -> Self {
+        Mcfg { sdt: sdt }
+    }
+
+    /// Return all the configuration space entries in this table.
+    pub fn entries(&self) -> &'static [McfgEntry] {
+        // Skip the eight reserved bytes after the header.
+        let len = self.sdt.data_len().saturating_sub(8) / mem::size_of::<McfgEntry>();
+
+        unsafe { slice::from_raw_parts((self.sdt.data_address() + 8) as *const McfgEntry, len) }
+    }
+}
@@ -1,71 +1,183 @@
 use arch::memory::paging::{ActivePageTable, Page, PhysicalAddress, VirtualAddress};
 use arch::memory::Frame;
 use arch::memory::paging::entry::EntryFlags;
-use core::mem;
+use alloc::Vec;
+use alloc::btree_map::BTreeMap;
+use core::{mem, str};
+use spin::Mutex;
 
 pub mod rsdp;
 pub mod sdt;
 pub mod rsdt;
 pub mod xsdt;
 pub mod madt;
+pub mod fadt;
+pub mod hpet;
+pub mod mcfg;
+pub mod aml;
 
-/// Retrieve an SDT from a pointer found using the RSDP
-fn get_sdt(address: usize, active_table: &mut ActivePageTable) -> &'static sdt::SdtHeader {
-    {
-        let page = Page::containing_address(VirtualAddress::new(address));
+lazy_static! {
+    /// Every valid SDT found on the system, keyed by signature. Some tables, such as the SSDT,
+    /// can appear more than once.
+    pub static ref SDT_TABLES: Mutex<BTreeMap<[u8; 4], Vec<&'static sdt::SdtHeader>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// A typed view of an SDT.
+pub enum TableType {
+    Madt(madt::Madt),
+    Fadt(&'static fadt::Fadt),
+    Hpet(&'static hpet::Hpet),
+    Mcfg(mcfg::Mcfg),
+    Dsdt(&'static sdt::SdtHeader),
+    Ssdt(&'static sdt::SdtHeader),
+    Unknown(&'static sdt::SdtHeader),
+}
+
+impl TableType {
+    /// Interpret a table based on its signature.
+    pub fn from_sdt(sdt: &'static sdt::SdtHeader) -> Self {
+        match &sdt.signature {
+            b"APIC" => TableType::Madt(madt::Madt::new(sdt)),
+            b"FACP" => TableType::Fadt(fadt::Fadt::new(sdt)),
+            b"HPET" => TableType::Hpet(hpet::Hpet::new(sdt)),
+            b"MCFG" => TableType::Mcfg(mcfg::Mcfg::new(sdt)),
+            b"DSDT" => TableType::Dsdt(sdt),
+            b"SSDT" => TableType::Ssdt(sdt),
+            _ => TableType::Unknown(sdt),
+        }
+    }
+}
+
+/// Identity map a range of physical memory, skipping pages which are already mapped.
+fn map_range(start: usize, end: usize, flags: EntryFlags, active_table: &mut ActivePageTable) {
+    let start_page = Page::containing_address(VirtualAddress::new(start));
+    let end_page = Page::containing_address(VirtualAddress::new(end));
+
+    for page in Page::range_inclusive(start_page, end_page) {
+        // Check if this page has already been mapped to a frame.
         if active_table.translate_page(page).is_none() {
             let frame = Frame::containing_address(PhysicalAddress::new(page.start_address().get()));
-            let result =
-                active_table.map_to(page, frame, EntryFlags::PRESENT | EntryFlags::NO_EXECUTE);
+            let result = active_table.map_to(page, frame, EntryFlags::PRESENT | flags);
             result.flush(active_table);
         }
     }
+}
+
+/// Retrieve an SDT from a pointer found using the RSDP. The table is mapped and its checksum
+/// verified before it is returned.
+fn get_sdt(
+    address: usize,
+    active_table: &mut ActivePageTable,
+) -> Result<&'static sdt::SdtHeader, &'static str> {
+    // Map the header first, so that we can read the length of the table.
+    map_range(
+        address,
+        address + mem::size_of::<sdt::SdtHeader>() - 1,
+        EntryFlags::NO_EXECUTE,
+        active_table,
+    );
 
     let sdt = unsafe { &*(address as *const sdt::SdtHeader) };
 
-    {
-        // Map next page, and all pages within the range occupied by the data table.
-        let start_page = Page::containing_address(VirtualAddress::new(address + 4096));
-        let end_page = Page::containing_address(VirtualAddress::new(address + sdt.length as usize));
-        for page in Page::range_inclusive(start_page, end_page) {
-            // Check if this page has already been mapped to a frame.
-            if active_table.translate_page(page).is_none() {
-                let frame =
-                    Frame::containing_address(PhysicalAddress::new(page.start_address().get()));
-                let result =
-                    active_table.map_to(page, frame, EntryFlags::PRESENT | EntryFlags::NO_EXECUTE);
-                result.flush(active_table);
-            }
+    if (sdt.length as usize) < mem::size_of::<sdt::SdtHeader>() {
+        return Err("SDT length is smaller than its header");
+    }
+
+    // Map all pages within the range occupied by the data table.
+    map_range(
+        address,
+        address + sdt.length as usize - 1,
+        EntryFlags::NO_EXECUTE,
+        active_table,
+    );
+
+    if !sdt.is_valid() {
+        return Err("SDT checksum is invalid");
+    }
+
+    Ok(sdt)
+}
+
+/// Map, validate and add the table at `address` to the registry.
+fn load_sdt(address: usize, active_table: &mut ActivePageTable) -> Option<&'static sdt::SdtHeader> {
+    match get_sdt(address, active_table) {
+        Ok(sdt) => {
+            println!(
+                "[ acpi ] Found {} at address {:#x}, length {}",
+                str::from_utf8(&sdt.signature).unwrap_or("????"),
+                address,
+                sdt.length
+            );
+
+            SDT_TABLES
+                .lock()
+                .entry(sdt.signature)
+                .or_insert_with(Vec::new)
+                .push(sdt);
+
+            Some(sdt)
+        }
+        Err(e) => {
+            println!("[ acpi ] Ignoring table at {:#x}: {}", address, e);
+            None
         }
     }
+}
 
-    sdt
+/// Return every table with the given signature.
+pub fn find_sdt(signature: &[u8; 4]) -> Vec<&'static sdt::SdtHeader> {
+    SDT_TABLES
+        .lock()
+        .get(signature)
+        .cloned()
+        .unwrap_or_else(Vec::new)
+}
+
+/// Return a typed view of the first table with the given signature.
+pub fn get_table(signature: &[u8; 4]) -> Option<TableType> {
+    find_sdt(signature)
+        .first()
+        .map(|&sdt| TableType::from_sdt(sdt))
 }
 
 pub unsafe fn init(active_table: &mut ActivePageTable) {
     let rsdp = rsdp::RsdpDescriptor::init(active_table).expect("Could not find rsdp, aborting ...");
-    let sdt = get_sdt(rsdp.sdt(), active_table);
-    let rsdt = rsdt::Rsdt::new(sdt);
+    let root = get_sdt(rsdp.sdt(), active_table).expect("Root system descriptor table is corrupt");
 
-    println!(
-        "[ apci ] Found RSDT at address {:#x}",
-        rsdt.sdt as *const sdt::SdtHeader as usize
-    );
+    let addresses = match &root.signature {
+        b"RSDT" => rsdt::Rsdt::new(root).addresses(),
+        b"XSDT" => xsdt::Xsdt::new(root)
+            .expect("Could not parse XSDT")
+            .addresses(),
+        _ => panic!("Non-matching root table signature, aborting ..."),
+    };
 
     println!(
-        "[ acpi ] RSDT length {}, data length {}",
-        rsdt.sdt.length,
-        rsdt.sdt.length as usize - mem::size_of::<sdt::SdtHeader>()
+        "[ acpi ] Found {} at address {:#x}",
+        str::from_utf8(&root.signature).unwrap_or("????"),
+        root as *const sdt::SdtHeader as usize
     );
 
     println!(
-        "[ acpi ] RSDT points to {} tables",
-        rsdt.other_entries.len()
+        "[ acpi ] Root table length {}, data length {}",
+        root.length,
+        root.data_len()
     );
 
-    // let mut madt: madt::Madt = unsafe { *(&*(0 as *const madt::Madt)) };
-    match rsdt.find_sdt(b"APIC") {
-        Some(rsdt::TableType::Madt(mut m)) => {
+    println!("[ acpi ] Root table points to {} tables", addresses.len());
+
+    for address in addresses {
+        load_sdt(address, active_table);
+    }
+
+    // The DSDT is not referenced by the root table, only by the FADT.
+    if let Some(TableType::Fadt(fadt)) = get_table(b"FACP") {
+        load_sdt(fadt.dsdt_address(), active_table);
+    }
+
+    match get_table(b"APIC") {
+        Some(TableType::Madt(mut m)) => {
             println!(
                 "[ apci ] Found MADT at address {:#x}",
                 m.sdt as *const sdt::SdtHeader as usize
@@ -75,4 +187,50 @@ pub unsafe fn init(active_table: &mut ActivePageTable) {
         }
         _ => println!("Could not find MADT."),
     }
+
+    aml::init();
+
+    // We route interrupts through the I/O APIC, so ask the firmware for matching routing tables.
+    if ::device::apic::APIC_MANAGER.lock().is_some() {
+        aml::set_interrupt_model(1);
+    }
+}
+
+/// How long to wait for the firmware to switch to ACPI mode, in microseconds.
+const ACPI_ENABLE_TIMEOUT: u64 = 3_000_000;
+
+/// Put the machine into the S5 soft-off state.
+pub fn shutdown() -> Result<(), &'static str> {
+    use device::io::cpuio::x86_io::{inw, outb, outw};
+    use device::pit::Timeout;
+
+    let fadt = match get_table(b"FACP") {
+        Some(TableType::Fadt(fadt)) => fadt,
+        _ => return Err("Could not find FADT"),
+    };
+
+    let (slp_typa, slp_typb) = aml::sleep_type(5).map_err(|_| "Could not evaluate \\_S5")?;
+
+    unsafe {
+        // Switch to ACPI mode if the firmware has not already done so.
+        if inw(fadt.pm1a_control_block as u16) & 1 == 0 && fadt.smi_command_port != 0 {
+            outb(fadt.acpi_enable, fadt.smi_command_port as u16);
+
+            let mut timeout = Timeout::new(ACPI_ENABLE_TIMEOUT);
+            while inw(fadt.pm1a_control_block as u16) & 1 == 0 {
+                if timeout.expired() {
+                    return Err("Firmware did not switch to ACPI mode");
+                }
+            }
+        }
+
+        // SLP_TYP lives in bits 10-12, SLP_EN is bit 13.
+        outw(slp_typa << 10 | 1 << 13, fadt.pm1a_control_block as u16);
+
+        if fadt.pm1b_control_block != 0 {
+            outw(slp_typb << 10 | 1 << 13, fadt.pm1b_control_block as u16);
+        }
+    }
+
+    Err("Machine did not power off")
 }
@@ -2,6 +2,7 @@ use arch::memory::Frame;
 use arch::memory::paging::{Page, PhysicalAddress, VirtualAddress};
 use arch::memory::paging::ActivePageTable;
 use arch::memory::paging::entry::EntryFlags;
+use core::{mem, slice};
 
 #[derive(Copy, Clone, Debug)]
 #[repr(packed)]
@@ -47,7 +48,9 @@ impl RsdpDescriptor {
     fn search(start_addr: usize, end_addr: usize) -> Option<RsdpDescriptor> {
         for i in 0..(end_addr + 1 - start_addr) / 16 {
             let rsdp = unsafe { &*((start_addr + i * 16) as *const RsdpDescriptor) };
-            if &rsdp.signature == b"RSD PTR " {
+            let remaining = end_addr + 1 - (start_addr + i * 16);
+
+            if &rsdp.signature == b"RSD PTR " && rsdp.is_valid(remaining) {
                 println!(
                     "[ acpi ] Found RSDP at {:#x}",
                     rsdp as *const RsdpDescriptor as usize
@@ -59,6 +62,25 @@ impl RsdpDescriptor {
         None
     }
 
+    /// Verify the checksum over the first 20 bytes of the descriptor, which are common to all
+    /// ACPI revisions. From revision 2 the extended checksum over `length` bytes, which covers the
+    /// XSDT address, must also be correct. `remaining` is the number of mapped bytes from the
+    /// start of the descriptor, which `length` must not run past.
+    fn is_valid(&self, remaining: usize) -> bool {
+        if !checksum(self as *const _ as usize, 20) {
+            return false;
+        }
+
+        if self.revision < 2 {
+            return true;
+        }
+
+        let length = self.length as usize;
+
+        length >= mem::size_of::<RsdpDescriptor>() && length <= remaining
+            && checksum(self as *const _ as usize, length)
+    }
+
     /// Dependent on ACPI version, return the address of the XSDT/RSDT.
     pub fn sdt(&self) -> usize {
         if self.revision >= 2 {
@@ -68,3 +90,10 @@ impl RsdpDescriptor {
         }
     }
 }
+
+/// Whether the `length` bytes at `address` sum to zero.
+fn checksum(address: usize, length: usize) -> bool {
+    let bytes = unsafe { slice::from_raw_parts(address as *const u8, length) };
+
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
@@ -1,8 +1,8 @@
 use super::sdt::SdtHeader;
+use super::TableType;
+use alloc::Vec;
 use core::slice;
 
-use super::madt::Madt;
-
 #[derive(Debug)]
 pub struct Rsdt<'a> {
     pub sdt: &'static SdtHeader,
@@ -24,7 +24,8 @@ impl<'a> Rsdt<'a> {
         }
     }
 
-    /// Retrieve a pointed-to table using a byte signature.
+    /// Retrieve a pointed-to table using a byte signature. The pointed-to tables must already have
+    /// been mapped.
     pub fn find_sdt(&self, signature: &[u8]) -> Option<TableType> {
         // Iterate over all the pointers to other tables.
         for i in self.other_entries.iter() {
@@ -33,29 +34,22 @@ impl<'a> Rsdt<'a> {
 
             let sig: &[u8] = &sdt.signature;
 
-            if sig != signature {
-                continue;
-            } else {
-                match signature {
-                    // TODO: Support more tables.
-                    b"APIC" => return Some(TableType::Madt(Madt::new(sdt))),
-                    _ => return None,
-                }
+            if sig == signature {
+                return Some(TableType::from_sdt(sdt));
             }
         }
 
         None
     }
 
+    /// Return the physical addresses of all the tables this RSDT points to.
+    pub fn addresses(&self) -> Vec<usize> {
+        self.other_entries.iter().map(|&a| a as usize).collect()
+    }
+
     /// Return RSDT data.
     pub fn data(sdt: &'static SdtHeader) -> &[u32] {
         // len - sizeof(header) / 4.
         unsafe { slice::from_raw_parts(sdt.data_address() as *const u32, sdt.data_len() / 4) }
     }
 }
-
-pub enum TableType {
-    Madt(Madt),
-    Facp,
-    Hpet,
-}
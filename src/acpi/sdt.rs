@@ -44,4 +44,26 @@ impl SdtHeader {
     pub unsafe fn data(&self) -> &[u8] {
         slice::from_raw_parts(self.data_address() as *const u8, self.data_len())
     }
+
+    /// Verify the checksum of this table. All the bytes of the table, including the header, must
+    /// sum to zero.
+    pub fn is_valid(&self) -> bool {
+        let bytes = unsafe {
+            slice::from_raw_parts(self as *const _ as *const u8, self.length as usize)
+        };
+
+        bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+    }
+}
+
+/// A generic address structure, used by ACPI to describe the location of registers.
+#[derive(Copy, Clone, Debug)]
+#[repr(packed)]
+pub struct GenericAddress {
+    /// 0 - system memory, 1 - system I/O.
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
 }
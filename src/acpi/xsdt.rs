@@ -1,7 +1,9 @@
 use super::sdt::SdtHeader;
+use alloc::Vec;
+use core::ptr;
 
 #[derive(Debug)]
-pub struct Xsdt(&'static SdtHeader);
+pub struct Xsdt(pub &'static SdtHeader);
 
 impl Xsdt {
     pub fn new(sdt: &'static SdtHeader) -> Option<Xsdt> {
@@ -10,4 +12,14 @@ impl Xsdt {
             _ => None,
         }
     }
+
+    /// Return the physical addresses of all the tables this XSDT points to. The entries are
+    /// 64 bits wide but only 4-byte aligned, so they are read unaligned.
+    pub fn addresses(&self) -> Vec<usize> {
+        let base = self.0.data_address() as *const u64;
+
+        (0..self.0.data_len() / 8)
+            .map(|i| unsafe { ptr::read_unaligned(base.offset(i as isize)) as usize })
+            .collect()
+    }
 }
@@ -8,7 +8,18 @@ header_start:
 
     ; insert optional multiboot tags here
 
+    ; framebuffer tag: ask for a linear framebuffer, which the console draws text on. It is optional,
+    ; so the bootloader may leave us in VGA text mode instead.
+    align 8, db 0
+    dw 5    ; type
+    dw 1    ; flags (optional)
+    dd 20   ; size
+    dd 1024 ; width
+    dd 768  ; height
+    dd 32   ; depth
+
     ; required end tag
+    align 8, db 0
     dw 0    ; type
     dw 0    ; flags
     dd 8    ; size
@@ -0,0 +1,40 @@
+//! The kernel command line passed by the bootloader, as space separated `key=value` options.
+
+use alloc::String;
+use core::str;
+use spin::Once;
+use super::multiboot;
+
+/// The multiboot2 tag holding the command line.
+const COMMAND_LINE_TAG: u32 = 1;
+
+static COMMAND_LINE: Once<String> = Once::new();
+
+/// Find the command line tag in the multiboot information structure and keep a copy of it.
+pub unsafe fn init(multiboot_info: usize) {
+    if let Some(bytes) = multiboot::find(multiboot_info, COMMAND_LINE_TAG) {
+        // The string is null terminated within the tag.
+        let length = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        let line = str::from_utf8(&bytes[..length]).unwrap_or("");
+
+        println!("[ INFO ] Command line: {}", line);
+        COMMAND_LINE.call_once(|| String::from(line));
+    }
+}
+
+/// The whole command line, or an empty string if there wasn't one.
+pub fn command_line() -> &'static str {
+    COMMAND_LINE.try().map_or("", |line| line.as_str())
+}
+
+/// The value of the option `key`, given as `key=value`.
+pub fn option(key: &str) -> Option<&'static str> {
+    command_line().split_whitespace().filter_map(|option| {
+        let mut parts = option.splitn(2, '=');
+
+        match (parts.next(), parts.next()) {
+            (Some(k), Some(value)) if k == key => Some(value),
+            _ => None,
+        }
+    }).next()
+}
@@ -1,6 +1,10 @@
 use super::interrupts;
 use super::memory;
+use super::memory::paging::entry::EntryFlags;
+use core::slice;
 use device;
+use fs;
+use super::multiboot::{self, BootInformation};
 
 /// Main kernel init function. This sets everything up for us.
 pub unsafe fn init(multiboot_info: usize) {
@@ -17,7 +21,7 @@ pub unsafe fn init(multiboot_info: usize) {
         device::vga::buffer::clear_screen();
         println!("[ INFO ] lambdaOS: Begin init.");
 
-        let boot_info = ::multiboot2::load(multiboot_info);
+        let boot_info = multiboot::load(multiboot_info);
 
         // Set safety bits in certain registers.
         enable_nxe_bit();
@@ -25,16 +29,51 @@ pub unsafe fn init(multiboot_info: usize) {
 
         // Setup memory management.
         let mut memory_controller = memory::init(&boot_info);
+        super::cmdline::init(multiboot_info);
+
+        // Draw text on the framebuffer if the bootloader set one up. This has to be done before
+        // the TTYs are created, as they take the size of the screen.
+        device::vga::framebuffer::init(multiboot_info);
         interrupts::init(&mut memory_controller);
 
         // Setup hardware devices.
         device::init();
+
+        // Mount the root filesystem, populated from the initramfs. Everything in the initramfs
+        // is copied into the root filesystem, so its memory is reused afterwards.
+        let archive = initramfs(&boot_info);
+        fs::init(archive);
+
+        if let Some(archive) = archive {
+            let start = archive.as_ptr() as usize;
+            memory::release_range(start, start + archive.len());
+        }
     }
     asm!("sti");
 
     println!("[ OK ] Init successful, you may now type.")
 }
 
+/// Find the initramfs among the modules loaded by the bootloader, and map it so that it can be
+/// unpacked. A module named `initramfs` is preferred, otherwise the first module is used.
+fn initramfs(boot_info: &BootInformation) -> Option<&'static [u8]> {
+    let module = boot_info
+        .modules()
+        .find(|m| m.name() == "initramfs")
+        .or_else(|| boot_info.modules().next())?;
+
+    let start = module.start_address();
+    let size = module.end_address() - start;
+
+    if size == 0 {
+        return None;
+    }
+
+    memory::identity_map(start, size, EntryFlags::NO_EXECUTE);
+
+    Some(unsafe { slice::from_raw_parts(start as *const u8, size) })
+}
+
 pub fn enable_nxe_bit() {
     use x86_64::registers::msr::{rdmsr, wrmsr, IA32_EFER};
 
@@ -0,0 +1,82 @@
+//! Work deferred out of interrupt handlers. A handler does the minimum the hardware needs, such
+//! as reading a data port, then schedules a bottom half to finish the job. Pending bottom halves
+//! run outside of interrupt context, from the idle loop and from tasks waiting in `task::wait`,
+//! where no kernel lock is held. They can print and take locks such as `PS2` and the VTs without
+//! deadlocking against the code an interrupt landed in. So that busy tasks can't hold them off,
+//! the timer reschedules as soon as any are pending, and the scheduler then runs the idle loop
+//! first.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+use super::disable_interrupts_and_then;
+
+/// The number of bottom halves which can be registered.
+pub const MAX_BOTTOM_HALVES: usize = 8;
+
+pub type BottomHalf = fn();
+
+static HANDLERS: Mutex<[Option<BottomHalf>; MAX_BOTTOM_HALVES]> =
+    Mutex::new([None; MAX_BOTTOM_HALVES]);
+
+/// One bit for each bottom half which has been scheduled but not yet run.
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Set while bottom halves are running, so that an interrupt arriving meanwhile leaves its work
+/// to the loop which is already running instead of nesting.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Register a bottom half, returning the id to schedule it with. This must be done before the
+/// interrupt which schedules it is enabled.
+pub fn register(handler: BottomHalf) -> Result<usize, &'static str> {
+    disable_interrupts_and_then(|| {
+        let mut handlers = HANDLERS.lock();
+
+        for (id, slot) in handlers.iter_mut().enumerate() {
+            if slot.is_none() {
+                *slot = Some(handler);
+                return Ok(id);
+            }
+        }
+
+        Err("Too many bottom halves registered")
+    })
+}
+
+/// Mark a bottom half as pending. This is safe to call from an interrupt handler.
+pub fn schedule(id: usize) {
+    PENDING.fetch_or(1 << id, Ordering::SeqCst);
+}
+
+/// Whether any bottom half has been scheduled but not yet run.
+pub fn is_pending() -> bool {
+    PENDING.load(Ordering::SeqCst) != 0
+}
+
+/// Run every pending bottom half. This must never be called from an interrupt handler or with a
+/// lock held, since the bottom halves take locks of their own.
+pub fn run_pending() {
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    loop {
+        let pending = PENDING.swap(0, Ordering::SeqCst);
+
+        if pending == 0 {
+            break;
+        }
+
+        // Copy the handlers out, so that the lock is not held while they run.
+        let handlers = disable_interrupts_and_then(|| *HANDLERS.lock());
+
+        for (id, handler) in handlers.iter().enumerate() {
+            if pending & (1 << id) != 0 {
+                if let Some(handler) = *handler {
+                    handler();
+                }
+            }
+        }
+    }
+
+    RUNNING.store(false, Ordering::SeqCst);
+}
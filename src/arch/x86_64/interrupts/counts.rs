@@ -0,0 +1,30 @@
+//! Per-vector interrupt counters, so that interrupt activity can be inspected at runtime.
+
+use alloc::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+lazy_static! {
+    /// One counter for each IDT vector. This is forced in `init`, so that interrupt handlers
+    /// never allocate.
+    static ref COUNTS: Vec<AtomicUsize> = (0..256).map(|_| AtomicUsize::new(0)).collect();
+}
+
+/// Allocate the counters.
+pub fn init() {
+    let _ = COUNTS.len();
+}
+
+/// Count an interrupt on `vector`.
+pub fn record(vector: u8) {
+    COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Return the count of every vector which has fired at least once.
+pub fn counts() -> Vec<(u8, usize)> {
+    COUNTS
+        .iter()
+        .enumerate()
+        .map(|(vector, count)| (vector as u8, count.load(Ordering::Relaxed)))
+        .filter(|&(_, count)| count != 0)
+        .collect()
+}
@@ -1,23 +1,38 @@
 use device::pic::PICS;
-use device::keyboard::ps2_keyboard::parse_key;
-use device::ps2_8042::read_char;
+use device::keyboard::ps2_keyboard;
+use device::mouse::ps2_mouse;
+use device::serial;
 use x86_64::structures::idt::ExceptionStackFrame;
 use super::disable_interrupts_and_then;
 use device::apic;
 
-/// Timer handler checks the tick counter and if it exceeds 10, performs a round-robin context
-/// switch to the next process.
+/// Timer handler for the PIT through the legacy PIC, at vector 0x20.
 pub extern "x86-interrupt" fn timer_handler(_stack_frame: &mut ExceptionStackFrame) {
+    timer(0x20);
+}
+
+/// Timer handler for the PIT routed through the I/O APIC, at vector 0x30.
+pub extern "x86-interrupt" fn apic_timer_handler(_stack_frame: &mut ExceptionStackFrame) {
+    timer(0x30);
+}
+
+/// Checks the tick counter and if it exceeds 10, performs a round-robin context switch to the
+/// next process. The timeslice is cut short while bottom halves are pending, so that the idle
+/// loop gets to run them. `vector` is the one the interrupt arrived on.
+fn timer(vector: u8) {
     use core::sync::atomic::Ordering;
-    use device::pit::PIT_TICKS;
+    use device::pit::{self, PIT_TICKS};
     use task::{Scheduling, SCHEDULER};
+    use super::bottom_half;
 
     println!("timer interrupt.");
+    super::counts::record(vector);
+    pit::tick();
 
     apic::eoi();
     
     // Check if allocated timeslice finished (~20ms).
-    if PIT_TICKS.fetch_add(1, Ordering::SeqCst) >= 10 {
+    if PIT_TICKS.fetch_add(1, Ordering::SeqCst) >= 10 || bottom_half::is_pending() {
         PIT_TICKS.store(0, Ordering::SeqCst);
 
         unsafe {
@@ -29,11 +44,32 @@ pub extern "x86-interrupt" fn timer_handler(_stack_frame: &mut ExceptionStackFra
     }
 }
 
+/// Keyboard handler reads the scancode into a ring and leaves decoding to a bottom half, which
+/// runs outside of interrupt context.
 pub extern "x86-interrupt" fn keyboard_handler(_stack_frame: &mut ExceptionStackFrame) {
-    println!("keyboard interrupt.");
-    let code = read_char();
+    super::counts::record(0x31);
+
+    ps2_keyboard::handle_interrupt();
+
+    apic::eoi();
+}
+
+/// Mouse handler, which like the keyboard handler only reads the byte and leaves the rest to a
+/// bottom half.
+pub extern "x86-interrupt" fn mouse_handler(_stack_frame: &mut ExceptionStackFrame) {
+    super::counts::record(0x3C);
+
+    ps2_mouse::handle_interrupt();
+
+    apic::eoi();
+}
+
+/// Serial handler, which reads whatever COM1 has received and leaves turning it into input events
+/// to a bottom half.
+pub extern "x86-interrupt" fn serial_handler(_stack_frame: &mut ExceptionStackFrame) {
+    super::counts::record(0x34);
+
+    serial::handle_interrupt();
 
-    parse_key(code);
-        
     apic::eoi();
 }
@@ -3,10 +3,13 @@ use x86_64::structures::tss::TaskStateSegment;
 use x86_64::structures::idt::{Idt, ExceptionStackFrame};
 use spin::Once;
 
+pub mod bottom_half;
+pub mod counts;
 pub mod gdt;
 pub mod exceptions;
 pub mod irq;
 pub mod utils;
+pub mod vectors;
 
 pub use self::utils::*;
 
@@ -43,9 +46,16 @@ lazy_static! {
         idt.interrupts[0].set_handler_fn(irq::timer_handler);
         // idt.interrupts[1].set_handler_fn(irq::keyboard_handler);
         
-        idt.interrupts[0x30 - 0x20].set_handler_fn(irq::timer_handler);
-        // idt.interrupts[17].set_handler_fn(irq::keyboard_handler);
+        idt.interrupts[0x30 - 0x20].set_handler_fn(irq::apic_timer_handler);
+        idt.interrupts[0x31 - 0x20].set_handler_fn(irq::keyboard_handler);
+        idt.interrupts[0x34 - 0x20].set_handler_fn(irq::serial_handler);
+        idt.interrupts[0x3C - 0x20].set_handler_fn(irq::mouse_handler);
         
+        // Dynamically allocated vectors, used by MSI and MSI-X.
+        for (i, stub) in vectors::STUBS.iter().enumerate() {
+            idt.interrupts[vectors::DYNAMIC_VECTOR_BASE as usize - 0x20 + i].set_handler_fn(*stub);
+        }
+
         // APIC NMI.
         for vec in (0x90-0x20)..(0x97-0x20) {
             idt.interrupts[vec].set_handler_fn(apic_nmi_handler);
@@ -101,16 +111,20 @@ pub fn init(memory_controller: &mut MemoryController) {
         load_tss(tss_selector);
     }
 
+    counts::init();
+
     // Load the IDT
     IDT.load();
     println!("[ tables ] Successfully loaded IDT.")
 }
 
 pub extern "x86-interrupt" fn apic_nmi_handler(stack_frame: &mut ExceptionStackFrame) {
+    counts::record(0x90);
     println!("NON-MASKABLE APIC INTERRUPT!");
     loop {}
 }
 
 pub extern "x86-interrupt" fn spurious_interrupt_handler(stack_frame: &mut ExceptionStackFrame) {
+    counts::record(0xff);
     println!("SPURIOUS INTERRUPT!");
 }
@@ -6,6 +6,28 @@ unsafe fn enable() {
     asm!("sti");
 }
 
+/// Enable interrupts and halt until the next one arrives.
+pub fn wait_for_interrupt() {
+    unsafe {
+        asm!("sti; hlt" :::: "volatile");
+    }
+}
+
+/// Halt until the next interrupt, unless `ready` says there is already something to do. It is
+/// checked with interrupts disabled, and `sti` only lets them in once the `hlt` after it has
+/// executed, so an interrupt making `ready` true can't slip in between the check and the halt.
+pub fn wait_for_interrupt_unless<F: FnOnce() -> bool>(ready: F) {
+    unsafe {
+        disable();
+
+        if ready() {
+            enable();
+        } else {
+            asm!("sti; hlt" :::: "volatile");
+        }
+    }
+}
+
 /// Disable all interrupts and save the PIC masks
 pub fn disable_interrupts() -> (u8, u8) {
     use device::pic::PICS;
@@ -0,0 +1,134 @@
+//! A pool of IDT vectors which can be allocated at runtime, for interrupts whose vector is chosen
+//! by the kernel rather than being fixed, such as MSI and MSI-X. Each vector has a small stub
+//! installed in the IDT which dispatches to the handler registered for it.
+
+use device::apic;
+use spin::Mutex;
+use x86_64::structures::idt::{ExceptionStackFrame, HandlerFunc};
+use super::disable_interrupts_and_then;
+
+/// The first dynamically allocated vector. Vectors below this are used by the PIC and the I/O
+/// APIC redirections.
+pub const DYNAMIC_VECTOR_BASE: u8 = 0x50;
+
+/// The number of dynamically allocated vectors.
+pub const DYNAMIC_VECTOR_COUNT: usize = 32;
+
+/// A handler for a dynamically allocated vector. It is passed the vector that fired.
+pub type VectorHandler = fn(u8);
+
+#[derive(Clone, Copy)]
+struct Slot {
+    allocated: bool,
+    handler: Option<VectorHandler>,
+}
+
+static SLOTS: Mutex<[Slot; DYNAMIC_VECTOR_COUNT]> = Mutex::new(
+    [Slot {
+        allocated: false,
+        handler: None,
+    }; DYNAMIC_VECTOR_COUNT],
+);
+
+fn slot_index(vector: u8) -> Option<usize> {
+    let index = vector.wrapping_sub(DYNAMIC_VECTOR_BASE) as usize;
+
+    if vector >= DYNAMIC_VECTOR_BASE && index < DYNAMIC_VECTOR_COUNT {
+        Some(index)
+    } else {
+        None
+    }
+}
+
+/// Allocate a free vector. Returns `None` if every vector is in use.
+pub fn allocate_vector() -> Option<u8> {
+    disable_interrupts_and_then(|| {
+        let mut slots = SLOTS.lock();
+
+        for (i, slot) in slots.iter_mut().enumerate() {
+            if !slot.allocated {
+                slot.allocated = true;
+                return Some(DYNAMIC_VECTOR_BASE + i as u8);
+            }
+        }
+
+        None
+    })
+}
+
+/// Return a vector to the pool, removing its handler.
+pub fn free_vector(vector: u8) {
+    if let Some(i) = slot_index(vector) {
+        disable_interrupts_and_then(|| {
+            SLOTS.lock()[i] = Slot {
+                allocated: false,
+                handler: None,
+            };
+        });
+    }
+}
+
+/// Register the handler which is called whenever `vector` fires. The vector must have been
+/// allocated with `allocate_vector`.
+pub fn register_handler(vector: u8, handler: VectorHandler) -> Result<(), &'static str> {
+    let i = slot_index(vector).ok_or("Vector is not dynamically allocatable")?;
+
+    disable_interrupts_and_then(|| {
+        let mut slots = SLOTS.lock();
+
+        if !slots[i].allocated {
+            return Err("Vector has not been allocated");
+        }
+
+        slots[i].handler = Some(handler);
+        Ok(())
+    })
+}
+
+/// Remove the handler for `vector`, without freeing the vector itself.
+pub fn unregister_handler(vector: u8) {
+    if let Some(i) = slot_index(vector) {
+        disable_interrupts_and_then(|| {
+            SLOTS.lock()[i].handler = None;
+        });
+    }
+}
+
+/// Call the handler registered for `vector` and acknowledge the interrupt.
+fn dispatch(vector: u8) {
+    super::counts::record(vector);
+
+    // Copy the handler out, so that the lock is not held while it runs.
+    let handler = slot_index(vector).and_then(|i| SLOTS.lock()[i].handler);
+
+    match handler {
+        Some(handler) => handler(vector),
+        None => println!("[ interrupts ] Unhandled interrupt on vector {:#x}", vector),
+    }
+
+    apic::eoi();
+}
+
+macro_rules! vector_stubs {
+    ($($name:ident => $offset:expr),*) => {
+        $(
+            extern "x86-interrupt" fn $name(_stack_frame: &mut ExceptionStackFrame) {
+                dispatch(DYNAMIC_VECTOR_BASE + $offset);
+            }
+        )*
+
+        /// The IDT entries for each dynamically allocated vector, in order.
+        pub static STUBS: [HandlerFunc; DYNAMIC_VECTOR_COUNT] = [$($name),*];
+    }
+}
+
+vector_stubs!(
+    stub_0 => 0, stub_1 => 1, stub_2 => 2, stub_3 => 3,
+    stub_4 => 4, stub_5 => 5, stub_6 => 6, stub_7 => 7,
+    stub_8 => 8, stub_9 => 9, stub_10 => 10, stub_11 => 11,
+    stub_12 => 12, stub_13 => 13, stub_14 => 14, stub_15 => 15,
+    stub_16 => 16, stub_17 => 17, stub_18 => 18, stub_19 => 19,
+    stub_20 => 20, stub_21 => 21, stub_22 => 22, stub_23 => 23,
+    stub_24 => 24, stub_25 => 25, stub_26 => 26, stub_27 => 27,
+    stub_28 => 28, stub_29 => 29, stub_30 => 30, stub_31 => 31
+);
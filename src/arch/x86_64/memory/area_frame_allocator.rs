@@ -1,5 +1,7 @@
+use alloc::Vec;
+use core::cmp;
 use arch::memory::{Frame, FrameAllocator};
-use multiboot2::{MemoryArea, MemoryAreaIter};
+use arch::multiboot::{MemoryArea, MemoryAreaIter};
 use arch::memory::paging::PhysicalAddress;
 
 /// A frame allocator that uses the memory areas from the multiboot information structure as
@@ -11,7 +13,7 @@ pub struct AreaFrameAllocator {
     /// The next available physical frame.
     next_free_frame: Frame,
     /// The current memory area, detected by multiboot using the e820.
-    current_area: Option<&'static MemoryArea>,
+    current_area: Option<MemoryArea>,
     /// An iterator over all memory areas.
     areas: MemoryAreaIter,
     /// The starting frame of the kernel in physical memory.
@@ -23,8 +25,19 @@ pub struct AreaFrameAllocator {
     multiboot_start: Frame,
     /// The end frame of the multiboot data structure in physical memory.
     multiboot_end: Frame,
+    /// Runs of frames which have been freed, or skipped over to satisfy alignment, as sorted and
+    /// coalesced (first frame number, frame count) pairs. Single, unaligned allocations never
+    /// add to this, so the early paging code can run before the heap exists.
+    free: Vec<(usize, usize)>,
+    /// Other ranges of frames which must never be handed out, as inclusive (first, last) frame
+    /// numbers. This is a fixed array since it is filled in before the heap exists.
+    reserved: [(usize, usize); MAX_RESERVED],
+    reserved_count: usize,
 }
 
+/// The most ranges which can be reserved with `AreaFrameAllocator::reserve`.
+const MAX_RESERVED: usize = 8;
+
 impl AreaFrameAllocator {
     pub fn new(
         kernel_start: usize,
@@ -41,6 +54,9 @@ impl AreaFrameAllocator {
             kernel_end: Frame::containing_address(PhysicalAddress::new(kernel_end)),
             multiboot_start: Frame::containing_address(PhysicalAddress::new(multiboot_start)),
             multiboot_end: Frame::containing_address(PhysicalAddress::new(multiboot_end)),
+            free: Vec::new(),
+            reserved: [(0, 0); MAX_RESERVED],
+            reserved_count: 0,
         };
         allocator.choose_next_area();
         allocator.allocate_frame(1);
@@ -53,7 +69,7 @@ impl AreaFrameAllocator {
             .clone()
             .filter(|area| {
                 let address = area.start_address() + area.size() - 1;
-                Frame::containing_address(PhysicalAddress::new(address as usize))
+                Frame::containing_address(PhysicalAddress::new(address))
                     >= self.next_free_frame
             })
             .min_by_key(|area| area.start_address());
@@ -71,64 +87,228 @@ impl AreaFrameAllocator {
     }
 }
 
-impl FrameAllocator for AreaFrameAllocator {
-    /// Allocate a single frame. Return `None` if we are out of memory.
-    fn allocate_frame(&mut self, count: usize) -> Option<Frame> {
-        if count == 0 {
+/// Round `number` up to a multiple of `align`.
+fn align_up(number: usize, align: usize) -> usize {
+    (number + align - 1) / align * align
+}
+
+impl AreaFrameAllocator {
+    /// Stop the physical range `start` to `end`, inclusive, from ever being allocated. This must
+    /// be called before any frames in the range have been handed out. Once every slot is used, the
+    /// range is merged into the nearest reserved range, along with whatever lies between them.
+    pub fn reserve(&mut self, start: usize, end: usize) {
+        let first = Frame::containing_address(PhysicalAddress::new(start)).number;
+        let last = Frame::containing_address(PhysicalAddress::new(end)).number;
+
+        if self.reserved_count < MAX_RESERVED {
+            self.reserved[self.reserved_count] = (first, last);
+            self.reserved_count += 1;
+            return;
+        }
+
+        let gap = |&(f, l): &(usize, usize)| {
+            if l < first {
+                first - l
+            } else if f > last {
+                f - last
+            } else {
+                0
+            }
+        };
+
+        let nearest = (0..self.reserved_count)
+            .min_by_key(|&i| gap(&self.reserved[i]))
+            .expect("No reserved ranges");
+
+        let (f, l) = self.reserved[nearest];
+        self.reserved[nearest] = (cmp::min(f, first), cmp::max(l, last));
+    }
+
+    /// Allocate `count` physically contiguous frames. The first frame number is a multiple of
+    /// `align`, and every frame number is below `limit`. Return `None` if no such run is free.
+    pub fn allocate_frames_constrained(
+        &mut self,
+        count: usize,
+        align: usize,
+        limit: usize,
+    ) -> Option<Frame> {
+        if count == 0 || align == 0 {
             return None;
-        } else if let Some(area) = self.current_area {
-            // "clone" the frame to return it if it's free. Frame doesn't
-            // implement Clone, but we can construct an identical frame.
-            let start_frame = Frame {
-                number: self.next_free_frame.number,
-            };
+        }
 
-            let end_frame = Frame {
-                number: self.next_free_frame.number + (count - 1),
-            };
+        self.allocate_from_free_list(count, align, limit)
+            .or_else(|| self.allocate_from_areas(count, align, limit))
+    }
 
-            // the last frame of the current area
-            let current_area_last_frame = {
-                let address = area.start_address() + area.size() - 1;
-                Frame::containing_address(PhysicalAddress::new(address))
+    /// Take a run of frames from the list of freed frames, splitting the run it comes from.
+    fn allocate_from_free_list(&mut self, count: usize, align: usize, limit: usize) -> Option<Frame> {
+        for i in 0..self.free.len() {
+            let (start, length) = self.free[i];
+            let aligned = align_up(start, align);
+
+            if aligned + count <= start + length && aligned + count <= limit {
+                self.free.remove(i);
+
+                if aligned > start {
+                    self.release_unused(start, aligned - start);
+                }
+                if aligned + count < start + length {
+                    self.release_unused(aligned + count, start + length - aligned - count);
+                }
+
+                return Some(Frame { number: aligned });
+            }
+        }
+
+        None
+    }
+
+    /// Take a run of frames from the never allocated part of the memory areas.
+    fn allocate_from_areas(&mut self, count: usize, align: usize, limit: usize) -> Option<Frame> {
+        loop {
+            let area = match self.current_area {
+                Some(area) => area,
+                // No free frames left.
+                None => return None,
             };
 
-            if end_frame > current_area_last_frame {
-                // all frames of current area are used, switch to next area
+            let start = align_up(self.next_free_frame.number, align);
+            let end = start + count - 1;
+
+            // Frames are handed out in increasing order, so nothing later will fit either.
+            if end >= limit {
+                return None;
+            }
+
+            // The last frame of the current area.
+            let area_last = Frame::containing_address(PhysicalAddress::new(
+                area.start_address() + area.size() - 1,
+            )).number;
+
+            if end > area_last {
+                // The run does not fit in the rest of this area, so keep the remainder for smaller
+                // allocations and move on to the next area.
+                if self.next_free_frame.number <= area_last {
+                    let next = self.next_free_frame.number;
+                    self.release_unused(next, area_last + 1 - next);
+                }
+
+                self.next_free_frame = Frame {
+                    number: area_last + 1,
+                };
                 self.choose_next_area();
-            } else if (start_frame >= self.kernel_start && start_frame <= self.kernel_end)
-                || (end_frame >= self.kernel_start && start_frame <= self.kernel_end)
-            {
-                // frame range is used by the kernel.
+            } else if start <= self.kernel_end.number && end >= self.kernel_start.number {
+                // Frame range is used by the kernel.
                 self.next_free_frame = Frame {
                     number: self.kernel_end.number + 1,
                 };
-            } else if (start_frame >= self.multiboot_start && start_frame <= self.multiboot_end)
-                || (end_frame >= self.multiboot_start && end_frame <= self.multiboot_end)
-            {
-                // `frame` is used by the multiboot information structure
+            } else if start <= self.multiboot_end.number && end >= self.multiboot_start.number {
+                // Frame range is used by the multiboot information structure.
                 self.next_free_frame = Frame {
                     number: self.multiboot_end.number + 1,
                 };
+            } else if let Some(&(_, last)) = self.reserved[..self.reserved_count]
+                .iter()
+                .find(|&&(first, last)| start <= last && end >= first)
+            {
+                // Frame range is reserved.
+                self.next_free_frame = Frame { number: last + 1 };
             } else {
-                // frame is unused, increment `next_free_frame` and return it
-                self.next_free_frame.number += 1;
-                return Some(start_frame);
+                // Keep any frames skipped for alignment.
+                if start > self.next_free_frame.number {
+                    let next = self.next_free_frame.number;
+                    self.release_unused(next, start - next);
+                }
+
+                self.next_free_frame = Frame { number: end + 1 };
+                return Some(Frame { number: start });
             }
-            // `frame` was not valid, try it again with the updated `next_free_frame`
-            self.allocate_frame(count)
-        } else {
-            None // no free frames left
         }
     }
 
-    fn deallocate_frame(&mut self, _frame: Frame) {
-        unimplemented!()
+    /// Return the memory areas given by the bootloader, as `(start, size)` pairs in bytes.
+    pub fn areas(&self) -> Vec<(usize, usize)> {
+        self.areas
+            .clone()
+            .map(|area| (area.start_address(), area.size()))
+            .collect()
+    }
+
+    /// Put a run of frames which were never handed out on the free list, leaving out any which
+    /// hold the kernel or the multiboot information structure or which are reserved.
+    fn release_unused(&mut self, start: usize, count: usize) {
+        let end = start + count;
+        let mut next = start;
+
+        while next < end {
+            match self.first_excluded(next, end) {
+                Some((first, last)) => {
+                    if first > next {
+                        self.release(next, first - next);
+                    }
+                    next = last + 1;
+                }
+                None => {
+                    self.release(next, end - next);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// The lowest range of frames which must not be handed out that overlaps the frames `start`
+    /// up to `end`, as inclusive (first, last) frame numbers.
+    fn first_excluded(&self, start: usize, end: usize) -> Option<(usize, usize)> {
+        let kernel = (self.kernel_start.number, self.kernel_end.number);
+        let multiboot = (self.multiboot_start.number, self.multiboot_end.number);
+
+        [kernel, multiboot]
+            .iter()
+            .chain(self.reserved[..self.reserved_count].iter())
+            .filter(|&&(first, last)| first < end && last >= start)
+            .min_by_key(|&&(first, _)| first)
+            .cloned()
+    }
+
+    /// Return a run of frames to the free list, merging it with its neighbours.
+    pub fn release(&mut self, start: usize, count: usize) {
+        if count == 0 {
+            return;
+        }
+
+        let index = self.free
+            .iter()
+            .position(|&(s, _)| s > start)
+            .unwrap_or(self.free.len());
+        self.free.insert(index, (start, count));
+
+        // Merge with the following run.
+        if index + 1 < self.free.len() && start + count == self.free[index + 1].0 {
+            self.free[index].1 += self.free[index + 1].1;
+            self.free.remove(index + 1);
+        }
+
+        // Merge with the preceding run.
+        if index > 0 && self.free[index - 1].0 + self.free[index - 1].1 == start {
+            self.free[index - 1].1 += self.free[index].1;
+            self.free.remove(index);
+        }
+    }
+}
+
+impl FrameAllocator for AreaFrameAllocator {
+    /// Allocate `count` contiguous frames. Return `None` if we are out of memory.
+    fn allocate_frame(&mut self, count: usize) -> Option<Frame> {
+        self.allocate_frames_constrained(count, 1, usize::max_value())
+    }
+
+    fn deallocate_frame(&mut self, frame: Frame) {
+        self.release(frame.number, 1);
     }
 
     /// Get a count of available free frames.
     fn free_frames(&mut self) -> usize {
-        let mut count = 0;
+        let mut count = self.free.iter().map(|&(_, length)| length).sum();
 
         for area in self.areas.clone() {
             let start_frame = Frame::containing_address(PhysicalAddress::new(area.start_address()));
@@ -4,7 +4,9 @@ pub use self::stack_allocator::Stack;
 use self::paging::{PhysicalAddress, VirtualAddress};
 use self::paging::entry::EntryFlags;
 use acpi;
-use multiboot2::BootInformation;
+use alloc::Vec;
+use core::cmp;
+use arch::multiboot::BootInformation;
 use spin::Mutex;
 
 pub mod area_frame_allocator;
@@ -20,19 +22,16 @@ pub static ALLOCATOR: Mutex<Option<AreaFrameAllocator>> = Mutex::new(None);
 pub fn init(boot_info: &BootInformation) -> MemoryController {
     assert_has_not_been_called!("memory::init must be called only once");
 
-    let memory_map_tag = boot_info.memory_map_tag().expect("Memory map tag required");
-    let elf_sections_tag = boot_info
-        .elf_sections_tag()
-        .expect("Elf sections tag required");
+    let memory_areas = boot_info.memory_areas().expect("Memory map tag required");
+    let elf_sections = boot_info.elf_sections().expect("Elf sections tag required");
 
-    let kernel_start = elf_sections_tag
-        .sections()
+    let kernel_start = elf_sections
+        .clone()
         .filter(|s| s.is_allocated())
         .map(|s| s.start_address())
         .min()
         .unwrap();
-    let kernel_end = elf_sections_tag
-        .sections()
+    let kernel_end = elf_sections
         .filter(|s| s.is_allocated())
         .map(|s| s.start_address() + s.size())
         .max()
@@ -49,14 +48,28 @@ pub fn init(boot_info: &BootInformation) -> MemoryController {
     );
 
     // Construct a physical frame allocator based on parameters passed to the main kernel.
-    let frame_allocator = AreaFrameAllocator::new(
-        kernel_start as usize,
-        kernel_end as usize,
+    let mut frame_allocator = AreaFrameAllocator::new(
+        kernel_start,
+        kernel_end,
         boot_info.start_address(),
         boot_info.end_address(),
-        memory_map_tag.memory_areas(),
+        memory_areas,
     );
 
+    // Keep the modules loaded by the bootloader, such as the initramfs, out of the allocator.
+    for module in boot_info.modules() {
+        println!(
+            "[ pmm ] Module {} start: {:#x}, end: {:#x}",
+            module.name(),
+            module.start_address(),
+            module.end_address()
+        );
+
+        if module.end_address() > module.start_address() {
+            frame_allocator.reserve(module.start_address(), module.end_address() - 1);
+        }
+    }
+
     *ALLOCATOR.lock() = Some(frame_allocator);
 
     let mut active_table = paging::init(&boot_info);
@@ -173,7 +186,80 @@ pub trait FrameAllocator {
     fn free_frames(&mut self) -> usize;
 }
 
-/// Allocate a frame.
+/// Identity map a range of physical memory with the given flags. Pages which are already mapped
+/// are left alone.
+pub fn identity_map(start: usize, size: usize, flags: EntryFlags) {
+    use self::paging::Page;
+
+    let mut active_table = unsafe { ActivePageTable::new() };
+
+    let start_page = Page::containing_address(VirtualAddress::new(start));
+    let end_page = Page::containing_address(VirtualAddress::new(start + size - 1));
+
+    for page in Page::range_inclusive(start_page, end_page) {
+        if active_table.translate_page(page).is_none() {
+            let frame = Frame::containing_address(PhysicalAddress::new(page.start_address().get()));
+            let result = active_table.map_to(page, frame, EntryFlags::PRESENT | flags);
+            result.flush(&mut active_table);
+        }
+    }
+}
+
+/// Identity map a range of device memory as uncached, writable and non-executable. Pages which
+/// are already mapped are left alone.
+pub fn map_mmio(start: usize, size: usize) {
+    identity_map(
+        start,
+        size,
+        EntryFlags::WRITABLE | EntryFlags::NO_CACHE | EntryFlags::NO_EXECUTE,
+    );
+}
+
+/// Whether the page holding `address` is mapped.
+pub fn is_mapped(address: usize) -> bool {
+    use self::paging::Page;
+
+    let active_table = unsafe { ActivePageTable::new() };
+    active_table
+        .translate_page(Page::containing_address(VirtualAddress::new(address)))
+        .is_some()
+}
+
+/// Remove the mappings of a range mapped with `identity_map` or `map_mmio`. The frames are not
+/// freed, and pages which aren't mapped are left alone.
+pub fn unmap(start: usize, size: usize) {
+    use self::paging::Page;
+
+    let mut active_table = unsafe { ActivePageTable::new() };
+
+    let start_page = Page::containing_address(VirtualAddress::new(start));
+    let end_page = Page::containing_address(VirtualAddress::new(start + size - 1));
+
+    for page in Page::range_inclusive(start_page, end_page) {
+        if active_table.translate_page(page).is_some() {
+            let result = active_table.unmap(page);
+            result.flush(&mut active_table);
+        }
+    }
+}
+
+/// The usable memory areas reported by the bootloader, as `(start, size)` pairs in bytes.
+pub fn memory_areas() -> Vec<(usize, usize)> {
+    match *ALLOCATOR.lock() {
+        Some(ref frame_allocator) => frame_allocator.areas(),
+        None => panic!("Frame allocator called before init."),
+    }
+}
+
+/// The number of physical frames which are free.
+pub fn free_frames() -> usize {
+    match *ALLOCATOR.lock() {
+        Some(ref mut frame_allocator) => frame_allocator.free_frames(),
+        None => panic!("Frame allocator called before init."),
+    }
+}
+
+/// Allocate `count` physically contiguous frames.
 pub fn allocate_frames(count: usize) -> Option<Frame> {
     if let Some(ref mut frame_allocator) = *ALLOCATOR.lock() {
         return frame_allocator.allocate_frame(count);
@@ -181,3 +267,50 @@ pub fn allocate_frames(count: usize) -> Option<Frame> {
         panic!("Frame allocator called before init.");
     }
 }
+
+/// Return `count` contiguous frames, starting at `frame`, to the frame allocator.
+pub fn deallocate_frames(frame: Frame, count: usize) {
+    if let Some(ref mut frame_allocator) = *ALLOCATOR.lock() {
+        frame_allocator.release(frame.number, count);
+    } else {
+        panic!("Frame allocator called before init.");
+    }
+}
+
+/// Remove the identity mapping of the frames lying wholly within the physical range `start` to
+/// `end`, exclusive, and return them to the frame allocator, as for a module which is no longer
+/// needed. Frames the range only partly covers are kept, since they may hold something else.
+pub fn release_range(start: usize, end: usize) {
+    let first = (start + PAGE_SIZE - 1) / PAGE_SIZE;
+    let last = end / PAGE_SIZE;
+
+    if last > first {
+        unmap(first * PAGE_SIZE, (last - first) * PAGE_SIZE);
+        deallocate_frames(Frame { number: first }, last - first);
+    }
+}
+
+/// Allocate physically contiguous memory of at least `size` bytes, starting at a multiple of
+/// `align` bytes and ending below the physical address `limit`. Returns the physical address of
+/// the allocation.
+pub fn physalloc(size: usize, align: usize, limit: usize) -> Result<usize, &'static str> {
+    let count = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+    let align = cmp::max((align + PAGE_SIZE - 1) / PAGE_SIZE, 1);
+
+    let frame = match *ALLOCATOR.lock() {
+        Some(ref mut frame_allocator) => {
+            frame_allocator.allocate_frames_constrained(count, align, limit / PAGE_SIZE)
+        }
+        None => panic!("Frame allocator called before init."),
+    };
+
+    frame
+        .map(|frame| frame.start_address().get())
+        .ok_or("Out of physical memory")
+}
+
+/// Free memory allocated with `physalloc`.
+pub fn physfree(address: usize, size: usize) {
+    let count = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+    deallocate_frames(Frame::containing_address(PhysicalAddress::new(address)), count);
+}
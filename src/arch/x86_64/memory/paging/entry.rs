@@ -1,5 +1,5 @@
 use arch::memory::Frame;
-use multiboot2::ElfSection;
+use arch::multiboot::ElfSection;
 use arch::memory::paging::PhysicalAddress;
 
 /// A page table entry.
@@ -69,7 +69,7 @@ bitflags! {
 impl EntryFlags {
     /// Parse the flags on an ELF section to our `EntryFlags` struct.
     pub fn from_elf_section_flags(section: &ElfSection) -> EntryFlags {
-        use multiboot2::ElfSectionFlags;
+        use arch::multiboot::ElfSectionFlags;
 
         let mut flags = EntryFlags::empty();
 
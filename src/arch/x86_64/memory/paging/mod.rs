@@ -4,7 +4,7 @@ use arch::memory::{Frame, PAGE_SIZE};
 use arch::memory::allocate_frames;
 use self::temporary_page::TemporaryPage;
 use core::ops::{Add, Deref, DerefMut};
-use multiboot2::BootInformation;
+use arch::multiboot::BootInformation;
 
 pub mod entry;
 mod table;
@@ -258,19 +258,19 @@ pub fn init(boot_info: &BootInformation) -> ActivePageTable {
     active_table.with(&mut new_table, &mut temporary_page, |mapper| {
         println!("[ vmm ] Initialising paging.");
 
-        let elf_sections_tag = boot_info
-            .elf_sections_tag()
-            .expect("Memory map tag required");
+        let elf_sections = boot_info
+            .elf_sections()
+            .expect("Elf sections tag required");
 
         // identity map the entire kernel.
-        for section in elf_sections_tag.sections() {
+        for section in elf_sections {
             if !section.is_allocated() {
                 // section is not loaded to memory
                 continue;
             }
 
             assert!(
-                section.start_address() as usize % PAGE_SIZE == 0,
+                section.start_address() % PAGE_SIZE == 0,
                 "sections need to be page aligned"
             );
             println!(
@@ -284,10 +284,9 @@ pub fn init(boot_info: &BootInformation) -> ActivePageTable {
             let flags = EntryFlags::from_elf_section_flags(&section);
 
             let start_frame =
-                Frame::containing_address(PhysicalAddress::new(section.start_address() as usize));
-            let end_frame = Frame::containing_address(PhysicalAddress::new(
-                (section.end_address() - 1) as usize,
-            ));
+                Frame::containing_address(PhysicalAddress::new(section.start_address()));
+            let end_frame =
+                Frame::containing_address(PhysicalAddress::new(section.end_address() - 1));
             for frame in Frame::range_inclusive(start_frame, end_frame) {
                 let result = mapper.identity_map(frame, flags);
                 // Ignore this result since this table is not currently active.
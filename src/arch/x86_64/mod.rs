@@ -1,7 +1,9 @@
 //! Architecture-specific code for AMD64.
 
+pub mod cmdline;
 pub mod interrupts;
 pub mod memory;
 pub mod init;
+pub mod multiboot;
 
 pub use self::init::init;
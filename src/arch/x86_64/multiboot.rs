@@ -0,0 +1,360 @@
+//! The multiboot2 information structure. The parts used to set up memory, the memory map, the ELF
+//! sections of the kernel and the modules loaded alongside it, are parsed here. Other tags are
+//! looked up where they are used, with `find`.
+
+use core::{slice, str};
+
+const END_TAG: u32 = 0;
+const MODULE_TAG: u32 = 3;
+const MEMORY_MAP_TAG: u32 = 6;
+const ELF_SECTIONS_TAG: u32 = 9;
+
+/// The memory map entry type of RAM which is free to use.
+const AVAILABLE: u32 = 1;
+
+/// The size of a memory map entry: its base address, length, type and a reserved field.
+const MEMORY_AREA_SIZE: usize = 24;
+
+/// The ELF section type of the header which comes first and doesn't describe a section.
+const SECTION_NULL: u32 = 0;
+
+/// The size of a 64 bit ELF section header.
+const SECTION_HEADER_SIZE: usize = 64;
+
+/// Read the little endian `u32` at offset `at` of a tag's contents.
+pub fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    (bytes[at] as u32) | (bytes[at + 1] as u32) << 8 | (bytes[at + 2] as u32) << 16
+        | (bytes[at + 3] as u32) << 24
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    read_u32(bytes, at) as u64 | (read_u32(bytes, at + 4) as u64) << 32
+}
+
+/// An iterator over the tags of a multiboot2 information structure, as their type and the
+/// contents following their type and size.
+#[derive(Clone)]
+pub struct Tags {
+    next: usize,
+    end: usize,
+}
+
+impl Iterator for Tags {
+    type Item = (u32, &'static [u8]);
+
+    fn next(&mut self) -> Option<(u32, &'static [u8])> {
+        if self.next + 8 > self.end {
+            return None;
+        }
+
+        let (tag_type, size) = unsafe {
+            (
+                *(self.next as *const u32),
+                *((self.next + 4) as *const u32) as usize,
+            )
+        };
+
+        if tag_type == END_TAG || size < 8 || self.next + size > self.end {
+            self.next = self.end;
+            return None;
+        }
+
+        let contents = unsafe { slice::from_raw_parts((self.next + 8) as *const u8, size - 8) };
+
+        // Tags are 8 byte aligned.
+        self.next += (size + 7) & !7;
+
+        Some((tag_type, contents))
+    }
+}
+
+/// Iterate over the tags of the multiboot information structure at `multiboot_info`.
+pub unsafe fn tags(multiboot_info: usize) -> Tags {
+    let total_size = *(multiboot_info as *const u32) as usize;
+
+    Tags {
+        next: multiboot_info + 8,
+        end: multiboot_info + total_size,
+    }
+}
+
+/// The contents of the first tag of type `tag_type` in the multiboot information structure at
+/// `multiboot_info`, following its type and size.
+pub unsafe fn find(multiboot_info: usize, tag_type: u32) -> Option<&'static [u8]> {
+    tags(multiboot_info)
+        .find(|&(this_type, _)| this_type == tag_type)
+        .map(|(_, contents)| contents)
+}
+
+/// The multiboot information structure the bootloader passed to the kernel.
+pub struct BootInformation {
+    address: usize,
+    total_size: usize,
+}
+
+/// Read the multiboot information structure at `address`. It must stay identity mapped for as long
+/// as anything found in it is used.
+pub unsafe fn load(address: usize) -> BootInformation {
+    BootInformation {
+        address: address,
+        total_size: *(address as *const u32) as usize,
+    }
+}
+
+impl BootInformation {
+    pub fn start_address(&self) -> usize {
+        self.address
+    }
+
+    /// The address just past the end of the structure.
+    pub fn end_address(&self) -> usize {
+        self.address + self.total_size
+    }
+
+    fn tags(&self) -> Tags {
+        unsafe { tags(self.address) }
+    }
+
+    fn find(&self, tag_type: u32) -> Option<&'static [u8]> {
+        unsafe { find(self.address, tag_type) }
+    }
+
+    /// The areas of RAM which are free to use, or `None` if there is no memory map.
+    pub fn memory_areas(&self) -> Option<MemoryAreaIter> {
+        // The size and version of an entry, then the entries.
+        let tag = self.find(MEMORY_MAP_TAG)?;
+
+        if tag.len() < 8 {
+            return None;
+        }
+
+        let entry_size = read_u32(tag, 0) as usize;
+
+        if entry_size < MEMORY_AREA_SIZE {
+            return None;
+        }
+
+        Some(MemoryAreaIter {
+            entries: &tag[8..],
+            entry_size: entry_size,
+        })
+    }
+
+    /// The sections of the kernel's ELF image, or `None` if the bootloader didn't give them.
+    pub fn elf_sections(&self) -> Option<ElfSectionIter> {
+        // The number and size of the section headers and the index of the one holding their
+        // names, then the headers.
+        let tag = self.find(ELF_SECTIONS_TAG)?;
+
+        if tag.len() < 12 {
+            return None;
+        }
+
+        let count = read_u32(tag, 0) as usize;
+        let entry_size = read_u32(tag, 4) as usize;
+
+        if entry_size < SECTION_HEADER_SIZE {
+            return None;
+        }
+
+        Some(ElfSectionIter {
+            headers: &tag[12..],
+            entry_size: entry_size,
+            remaining: count,
+        })
+    }
+
+    /// The modules loaded by the bootloader.
+    pub fn modules(&self) -> ModuleIter {
+        ModuleIter { tags: self.tags() }
+    }
+}
+
+/// An area of RAM which is free to use.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryArea {
+    start: usize,
+    size: usize,
+}
+
+impl MemoryArea {
+    pub fn start_address(&self) -> usize {
+        self.start
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+/// An iterator over the areas of the memory map which are free to use. Areas of no size are left
+/// out, so every area has a last byte.
+#[derive(Clone)]
+pub struct MemoryAreaIter {
+    entries: &'static [u8],
+    entry_size: usize,
+}
+
+impl Iterator for MemoryAreaIter {
+    type Item = MemoryArea;
+
+    fn next(&mut self) -> Option<MemoryArea> {
+        loop {
+            let entries = self.entries;
+
+            if entries.len() < self.entry_size {
+                return None;
+            }
+
+            let (entry, rest) = entries.split_at(self.entry_size);
+            self.entries = rest;
+
+            let size = read_u64(entry, 8) as usize;
+
+            if read_u32(entry, 16) == AVAILABLE && size != 0 {
+                return Some(MemoryArea {
+                    start: read_u64(entry, 0) as usize,
+                    size: size,
+                });
+            }
+        }
+    }
+}
+
+bitflags! {
+    pub struct ElfSectionFlags: u64 {
+        const WRITABLE = 1 << 0;
+        const ALLOCATED = 1 << 1;
+        const EXECUTABLE = 1 << 2;
+    }
+}
+
+/// A section of the kernel's ELF image.
+#[derive(Debug, Clone, Copy)]
+pub struct ElfSection {
+    flags: u64,
+    address: usize,
+    size: usize,
+}
+
+impl ElfSection {
+    pub fn start_address(&self) -> usize {
+        self.address
+    }
+
+    /// The address just past the end of the section.
+    pub fn end_address(&self) -> usize {
+        self.address + self.size
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn flags(&self) -> ElfSectionFlags {
+        ElfSectionFlags::from_bits_truncate(self.flags)
+    }
+
+    /// Whether the section is loaded into memory.
+    pub fn is_allocated(&self) -> bool {
+        self.flags().contains(ElfSectionFlags::ALLOCATED)
+    }
+}
+
+/// An iterator over the sections of the kernel's ELF image, leaving out the null section.
+#[derive(Clone)]
+pub struct ElfSectionIter {
+    headers: &'static [u8],
+    entry_size: usize,
+    remaining: usize,
+}
+
+impl Iterator for ElfSectionIter {
+    type Item = ElfSection;
+
+    fn next(&mut self) -> Option<ElfSection> {
+        while self.remaining > 0 {
+            let headers = self.headers;
+
+            if headers.len() < self.entry_size {
+                return None;
+            }
+
+            let (header, rest) = headers.split_at(self.entry_size);
+            self.headers = rest;
+            self.remaining -= 1;
+
+            if read_u32(header, 4) != SECTION_NULL {
+                return Some(ElfSection {
+                    flags: read_u64(header, 8),
+                    address: read_u64(header, 16) as usize,
+                    size: read_u64(header, 32) as usize,
+                });
+            }
+        }
+
+        None
+    }
+}
+
+/// A module loaded by the bootloader, such as the initramfs.
+#[derive(Debug, Clone, Copy)]
+pub struct Module {
+    start: usize,
+    end: usize,
+    name: &'static str,
+}
+
+impl Module {
+    pub fn start_address(&self) -> usize {
+        self.start
+    }
+
+    /// The address just past the end of the module.
+    pub fn end_address(&self) -> usize {
+        self.end
+    }
+
+    /// The string given after the module in the bootloader's configuration.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// An iterator over the modules loaded by the bootloader. Modules which end before they start are
+/// left out.
+#[derive(Clone)]
+pub struct ModuleIter {
+    tags: Tags,
+}
+
+impl Iterator for ModuleIter {
+    type Item = Module;
+
+    fn next(&mut self) -> Option<Module> {
+        // The start and end of the module, then its null terminated name.
+        while let Some((tag_type, tag)) = self.tags.next() {
+            if tag_type != MODULE_TAG || tag.len() < 8 {
+                continue;
+            }
+
+            let start = read_u32(tag, 0) as usize;
+            let end = read_u32(tag, 4) as usize;
+
+            if end < start {
+                continue;
+            }
+
+            let name = &tag[8..];
+            let length = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+
+            return Some(Module {
+                start: start,
+                end: end,
+                name: str::from_utf8(&name[..length]).unwrap_or(""),
+            });
+        }
+
+        None
+    }
+}
@@ -0,0 +1,235 @@
+//! A SATA disk attached to an AHCI port. Commands are issued one at a time from slot 0, and their
+//! completion is polled for.
+
+use alloc::String;
+use arch::memory::PAGE_SIZE;
+use core::{cmp, mem, ptr};
+use device::io::dma::Dma;
+use super::fis::{FisRegH2D, FisType};
+use super::hba::{HbaCmdHeader, HbaCmdTable, HbaPort, CMD_HEADER_WRITE, PRDT_ENTRIES};
+
+const ATA_CMD_READ_DMA_EXT: u8 = 0x25;
+const ATA_CMD_WRITE_DMA_EXT: u8 = 0x35;
+const ATA_CMD_IDENTIFY: u8 = 0xEC;
+
+/// The size of a sector, in bytes.
+pub const SECTOR_SIZE: usize = 512;
+
+/// The size of the bounce buffer, with one PRDT entry per page.
+const BUFFER_SIZE: usize = PRDT_ENTRIES * PAGE_SIZE;
+
+/// The most sectors a single command can transfer, limited by the bounce buffer.
+const MAX_SECTORS: usize = BUFFER_SIZE / SECTOR_SIZE;
+
+/// Copy a string out of IDENTIFY data, where each word holds two characters in big endian order.
+fn identify_string(words: &[u16]) -> String {
+    let mut string = String::new();
+
+    for word in words {
+        string.push((word >> 8) as u8 as char);
+        string.push(*word as u8 as char);
+    }
+
+    String::from(string.trim())
+}
+
+pub struct AhciDisk {
+    /// The index of the port on the HBA.
+    pub port_number: usize,
+    port: &'static mut HbaPort,
+    /// The command list, with the received FIS area in the same page.
+    clb: Dma<[u8; PAGE_SIZE]>,
+    /// The command table for slot 0.
+    ctba: Dma<[u8; PAGE_SIZE]>,
+    /// The bounce buffer for transfers.
+    buffer: Dma<[u8; BUFFER_SIZE]>,
+    /// The number of sectors on the disk.
+    pub sectors: u64,
+    pub model: String,
+    pub serial: String,
+}
+
+impl AhciDisk {
+    /// Set up the command list and FIS area of a port, and identify the disk attached to it.
+    /// All DMA memory is allocated below `limit`.
+    pub fn new(
+        port_number: usize,
+        port: &'static mut HbaPort,
+        limit: usize,
+    ) -> Result<Self, &'static str> {
+        let mut clb: Dma<[u8; PAGE_SIZE]> = unsafe { Dma::zeroed(PAGE_SIZE, limit)? };
+        let ctba: Dma<[u8; PAGE_SIZE]> = unsafe { Dma::zeroed(PAGE_SIZE, limit)? };
+        let buffer: Dma<[u8; BUFFER_SIZE]> = unsafe { Dma::zeroed(PAGE_SIZE, limit)? };
+
+        port.stop()?;
+
+        // The command list takes the first 1 KiB of the page, and the received FIS area the
+        // 256 bytes after it.
+        let command_list = clb.phys_addr();
+        let fb = command_list + 1024;
+        port.clb.write(command_list as u32);
+        port.clbu.write((command_list as u64 >> 32) as u32);
+        port.fb.write(fb as u32);
+        port.fbu.write((fb as u64 >> 32) as u32);
+
+        unsafe {
+            let header = &mut *(clb.as_mut_ptr() as *mut HbaCmdHeader);
+            header.ctba.write(ctba.phys_addr() as u32);
+            header.ctbau.write((ctba.phys_addr() as u64 >> 32) as u32);
+        }
+
+        // Clear any errors left over from the firmware.
+        let serr = port.serr.read();
+        port.serr.write(serr);
+        let is = port.is.read();
+        port.is.write(is);
+
+        port.start()?;
+
+        let mut disk = AhciDisk {
+            port_number: port_number,
+            port: port,
+            clb: clb,
+            ctba: ctba,
+            buffer: buffer,
+            sectors: 0,
+            model: String::new(),
+            serial: String::new(),
+        };
+
+        disk.identify()?;
+
+        Ok(disk)
+    }
+
+    /// Issue IDENTIFY DEVICE and record the size, model and serial number of the disk.
+    fn identify(&mut self) -> Result<(), &'static str> {
+        self.command(ATA_CMD_IDENTIFY, 0, 0, false)?;
+
+        let words = unsafe { ::core::slice::from_raw_parts(self.buffer.as_ptr() as *const u16, 256) };
+
+        // Words 100-103 hold the LBA48 sector count, falling back to the LBA28 count in 60-61.
+        let lba48 = words[100] as u64 | (words[101] as u64) << 16 | (words[102] as u64) << 32
+            | (words[103] as u64) << 48;
+        let lba28 = words[60] as u64 | (words[61] as u64) << 16;
+
+        self.sectors = if lba48 != 0 { lba48 } else { lba28 };
+        self.serial = identify_string(&words[10..20]);
+        self.model = identify_string(&words[27..47]);
+
+        Ok(())
+    }
+
+    /// Build a command in slot 0 transferring `count` sectors through the bounce buffer, issue
+    /// it, and wait for it to complete.
+    fn command(&mut self, command: u8, lba: u64, count: usize, write: bool) -> Result<(), &'static str> {
+        assert!(count <= MAX_SECTORS);
+
+        // IDENTIFY transfers a single sector without a sector count.
+        let bytes = cmp::max(count, 1) * SECTOR_SIZE;
+        let entries = (bytes + PAGE_SIZE - 1) / PAGE_SIZE;
+
+        unsafe {
+            let header = &mut *(self.clb.as_mut_ptr() as *mut HbaCmdHeader);
+            let flags = if write { CMD_HEADER_WRITE } else { 0 };
+            header.cfl.write((mem::size_of::<FisRegH2D>() / 4) as u8 | flags);
+            header.pm.write(0);
+            header.prdtl.write(entries as u16);
+            header.prdbc.write(0);
+
+            // Clear the command FIS and ATAPI command area.
+            ptr::write_bytes(self.ctba.as_mut_ptr(), 0, 128);
+
+            let table = &mut *(self.ctba.as_mut_ptr() as *mut HbaCmdTable);
+
+            let mut remaining = bytes;
+            for i in 0..entries {
+                let buffer = self.buffer.phys_addr() + i * PAGE_SIZE;
+                let length = cmp::min(remaining, PAGE_SIZE);
+
+                table.prdt_entry[i].dba.write(buffer as u32);
+                table.prdt_entry[i].dbau.write((buffer as u64 >> 32) as u32);
+                table.prdt_entry[i].dbc.write(length as u32 - 1);
+
+                remaining -= length;
+            }
+
+            let fis = &mut *(table.cfis.as_mut_ptr() as *mut FisRegH2D);
+            fis.fis_type.write(FisType::RegH2D as u8);
+            // This is a command, not a control update.
+            fis.pm.write(1 << 7);
+            fis.command.write(command);
+
+            if command != ATA_CMD_IDENTIFY {
+                // LBA mode.
+                fis.device.write(1 << 6);
+
+                fis.lba0.write(lba as u8);
+                fis.lba1.write((lba >> 8) as u8);
+                fis.lba2.write((lba >> 16) as u8);
+                fis.lba3.write((lba >> 24) as u8);
+                fis.lba4.write((lba >> 32) as u8);
+                fis.lba5.write((lba >> 40) as u8);
+
+                fis.countl.write(count as u8);
+                fis.counth.write((count >> 8) as u8);
+            }
+        }
+
+        self.port.wait_ready()?;
+        self.port.issue(0)
+    }
+
+    fn check_request(&self, lba: u64, len: usize) -> Result<usize, &'static str> {
+        if len % SECTOR_SIZE != 0 {
+            return Err("Buffer is not a multiple of the sector size");
+        }
+
+        let count = len / SECTOR_SIZE;
+        if lba + count as u64 > self.sectors {
+            return Err("Request goes past the end of the disk");
+        }
+
+        Ok(count)
+    }
+
+    /// Read sectors starting at `lba` into `buffer`, whose length must be a multiple of the
+    /// sector size.
+    pub fn read(&mut self, lba: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let count = self.check_request(lba, buffer.len())?;
+        let mut done = 0;
+
+        while done < count {
+            let chunk = cmp::min(count - done, MAX_SECTORS);
+            self.command(ATA_CMD_READ_DMA_EXT, lba + done as u64, chunk, false)?;
+
+            let offset = done * SECTOR_SIZE;
+            let length = chunk * SECTOR_SIZE;
+            buffer[offset..offset + length].copy_from_slice(&self.buffer[..length]);
+
+            done += chunk;
+        }
+
+        Ok(buffer.len())
+    }
+
+    /// Write `buffer` to the sectors starting at `lba`. Its length must be a multiple of the
+    /// sector size.
+    pub fn write(&mut self, lba: u64, buffer: &[u8]) -> Result<usize, &'static str> {
+        let count = self.check_request(lba, buffer.len())?;
+        let mut done = 0;
+
+        while done < count {
+            let chunk = cmp::min(count - done, MAX_SECTORS);
+
+            let offset = done * SECTOR_SIZE;
+            let length = chunk * SECTOR_SIZE;
+            self.buffer[..length].copy_from_slice(&buffer[offset..offset + length]);
+
+            self.command(ATA_CMD_WRITE_DMA_EXT, lba + done as u64, chunk, true)?;
+            done += chunk;
+        }
+
+        Ok(buffer.len())
+    }
+}
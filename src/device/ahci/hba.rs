@@ -1,3 +1,258 @@
-use core::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT};
+//! AHCI host bus adapter registers and command structures, from the AHCI 1.3.1 specification.
 
-pub static AHCI_BASE: AtomicUsize = ATOMIC_USIZE_INIT;
+use device::io::mmio::Mmio;
+use device::pit::Timeout;
+
+/// Global HBA control bits.
+pub const GHC_AHCI_ENABLE: u32 = 1 << 31;
+pub const GHC_INTERRUPT_ENABLE: u32 = 1 << 1;
+
+/// Port command and status bits.
+pub const PORT_CMD_ST: u32 = 1 << 0;
+pub const PORT_CMD_FRE: u32 = 1 << 4;
+pub const PORT_CMD_FR: u32 = 1 << 14;
+pub const PORT_CMD_CR: u32 = 1 << 15;
+
+/// Task file data status bits.
+pub const ATA_DEV_BUSY: u32 = 0x80;
+pub const ATA_DEV_DRQ: u32 = 0x08;
+pub const ATA_DEV_ERR: u32 = 0x01;
+
+/// Task file error status, in the port interrupt status register.
+pub const PORT_IS_TFES: u32 = 1 << 30;
+
+/// Port signatures for each kind of attached device.
+const SATA_SIG_ATA: u32 = 0x00000101;
+const SATA_SIG_ATAPI: u32 = 0xEB140101;
+const SATA_SIG_SEMB: u32 = 0xC33C0101;
+const SATA_SIG_PM: u32 = 0x96690101;
+
+/// Device detection and power management states in the SATA status register.
+const HBA_PORT_DET_PRESENT: u32 = 0x3;
+const HBA_PORT_IPM_ACTIVE: u32 = 0x1;
+
+/// How long to poll a register before giving up, in microseconds. The port has to stop within
+/// 500 ms, and commands are given longer to let a disk spin up. Time is measured with
+/// `pit::Timeout`, so that it doesn't depend on how fast the CPU runs the loop.
+pub const STOP_TIMEOUT: u64 = 500_000;
+pub const COMMAND_TIMEOUT: u64 = 5_000_000;
+
+/// The kind of device attached to a port.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HbaPortType {
+    None,
+    Sata,
+    Satapi,
+    Semb,
+    PortMultiplier,
+    Unknown(u32),
+}
+
+/// The registers of a single port.
+#[repr(packed)]
+pub struct HbaPort {
+    pub clb: Mmio<u32>,   // 0x00, command list base address, 1K-byte aligned
+    pub clbu: Mmio<u32>,  // 0x04, command list base address upper 32 bits
+    pub fb: Mmio<u32>,    // 0x08, FIS base address, 256-byte aligned
+    pub fbu: Mmio<u32>,   // 0x0C, FIS base address upper 32 bits
+    pub is: Mmio<u32>,    // 0x10, interrupt status
+    pub ie: Mmio<u32>,    // 0x14, interrupt enable
+    pub cmd: Mmio<u32>,   // 0x18, command and status
+    pub rsv0: Mmio<u32>,  // 0x1C, Reserved
+    pub tfd: Mmio<u32>,   // 0x20, task file data
+    pub sig: Mmio<u32>,   // 0x24, signature
+    pub ssts: Mmio<u32>,  // 0x28, SATA status (SCR0:SStatus)
+    pub sctl: Mmio<u32>,  // 0x2C, SATA control (SCR2:SControl)
+    pub serr: Mmio<u32>,  // 0x30, SATA error (SCR1:SError)
+    pub sact: Mmio<u32>,  // 0x34, SATA active (SCR3:SActive)
+    pub ci: Mmio<u32>,    // 0x38, command issue
+    pub sntf: Mmio<u32>,  // 0x3C, SATA notification (SCR4:SNotification)
+    pub fbs: Mmio<u32>,   // 0x40, FIS-based switch control
+    pub rsv1: [Mmio<u32>; 11], // 0x44 ~ 0x6F, Reserved
+    pub vendor: [Mmio<u32>; 4], // 0x70 ~ 0x7F, vendor specific
+}
+
+impl HbaPort {
+    /// Determine what kind of device is attached to this port, if any.
+    pub fn probe(&self) -> HbaPortType {
+        let ssts = self.ssts.read();
+
+        let det = ssts & 0xF;
+        let ipm = (ssts >> 8) & 0xF;
+
+        if det != HBA_PORT_DET_PRESENT || ipm != HBA_PORT_IPM_ACTIVE {
+            return HbaPortType::None;
+        }
+
+        match self.sig.read() {
+            SATA_SIG_ATA => HbaPortType::Sata,
+            SATA_SIG_ATAPI => HbaPortType::Satapi,
+            SATA_SIG_SEMB => HbaPortType::Semb,
+            SATA_SIG_PM => HbaPortType::PortMultiplier,
+            sig => HbaPortType::Unknown(sig),
+        }
+    }
+
+    /// Wait until all the bits in `mask` are clear in the command register.
+    fn wait_cmd_clear(&self, mask: u32) -> Result<(), &'static str> {
+        let mut timeout = Timeout::new(STOP_TIMEOUT);
+
+        while !timeout.expired() {
+            if self.cmd.read() & mask == 0 {
+                return Ok(());
+            }
+        }
+
+        Err("Timed out waiting for port to stop")
+    }
+
+    /// Stop the command engine and FIS receive, so that the command list and FIS base can be
+    /// changed.
+    pub fn stop(&mut self) -> Result<(), &'static str> {
+        let cmd = self.cmd.read();
+        self.cmd.write(cmd & !PORT_CMD_ST);
+        self.wait_cmd_clear(PORT_CMD_CR)?;
+
+        let cmd = self.cmd.read();
+        self.cmd.write(cmd & !PORT_CMD_FRE);
+        self.wait_cmd_clear(PORT_CMD_FR)
+    }
+
+    /// Start FIS receive and the command engine.
+    pub fn start(&mut self) -> Result<(), &'static str> {
+        self.wait_cmd_clear(PORT_CMD_CR)?;
+
+        let cmd = self.cmd.read();
+        self.cmd.write(cmd | PORT_CMD_FRE);
+
+        let cmd = self.cmd.read();
+        self.cmd.write(cmd | PORT_CMD_ST);
+
+        Ok(())
+    }
+
+    /// Wait for the device to finish whatever it is doing, so that a new command can be issued.
+    pub fn wait_ready(&self) -> Result<(), &'static str> {
+        let mut timeout = Timeout::new(COMMAND_TIMEOUT);
+
+        while !timeout.expired() {
+            if self.tfd.read() & (ATA_DEV_BUSY | ATA_DEV_DRQ) == 0 {
+                return Ok(());
+            }
+        }
+
+        Err("Timed out waiting for device to become ready")
+    }
+
+    /// Issue the command in `slot` and poll until it completes.
+    pub fn issue(&mut self, slot: u32) -> Result<(), &'static str> {
+        // Clear any stale interrupt and error state.
+        let is = self.is.read();
+        self.is.write(is);
+        let serr = self.serr.read();
+        self.serr.write(serr);
+
+        self.ci.write(1 << slot);
+
+        let mut timeout = Timeout::new(COMMAND_TIMEOUT);
+
+        while !timeout.expired() {
+            if self.is.read() & PORT_IS_TFES != 0 {
+                return Err("Task file error");
+            }
+
+            if self.ci.read() & (1 << slot) == 0 {
+                if self.tfd.read() & ATA_DEV_ERR != 0 {
+                    return Err("Device reported an error");
+                }
+
+                return Ok(());
+            }
+        }
+
+        Err("Timed out waiting for command to complete")
+    }
+}
+
+/// The memory mapped registers of the HBA, pointed to by the ABAR.
+#[repr(packed)]
+pub struct HbaMem {
+    pub cap: Mmio<u32>,     // 0x00, Host capability
+    pub ghc: Mmio<u32>,     // 0x04, Global host control
+    pub is: Mmio<u32>,      // 0x08, Interrupt status
+    pub pi: Mmio<u32>,      // 0x0C, Port implemented
+    pub vs: Mmio<u32>,      // 0x10, Version
+    pub ccc_ctl: Mmio<u32>, // 0x14, Command completion coalescing control
+    pub ccc_pts: Mmio<u32>, // 0x18, Command completion coalescing ports
+    pub em_loc: Mmio<u32>,  // 0x1C, Enclosure management location
+    pub em_ctl: Mmio<u32>,  // 0x20, Enclosure management control
+    pub cap2: Mmio<u32>,    // 0x24, Host capabilities extended
+    pub bohc: Mmio<u32>,    // 0x28, BIOS/OS handoff control and status
+    pub rsv: [Mmio<u8>; 116], // 0x2C ~ 0x9F, Reserved
+    pub vendor: [Mmio<u8>; 96], // 0xA0 ~ 0xFF, Vendor specific registers
+    pub ports: [HbaPort; 32], // 0x100 ~ 0x10FF, Port control registers
+}
+
+impl HbaMem {
+    /// Switch the HBA into AHCI mode, with interrupts disabled since we poll for completion.
+    pub fn init(&mut self) {
+        let ghc = self.ghc.read();
+        self.ghc.write((ghc | GHC_AHCI_ENABLE) & !GHC_INTERRUPT_ENABLE);
+
+        let is = self.is.read();
+        self.is.write(is);
+    }
+
+    /// The number of command slots supported by each port.
+    pub fn command_slots(&self) -> u32 {
+        ((self.cap.read() >> 8) & 0x1F) + 1
+    }
+
+    /// Whether the HBA can address memory above 4 GiB.
+    pub fn supports_64bit(&self) -> bool {
+        self.cap.read() & (1 << 31) != 0
+    }
+}
+
+/// An entry in a port's command list.
+#[repr(packed)]
+pub struct HbaCmdHeader {
+    // DW0
+    pub cfl: Mmio<u8>, // Command FIS length in DWORDS, 2 ~ 16, ATAPI (bit 5), write (bit 6), prefetchable (bit 7)
+    pub pm: Mmio<u8>,   // Reset (bit 0), BIST (bit 1), clear busy upon R_OK (bit 2), port multiplier (bits 4-7)
+    pub prdtl: Mmio<u16>, // Physical region descriptor table length in entries
+
+    // DW1
+    pub prdbc: Mmio<u32>, // Physical region descriptor byte count transferred
+
+    // DW2, 3
+    pub ctba: Mmio<u32>,  // Command table descriptor base address
+    pub ctbau: Mmio<u32>, // Command table descriptor base address upper 32 bits
+
+    // DW4 - 7
+    pub rsv1: [Mmio<u32>; 4], // Reserved
+}
+
+/// Bits of `HbaCmdHeader::cfl`.
+pub const CMD_HEADER_WRITE: u8 = 1 << 6;
+
+/// A physical region descriptor, describing one buffer of a transfer.
+#[repr(packed)]
+pub struct HbaPrdtEntry {
+    pub dba: Mmio<u32>,  // Data base address
+    pub dbau: Mmio<u32>, // Data base address upper 32 bits
+    pub rsv0: Mmio<u32>, // Reserved
+    pub dbc: Mmio<u32>,  // Byte count minus one, 4M max (bits 0-21), interrupt on completion (bit 31)
+}
+
+/// The number of PRDT entries in each command table.
+pub const PRDT_ENTRIES: usize = 16;
+
+/// The command table a command header points to.
+#[repr(packed)]
+pub struct HbaCmdTable {
+    pub cfis: [Mmio<u8>; 64], // Command FIS
+    pub acmd: [Mmio<u8>; 16], // ATAPI command, 12 or 16 bytes
+    pub rsv: [Mmio<u8>; 48],  // Reserved
+    pub prdt_entry: [HbaPrdtEntry; PRDT_ENTRIES], // Physical region descriptor table entries
+}
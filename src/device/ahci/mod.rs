@@ -1,2 +1,124 @@
+//! A driver for AHCI SATA controllers.
+
+use alloc::arc::Arc;
+use arch::memory;
+use core::mem;
+use device::block::{self, BlockDevice};
+use device::io::dma;
+use device::pci::{Device, DeviceClass, DeviceId, PciDriver};
+use spin::Mutex;
+
+pub mod disk;
 pub mod fis;
 pub mod hba;
+
+use self::disk::AhciDisk;
+use self::hba::{HbaMem, HbaPortType};
+
+/// The block device interface to a disk on an AHCI port.
+pub struct AhciBlockDevice {
+    disk: Mutex<AhciDisk>,
+    sectors: u64,
+}
+
+impl BlockDevice for AhciBlockDevice {
+    fn sector_size(&self) -> usize {
+        disk::SECTOR_SIZE
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read(&self, lba: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        self.disk.lock().read(lba, buffer)
+    }
+
+    fn write(&self, lba: u64, buffer: &[u8]) -> Result<usize, &'static str> {
+        self.disk.lock().write(lba, buffer)
+    }
+}
+
+/// AHCI controllers are mass storage controllers with the SATA subclass and AHCI 1.0 interface.
+static AHCI_IDS: [DeviceId; 1] = [
+    DeviceId::Class {
+        class: DeviceClass::MassStorage,
+        subclass: 0x06,
+        prog_if: Some(0x01),
+    },
+];
+
+pub struct AhciDriver;
+
+pub static DRIVER: AhciDriver = AhciDriver;
+
+impl PciDriver for AhciDriver {
+    fn name(&self) -> &'static str {
+        "ahci"
+    }
+
+    fn id_table(&self) -> &'static [DeviceId] {
+        &AHCI_IDS
+    }
+
+    fn probe(&self, device: &Device) -> Result<(), &'static str> {
+        // The ABAR lives in BAR5.
+        let address = device.bar_address(5).ok_or("ABAR is not a memory BAR")? as usize;
+
+        memory::map_mmio(address, mem::size_of::<HbaMem>());
+
+        // Let the HBA master the bus, so that it can perform DMA.
+        unsafe { device.set_flag(0x04, 1 << 2 | 1 << 1, true) };
+
+        let hba = unsafe { &mut *(address as *mut HbaMem) };
+        hba.init();
+
+        println!(
+            "[ dev ] Found AHCI controller at {:#x}, version {:#x}, {} command slots",
+            address,
+            hba.vs.read(),
+            hba.command_slots()
+        );
+
+        let implemented = hba.pi.read();
+
+        // Without 64-bit addressing, the HBA can only reach DMA memory in the low 4 GiB.
+        let limit = if hba.supports_64bit() {
+            usize::max_value()
+        } else {
+            dma::LIMIT_4GIB
+        };
+
+        for (i, port) in hba.ports.iter_mut().enumerate() {
+            if implemented & (1 << i) == 0 {
+                continue;
+            }
+
+            match port.probe() {
+                HbaPortType::Sata => match AhciDisk::new(i, port, limit) {
+                    Ok(disk) => {
+                        println!(
+                            "[ dev ] AHCI port {}: {} ({}), {} sectors",
+                            i, disk.model, disk.serial, disk.sectors
+                        );
+
+                        let device = AhciBlockDevice {
+                            sectors: disk.sectors,
+                            disk: Mutex::new(disk),
+                        };
+
+                        let name = block::next_name("sd");
+                        if let Err(e) = block::register(name, Arc::new(device)) {
+                            println!("[ dev ] AHCI port {}: {}", i, e);
+                        }
+                    }
+                    Err(e) => println!("[ dev ] AHCI port {}: failed to initialise: {}", i, e),
+                },
+                HbaPortType::None => {}
+                kind => println!("[ dev ] AHCI port {}: unsupported device {:?}", i, kind),
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -9,6 +9,13 @@ use heapless::Vec as StaticVec;
 use spin::Mutex;
 use acpi::madt;
 
+/// The first vector legacy ISA IRQs are redirected to.
+pub const ISA_VECTOR_BASE: u8 = 0x30;
+
+/// The offsets of the I/O APIC register select and data window registers.
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+
 /// This will manage all the apic hardware on the system.
 pub struct ApicManager {
     /// The base address of the local APIC register space.
@@ -34,12 +41,14 @@ impl ApicManager {
         }
     }
 
+    /// The local APIC is identity mapped by `init`, so registers are accessed at their physical
+    /// address.
     pub fn lapic_read(&self, register: u32) -> u32 {
-        unsafe { ptr::read_volatile(&(self.lapic_base + register) as *const u32) }
+        unsafe { ptr::read_volatile((self.lapic_base + register) as usize as *const u32) }
     }
 
     pub fn lapic_write(&self, register: u32, value: u32) {
-        unsafe { ptr::write_volatile(&mut (self.lapic_base + register) as *mut u32, value) }
+        unsafe { ptr::write_volatile((self.lapic_base + register) as usize as *mut u32, value) }
     }
 
     pub fn lapic_set_nmi(&self, vec: u8, flags: u16, lint: u8) {
@@ -82,41 +91,31 @@ impl ApicManager {
         self.lapic_write(0xf0, read | (0x100 | 0xff));
     }
 
+    /// Read register `reg` of I/O APIC `num`, by selecting it with IOREGSEL and reading IOWIN.
     pub fn io_apic_read(&self, reg: u32, num: usize) -> u32 {
-        // First, find the base address of the I/O APIC referenced by `num`
-        // in our list of entries.
-        let mut addr: u32 = self.io_apics[num].address;
+        let base = self.io_apics[num].address as usize;
 
         unsafe {
-            let val = reg;
-            let ioregsel = &mut addr as *mut u32;
-            // Tell the apic which register we which to use.
-            ptr::write_volatile(ioregsel, val);
-
-            let ioregwin = &mut (addr + 4) as *mut u32;
-            ptr::read_volatile(ioregwin)
+            ptr::write_volatile((base + IOREGSEL) as *mut u32, reg);
+            ptr::read_volatile((base + IOWIN) as *const u32)
         }
     }
 
     pub fn io_apic_write(&self, reg: u32, num: usize, data: u32) {
-        let mut addr: u32 = self.io_apics[num].address;
+        let base = self.io_apics[num].address as usize;
 
         unsafe {
-            let val = reg;
-            let ioregsel = &mut addr as *mut u32;
-            ptr::write_volatile(ioregsel, val);
-            
-            let ioregwin = &mut (addr + 4) as *mut u32;
-            ptr::write_volatile(ioregwin, data);
-        };
+            ptr::write_volatile((base + IOREGSEL) as *mut u32, reg);
+            ptr::write_volatile((base + IOWIN) as *mut u32, data);
+        }
     }
 
+    /// Find the I/O APIC whose redirection table covers `gsi`.
     pub fn io_apic_from_gsi(&self, gsi: u32) -> Option<usize> {
         for (i, apic) in self.io_apics.iter().enumerate() {
-            if apic.gsib < gsi && apic.gsib + self.get_max_redirect(i) > gsi {
+            // The maximum redirection entry is the index of the last entry, not the count.
+            if apic.gsib <= gsi && gsi <= apic.gsib + self.get_max_redirect(i) {
                 return Some(i);
-            } else {
-                continue;
             }
         }
 
@@ -127,37 +126,55 @@ impl ApicManager {
         (self.io_apic_read(1, num) & 0xff0000) >> 16
     }
      
-    /// Set the redirect for a given IRQ and GSI.
-    pub fn set_redirect(&self, irq: u8, gsi: u32, flags: u16, id: u8) {
-        let apic = self.io_apic_from_gsi(gsi);
-
-        if apic.is_none() {
-            println!("[ apic ] Error: Could not find an I/O APIC that handles GSI: {}", gsi);
-            // return;
-        } else {
-            let io_apic = apic.unwrap();
-
-            let mut redirection: u64 = irq as u64 + 0x30;
-            if flags & 2 != 0 {
-                redirection |= 1<<13;
-            } else if flags & 8 != 0 {
-                redirection |= 1<<15;
-            }
+    /// Redirect `gsi` to `vector` on the local APIC `id`. `flags` are the MPS INTI flags of an
+    /// interrupt source override.
+    pub fn set_redirect(&self, gsi: u32, vector: u8, flags: u16, id: u8) -> Result<(), &'static str> {
+        let io_apic = self.io_apic_from_gsi(gsi)
+            .ok_or("Could not find an I/O APIC that handles the GSI")?;
 
-            redirection |= (id as u64) << 56;
+        let mut redirection: u64 = vector as u64;
 
-            let ioredtbl: u32 = (gsi - self.io_apics[io_apic].gsib) * 2 + 16;
-            
-            println!("[ dev ] Redirecting IRQ {}, redirection data: {}", irq, redirection);
+        // Active low.
+        if flags & 2 != 0 {
+            redirection |= 1 << 13;
+        }
 
-            self.io_apic_write(ioredtbl, io_apic, redirection as u32);
-            self.io_apic_write(ioredtbl + 1, io_apic, redirection as u32);
+        // Level triggered.
+        if flags & 8 != 0 {
+            redirection |= 1 << 15;
         }
+
+        redirection |= (id as u64) << 56;
+
+        let ioredtbl: u32 = (gsi - self.io_apics[io_apic].gsib) * 2 + 16;
+
+        println!("[ dev ] Redirecting GSI {} to vector {:#x}, redirection data: {:#x}", gsi, vector, redirection);
+
+        self.io_apic_write(ioredtbl, io_apic, redirection as u32);
+        self.io_apic_write(ioredtbl + 1, io_apic, (redirection >> 32) as u32);
+
+        Ok(())
+    }
+
+    /// Redirect the legacy ISA `irq` to `ISA_VECTOR_BASE + irq` on the BSP. ISA IRQs are identity
+    /// mapped to GSIs and are edge triggered and active high unless an interrupt source override
+    /// says otherwise.
+    pub fn route_isa_irq(&self, irq: u8) -> Result<(), &'static str> {
+        let (gsi, flags) = match self.isos.iter().find(|iso| iso.bus_source == 0 && iso.irq_source == irq) {
+            Some(iso) => (iso.gsi, iso.flags),
+            None => (irq as u32, 0),
+        };
+
+        let id = self.local_apics.first().ok_or("No local APIC to deliver to")?.id;
+
+        self.set_redirect(gsi, ISA_VECTOR_BASE + irq, flags, id)
     }
 
     pub fn install_redirects(&self) {
         for iso in self.isos.iter() {
-            self.set_redirect(iso.irq_source, iso.gsi, iso.flags, self.local_apics[0].id)
+            if let Err(e) = self.set_redirect(iso.gsi, ISA_VECTOR_BASE + iso.irq_source, iso.flags, self.local_apics[0].id) {
+                println!("[ apic ] Error: Could not redirect IRQ {}: {}", iso.irq_source, e);
+            }
         }
     }
 
@@ -171,10 +188,6 @@ pub fn init(active_table: &mut ActivePageTable) {
         println!("[ dev ] Initialising APIC, lapic base at {:#x}", apic_manager.lapic_base);
         println!("[ dev ] Mapping local APIC address space...");
         
-        for (i, _) in apic_manager.io_apics.iter().enumerate() {
-            println!("Max redirect for this i/o apic is {}", apic_manager.get_max_redirect(i));
-        }
-
         {
             let page = Page::containing_address(VirtualAddress::new(apic_manager.lapic_base as usize));
             let frame = Frame::containing_address(PhysicalAddress::new(apic_manager.lapic_base as usize));
@@ -197,6 +210,11 @@ pub fn init(active_table: &mut ActivePageTable) {
             }
         }
 
+        // The I/O APICs can only be read once they are mapped.
+        for (i, _) in apic_manager.io_apics.iter().enumerate() {
+            println!("Max redirect for this i/o apic is {}", apic_manager.get_max_redirect(i));
+        }
+
         println!("[ dev ] Installing non-maskable interrupts...");
         apic_manager.install_nmis();
         println!("[ dev ] Installing interrupt source overrides...");
@@ -206,6 +224,23 @@ pub fn init(active_table: &mut ActivePageTable) {
     }
 }
 
+/// Return the APIC ID of the bootstrap processor, which all device interrupts are currently
+/// delivered to.
+pub fn bsp_id() -> Option<u8> {
+    match *APIC_MANAGER.lock() {
+        Some(ref apic_manager) => apic_manager.local_apics.first().map(|lapic| lapic.id),
+        None => None,
+    }
+}
+
+/// Route the legacy ISA `irq` through the I/O APIC, honouring any interrupt source override.
+pub fn route_isa_irq(irq: u8) -> Result<(), &'static str> {
+    match *APIC_MANAGER.lock() {
+        Some(ref apic_manager) => apic_manager.route_isa_irq(irq),
+        None => Err("APIC not initialised"),
+    }
+}
+
 pub fn eoi() {
     if let Some(ref mut apic_manager) = *APIC_MANAGER.lock() {
         apic_manager.eoi();
@@ -0,0 +1,150 @@
+//! A write-back cache of disk blocks, evicting the least recently used block when full.
+
+use alloc::btree_map::BTreeMap;
+use alloc::Vec;
+
+struct CacheEntry {
+    data: Vec<u8>,
+    dirty: bool,
+    /// The value of the cache's clock when this block was last touched.
+    last_used: u64,
+}
+
+pub struct BufferCache {
+    block_size: usize,
+    capacity: usize,
+    blocks: BTreeMap<u64, CacheEntry>,
+    /// Incremented on every access, to order blocks by recency.
+    clock: u64,
+}
+
+impl BufferCache {
+    pub fn new(block_size: usize, capacity: usize) -> Self {
+        BufferCache {
+            block_size: block_size,
+            capacity: capacity,
+            blocks: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Look up a block, marking it as recently used.
+    pub fn get(&mut self, lba: u64) -> Option<&[u8]> {
+        let now = self.tick();
+
+        match self.blocks.get_mut(&lba) {
+            Some(entry) => {
+                entry.last_used = now;
+                Some(&entry.data)
+            }
+            None => None,
+        }
+    }
+
+    /// Insert or replace a block. If this pushes out a dirty block, it is returned so that the
+    /// caller can write it back.
+    pub fn insert(&mut self, lba: u64, data: Vec<u8>, dirty: bool) -> Option<(u64, Vec<u8>)> {
+        assert_eq!(data.len(), self.block_size);
+
+        let now = self.tick();
+
+        if let Some(entry) = self.blocks.get_mut(&lba) {
+            entry.data = data;
+            entry.dirty |= dirty;
+            entry.last_used = now;
+            return None;
+        }
+
+        let evicted = if self.blocks.len() >= self.capacity {
+            self.evict()
+        } else {
+            None
+        };
+
+        self.blocks.insert(
+            lba,
+            CacheEntry {
+                data: data,
+                dirty: dirty,
+                last_used: now,
+            },
+        );
+
+        evicted
+    }
+
+    /// Remove the least recently used clean block, or if every block is dirty the least recently
+    /// used one, returning it if it is dirty.
+    fn evict(&mut self) -> Option<(u64, Vec<u8>)> {
+        let lba = match self.blocks
+            .iter()
+            .min_by_key(|&(_, entry)| (entry.dirty, entry.last_used))
+        {
+            Some((&lba, _)) => lba,
+            None => return None,
+        };
+
+        match self.blocks.remove(&lba) {
+            Some(ref entry) if !entry.dirty => None,
+            Some(entry) => Some((lba, entry.data)),
+            None => None,
+        }
+    }
+
+    /// Return a copy of every dirty block. They stay dirty until `mark_clean` is called once
+    /// they have been written.
+    pub fn dirty(&self) -> Vec<(u64, Vec<u8>)> {
+        self.blocks
+            .iter()
+            .filter(|&(_, entry)| entry.dirty)
+            .map(|(&lba, entry)| (lba, entry.data.clone()))
+            .collect()
+    }
+
+    /// Mark a block clean now that `data` has been written to it, unless it has been changed
+    /// since.
+    pub fn mark_clean(&mut self, lba: u64, data: &[u8]) {
+        if let Some(entry) = self.blocks.get_mut(&lba) {
+            if entry.data.as_slice() == data {
+                entry.dirty = false;
+            }
+        }
+    }
+
+    /// Put back a dirty block which could not be written, unless the cache already holds a newer
+    /// copy. Nothing is evicted for it, so the cache may briefly hold more than its capacity.
+    pub fn restore(&mut self, lba: u64, data: Vec<u8>) {
+        if self.blocks.contains_key(&lba) {
+            return;
+        }
+
+        let now = self.tick();
+
+        self.blocks.insert(
+            lba,
+            CacheEntry {
+                data: data,
+                dirty: true,
+                last_used: now,
+            },
+        );
+    }
+
+    /// Drop every clean block, for example after the device was written to behind our back.
+    pub fn invalidate_clean(&mut self) {
+        let clean: Vec<u64> = self.blocks
+            .iter()
+            .filter(|&(_, entry)| !entry.dirty)
+            .map(|(&lba, _)| lba)
+            .collect();
+
+        for lba in clean {
+            self.blocks.remove(&lba);
+        }
+    }
+}
@@ -0,0 +1,369 @@
+//! Block devices. Drivers implement `BlockDevice` and register it under a name, after which all
+//! access goes through a `Disk`, which adds a write-back buffer cache and a request queue in front
+//! of the driver. Filesystems only ever see a `Disk`, so they don't care where their blocks come
+//! from. Partitions go through the `Disk` of the disk they are on, so they have no cache or queue
+//! of their own.
+
+use alloc::arc::Arc;
+use alloc::btree_map::BTreeMap;
+use alloc::{String, Vec};
+use core::cmp;
+use fs::devfs;
+use spin::{Mutex, RwLock};
+use task;
+
+pub mod cache;
+pub mod partition;
+pub mod queue;
+pub mod ramdisk;
+
+use self::cache::BufferCache;
+use self::partition::PartitionInfo;
+use self::queue::{Completion, RequestKind, RequestQueue};
+
+/// The size of each disk's buffer cache in bytes. Every disk has its own, and the kernel heap is
+/// small.
+const CACHE_SIZE: usize = 32 * 1024;
+
+/// A device which stores data in fixed size sectors.
+pub trait BlockDevice: Send + Sync {
+    /// The size of a sector in bytes.
+    fn sector_size(&self) -> usize;
+
+    /// The number of sectors on the device.
+    fn sector_count(&self) -> u64;
+
+    /// Read sectors starting at `lba` into `buffer`, whose length is a multiple of the sector
+    /// size. Returns the number of bytes read.
+    fn read(&self, lba: u64, buffer: &mut [u8]) -> Result<usize, &'static str>;
+
+    /// Write `buffer` to the sectors starting at `lba`. Returns the number of bytes written.
+    fn write(&self, lba: u64, buffer: &[u8]) -> Result<usize, &'static str>;
+
+    /// Start a transfer, and call `completion.complete` with its result once it has finished: the
+    /// data read for a read, or an empty buffer for a write. For a write, `buffer` holds the data
+    /// to write; for a read, it is zeroed and sized for the sectors to read. Drivers which can run
+    /// transfers in the background override this and complete them from their interrupt handler.
+    /// By default the transfer is done with `read` or `write` before this returns.
+    fn start(&self, kind: RequestKind, lba: u64, mut buffer: Vec<u8>, completion: Arc<Completion>) {
+        let result = match kind {
+            RequestKind::Read => self.read(lba, &mut buffer).map(|_| buffer),
+            RequestKind::Write => self.write(lba, &buffer).map(|_| Vec::new()),
+        };
+
+        completion.complete(result);
+    }
+
+    /// Make sure every completed write has reached stable storage.
+    fn flush(&self) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    /// Where this device lives in a partition table, if it is a partition.
+    fn partition_info(&self) -> Option<&PartitionInfo> {
+        None
+    }
+}
+
+/// A registered block device, with its cache and request queue.
+pub struct Disk {
+    name: String,
+    device: Arc<BlockDevice>,
+    /// The buffer cache, which partitions don't have.
+    cache: Option<Mutex<BufferCache>>,
+    queue: RequestQueue,
+}
+
+impl Disk {
+    fn new(name: String, device: Arc<BlockDevice>) -> Self {
+        let sector_size = device.sector_size();
+
+        let cache = if device.partition_info().is_some() {
+            None
+        } else {
+            Some(Mutex::new(BufferCache::new(
+                sector_size,
+                cmp::max(CACHE_SIZE / sector_size, 1),
+            )))
+        };
+
+        Disk {
+            name: name,
+            device: device,
+            cache: cache,
+            queue: RequestQueue::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn sector_size(&self) -> usize {
+        self.device.sector_size()
+    }
+
+    pub fn sector_count(&self) -> u64 {
+        self.device.sector_count()
+    }
+
+    /// The partition table entry of this disk, if it is a partition.
+    pub fn partition_info(&self) -> Option<&PartitionInfo> {
+        self.device.partition_info()
+    }
+
+    /// The underlying driver, bypassing the cache and queue.
+    pub fn device(&self) -> &Arc<BlockDevice> {
+        &self.device
+    }
+
+    /// Queue a transfer without waiting for it. `data` holds the bytes to write for a write, and
+    /// is ignored for a read of `count` sectors.
+    pub fn submit(&self, kind: RequestKind, lba: u64, count: usize, data: Vec<u8>) -> Arc<Completion> {
+        self.queue.submit(kind, lba, count, data)
+    }
+
+    /// Process queued requests until the given one has completed, and return its result. While
+    /// the driver works on a transfer in the background, other tasks run. This must not be
+    /// called with any lock held.
+    pub fn wait(&self, completion: &Completion) -> Result<Vec<u8>, &'static str> {
+        loop {
+            if let Some(result) = completion.take() {
+                return result;
+            }
+
+            self.queue.run(&*self.device);
+
+            if !completion.is_complete() {
+                task::wait();
+            }
+        }
+    }
+
+    fn check_request(&self, lba: u64, len: usize) -> Result<usize, &'static str> {
+        let sector_size = self.sector_size();
+
+        if len % sector_size != 0 {
+            return Err("Buffer is not a multiple of the sector size");
+        }
+
+        let count = len / sector_size;
+        if lba + count as u64 > self.sector_count() {
+            return Err("Request goes past the end of the disk");
+        }
+
+        Ok(count)
+    }
+
+    /// Read sectors starting at `lba` into `buffer`, through the buffer cache. Sectors which
+    /// miss the cache are queued individually, and the queue merges adjacent ones into a single
+    /// transfer.
+    pub fn read(&self, lba: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let count = self.check_request(lba, buffer.len())?;
+        let sector_size = self.sector_size();
+
+        let cache = match self.cache {
+            Some(ref cache) => cache,
+            None => return self.device.read(lba, buffer),
+        };
+
+        let mut misses = Vec::new();
+
+        {
+            let mut cache = cache.lock();
+
+            for (i, chunk) in buffer.chunks_mut(sector_size).enumerate().take(count) {
+                let sector = lba + i as u64;
+
+                match cache.get(sector) {
+                    Some(data) => chunk.copy_from_slice(data),
+                    None => misses.push((i, self.submit(RequestKind::Read, sector, 1, Vec::new()))),
+                }
+            }
+        }
+
+        for (i, completion) in misses {
+            let data = self.wait(&completion)?;
+            let sector = lba + i as u64;
+            let chunk = &mut buffer[i * sector_size..(i + 1) * sector_size];
+
+            let evicted = {
+                let mut cache = cache.lock();
+
+                // Another task may have read or written the sector while we waited for the device.
+                // What it left in the cache is at least as new as what we read, and may be dirty,
+                // so it is kept and returned instead.
+                let cached = match cache.get(sector) {
+                    Some(cached) => {
+                        chunk.copy_from_slice(cached);
+                        true
+                    }
+                    None => false,
+                };
+
+                if cached {
+                    None
+                } else {
+                    chunk.copy_from_slice(&data);
+                    cache.insert(sector, data, false)
+                }
+            };
+
+            self.write_back(evicted)?;
+        }
+
+        Ok(buffer.len())
+    }
+
+    /// Write `buffer` to the sectors starting at `lba`. The data stays in the cache until it is
+    /// evicted or the disk is flushed.
+    pub fn write(&self, lba: u64, buffer: &[u8]) -> Result<usize, &'static str> {
+        let count = self.check_request(lba, buffer.len())?;
+        let sector_size = self.sector_size();
+
+        let cache = match self.cache {
+            Some(ref cache) => cache,
+            None => return self.device.write(lba, buffer),
+        };
+
+        let mut evicted = Vec::new();
+
+        {
+            let mut cache = cache.lock();
+
+            for (i, chunk) in buffer.chunks(sector_size).enumerate().take(count) {
+                evicted.extend(cache.insert(lba + i as u64, chunk.to_vec(), true));
+            }
+        }
+
+        self.write_back(evicted)?;
+
+        Ok(buffer.len())
+    }
+
+    /// Queue writes for a set of dirty blocks and wait for all of them. Blocks are only marked
+    /// clean once they have been written. Those which fail stay dirty, and are put back in the
+    /// cache if they were evicted, so that a later flush retries them.
+    fn write_back<I: IntoIterator<Item = (u64, Vec<u8>)>>(&self, blocks: I) -> Result<(), &'static str> {
+        // Only disks with a cache have dirty blocks.
+        let cache = match self.cache {
+            Some(ref cache) => cache,
+            None => return Ok(()),
+        };
+
+        let writes: Vec<(u64, Vec<u8>, Arc<Completion>)> = blocks
+            .into_iter()
+            .map(|(lba, data)| {
+                let completion = self.submit(RequestKind::Write, lba, 1, data.clone());
+                (lba, data, completion)
+            })
+            .collect();
+
+        let mut result = Ok(());
+
+        for (lba, data, completion) in writes {
+            match self.wait(&completion) {
+                Ok(_) => cache.lock().mark_clean(lba, &data),
+                Err(e) => {
+                    cache.lock().restore(lba, data);
+
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Write every dirty block back to the device, then flush the device itself.
+    pub fn flush(&self) -> Result<(), &'static str> {
+        if let Some(ref cache) = self.cache {
+            let dirty = cache.lock().dirty();
+            self.write_back(dirty)?;
+        }
+
+        self.device.flush()
+    }
+}
+
+lazy_static! {
+    /// Every registered disk, by name.
+    static ref DISKS: RwLock<BTreeMap<String, Arc<Disk>>> = RwLock::new(BTreeMap::new());
+}
+
+/// Register a block device under `name`. Whole disks have their partition table read, and each
+/// partition is registered as well.
+pub fn register(name: String, device: Arc<BlockDevice>) -> Result<Arc<Disk>, &'static str> {
+    let disk = {
+        let mut disks = DISKS.write();
+
+        if disks.contains_key(&name) {
+            return Err("A disk with that name is already registered");
+        }
+
+        println!(
+            "[ dev ] Registered block device {}, {} sectors of {} bytes",
+            name,
+            device.sector_count(),
+            device.sector_size()
+        );
+
+        let disk = Arc::new(Disk::new(name.clone(), device));
+        disks.insert(name, disk.clone());
+        disk
+    };
+
+    if let Err(e) = devfs::register_block(disk.name(), disk.clone()) {
+        println!("[ dev ] Could not add /dev/{}: {:?}", disk.name(), e);
+    }
+
+    if disk.partition_info().is_none() {
+        partition::scan(&disk);
+    }
+
+    Ok(disk)
+}
+
+/// Flush and remove the disk registered under `name`.
+pub fn unregister(name: &str) -> Result<(), &'static str> {
+    let disk = DISKS.write().remove(name).ok_or("No such disk")?;
+    let _ = devfs::unregister(name);
+    disk.flush()
+}
+
+/// Find a disk by name.
+pub fn get(name: &str) -> Option<Arc<Disk>> {
+    DISKS.read().get(name).cloned()
+}
+
+/// Return the names of every registered disk.
+pub fn names() -> Vec<String> {
+    DISKS.read().keys().cloned().collect()
+}
+
+/// Return the first unused name made of `prefix` and a letter, such as `sda`, `sdb` and so on.
+pub fn next_name(prefix: &str) -> String {
+    let disks = DISKS.read();
+
+    for letter in b'a'..b'z' + 1 {
+        let name = format!("{}{}", prefix, letter as char);
+        if !disks.contains_key(&name) {
+            return name;
+        }
+    }
+
+    format!("{}{}", prefix, disks.len())
+}
+
+/// Write back the caches of every disk.
+pub fn sync() {
+    let disks: Vec<Arc<Disk>> = DISKS.read().values().cloned().collect();
+
+    for disk in disks {
+        if let Err(e) = disk.flush() {
+            println!("[ dev ] Failed to flush {}: {}", disk.name(), e);
+        }
+    }
+}
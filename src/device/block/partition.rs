@@ -0,0 +1,408 @@
+//! Partition table parsing. MBR disks, including logical partitions inside an extended partition,
+//! and GPT disks are supported. Every partition found is registered as a block device of its own,
+//! named after the disk with the partition number appended. Partitions are stacked on the `Disk`
+//! of their disk and have no cache of their own, so their sectors are only cached once, and the
+//! disk and its partitions always agree on what is in them.
+
+use alloc::arc::Arc;
+use alloc::{String, Vec};
+use core::fmt;
+use super::{BlockDevice, Disk};
+
+/// The MBR partition type of a protective MBR in front of a GPT.
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+
+/// MBR partition types which hold a chain of extended boot records.
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+
+/// The most logical partitions followed in an extended partition, in case the chain loops.
+const MAX_LOGICAL_PARTITIONS: usize = 64;
+
+const GPT_SIGNATURE: &'static [u8; 8] = b"EFI PART";
+
+/// The largest GPT partition entry array we read, in bytes. This is the size the specification
+/// requires room for, and what every partitioning tool writes.
+const MAX_GPT_ENTRY_ARRAY: usize = 16 * 1024;
+
+/// A GUID, stored in the mixed endian layout used on disk.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|&b| b == 0)
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+
+        write!(
+            f,
+            "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-",
+            b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9]
+        )?;
+
+        for byte in &b[10..] {
+            write!(f, "{:02X}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// What a partition table says about one of its partitions.
+#[derive(Debug, Clone)]
+pub struct PartitionInfo {
+    /// The partition number, starting at 1. Logical MBR partitions start at 5.
+    pub number: u32,
+    /// The first sector of the partition on the disk.
+    pub start: u64,
+    pub sectors: u64,
+    /// The MBR partition type byte, for MBR partitions.
+    pub mbr_type: Option<u8>,
+    /// The partition type GUID, for GPT partitions.
+    pub type_guid: Option<Guid>,
+    /// The unique GUID of this partition, for GPT partitions.
+    pub unique_guid: Option<Guid>,
+    /// The partition name, for GPT partitions.
+    pub name: String,
+}
+
+/// A partition, as a block device delegating to the disk it lives on.
+pub struct Partition {
+    disk: Arc<Disk>,
+    info: PartitionInfo,
+}
+
+impl Partition {
+    fn check_request(&self, lba: u64, len: usize) -> Result<(), &'static str> {
+        let sectors = (len / self.disk.sector_size()) as u64;
+
+        if lba + sectors > self.info.sectors {
+            Err("Request goes past the end of the partition")
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl BlockDevice for Partition {
+    fn sector_size(&self) -> usize {
+        self.disk.sector_size()
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.info.sectors
+    }
+
+    fn read(&self, lba: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        self.check_request(lba, buffer.len())?;
+        self.disk.read(self.info.start + lba, buffer)
+    }
+
+    fn write(&self, lba: u64, buffer: &[u8]) -> Result<usize, &'static str> {
+        self.check_request(lba, buffer.len())?;
+        self.disk.write(self.info.start + lba, buffer)
+    }
+
+    fn flush(&self) -> Result<(), &'static str> {
+        self.disk.flush()
+    }
+
+    fn partition_info(&self) -> Option<&PartitionInfo> {
+        Some(&self.info)
+    }
+}
+
+/// The CRC32 used by GPT, which is the same as the one used by zlib and Ethernet.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFF_u32;
+
+    for &byte in data {
+        crc ^= byte as u32;
+
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+
+    !crc
+}
+
+fn read_u16(bytes: &[u8], i: usize) -> u16 {
+    bytes[i] as u16 | (bytes[i + 1] as u16) << 8
+}
+
+fn read_u32(bytes: &[u8], i: usize) -> u32 {
+    read_u16(bytes, i) as u32 | (read_u16(bytes, i + 2) as u32) << 16
+}
+
+fn read_u64(bytes: &[u8], i: usize) -> u64 {
+    read_u32(bytes, i) as u64 | (read_u32(bytes, i + 4) as u64) << 32
+}
+
+fn read_guid(bytes: &[u8], i: usize) -> Guid {
+    let mut guid = [0; 16];
+    guid.copy_from_slice(&bytes[i..i + 16]);
+    Guid(guid)
+}
+
+fn read_sectors(disk: &Disk, lba: u64, count: usize) -> Result<Vec<u8>, &'static str> {
+    let mut buffer = vec![0; count * disk.sector_size()];
+    disk.read(lba, &mut buffer)?;
+    Ok(buffer)
+}
+
+/// A primary MBR entry.
+struct MbrEntry {
+    kind: u8,
+    start: u64,
+    sectors: u64,
+}
+
+fn mbr_entries(sector: &[u8]) -> Vec<MbrEntry> {
+    (0..4)
+        .map(|i| {
+            let entry = &sector[446 + i * 16..446 + (i + 1) * 16];
+
+            MbrEntry {
+                kind: entry[4],
+                start: read_u32(entry, 8) as u64,
+                sectors: read_u32(entry, 12) as u64,
+            }
+        })
+        .collect()
+}
+
+fn mbr_partition(number: u32, kind: u8, start: u64, sectors: u64) -> PartitionInfo {
+    PartitionInfo {
+        number: number,
+        start: start,
+        sectors: sectors,
+        mbr_type: Some(kind),
+        type_guid: None,
+        unique_guid: None,
+        name: String::new(),
+    }
+}
+
+/// Follow the chain of extended boot records in an extended partition.
+fn parse_extended(
+    disk: &Disk,
+    base: u64,
+    partitions: &mut Vec<PartitionInfo>,
+) -> Result<(), &'static str> {
+    let mut ebr = base;
+    let mut number = 5;
+
+    for _ in 0..MAX_LOGICAL_PARTITIONS {
+        let sector = read_sectors(disk, ebr, 1)?;
+
+        if sector[510] != 0x55 || sector[511] != 0xAA {
+            return Err("Extended boot record has an invalid signature");
+        }
+
+        let entries = mbr_entries(&sector);
+
+        // The first entry is relative to this EBR, the second to the start of the extended
+        // partition.
+        if entries[0].kind != 0 && entries[0].sectors != 0 {
+            partitions.push(mbr_partition(
+                number,
+                entries[0].kind,
+                ebr + entries[0].start,
+                entries[0].sectors,
+            ));
+            number += 1;
+        }
+
+        if entries[1].kind == 0 || entries[1].start == 0 {
+            return Ok(());
+        }
+
+        ebr = base + entries[1].start;
+    }
+
+    Err("Too many logical partitions")
+}
+
+/// Parse a GPT header and its entry array, starting from the header at `lba`.
+fn parse_gpt_at(disk: &Disk, lba: u64) -> Result<Vec<PartitionInfo>, &'static str> {
+    let mut header = read_sectors(disk, lba, 1)?;
+
+    if &header[0..8] != GPT_SIGNATURE {
+        return Err("GPT header has an invalid signature");
+    }
+
+    let header_size = read_u32(&header, 12) as usize;
+    if header_size < 92 || header_size > header.len() {
+        return Err("GPT header has an invalid size");
+    }
+
+    // The checksum covers the header with the checksum field itself zeroed.
+    let header_crc = read_u32(&header, 16);
+    for byte in &mut header[16..20] {
+        *byte = 0;
+    }
+
+    if crc32(&header[..header_size]) != header_crc {
+        return Err("GPT header checksum is invalid");
+    }
+
+    let entries_lba = read_u64(&header, 72);
+    let entry_count = read_u32(&header, 80) as usize;
+    let entry_size = read_u32(&header, 84) as usize;
+    let entries_crc = read_u32(&header, 88);
+
+    // Entries are at least 128 bytes and a multiple of 8, and the array is read in one go.
+    if entry_size < 128 || entry_size % 8 != 0 {
+        return Err("GPT entries have an invalid size");
+    }
+
+    let bytes = match entry_count.checked_mul(entry_size) {
+        Some(bytes) if bytes <= MAX_GPT_ENTRY_ARRAY => bytes,
+        _ => return Err("GPT entry array is too large"),
+    };
+
+    let sector_size = disk.sector_size();
+    let entries = read_sectors(disk, entries_lba, (bytes + sector_size - 1) / sector_size)?;
+
+    if crc32(&entries[..bytes]) != entries_crc {
+        return Err("GPT entry array checksum is invalid");
+    }
+
+    let mut partitions = Vec::new();
+
+    for i in 0..entry_count {
+        let entry = &entries[i * entry_size..(i + 1) * entry_size];
+        let type_guid = read_guid(entry, 0);
+
+        if type_guid.is_zero() {
+            continue;
+        }
+
+        let first = read_u64(entry, 32);
+        let last = read_u64(entry, 40);
+
+        if last < first {
+            continue;
+        }
+
+        // The name is up to 36 UTF-16LE code units, padded with zeroes.
+        let name = (0..36)
+            .map(|c| read_u16(entry, 56 + c * 2))
+            .take_while(|&c| c != 0)
+            .map(|c| ::core::char::from_u32(c as u32).unwrap_or('?'))
+            .collect();
+
+        partitions.push(PartitionInfo {
+            number: i as u32 + 1,
+            start: first,
+            sectors: last - first + 1,
+            mbr_type: None,
+            type_guid: Some(type_guid),
+            unique_guid: Some(read_guid(entry, 16)),
+            name: name,
+        });
+    }
+
+    Ok(partitions)
+}
+
+/// Parse the GPT, falling back to the backup header at the end of the disk if the primary one
+/// is damaged.
+fn parse_gpt(disk: &Disk) -> Result<Vec<PartitionInfo>, &'static str> {
+    parse_gpt_at(disk, 1).or_else(|e| {
+        println!("[ dev ] {}: {}, trying the backup GPT", disk.name(), e);
+        parse_gpt_at(disk, disk.sector_count() - 1)
+    })
+}
+
+/// Read the partition table of a disk. Returns an empty list if the disk is not partitioned.
+pub fn parse(disk: &Disk) -> Result<Vec<PartitionInfo>, &'static str> {
+    let mbr = read_sectors(disk, 0, 1)?;
+
+    if mbr[510] != 0x55 || mbr[511] != 0xAA {
+        return Ok(Vec::new());
+    }
+
+    let entries = mbr_entries(&mbr);
+
+    if entries.iter().any(|e| e.kind == MBR_TYPE_GPT_PROTECTIVE) {
+        return parse_gpt(disk);
+    }
+
+    let mut partitions = Vec::new();
+
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.kind == 0 || entry.sectors == 0 {
+            continue;
+        }
+
+        if MBR_TYPES_EXTENDED.contains(&entry.kind) {
+            if let Err(e) = parse_extended(disk, entry.start, &mut partitions) {
+                println!("[ dev ] {}: {}", disk.name(), e);
+            }
+        } else {
+            partitions.push(mbr_partition(i as u32 + 1, entry.kind, entry.start, entry.sectors));
+        }
+    }
+
+    Ok(partitions)
+}
+
+/// Register every partition on `disk` as a block device.
+pub fn scan(disk: &Arc<Disk>) {
+    let partitions = match parse(disk) {
+        Ok(partitions) => partitions,
+        Err(e) => {
+            println!("[ dev ] {}: could not read partition table: {}", disk.name(), e);
+            return;
+        }
+    };
+
+    for info in partitions {
+        if info.start + info.sectors > disk.sector_count() {
+            println!(
+                "[ dev ] {}: partition {} goes past the end of the disk",
+                disk.name(),
+                info.number
+            );
+            continue;
+        }
+
+        let name = format!("{}{}", disk.name(), info.number);
+
+        match info.type_guid {
+            Some(guid) => println!(
+                "[ dev ] {}: start {}, {} sectors, type {}, name \"{}\"",
+                name, info.start, info.sectors, guid, info.name
+            ),
+            None => println!(
+                "[ dev ] {}: start {}, {} sectors, type {:#x}",
+                name,
+                info.start,
+                info.sectors,
+                info.mbr_type.unwrap_or(0)
+            ),
+        }
+
+        let partition = Partition {
+            disk: disk.clone(),
+            info: info,
+        };
+
+        if let Err(e) = super::register(name, Arc::new(partition)) {
+            println!("[ dev ] {}", e);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! A per-device queue of transfers. Requests are handed to the driver in the order they were
+//! submitted, but runs of requests of the same kind covering adjacent sectors are merged into a
+//! single transfer first. Only one transfer is in flight at a time, so drivers which complete
+//! transfers in the background still see them in order.
+
+use alloc::arc::Arc;
+use alloc::{Vec, VecDeque};
+use spin::Mutex;
+use super::BlockDevice;
+
+/// The largest transfer the queue builds by merging, in sectors.
+const MAX_MERGED_SECTORS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RequestKind {
+    Read,
+    Write,
+}
+
+/// The result of a request, filled in by the queue once the transfer is done.
+pub struct Completion {
+    result: Mutex<Option<Result<Vec<u8>, &'static str>>>,
+}
+
+impl Completion {
+    pub fn new() -> Self {
+        Completion {
+            result: Mutex::new(None),
+        }
+    }
+
+    /// Record the result of the transfer. Drivers call this once a transfer they started has
+    /// finished, which may be from their interrupt handler.
+    pub fn complete(&self, result: Result<Vec<u8>, &'static str>) {
+        *self.result.lock() = Some(result);
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.result.lock().is_some()
+    }
+
+    /// Take the result, if the request has completed. A read yields the data read, and a write
+    /// yields an empty buffer.
+    pub fn take(&self) -> Option<Result<Vec<u8>, &'static str>> {
+        self.result.lock().take()
+    }
+}
+
+struct Request {
+    kind: RequestKind,
+    lba: u64,
+    count: usize,
+    data: Vec<u8>,
+    completion: Arc<Completion>,
+}
+
+/// A merged transfer handed to the driver, with the requests it was built from.
+struct InFlight {
+    completion: Arc<Completion>,
+    batch: Vec<Request>,
+}
+
+pub struct RequestQueue {
+    pending: Mutex<VecDeque<Request>>,
+    in_flight: Mutex<Option<InFlight>>,
+}
+
+impl RequestQueue {
+    pub fn new() -> Self {
+        RequestQueue {
+            pending: Mutex::new(VecDeque::new()),
+            in_flight: Mutex::new(None),
+        }
+    }
+
+    /// Add a request to the back of the queue.
+    pub fn submit(&self, kind: RequestKind, lba: u64, count: usize, data: Vec<u8>) -> Arc<Completion> {
+        let completion = Arc::new(Completion::new());
+
+        self.pending.lock().push_back(Request {
+            kind: kind,
+            lba: lba,
+            count: count,
+            data: data,
+            completion: completion.clone(),
+        });
+
+        completion
+    }
+
+    /// Remove the request at the front of the queue, along with every following request which
+    /// continues it.
+    fn next_batch(&self) -> Vec<Request> {
+        let mut pending = self.pending.lock();
+        let mut batch: Vec<Request> = Vec::new();
+        let mut sectors = 0;
+
+        while let Some(request) = pending.pop_front() {
+            let mergeable = match batch.last() {
+                None => true,
+                Some(last) => {
+                    last.kind == request.kind && last.lba + last.count as u64 == request.lba
+                        && sectors + request.count <= MAX_MERGED_SECTORS
+                }
+            };
+
+            if !mergeable {
+                pending.push_front(request);
+                break;
+            }
+
+            sectors += request.count;
+            batch.push(request);
+        }
+
+        batch
+    }
+
+    /// Hand queued requests to `device`, and complete each request once the transfer it is part
+    /// of has finished. This returns without waiting if the driver finishes transfers in the
+    /// background, so callers run the queue again until their request completes.
+    pub fn run(&self, device: &BlockDevice) {
+        let sector_size = device.sector_size();
+
+        loop {
+            let mut in_flight = self.in_flight.lock();
+
+            if let Some(transfer) = in_flight.take() {
+                match transfer.completion.take() {
+                    Some(result) => finish(transfer.batch, result, sector_size),
+                    None => {
+                        *in_flight = Some(transfer);
+                        return;
+                    }
+                }
+            }
+
+            let batch = self.next_batch();
+
+            let (kind, lba) = match batch.first() {
+                Some(first) => (first.kind, first.lba),
+                None => return,
+            };
+
+            let sectors: usize = batch.iter().map(|r| r.count).sum();
+
+            let buffer = match kind {
+                RequestKind::Read => vec![0; sectors * sector_size],
+                RequestKind::Write => {
+                    let mut buffer = Vec::with_capacity(sectors * sector_size);
+                    for request in batch.iter() {
+                        buffer.extend_from_slice(&request.data);
+                    }
+                    buffer
+                }
+            };
+
+            let completion = Arc::new(Completion::new());
+            *in_flight = Some(InFlight {
+                completion: completion.clone(),
+                batch: batch,
+            });
+
+            // The driver may complete the transfer before this returns, in which case the next
+            // time round the loop finishes it.
+            device.start(kind, lba, buffer, completion);
+        }
+    }
+}
+
+/// Complete every request of a merged transfer, handing each its part of the data read.
+fn finish(batch: Vec<Request>, result: Result<Vec<u8>, &'static str>, sector_size: usize) {
+    match result {
+        Ok(buffer) => {
+            let mut offset = 0;
+
+            for request in batch {
+                let data = match request.kind {
+                    RequestKind::Read => {
+                        let length = request.count * sector_size;
+                        offset += length;
+                        buffer
+                            .get(offset - length..offset)
+                            .map(|data| data.to_vec())
+                            .ok_or("Driver returned a short read")
+                    }
+                    RequestKind::Write => Ok(Vec::new()),
+                };
+
+                request.completion.complete(data);
+            }
+        }
+        Err(e) => for request in batch {
+            request.completion.complete(Err(e));
+        },
+    }
+}
@@ -0,0 +1,64 @@
+//! A block device backed by kernel memory.
+
+use alloc::Vec;
+use spin::Mutex;
+use super::BlockDevice;
+
+pub struct RamDisk {
+    data: Mutex<Vec<u8>>,
+    sector_size: usize,
+}
+
+impl RamDisk {
+    /// Create a zeroed ramdisk of `sectors` sectors.
+    pub fn new(sectors: usize, sector_size: usize) -> Self {
+        RamDisk {
+            data: Mutex::new(vec![0; sectors * sector_size]),
+            sector_size: sector_size,
+        }
+    }
+
+    /// Create a ramdisk holding a copy of `image`, padded to a whole number of sectors.
+    pub fn from_image(image: &[u8], sector_size: usize) -> Self {
+        let mut data = image.to_vec();
+        let padded = (data.len() + sector_size - 1) / sector_size * sector_size;
+        data.resize(padded, 0);
+
+        RamDisk {
+            data: Mutex::new(data),
+            sector_size: sector_size,
+        }
+    }
+
+    fn range(&self, lba: u64, len: usize) -> Result<(usize, usize), &'static str> {
+        let start = lba as usize * self.sector_size;
+
+        if len % self.sector_size != 0 || start + len > self.data.lock().len() {
+            return Err("Request goes past the end of the ramdisk");
+        }
+
+        Ok((start, start + len))
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+
+    fn sector_count(&self) -> u64 {
+        (self.data.lock().len() / self.sector_size) as u64
+    }
+
+    fn read(&self, lba: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let (start, end) = self.range(lba, buffer.len())?;
+        buffer.copy_from_slice(&self.data.lock()[start..end]);
+        Ok(buffer.len())
+    }
+
+    fn write(&self, lba: u64, buffer: &[u8]) -> Result<usize, &'static str> {
+        let (start, end) = self.range(lba, buffer.len())?;
+        self.data.lock()[start..end].copy_from_slice(buffer);
+        Ok(buffer.len())
+    }
+}
@@ -0,0 +1,285 @@
+//! The input core. Drivers produce events, such as keys from the PS/2 keyboard, motion and buttons
+//! from the mouse and characters from the serial console. Each event is timestamped and copied
+//! into every consumer queue which wants events from its source, so the TTYs, `/dev/ttyS0` and
+//! `/dev/input` each see the events they care about without taking them from one another.
+//!
+//! Each event reads from `/dev/input` as a 16 byte record: a 64 bit timestamp in microseconds
+//! since boot, a 16 bit type, a 16 bit code and a 32 bit signed value, all little endian. A sync
+//! event follows each group of events which happened together, such as the motion and buttons of
+//! one mouse packet.
+
+use alloc::arc::Arc;
+use alloc::{Vec, VecDeque};
+use arch::interrupts::{bottom_half, disable_interrupts_and_then};
+use device::keyboard::KeyEvent;
+use device::pit;
+use fs::{self, devfs};
+use spin::Mutex;
+use task;
+
+/// The size of an event read from `/dev/input`.
+pub const EVENT_SIZE: usize = 16;
+
+/// The number of events kept for a consumer which isn't reading. Past this the oldest are
+/// dropped.
+const MAX_QUEUED_EVENTS: usize = 256;
+
+/// Event types, as read from `/dev/input`.
+pub const TYPE_SYNC: u16 = 0;
+pub const TYPE_BUTTON: u16 = 1;
+pub const TYPE_RELATIVE: u16 = 2;
+pub const TYPE_KEY: u16 = 3;
+pub const TYPE_CHAR: u16 = 4;
+
+bitflags! {
+    /// The devices events come from, which consumers choose between.
+    pub struct Sources: u8 {
+        const KEYBOARD = 1 << 0;
+        const MOUSE = 1 << 1;
+        const SERIAL = 1 << 2;
+    }
+}
+
+/// An axis of relative motion. Positive X is to the right, positive Y is down and a positive
+/// wheel is scrolled away from the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X = 0,
+    Y = 1,
+    Wheel = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Left = 0,
+    Right = 1,
+    Middle = 2,
+    Side = 3,
+    Extra = 4,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// A key was pressed or released. Its meaning depends on the layout and modifiers, which are
+    /// left to the consumer.
+    Key(KeyEvent),
+    /// A byte from a device which sends characters rather than keys, like a serial console.
+    Char(u8),
+    Relative(Axis, i32),
+    Button(Button, bool),
+    /// The end of a group of events.
+    Sync,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    /// Microseconds since boot.
+    pub time: u64,
+    pub source: Sources,
+    pub kind: EventKind,
+}
+
+impl InputEvent {
+    /// The event as read from `/dev/input`.
+    pub fn encode(&self) -> [u8; EVENT_SIZE] {
+        let (kind, code, value) = match self.kind {
+            EventKind::Key(event) => (TYPE_KEY, event.code() as u16, event.is_pressed() as i32),
+            EventKind::Char(byte) => (TYPE_CHAR, 0, byte as i32),
+            EventKind::Relative(axis, value) => (TYPE_RELATIVE, axis as u16, value),
+            EventKind::Button(button, pressed) => (TYPE_BUTTON, button as u16, pressed as i32),
+            EventKind::Sync => (TYPE_SYNC, 0, 0),
+        };
+
+        let mut bytes = [0; EVENT_SIZE];
+
+        for i in 0..8 {
+            bytes[i] = (self.time >> (i * 8)) as u8;
+        }
+
+        bytes[8] = kind as u8;
+        bytes[9] = (kind >> 8) as u8;
+        bytes[10] = code as u8;
+        bytes[11] = (code >> 8) as u8;
+
+        for i in 0..4 {
+            bytes[12 + i] = (value >> (i * 8)) as u8;
+        }
+
+        bytes
+    }
+}
+
+/// The events waiting for one consumer.
+struct Queue {
+    sources: Sources,
+    events: VecDeque<InputEvent>,
+    /// A bottom half to schedule when events arrive, for consumers which handle events as they
+    /// come rather than waiting to be read.
+    bottom_half: Option<usize>,
+}
+
+lazy_static! {
+    static ref QUEUES: Mutex<Vec<Option<Queue>>> = Mutex::new(Vec::new());
+}
+
+/// A consumer's handle on its queue. The queue is removed when this is dropped.
+pub struct Consumer {
+    id: usize,
+}
+
+impl Consumer {
+    /// Start queueing events from `sources`.
+    pub fn new(sources: Sources) -> Consumer {
+        Consumer::register(Queue {
+            sources: sources,
+            events: VecDeque::new(),
+            bottom_half: None,
+        })
+    }
+
+    /// Start queueing events from `sources`, scheduling the bottom half `id` whenever some
+    /// arrive.
+    pub fn with_bottom_half(sources: Sources, id: usize) -> Consumer {
+        Consumer::register(Queue {
+            sources: sources,
+            events: VecDeque::new(),
+            bottom_half: Some(id),
+        })
+    }
+
+    fn register(queue: Queue) -> Consumer {
+        disable_interrupts_and_then(|| {
+            let mut queues = QUEUES.lock();
+
+            let id = match queues.iter().position(|q| q.is_none()) {
+                Some(id) => id,
+                None => {
+                    queues.push(None);
+                    queues.len() - 1
+                }
+            };
+
+            queues[id] = Some(queue);
+            Consumer { id: id }
+        })
+    }
+
+    /// Take the oldest event from the queue.
+    pub fn pop(&self) -> Option<InputEvent> {
+        // Producers run in bottom halves, which any waiting task may run, so the lock is taken
+        // with interrupts disabled, or a task switched to while it is held would spin forever.
+        disable_interrupts_and_then(|| match QUEUES.lock()[self.id] {
+            Some(ref mut queue) => queue.events.pop_front(),
+            None => None,
+        })
+    }
+
+    /// Take the oldest event from the queue, blocking until there is one.
+    pub fn wait(&self) -> InputEvent {
+        loop {
+            if let Some(event) = self.pop() {
+                return event;
+            }
+
+            task::wait();
+        }
+    }
+}
+
+impl Drop for Consumer {
+    fn drop(&mut self) {
+        disable_interrupts_and_then(|| QUEUES.lock()[self.id] = None);
+    }
+}
+
+/// Timestamp an event and copy it to every consumer which wants events from `source`.
+pub fn push(source: Sources, kind: EventKind) {
+    let event = InputEvent {
+        time: pit::uptime_micros(),
+        source: source,
+        kind: kind,
+    };
+
+    disable_interrupts_and_then(|| {
+        for queue in QUEUES.lock().iter_mut() {
+            if let Some(ref mut queue) = *queue {
+                if !queue.sources.intersects(source) {
+                    continue;
+                }
+
+                if queue.events.len() >= MAX_QUEUED_EVENTS {
+                    queue.events.pop_front();
+                }
+
+                queue.events.push_back(event);
+
+                if let Some(id) = queue.bottom_half {
+                    bottom_half::schedule(id);
+                }
+            }
+        }
+    });
+}
+
+/// Read whole events into `buffer`, blocking until at least one is available. Returns the number
+/// of bytes read.
+fn read(consumer: &Consumer, buffer: &mut [u8]) -> fs::Result<usize> {
+    if buffer.len() < EVENT_SIZE {
+        return Err(fs::FsError::InvalidArgument);
+    }
+
+    buffer[..EVENT_SIZE].copy_from_slice(&consumer.wait().encode());
+    let mut count = EVENT_SIZE;
+
+    while count + EVENT_SIZE <= buffer.len() {
+        match consumer.pop() {
+            Some(event) => buffer[count..count + EVENT_SIZE].copy_from_slice(&event.encode()),
+            None => break,
+        }
+        count += EVENT_SIZE;
+    }
+
+    Ok(count)
+}
+
+/// A device node whose every open file gets its own queue of the events from `sources`, so that
+/// readers don't take events from one another.
+pub struct EventDevice {
+    pub sources: Sources,
+}
+
+impl devfs::CharDevice for EventDevice {
+    fn read(&self, _buffer: &mut [u8]) -> fs::Result<usize> {
+        Err(fs::FsError::BadFileDescriptor)
+    }
+
+    fn write(&self, _buffer: &[u8]) -> fs::Result<usize> {
+        Err(fs::FsError::NotSupported)
+    }
+
+    fn open(&self) -> fs::Result<Option<Arc<devfs::CharDevice>>> {
+        let file: Arc<devfs::CharDevice> = Arc::new(EventFile(Consumer::new(self.sources)));
+        Ok(Some(file))
+    }
+}
+
+/// An open `EventDevice`, which owns its queue until it is closed.
+struct EventFile(Consumer);
+
+impl devfs::CharDevice for EventFile {
+    fn read(&self, buffer: &mut [u8]) -> fs::Result<usize> {
+        read(&self.0, buffer)
+    }
+
+    fn write(&self, _buffer: &[u8]) -> fs::Result<usize> {
+        Err(fs::FsError::NotSupported)
+    }
+}
+
+pub fn init() {
+    let device = EventDevice {
+        sources: Sources::all(),
+    };
+
+    devfs::register_char("input", Arc::new(device)).expect("Could not register /dev/input");
+}
@@ -1,21 +1,179 @@
+//! Memory which devices access directly. Buffers are physically contiguous, can be constrained
+//! to an alignment and to the low 4 GiB for devices with 32-bit addressing, and are mapped
+//! uncached at a virtual address equal to their physical address.
+
+use alloc::Vec;
+use arch::memory::{self, Frame, PAGE_SIZE};
+use arch::memory::paging::{ActivePageTable, Page, PhysicalAddress, VirtualAddress};
+use arch::memory::paging::entry::EntryFlags;
 use core::{mem, ptr};
-use arch::memory;
+use core::ops::{Deref, DerefMut};
+
+/// The limit to pass for devices which can only address the low 4 GiB.
+pub const LIMIT_4GIB: usize = 0x1_0000_0000;
 
-// TODO: Add Drop impl.
-struct PhysBox {
+/// Physically contiguous memory, returned to the frame allocator when dropped.
+pub struct PhysBox {
     address: usize,
     size: usize,
 }
 
 impl PhysBox {
-    /// Allocate some physical memory and return the start address of the allocated frame.
-    fn new(size: usize) -> Result<Self, &'static str> {
-        let address = unsafe { memory::physalloc(size)? };
-        
+    /// Allocate at least `size` bytes of page aligned physical memory.
+    pub fn new(size: usize) -> Result<Self, &'static str> {
+        PhysBox::new_constrained(size, PAGE_SIZE, usize::max_value())
+    }
+
+    /// Allocate at least `size` bytes of physical memory, starting at a multiple of `align` and
+    /// ending below the physical address `limit`.
+    pub fn new_constrained(size: usize, align: usize, limit: usize) -> Result<Self, &'static str> {
+        let address = memory::physalloc(size, align, limit)?;
+
         Ok(PhysBox {
             address: address,
             size: size,
         })
+    }
+
+    /// The physical start address of the allocation.
+    pub fn address(&self) -> usize {
+        self.address
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl Drop for PhysBox {
+    fn drop(&mut self) {
+        memory::physfree(self.address, self.size);
+    }
+}
+
+fn pages(address: usize, size: usize) -> ::arch::memory::paging::PageIter {
+    let start_page = Page::containing_address(VirtualAddress::new(address));
+    let end_page = Page::containing_address(VirtualAddress::new(address + size - 1));
+
+    Page::range_inclusive(start_page, end_page)
+}
+
+/// Identity map a physical range as uncached. Pages which are already identity mapped are left
+/// alone, and the pages which were mapped here are returned, so that only those are unmapped.
+fn map(address: usize, size: usize) -> Result<Vec<Page>, &'static str> {
+    let mut active_table = unsafe { ActivePageTable::new() };
+    let mut mapped = Vec::new();
+
+    for page in pages(address, size) {
+        let frame = Frame::containing_address(PhysicalAddress::new(page.start_address().get()));
+
+        match active_table.translate_page(page) {
+            Some(ref existing) if *existing == frame => {}
+            Some(_) => {
+                unmap(&mapped);
+                return Err("DMA buffer overlaps an existing mapping");
+            }
+            None => {
+                let result = active_table.map_to(
+                    page,
+                    frame,
+                    EntryFlags::PRESENT | EntryFlags::WRITABLE | EntryFlags::NO_CACHE
+                        | EntryFlags::NO_EXECUTE,
+                );
+                result.flush(&mut active_table);
+                mapped.push(page);
+            }
+        }
+    }
+
+    Ok(mapped)
+}
+
+/// Remove mappings made by `map`.
+fn unmap(pages: &[Page]) {
+    let mut active_table = unsafe { ActivePageTable::new() };
+
+    for &page in pages {
+        let result = active_table.unmap(page);
+        result.flush(&mut active_table);
+    }
+}
+
+/// A value in DMA memory.
+pub struct Dma<T> {
+    phys: PhysBox,
+    virt: *mut T,
+    /// The pages mapped for this value, which were not mapped before.
+    mapped: Vec<Page>,
+}
+
+impl<T> Dma<T> {
+    /// Move `value` into page aligned DMA memory.
+    pub fn new(value: T) -> Result<Self, &'static str> {
+        Dma::with_constraints(value, PAGE_SIZE, usize::max_value())
+    }
+
+    /// Move `value` into DMA memory below 4 GiB.
+    pub fn new_32bit(value: T) -> Result<Self, &'static str> {
+        Dma::with_constraints(value, PAGE_SIZE, LIMIT_4GIB)
+    }
+
+    /// Move `value` into DMA memory aligned to `align` bytes and ending below `limit`.
+    pub fn with_constraints(value: T, align: usize, limit: usize) -> Result<Self, &'static str> {
+        unsafe {
+            let dma = Dma::allocate(align, limit)?;
+            ptr::write(dma.virt, value);
+            Ok(dma)
+        }
+    }
+
+    /// Allocate zeroed DMA memory aligned to `align` bytes and ending below `limit`. This avoids
+    /// building large values on the stack first.
+    ///
+    /// This is unsafe because all zeroes must be a valid `T`.
+    pub unsafe fn zeroed(align: usize, limit: usize) -> Result<Self, &'static str> {
+        let dma = Dma::allocate(align, limit)?;
+        ptr::write_bytes(dma.virt as *mut u8, 0, mem::size_of::<T>());
+        Ok(dma)
+    }
+
+    unsafe fn allocate(align: usize, limit: usize) -> Result<Self, &'static str> {
+        let phys = PhysBox::new_constrained(mem::size_of::<T>(), align, limit)?;
+        let mapped = map(phys.address(), phys.size())?;
+
+        Ok(Dma {
+            virt: phys.address() as *mut T,
+            phys: phys,
+            mapped: mapped,
+        })
+    }
 
+    /// The physical address of the value, for programming into a device.
+    pub fn phys_addr(&self) -> usize {
+        self.phys.address()
     }
 }
+
+impl<T> Deref for Dma<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.virt }
+    }
+}
+
+impl<T> DerefMut for Dma<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.virt }
+    }
+}
+
+impl<T> Drop for Dma<T> {
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place(self.virt) };
+        unmap(&self.mapped);
+    }
+}
+
+unsafe impl<T: Send> Send for Dma<T> {}
+unsafe impl<T: Sync> Sync for Dma<T> {}
@@ -1,4 +1,5 @@
 pub mod cpuio;
+pub mod dma;
 pub mod mmio;
 
 pub use self::cpuio::Port;
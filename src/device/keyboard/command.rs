@@ -0,0 +1,269 @@
+//! Commands sent to a PS/2 keyboard. During init, while the keyboard IRQ is off, commands are run
+//! by polling for each response. Afterwards the responses arrive through the interrupt handler,
+//! so commands wait in a queue and the bottom half sends each byte once the previous one has been
+//! acknowledged. A command the keyboard doesn't answer in time is dropped the next time the queue
+//! is looked at.
+
+use alloc::VecDeque;
+use arch::interrupts::disable_interrupts_and_then;
+use device::pit;
+use device::ps2_8042::{Ps2, ACK, PS2, RESEND, SELF_TEST_PASSED, SELF_TEST_TIMEOUT};
+use spin::Mutex;
+
+/// Command bytes.
+pub const SET_LEDS: u8 = 0xED;
+pub const SCANCODE_SET: u8 = 0xF0;
+pub const SET_TYPEMATIC: u8 = 0xF3;
+pub const ENABLE_SCANNING: u8 = 0xF4;
+pub const DISABLE_SCANNING: u8 = 0xF5;
+pub const RESET: u8 = 0xFF;
+
+/// Results of the self test the keyboard runs after a reset, besides `SELF_TEST_PASSED`.
+const SELF_TEST_FAILED: u8 = 0xFC;
+const SELF_TEST_FAILED_2: u8 = 0xFD;
+
+/// The number of times a byte is resent before the command is dropped.
+const RETRIES: usize = 3;
+
+/// How long the keyboard has to answer a byte before the command is dropped, in microseconds.
+const RESPONSE_TIMEOUT: u64 = 100_000;
+
+/// The number of commands which can wait in the queue. A keyboard which stops answering would
+/// otherwise stall the queue forever, so past this the oldest command is dropped.
+const MAX_QUEUED: usize = 8;
+
+bitflags! {
+    pub struct Leds: u8 {
+        const SCROLL_LOCK = 1 << 0;
+        const NUM_LOCK = 1 << 1;
+        const CAPS_LOCK = 1 << 2;
+    }
+}
+
+/// A command and its data byte, if it has one.
+#[derive(Debug, Clone, Copy)]
+pub struct Command {
+    bytes: [u8; 2],
+    length: usize,
+    /// The number of bytes which have been acknowledged.
+    acknowledged: usize,
+    retries: usize,
+    /// The uptime in microseconds when the command's last byte was sent.
+    sent_at: u64,
+}
+
+impl Command {
+    pub fn new(command: u8) -> Self {
+        Command {
+            bytes: [command, 0],
+            length: 1,
+            acknowledged: 0,
+            retries: 0,
+            sent_at: 0,
+        }
+    }
+
+    pub fn with_data(command: u8, data: u8) -> Self {
+        Command {
+            bytes: [command, data],
+            length: 2,
+            acknowledged: 0,
+            retries: 0,
+            sent_at: 0,
+        }
+    }
+
+    pub fn set_leds(leds: Leds) -> Self {
+        Command::with_data(SET_LEDS, leds.bits())
+    }
+
+    /// Set the repeat rate, from 0 (30 per second) to 31 (2 per second), and the delay before
+    /// repeating starts, from 0 (250ms) to 3 (1s).
+    pub fn set_typematic(rate: u8, delay: u8) -> Self {
+        Command::with_data(SET_TYPEMATIC, (delay & 0x3) << 5 | (rate & 0x1F))
+    }
+
+    pub fn set_scancode_set(set: u8) -> Self {
+        Command::with_data(SCANCODE_SET, set)
+    }
+
+    /// The command byte.
+    pub fn command(&self) -> u8 {
+        self.bytes[0]
+    }
+
+    /// The data byte, if the command has one.
+    pub fn data(&self) -> Option<u8> {
+        if self.length == 2 {
+            Some(self.bytes[1])
+        } else {
+            None
+        }
+    }
+
+    /// Whether every byte has been sent and acknowledged.
+    fn is_complete(&self) -> bool {
+        self.acknowledged == self.length
+    }
+
+    fn next_byte(&self) -> u8 {
+        self.bytes[self.acknowledged]
+    }
+
+    /// Whether the keyboard has taken too long to answer. A reset which has been acknowledged
+    /// waits for its self test, which takes far longer.
+    fn has_timed_out(&self, now: u64) -> bool {
+        let timeout = if self.is_complete() {
+            SELF_TEST_TIMEOUT
+        } else {
+            RESPONSE_TIMEOUT
+        };
+
+        now.saturating_sub(self.sent_at) > timeout
+    }
+
+    /// Run the command by polling for each response. This must only be used while the keyboard
+    /// IRQ can't fire, or the interrupt handler will take the responses.
+    pub fn run_polled(&self, ps2: &mut Ps2) -> Result<(), &'static str> {
+        for &byte in self.bytes[..self.length].iter() {
+            ps2.send_to_device(byte)?;
+        }
+
+        if self.bytes[0] == RESET {
+            match ps2.wait_then_read_for(SELF_TEST_TIMEOUT)? {
+                SELF_TEST_PASSED => (),
+                _ => return Err("Keyboard self test failed"),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+lazy_static! {
+    /// Commands waiting to be sent. The first has been sent and is waiting for a response.
+    static ref QUEUE: Mutex<VecDeque<Command>> = Mutex::new(VecDeque::new());
+}
+
+/// What a byte from the keyboard turned out to be.
+pub enum Response {
+    /// Not a response to a command, so it is a scancode.
+    Scancode,
+    /// A response which leaves the command at the front of the queue running, or a stray one.
+    Consumed,
+    /// The response which finished this command.
+    Finished(Command),
+}
+
+/// Send the next unacknowledged byte of the command at the front of the queue.
+fn send_front(queue: &mut VecDeque<Command>) {
+    if let Some(command) = queue.front_mut() {
+        command.sent_at = pit::uptime_micros();
+
+        if let Err(e) = PS2.lock().wait_then_write(command.next_byte()) {
+            println!("[ dev ] Could not send a keyboard command: {}", e);
+        }
+    }
+}
+
+/// Drop the command at the front of the queue if the keyboard hasn't answered it in time, and
+/// send the next one.
+fn expire(queue: &mut VecDeque<Command>) {
+    let now = pit::uptime_micros();
+
+    while queue.front().map_or(false, |c| c.has_timed_out(now)) {
+        println!("[ dev ] Keyboard didn't answer a command, dropping it");
+        queue.pop_front();
+        send_front(queue);
+    }
+}
+
+/// Queue a command to be sent once those before it have been acknowledged.
+pub fn queue(command: Command) {
+    disable_interrupts_and_then(|| {
+        let mut queue = QUEUE.lock();
+        expire(&mut queue);
+
+        if queue.len() >= MAX_QUEUED {
+            println!("[ dev ] Keyboard isn't answering commands, dropping one");
+            queue.pop_front();
+            queue.push_back(command);
+            send_front(&mut queue);
+        } else {
+            queue.push_back(command);
+
+            if queue.len() == 1 {
+                send_front(&mut queue);
+            }
+        }
+    });
+}
+
+/// Handle a byte from the keyboard if it is a response to a queued command.
+pub fn handle_response(byte: u8) -> Response {
+    disable_interrupts_and_then(|| {
+        let mut queue = QUEUE.lock();
+        expire(&mut queue);
+
+        let waiting_for_self_test = match queue.front() {
+            Some(command) => command.is_complete(),
+            None => false,
+        };
+
+        match byte {
+            // A reset which has been acknowledged has nothing left to resend.
+            ACK | RESEND if waiting_for_self_test => Response::Consumed,
+            ACK => {
+                let finished = match queue.front_mut() {
+                    Some(command) => {
+                        command.acknowledged += 1;
+                        command.retries = 0;
+                        command.is_complete() && command.bytes[0] != RESET
+                    }
+                    // Acknowledgements never mean anything else, so a stray one is dropped.
+                    None => return Response::Consumed,
+                };
+
+                let finished = if finished {
+                    queue.pop_front()
+                } else {
+                    None
+                };
+
+                // A reset which has been acknowledged waits for its self test result instead.
+                if queue.front().map_or(false, |c| !c.is_complete()) {
+                    send_front(&mut queue);
+                }
+
+                finished.map_or(Response::Consumed, Response::Finished)
+            }
+            RESEND => {
+                let give_up = match queue.front_mut() {
+                    Some(command) => {
+                        command.retries += 1;
+                        command.retries > RETRIES
+                    }
+                    None => return Response::Consumed,
+                };
+
+                if give_up {
+                    println!("[ dev ] Keyboard kept asking for a resend, dropping a command");
+                    queue.pop_front();
+                }
+
+                send_front(&mut queue);
+                Response::Consumed
+            }
+            SELF_TEST_PASSED | SELF_TEST_FAILED | SELF_TEST_FAILED_2 if waiting_for_self_test => {
+                if byte != SELF_TEST_PASSED {
+                    println!("[ dev ] Keyboard self test failed");
+                }
+
+                let finished = queue.pop_front();
+                send_front(&mut queue);
+                finished.map_or(Response::Consumed, Response::Finished)
+            }
+            _ => Response::Scancode,
+        }
+    })
+}
@@ -1,120 +1,451 @@
-use device::keyboard::ps2_keyboard::{Key, KeyEvent};
-use device::keyboard::ps2_keyboard::Key::*;
-use device::keyboard::ps2_keyboard::Modifiers::*;
+use core::mem;
+use device::keyboard::keycode::{KeyCode, KeyEvent};
+use device::keyboard::keycode::KeyCode::*;
+use self::Key::*;
 
-macro_rules! key_press {
-    ($x:expr) => (Some(KeyEvent::Pressed($x)))
+/// Possible modifications to state we could have.
+pub enum Modifiers {
+    AltLeft(bool),
+    AltRight(bool),
+    CapsLock,
+    ControlLeft(bool),
+    ControlRight(bool),
+    NumLock,
+    ScrollLock,
+    ShiftLeft(bool),
+    ShiftRight(bool),
+    /// Function keys, the usize represents the index
+    /// of the key, from 0 for F1.
+    FunctionKeys(usize),
 }
 
-macro_rules! key_release {
-    ($x:expr) => (Some(KeyEvent::Released($x)))
+/// Possible types of keyboard input we might receive.
+pub enum Key {
+    Ascii(u8),
+    Meta(Modifiers),
+    /// A key whose character depends on the layout.
+    Layout(KeyCode),
+    /// A keypad key. The digits and the period only produce input while num lock is on.
+    Keypad(u8),
 }
 
-/// Gets a key from a given keyboard event.
-pub fn get_key(scancode: u64) -> Option<Key> {
-    match get_key_event(scancode) {
-        Some(KeyEvent::Pressed(key)) => Some(key),
-        Some(KeyEvent::Released(key)) => Some(key),
-        _ => None,
-    }
+/// The scancode set the keyboard sends. With translation enabled the controller turns set 2 into
+/// set 1, which is the default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSet {
+    Set1,
+    Set2,
 }
 
-/// Calls `match_scancode` and is then matched on itself to retrieve a `Key` based on the returned
-/// `KeyEvent`.
-pub fn get_key_event(scancode: u64) -> Option<KeyEvent> {
-    match_scancode(scancode)
+/// Turns the bytes of a scancode set into key events, holding the state of multi byte sequences
+/// between bytes.
+pub struct Decoder {
+    set: ScancodeSet,
+    /// An `0xE0` prefix has been received.
+    extended: bool,
+    /// An `0xF0` prefix has been received, in set 2.
+    released: bool,
+    /// The number of bytes left of the Pause sequence.
+    pause_remaining: usize,
 }
 
-/// Special keys that are part of a byte sequence.
-pub fn is_special_key(byte: u8) -> Option<u8> {
-    match byte {
-        0x5B | 0xDB => Some(byte),
-        0x1D | 0x9D => Some(byte),
-        0x5C | 0xDC => Some(byte),
-        0x38 | 0xB8 => Some(byte),
-        0x5D | 0xDD => Some(byte),
-        0x52 | 0xD2 => Some(byte),
-        0x47 | 0x97 => Some(byte),
-        0x49 | 0xC9 => Some(byte),
-        0x53 | 0xD3 => Some(byte),
-        0x4F | 0xCF => Some(byte),
-        0x51 | 0xD1 => Some(byte),
-        0x48 | 0xC8 => Some(byte),
-        0x4B | 0xCB => Some(byte),
-        0x50 | 0xD0 => Some(byte),
-        0x4D | 0xCD => Some(byte),
-        0x35 | 0xB5 => Some(byte),
-        0x1C | 0x9C => Some(byte),
-        _ => None,
+impl Decoder {
+    pub const fn new(set: ScancodeSet) -> Self {
+        Decoder {
+            set: set,
+            extended: false,
+            released: false,
+            pause_remaining: 0,
+        }
+    }
+
+    pub fn set(&self) -> ScancodeSet {
+        self.set
+    }
+
+    /// Switch scancode set, dropping any partial sequence.
+    pub fn set_set(&mut self, set: ScancodeSet) {
+        *self = Decoder::new(set);
+    }
+
+    /// Decode one byte, returning an event if it completes a sequence.
+    pub fn feed(&mut self, byte: u8) -> Option<KeyEvent> {
+        // Pause sends a fixed sequence when pressed and nothing when released, so it is reported
+        // only as a press once the whole sequence has arrived.
+        if self.pause_remaining > 0 {
+            self.pause_remaining -= 1;
+
+            return if self.pause_remaining == 0 {
+                Some(KeyEvent::Pressed(Pause))
+            } else {
+                None
+            };
+        }
+
+        match self.set {
+            ScancodeSet::Set1 => self.feed_set1(byte),
+            ScancodeSet::Set2 => self.feed_set2(byte),
+        }
+    }
+
+    fn feed_set1(&mut self, byte: u8) -> Option<KeyEvent> {
+        match byte {
+            0xE0 => {
+                self.extended = true;
+                None
+            }
+            // E1 1D 45 E1 9D C5
+            0xE1 => {
+                self.pause_remaining = 5;
+                None
+            }
+            _ => {
+                let extended = mem::replace(&mut self.extended, false);
+                let code = if extended {
+                    set1_extended_key(byte & 0x7F)
+                } else {
+                    set1_key(byte & 0x7F)
+                };
+
+                // The top bit marks a break code.
+                code.map(|code| if byte & 0x80 != 0 {
+                    KeyEvent::Released(code)
+                } else {
+                    KeyEvent::Pressed(code)
+                })
+            }
+        }
+    }
+
+    fn feed_set2(&mut self, byte: u8) -> Option<KeyEvent> {
+        match byte {
+            0xE0 => {
+                self.extended = true;
+                None
+            }
+            0xF0 => {
+                self.released = true;
+                None
+            }
+            // E1 14 77 E1 F0 14 F0 77
+            0xE1 => {
+                self.pause_remaining = 7;
+                None
+            }
+            _ => {
+                let extended = mem::replace(&mut self.extended, false);
+                let released = mem::replace(&mut self.released, false);
+                let code = if extended {
+                    set2_extended_key(byte)
+                } else {
+                    set2_key(byte)
+                };
+
+                code.map(|code| if released {
+                    KeyEvent::Released(code)
+                } else {
+                    KeyEvent::Pressed(code)
+                })
+            }
+        }
     }
 }
 
-/// Use range matching to convert our passed scancode to some type of ASCII or to update modifiers,
-/// and return a key-event based on whether this was a key press/release (only relevant for
-/// modifiers).
-fn match_scancode(scancode: u64) -> Option<KeyEvent> {
-    let idx = scancode as usize;
-    match scancode {
-        // ASCII Keys by keyboard row.
-        0x02...0x0D => key_press!(LowerAscii(b"1234567890-="[idx - 0x02])),
-        0x10...0x1B => key_press!(LowerAscii(b"qwertyuiop[]"[idx - 0x10])),
-        0x1E...0x28 => key_press!(LowerAscii(b"asdfghjkl;'"[idx - 0x1E])),
-        0x2C...0x35 => key_press!(LowerAscii(b"zxcvbnm,./"[idx - 0x2C])),
-        0x29 => key_press!(LowerAscii(b'`')),
-        0x2B => key_press!(LowerAscii(b'\\')),
+/// Set 1 make codes without a prefix.
+fn set1_key(code: u8) -> Option<KeyCode> {
+    let key = match code {
+        0x01 => Escape,
+        0x02 => Num1,
+        0x03 => Num2,
+        0x04 => Num3,
+        0x05 => Num4,
+        0x06 => Num5,
+        0x07 => Num6,
+        0x08 => Num7,
+        0x09 => Num8,
+        0x0A => Num9,
+        0x0B => Num0,
+        0x0C => Minus,
+        0x0D => Equals,
+        0x0E => Backspace,
+        0x0F => Tab,
+        0x10 => Q,
+        0x11 => W,
+        0x12 => E,
+        0x13 => R,
+        0x14 => T,
+        0x15 => Y,
+        0x16 => U,
+        0x17 => I,
+        0x18 => O,
+        0x19 => P,
+        0x1A => LeftBracket,
+        0x1B => RightBracket,
+        0x1C => Enter,
+        0x1D => LeftControl,
+        0x1E => A,
+        0x1F => S,
+        0x20 => D,
+        0x21 => F,
+        0x22 => G,
+        0x23 => H,
+        0x24 => J,
+        0x25 => K,
+        0x26 => L,
+        0x27 => Semicolon,
+        0x28 => Apostrophe,
+        0x29 => Grave,
+        0x2A => LeftShift,
+        0x2B => Backslash,
+        0x2C => Z,
+        0x2D => X,
+        0x2E => C,
+        0x2F => V,
+        0x30 => B,
+        0x31 => N,
+        0x32 => M,
+        0x33 => Comma,
+        0x34 => Period,
+        0x35 => Slash,
+        0x36 => RightShift,
+        0x37 => KeypadMultiply,
+        0x38 => LeftAlt,
+        0x39 => Space,
+        0x3A => CapsLock,
+        0x3B => F1,
+        0x3C => F2,
+        0x3D => F3,
+        0x3E => F4,
+        0x3F => F5,
+        0x40 => F6,
+        0x41 => F7,
+        0x42 => F8,
+        0x43 => F9,
+        0x44 => F10,
+        0x45 => NumLock,
+        0x46 => ScrollLock,
+        0x47 => Keypad7,
+        0x48 => Keypad8,
+        0x49 => Keypad9,
+        0x4A => KeypadMinus,
+        0x4B => Keypad4,
+        0x4C => Keypad5,
+        0x4D => Keypad6,
+        0x4E => KeypadPlus,
+        0x4F => Keypad1,
+        0x50 => Keypad2,
+        0x51 => Keypad3,
+        0x52 => Keypad0,
+        0x53 => KeypadPeriod,
+        0x54 => SysRq,
+        0x56 => NonUsBackslash,
+        0x57 => F11,
+        0x58 => F12,
+        _ => return None,
+    };
 
-        // Non-modifiable ASCII keys
-        0x01 => key_press!(Ascii(0x1B)),  // escape
-        0x0E => key_press!(Ascii(0x8)),   // backspace
-        0x0F => key_press!(Ascii(b'\t')), // tab
-        0x1C => key_press!(Ascii(b'\n')), // newline
-        0x39 => key_press!(Ascii(b' ')),  // space
+    Some(key)
+}
 
+/// Set 1 make codes after an `0xE0` prefix. The fake shifts sent around some of these keys are
+/// not listed, so they are ignored.
+fn set1_extended_key(code: u8) -> Option<KeyCode> {
+    let key = match code {
+        0x1C => KeypadEnter,
+        0x1D => RightControl,
+        0x35 => KeypadDivide,
+        0x37 => PrintScreen,
+        0x38 => RightAlt,
+        // Control and Pause together send this instead of the Pause sequence.
+        0x46 => Pause,
+        0x47 => Home,
+        0x48 => Up,
+        0x49 => PageUp,
+        0x4B => Left,
+        0x4D => Right,
+        0x4F => End,
+        0x50 => Down,
+        0x51 => PageDown,
+        0x52 => Insert,
+        0x53 => Delete,
+        0x5B => LeftSuper,
+        0x5C => RightSuper,
+        0x5D => Menu,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+/// Set 2 make codes without a prefix.
+fn set2_key(code: u8) -> Option<KeyCode> {
+    let key = match code {
+        0x01 => F9,
+        0x03 => F5,
+        0x04 => F3,
+        0x05 => F1,
+        0x06 => F2,
+        0x07 => F12,
+        0x09 => F10,
+        0x0A => F8,
+        0x0B => F6,
+        0x0C => F4,
+        0x0D => Tab,
+        0x0E => Grave,
+        0x11 => LeftAlt,
+        0x12 => LeftShift,
+        0x14 => LeftControl,
+        0x15 => Q,
+        0x16 => Num1,
+        0x1A => Z,
+        0x1B => S,
+        0x1C => A,
+        0x1D => W,
+        0x1E => Num2,
+        0x21 => C,
+        0x22 => X,
+        0x23 => D,
+        0x24 => E,
+        0x25 => Num4,
+        0x26 => Num3,
+        0x29 => Space,
+        0x2A => V,
+        0x2B => F,
+        0x2C => T,
+        0x2D => R,
+        0x2E => Num5,
+        0x31 => N,
+        0x32 => B,
+        0x33 => H,
+        0x34 => G,
+        0x35 => Y,
+        0x36 => Num6,
+        0x3A => M,
+        0x3B => J,
+        0x3C => U,
+        0x3D => Num7,
+        0x3E => Num8,
+        0x41 => Comma,
+        0x42 => K,
+        0x43 => I,
+        0x44 => O,
+        0x45 => Num0,
+        0x46 => Num9,
+        0x49 => Period,
+        0x4A => Slash,
+        0x4B => L,
+        0x4C => Semicolon,
+        0x4D => P,
+        0x4E => Minus,
+        0x52 => Apostrophe,
+        0x54 => LeftBracket,
+        0x55 => Equals,
+        0x58 => CapsLock,
+        0x59 => RightShift,
+        0x5A => Enter,
+        0x5B => RightBracket,
+        0x5D => Backslash,
+        0x61 => NonUsBackslash,
+        0x66 => Backspace,
+        0x69 => Keypad1,
+        0x6B => Keypad4,
+        0x6C => Keypad7,
+        0x70 => Keypad0,
+        0x71 => KeypadPeriod,
+        0x72 => Keypad2,
+        0x73 => Keypad5,
+        0x74 => Keypad6,
+        0x75 => Keypad8,
+        0x76 => Escape,
+        0x77 => NumLock,
+        0x78 => F11,
+        0x79 => KeypadPlus,
+        0x7A => Keypad3,
+        0x7B => KeypadMinus,
+        0x7C => KeypadMultiply,
+        0x7D => Keypad9,
+        0x7E => ScrollLock,
+        0x83 => F7,
+        0x84 => SysRq,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+/// Set 2 make codes after an `0xE0` prefix.
+fn set2_extended_key(code: u8) -> Option<KeyCode> {
+    let key = match code {
+        0x11 => RightAlt,
+        0x14 => RightControl,
+        0x1F => LeftSuper,
+        0x27 => RightSuper,
+        0x2F => Menu,
+        0x4A => KeypadDivide,
+        0x5A => KeypadEnter,
+        0x69 => End,
+        0x6B => Left,
+        0x6C => Home,
+        0x70 => Insert,
+        0x71 => Delete,
+        0x72 => Down,
+        0x74 => Right,
+        0x75 => Up,
+        0x7A => PageDown,
+        0x7C => PrintScreen,
+        0x7D => PageUp,
+        0x7E => Pause,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+/// Gets a key from a given keyboard event. Releases only matter for modifiers, so other keys
+/// only produce a key when pressed.
+pub fn get_key(event: KeyEvent) -> Option<Key> {
+    let pressed = event.is_pressed();
+
+    let key = match event.code() {
         // Meta keys
-        0x1D => key_press!(Meta(ControlLeft(true))),
-        0xE01D => key_press!(Meta(ControlRight(true))),
-        0x2A => key_press!(Meta(ShiftLeft(true))),
-        0x36 => key_press!(Meta(ShiftRight(true))),
-        0x38 => key_press!(Meta(AltLeft(true))),
-        0xE038 => key_press!(Meta(AltRight(false))),
-        0x3A => key_press!(Meta(CapsLock)),
-        0x45 => key_press!(Meta(NumLock)),
-        0x46 => key_press!(Meta(ScrollLock)),
-        // F1 .. F10
-        0x3B => key_press!(Meta(FunctionKeys(0))),
-        0x3C => key_press!(Meta(FunctionKeys(1))),
-        0x3D => key_press!(Meta(FunctionKeys(2))),
-        0x3E => key_press!(Meta(FunctionKeys(3))),
-        0x3F => key_press!(Meta(FunctionKeys(4))),
-        0x40 => key_press!(Meta(FunctionKeys(5))),
-        0x41 => key_press!(Meta(FunctionKeys(6))),
-        0x42 => key_press!(Meta(FunctionKeys(7))),
-        0x43 => key_press!(Meta(FunctionKeys(8))),
-        0x44 => key_press!(Meta(FunctionKeys(9))),
-        // F11, F12
-        0x57 => key_press!(Meta(FunctionKeys(10))),
-        0x58 => key_press!(Meta(FunctionKeys(11))),
-
-        0xAA => key_release!(Meta(ShiftLeft(false))),
-        0xB6 => key_release!(Meta(ShiftRight(false))),
-        0x9D => key_release!(Meta(ControlLeft(false))),
-        0xE09D => key_release!(Meta(ControlRight(false))),
-        0xB8 => key_release!(Meta(AltLeft(false))),
-        0xE0B8 => key_release!(Meta(AltRight(false))),
-        // F1 .. F10
-        0xBB => key_release!(Meta(FunctionKeys(0))),
-        0xBC => key_release!(Meta(FunctionKeys(1))),
-        0xBD => key_release!(Meta(FunctionKeys(2))),
-        0xBE => key_release!(Meta(FunctionKeys(3))),
-        0xBF => key_release!(Meta(FunctionKeys(4))),
-        0xC0 => key_release!(Meta(FunctionKeys(5))),
-        0xC1 => key_release!(Meta(FunctionKeys(6))),
-        0xC2 => key_release!(Meta(FunctionKeys(7))),
-        0xC3 => key_release!(Meta(FunctionKeys(8))),
-        0xC4 => key_release!(Meta(FunctionKeys(9))),
-        0xD7 => key_release!(Meta(FunctionKeys(10))),
-        0xD8 => key_release!(Meta(FunctionKeys(11))),
-
-        _ => None,
-    }
+        LeftShift => Meta(Modifiers::ShiftLeft(pressed)),
+        RightShift => Meta(Modifiers::ShiftRight(pressed)),
+        LeftControl => Meta(Modifiers::ControlLeft(pressed)),
+        RightControl => Meta(Modifiers::ControlRight(pressed)),
+        LeftAlt => Meta(Modifiers::AltLeft(pressed)),
+        RightAlt => Meta(Modifiers::AltRight(pressed)),
+        _ if !pressed => return None,
+        CapsLock => Meta(Modifiers::CapsLock),
+        NumLock => Meta(Modifiers::NumLock),
+        ScrollLock => Meta(Modifiers::ScrollLock),
+        code if code.function_index().is_some() => Meta(Modifiers::FunctionKeys(code.function_index().unwrap())),
+
+        // Non-modifiable ASCII keys
+        Escape => Ascii(0x1B),
+        Backspace => Ascii(0x8),
+        Tab => Ascii(b'\t'),
+        Enter | KeypadEnter => Ascii(b'\n'),
+        Space => Ascii(b' '),
+
+        // The keypad
+        Keypad0 => Keypad(b'0'),
+        Keypad1 => Keypad(b'1'),
+        Keypad2 => Keypad(b'2'),
+        Keypad3 => Keypad(b'3'),
+        Keypad4 => Keypad(b'4'),
+        Keypad5 => Keypad(b'5'),
+        Keypad6 => Keypad(b'6'),
+        Keypad7 => Keypad(b'7'),
+        Keypad8 => Keypad(b'8'),
+        Keypad9 => Keypad(b'9'),
+        KeypadPeriod => Keypad(b'.'),
+        KeypadPlus => Keypad(b'+'),
+        KeypadMinus => Keypad(b'-'),
+        KeypadMultiply => Keypad(b'*'),
+        KeypadDivide => Keypad(b'/'),
+
+        code => Layout(code),
+    };
+
+    Some(key)
 }
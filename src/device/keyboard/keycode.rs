@@ -0,0 +1,146 @@
+//! Layout independent key codes, naming the physical position of a key on a US keyboard rather
+//! than the character it produces. A layout turns these into characters.
+
+/// A physical key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyCode {
+    Escape,
+    Num1,
+    Num2,
+    Num3,
+    Num4,
+    Num5,
+    Num6,
+    Num7,
+    Num8,
+    Num9,
+    Num0,
+    Minus,
+    Equals,
+    Backspace,
+    Tab,
+    Q,
+    W,
+    E,
+    R,
+    T,
+    Y,
+    U,
+    I,
+    O,
+    P,
+    LeftBracket,
+    RightBracket,
+    Enter,
+    LeftControl,
+    A,
+    S,
+    D,
+    F,
+    G,
+    H,
+    J,
+    K,
+    L,
+    Semicolon,
+    Apostrophe,
+    Grave,
+    LeftShift,
+    Backslash,
+    Z,
+    X,
+    C,
+    V,
+    B,
+    N,
+    M,
+    Comma,
+    Period,
+    Slash,
+    RightShift,
+    LeftAlt,
+    Space,
+    CapsLock,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    NumLock,
+    ScrollLock,
+    /// The key between left shift and Z, which US keyboards don't have.
+    NonUsBackslash,
+    Keypad0,
+    Keypad1,
+    Keypad2,
+    Keypad3,
+    Keypad4,
+    Keypad5,
+    Keypad6,
+    Keypad7,
+    Keypad8,
+    Keypad9,
+    KeypadPeriod,
+    KeypadPlus,
+    KeypadMinus,
+    KeypadMultiply,
+    KeypadDivide,
+    KeypadEnter,
+    RightControl,
+    RightAlt,
+    LeftSuper,
+    RightSuper,
+    Menu,
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
+    Left,
+    Right,
+    PrintScreen,
+    SysRq,
+    Pause,
+}
+
+impl KeyCode {
+    /// The index of a function key, from 0 for F1.
+    pub fn function_index(&self) -> Option<usize> {
+        use self::KeyCode::*;
+
+        let keys = [F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12];
+        keys.iter().position(|k| k == self)
+    }
+}
+
+/// A key is pressed or released, and a decoder reports each as a separate event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEvent {
+    Pressed(KeyCode),
+    Released(KeyCode),
+}
+
+impl KeyEvent {
+    pub fn code(&self) -> KeyCode {
+        match *self {
+            KeyEvent::Pressed(code) | KeyEvent::Released(code) => code,
+        }
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        match *self {
+            KeyEvent::Pressed(_) => true,
+            KeyEvent::Released(_) => false,
+        }
+    }
+}
@@ -0,0 +1,23 @@
+//! The German layout.
+
+use device::keyboard::KeyCode::*;
+use super::Layout;
+
+pub static LAYOUT: Layout = Layout {
+    name: "de",
+    base: "^1234567890ß´qwertzuiopü+asdfghjklöä#<yxcvbnm,.-",
+    shift: "°!\"\0$%&/()=?`QWERTZUIOPÜ*ASDFGHJKLÖÄ'>YXCVBNM;:_",
+    altgr: &[
+        (Num2, '²', '\0'),
+        (Num7, '{', '\0'),
+        (Num8, '[', '\0'),
+        (Num9, ']', '\0'),
+        (Num0, '}', '\0'),
+        (Minus, '\\', '\0'),
+        (Q, '@', '\0'),
+        (RightBracket, '~', '\0'),
+        (NonUsBackslash, '|', '\0'),
+        (M, 'µ', '\0'),
+    ],
+    dead: &['^', '´', '`'],
+};
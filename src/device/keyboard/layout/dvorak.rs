@@ -0,0 +1,11 @@
+//! The US Dvorak layout.
+
+use super::Layout;
+
+pub static LAYOUT: Layout = Layout {
+    name: "dvorak",
+    base: "`1234567890[]',.pyfgcrl/=aoeuidhtns-\\\\;qjkxbmwvz",
+    shift: "~!@#$%^&*(){}\"<>PYFGCRL?+AOEUIDHTNS_||:QJKXBMWVZ",
+    altgr: &[],
+    dead: &[],
+};
@@ -0,0 +1,24 @@
+//! The French AZERTY layout.
+
+use device::keyboard::KeyCode::*;
+use super::Layout;
+
+pub static LAYOUT: Layout = Layout {
+    name: "fr",
+    base: "²&é\"'(-è_çà)=azertyuiop^$qsdfghjklmù*<wxcvbn,;:!",
+    shift: "\01234567890°+AZERTYUIOP¨£QSDFGHJKLM%µ>WXCVBN?./\0",
+    altgr: &[
+        (Num2, '~', '\0'),
+        (Num3, '#', '\0'),
+        (Num4, '{', '\0'),
+        (Num5, '[', '\0'),
+        (Num6, '|', '\0'),
+        (Num7, '`', '\0'),
+        (Num8, '\\', '\0'),
+        (Num9, '^', '\0'),
+        (Num0, '@', '\0'),
+        (Minus, ']', '\0'),
+        (Equals, '}', '\0'),
+    ],
+    dead: &['^', '¨'],
+};
@@ -1,9 +1,154 @@
-#[cfg(feature = "uk")]
-pub mod uk_std;
-#[cfg(feature = "us")]
-pub mod us_std;
-
-#[cfg(feature = "uk")]
-pub use self::uk_std::map_to_upper;
-#[cfg(feature = "us")]
-pub use self::us_std::map_to_upper;
+//! Keyboard layouts, which turn key codes into characters. A layout gives the character of each
+//! key at four levels: unshifted, with shift, with AltGr and with AltGr and shift. Some characters
+//! are dead keys, which type nothing themselves but put an accent on the next character.
+//!
+//! What is typed is encoded in code page 437, so layouts leave out the characters it doesn't have.
+//! The accents of dead keys are the exception, and type a stand in when typed on their own.
+//!
+//! The layout in use can be changed at runtime with `set_layout`, through the keyboard ioctls on
+//! a TTY, or at boot with the `keymap=` command line option.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use device::keyboard::KeyCode;
+use device::keyboard::KeyCode::*;
+
+pub mod de;
+pub mod dvorak;
+pub mod fr;
+pub mod uk;
+pub mod us;
+
+/// The keys whose characters a layout gives in `base` and `shift`, in order: the number row, then
+/// the three letter rows, from left to right.
+pub const KEYS: [KeyCode; 48] = [
+    Grave, Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9, Num0, Minus, Equals,
+    Q, W, E, R, T, Y, U, I, O, P, LeftBracket, RightBracket,
+    A, S, D, F, G, H, J, K, L, Semicolon, Apostrophe, Backslash,
+    NonUsBackslash, Z, X, C, V, B, N, M, Comma, Period, Slash,
+];
+
+/// A keyboard layout.
+pub struct Layout {
+    pub name: &'static str,
+    /// The character of each key in `KEYS`, unshifted. `\0` marks a key which types nothing.
+    pub base: &'static str,
+    /// The character of each key in `KEYS` with shift held.
+    pub shift: &'static str,
+    /// The keys which type something with AltGr held, and what they type with AltGr and with
+    /// AltGr and shift.
+    pub altgr: &'static [(KeyCode, char, char)],
+    /// The characters of `base` and `shift` which are dead keys in this layout. What a key types
+    /// with AltGr is never a dead key.
+    pub dead: &'static [char],
+}
+
+/// What a key types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symbol {
+    Char(char),
+    /// A dead key, holding its accent.
+    Dead(char),
+}
+
+impl Layout {
+    /// The character of `code` at a level, if it has one.
+    pub fn symbol(&self, code: KeyCode, shift: bool, altgr: bool, caps_lock: bool) -> Option<Symbol> {
+        if altgr {
+            let &(_, altgr, shift_altgr) = self.altgr.iter().find(|k| k.0 == code)?;
+
+            let character = if shift { shift_altgr } else { altgr };
+
+            return if character == '\0' {
+                None
+            } else {
+                Some(Symbol::Char(character))
+            };
+        }
+
+        let character = {
+            let index = KEYS.iter().position(|&k| k == code)?;
+            let base = self.base.chars().nth(index)?;
+            let shifted = self.shift.chars().nth(index)?;
+
+            // Caps lock only affects letters, the keys where shift types the capital, and on them
+            // it inverts shift.
+            let is_letter = base.is_lowercase() && base.to_uppercase().eq(Some(shifted));
+
+            if shift ^ (caps_lock && is_letter) {
+                shifted
+            } else {
+                base
+            }
+        };
+
+        match character {
+            '\0' => None,
+            c if self.dead.contains(&c) => Some(Symbol::Dead(c)),
+            c => Some(Symbol::Char(c)),
+        }
+    }
+}
+
+/// The accents of dead keys: what the accent types on its own, the characters it can be put on,
+/// and the accented characters, in the same order. Only the accented characters code page 437 has
+/// are listed.
+const ACCENTS: [(char, char, &'static str, &'static str); 5] = [
+    ('^', '^', "aeiou", "âêîôû"),
+    ('´', '\'', "aeiouE", "áéíóúÉ"),
+    ('`', '`', "aeiou", "àèìòù"),
+    ('¨', '"', "aeiouyAOU", "äëïöüÿÄÖÜ"),
+    ('~', '~', "nN", "ñÑ"),
+];
+
+/// Put the accent of a dead key on `character`. Returns `None` if there is no such character.
+pub fn compose(accent: char, character: char) -> Option<char> {
+    let &(_, _, bases, accented) = ACCENTS.iter().find(|a| a.0 == accent)?;
+    let index = bases.chars().position(|c| c == character)?;
+
+    accented.chars().nth(index)
+}
+
+/// What the accent of a dead key types on its own, when the key is typed twice or followed by
+/// something it can't be put on.
+pub fn spacing(accent: char) -> char {
+    ACCENTS
+        .iter()
+        .find(|a| a.0 == accent)
+        .map_or(accent, |a| a.1)
+}
+
+/// Every built in layout. The index of a layout here is its number in the keyboard ioctls.
+pub static LAYOUTS: [&'static Layout; 5] = [&uk::LAYOUT, &us::LAYOUT, &de::LAYOUT, &fr::LAYOUT, &dvorak::LAYOUT];
+
+/// The index in `LAYOUTS` of the layout in use.
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+
+/// The layout in use.
+pub fn current() -> &'static Layout {
+    LAYOUTS[CURRENT.load(Ordering::Relaxed)]
+}
+
+/// The index in `LAYOUTS` of the layout in use.
+pub fn current_index() -> usize {
+    CURRENT.load(Ordering::Relaxed)
+}
+
+/// Switch to the layout at `index` in `LAYOUTS`.
+pub fn set_layout_index(index: usize) -> Result<(), &'static str> {
+    if index >= LAYOUTS.len() {
+        return Err("No such keyboard layout");
+    }
+
+    CURRENT.store(index, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Switch to the layout called `name`.
+pub fn set_layout(name: &str) -> Result<(), &'static str> {
+    let index = LAYOUTS
+        .iter()
+        .position(|l| l.name == name)
+        .ok_or("No such keyboard layout")?;
+
+    set_layout_index(index)
+}
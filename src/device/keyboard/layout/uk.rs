@@ -0,0 +1,18 @@
+//! The UK layout.
+
+use device::keyboard::KeyCode::*;
+use super::Layout;
+
+pub static LAYOUT: Layout = Layout {
+    name: "uk",
+    base: "`1234567890-=qwertyuiop[]asdfghjkl;'#\\zxcvbnm,./",
+    shift: "¬!\"£$%^&*()_+QWERTYUIOP{}ASDFGHJKL:@~|ZXCVBNM<>?",
+    altgr: &[
+        (A, 'á', '\0'),
+        (E, 'é', 'É'),
+        (I, 'í', '\0'),
+        (O, 'ó', '\0'),
+        (U, 'ú', '\0'),
+    ],
+    dead: &[],
+};
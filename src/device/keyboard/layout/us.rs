@@ -0,0 +1,11 @@
+//! The US layout.
+
+use super::Layout;
+
+pub static LAYOUT: Layout = Layout {
+    name: "us",
+    base: "`1234567890-=qwertyuiop[]asdfghjkl;'\\\\zxcvbnm,./",
+    shift: "~!@#$%^&*()_+QWERTYUIOP{}ASDFGHJKL:\"||ZXCVBNM<>?",
+    altgr: &[],
+    dead: &[],
+};
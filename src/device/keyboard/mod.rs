@@ -1,6 +1,9 @@
+pub mod command;
 pub mod keyboard;
+pub mod keycode;
 pub mod layout;
 pub mod ps2_keyboard;
 
 pub use self::keyboard::*;
+pub use self::keycode::*;
 pub use self::ps2_keyboard::*;
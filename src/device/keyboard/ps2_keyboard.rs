@@ -1,181 +1,169 @@
-use device::ps2_8042;
-use device::keyboard;
-use alloc::Vec;
-use alloc::string::{String, ToString};
-use spin::Mutex;
-
-/// A pair of keys on the left and the right of the keyboard.
-#[derive(Debug)]
-struct KeyPair {
-    left: bool,
-    right: bool,
-}
-
-impl KeyPair {
-    const fn new() -> Self {
-        KeyPair {
-            left: false,
-            right: false,
+use arch::interrupts::{bottom_half, disable_interrupts_and_then};
+use device::{apic, ps2_8042};
+use alloc::arc::Arc;
+use device::input::{self, EventDevice, EventKind, Sources};
+use device::keyboard::{Decoder, ScancodeSet};
+use device::keyboard::command::{self, Command, Leds, Response};
+use device::ring::ByteRing;
+use core::sync::atomic::{AtomicU8, Ordering};
+use fs::devfs;
+use spin::{Mutex, Once};
+
+/// The LEDs last set, so that they can be restored after a reset.
+static LEDS: AtomicU8 = AtomicU8::new(0);
+
+/// The IRQ the keyboard interrupts on.
+const KEYBOARD_IRQ: u8 = 1;
+
+/// Scancodes read by the interrupt handler, waiting to be decoded by the bottom half.
+static SCANCODES: ByteRing = ByteRing::new();
+
+/// The id of the bottom half which decodes `SCANCODES`.
+static BOTTOM_HALF: Once<usize> = Once::new();
+
+/// Turns scancodes into key events. Only the bottom half uses this.
+static DECODER: Mutex<Decoder> = Mutex::new(Decoder::new(ScancodeSet::Set1));
+
+/// The typematic settings used until they are changed: about 11 repeats per second, after half a
+/// second.
+const DEFAULT_TYPEMATIC_RATE: u8 = 0x0B;
+const DEFAULT_TYPEMATIC_DELAY: u8 = 1;
+
+/// Reset the keyboard and put it in a known state. The keyboard IRQ is not yet routed, so the
+/// commands are run by polling.
+fn reset_keyboard() {
+    let commands = [
+        Command::new(command::RESET),
+        // The controller translates set 2, and set 2 is the only set every keyboard has.
+        Command::set_scancode_set(2),
+        Command::set_typematic(DEFAULT_TYPEMATIC_RATE, DEFAULT_TYPEMATIC_DELAY),
+        Command::set_leds(Leds::empty()),
+        Command::new(command::ENABLE_SCANNING),
+    ];
+
+    let mut ps2 = ps2_8042::PS2.lock();
+
+    for command in commands.iter() {
+        if let Err(e) = command.run_polled(&mut ps2) {
+            println!("[ dev ] Keyboard command {:#x} failed: {}", command.command(), e);
         }
     }
-
-    fn is_pressed(&self) -> bool {
-        self.left || self.right
-    }
-}
-
-/// Possible modifications to state we could have.
-pub enum Modifiers {
-    AltLeft(bool),
-    AltRight(bool),
-    CapsLock,
-    ControlLeft(bool),
-    ControlRight(bool),
-    NumLock,
-    ScrollLock,
-    ShiftLeft(bool),
-    ShiftRight(bool),
-    /// Function keys, the usize represents the index
-    /// of the key in the array `functions` under `ModifierState`.
-    FunctionKeys(usize),
-}
-
-struct ModifierState {
-    shift: KeyPair,
-    control: KeyPair,
-    alt: KeyPair,
-    caps_lock: bool,
-    num_lock: bool,
-    scroll_lock: bool,
-    function_keys: [bool; 12],
 }
 
-impl ModifierState {
-    const fn new() -> Self {
-        ModifierState {
-            shift: KeyPair::new(),
-            control: KeyPair::new(),
-            alt: KeyPair::new(),
-            caps_lock: false,
-            num_lock: false,
-            scroll_lock: false,
-            function_keys: [false; 12],
-        }
-    }
+/// Reset the keyboard, register the bottom half and route the keyboard IRQ through the I/O APIC.
+pub fn init() {
+    reset_keyboard();
 
-    /// Should we use uppercase letters?
-    fn use_uppercase_letters(&self) -> bool {
-        self.shift.is_pressed() ^ self.caps_lock
-    }
+    let set = default_set();
+    DECODER.lock().set_set(set);
 
-    fn should_switch_tty(&self) -> (bool, usize) {
-        let is_ctrl: bool = self.control.left || self.control.right;
-
-        for i in 0..12 {
-            // If any one of the function keys is pressed
-            if self.function_keys[i] && is_ctrl {
-                return (true, i);
-            } else {
-                continue;
-            }
-        }
+    BOTTOM_HALF.call_once(|| {
+        bottom_half::register(decode_pending).expect("Could not register the keyboard bottom half")
+    });
 
-        (false, 0)
+    match apic::route_isa_irq(KEYBOARD_IRQ) {
+        Ok(()) => println!("[ dev ] PS/2 keyboard routed to vector {:#x}", apic::ISA_VECTOR_BASE + KEYBOARD_IRQ),
+        Err(e) => println!("[ dev ] Could not route the PS/2 keyboard IRQ: {}", e),
     }
 
-    /// Apply modifiers to ascii and return updated ascii.
-    fn apply_to(&self, ascii: char) -> String {
-        if self.use_uppercase_letters() {
-            use device::keyboard::layout::map_to_upper;
-
-            map_to_upper(ascii).iter().collect()
-        } else if self.should_switch_tty().0 {
-            let index = self.should_switch_tty().1;
-            tty_switch!(index);
+    // `/dev/keyboard` reads the key events of the keyboard in the same records as `/dev/input`.
+    let device = EventDevice {
+        sources: Sources::KEYBOARD,
+    };
 
-            ascii.to_string()
-        } else {
-            ascii.to_string()
-        }
+    if let Err(e) = devfs::register_char("keyboard", Arc::new(device)) {
+        println!("[ dev ] Could not add /dev/keyboard: {:?}", e);
     }
+}
 
-    /// Update modifier state.
-    fn update(&mut self, modifier: Modifiers) {
-        use self::Modifiers::*;
-
-        match modifier {
-            AltLeft(m) => self.alt.left = m,
-            AltRight(m) => self.alt.right = m,
-            CapsLock => self.caps_lock = !self.caps_lock,
-            ControlLeft(m) => self.control.left = m,
-            ControlRight(m) => self.control.right = m,
-            NumLock => self.num_lock = !self.num_lock,
-            ScrollLock => self.num_lock = !self.scroll_lock,
-            ShiftLeft(m) => self.shift.left = m,
-            ShiftRight(m) => self.shift.right = m,
-            FunctionKeys(m) => self.function_keys[m] = true,
-        }
+/// The scancode set which reaches us from a keyboard using set 2, as it does after a reset.
+fn default_set() -> ScancodeSet {
+    // Without translation the keyboard's own set 2 reaches us unchanged.
+    if ps2_8042::PS2.lock().translation {
+        ScancodeSet::Set1
+    } else {
+        ScancodeSet::Set2
     }
 }
 
-/// Possible types of keyboard input we might receive.
-pub enum Key {
-    Ascii(u8),
-    Meta(Modifiers),
-    LowerAscii(u8),
-}
+/// Called by the keyboard IRQ handler. This only reads the scancode into the ring, leaving the
+/// decoding to the bottom half.
+pub fn handle_interrupt() {
+    SCANCODES.push(ps2_8042::read_data());
 
-/// A key can be pressed or released and there are different scancodes as such.
-pub enum KeyEvent {
-    Pressed(Key),
-    Released(Key),
+    if let Some(&id) = BOTTOM_HALF.try() {
+        bottom_half::schedule(id);
+    }
 }
 
-static STATE: Mutex<ModifierState> = Mutex::new(ModifierState::new());
+/// Decode every scancode waiting in the ring, and hand the key events to the input core.
+fn decode_pending() {
+    while let Some(byte) = SCANCODES.pop() {
+        match command::handle_response(byte) {
+            Response::Scancode => (),
+            Response::Consumed => continue,
+            Response::Finished(finished) => {
+                finish(finished);
+                continue;
+            }
+        }
 
-/// Parse the retrieved key and print the output or update modifier state dependant on the type of
-/// key received. This is called by our keyboard IRQ handler.
-pub fn parse_key(scancode: u8) {
-    let sequence: u64 = retrieve_bytes(scancode);
+        // Interrupts are disabled while the decoder is locked, so that nothing scheduled while it
+        // is held can wait on it forever.
+        let event = disable_interrupts_and_then(|| DECODER.lock().feed(byte));
 
-    if let Some(key) = keyboard::get_key(sequence) {
-        match key {
-            Key::Ascii(k) => print_char(k as char),
-            Key::Meta(modifier) => STATE.lock().update(modifier),
-            Key::LowerAscii(byte) => print_str(STATE.lock().apply_to(byte as char)),
+        if let Some(event) = event {
+            input::push(Sources::KEYBOARD, EventKind::Key(event));
         }
     }
 }
 
-/// Read bytes until end of sequence and combine into a number.
-fn retrieve_bytes(scancode: u8) -> u64 {
-    let mut byte_sequence: Vec<u8> = vec![scancode];
+/// Switch the decoder once the keyboard has taken a command which changes its scancode set, so
+/// that scancodes sent before then are still decoded in the old set.
+fn finish(finished: Command) {
+    disable_interrupts_and_then(|| {
+        let set = match (finished.command(), finished.data()) {
+            (command::RESET, _) => default_set(),
+            (command::SCANCODE_SET, Some(1)) => ScancodeSet::Set1,
+            (command::SCANCODE_SET, Some(2)) => ScancodeSet::Set2,
+            _ => return,
+        };
+
+        DECODER.lock().set_set(set);
+    });
+}
 
-    // These scancodes are special - they indicate the start of a byte sequence which is sent when
-    // some keys are pressed. If they are the byte we receive, read until the end of the sequence.
-    if scancode == 0xE0 || scancode == 0xE1 {
-        // Read another byte from the keyboard.
-        let check: u8 = ps2_8042::read_char();
+/// Set the repeat rate, from 0 (30 per second) to 31 (2 per second), and the delay before
+/// repeating starts, from 0 (250ms) to 3 (1s).
+pub fn set_typematic(rate: u8, delay: u8) {
+    command::queue(Command::set_typematic(rate, delay));
+}
 
-        if let Some(byte) = keyboard::is_special_key(check) {
-            byte_sequence.push(byte);
-        }
+/// Switch the keyboard to another scancode set. The controller only translates set 2, so this
+/// needs translation to be off. The decoder follows once the keyboard acknowledges the switch.
+pub fn set_scancode_set(set: ScancodeSet) -> Result<(), &'static str> {
+    if ps2_8042::PS2.lock().translation {
+        return Err("The PS/2 controller is translating scancodes");
     }
 
-    byte_sequence
-        .iter()
-        .rev()
-        .fold(0, |acc, &b| (acc << 1) + b as u64)
+    let number = match set {
+        ScancodeSet::Set1 => 1,
+        ScancodeSet::Set2 => 2,
+    };
+
+    command::queue(Command::set_scancode_set(number));
+    Ok(())
 }
 
-/// Print an ascii character.
-pub fn print_char(character: char) {
-    match character {
-        '\n' | ' ' | '\t' | '\x08' => print!("{}", character),
-        _ => (),
+/// Set the keyboard LEDs. The TTYs call this when their lock keys change.
+pub fn set_leds(leds: Leds) {
+    if LEDS.swap(leds.bits(), Ordering::Relaxed) != leds.bits() {
+        command::queue(Command::set_leds(leds));
     }
 }
 
-pub fn print_str(string: String) {
-    print!("{}", string);
+/// Reset the keyboard, then restore its LEDs.
+pub fn reset() {
+    command::queue(Command::new(command::RESET));
+    command::queue(Command::set_leds(Leds::from_bits_truncate(LEDS.load(Ordering::Relaxed))));
 }
@@ -1,14 +1,20 @@
 #[macro_use]
 pub mod io;
+pub mod input;
 pub mod keyboard;
+pub mod mouse;
 pub mod ps2_8042;
 pub mod vga;
 pub mod pic;
 pub mod pit;
 pub mod ahci;
+pub mod block;
 pub mod pci;
+pub mod random;
+pub mod ring;
 pub mod apic;
 pub mod serial;
+pub mod tty;
 
 pub use self::io::cpuio::{Port, UnsafePort};
 pub use self::io::mmio;
@@ -17,8 +23,22 @@ use raw_cpuid::CpuId;
 
 /// Perform hardware init.
 pub unsafe fn init() {
-    vga::init();
+    serial::register_devices();
+    random::init();
+    input::init();
+    tty::init();
+    serial::init_input();
     pit::init();
-    ps2_8042::PS2.lock().init();
+    let ps2 = ps2_8042::PS2.lock().init();
+    match ps2 {
+        Ok(()) => {
+            keyboard::ps2_keyboard::init();
+            mouse::ps2_mouse::init();
+        }
+        Err(e) => println!("[ dev ] Could not initialise the PS/2 controller: {}", e),
+    }
+
+    // Drivers must be registered before enumeration, so that they are bound as devices are found.
+    pci::register_driver(&ahci::DRIVER);
     pci::init();
 }
@@ -0,0 +1,3 @@
+pub mod ps2_mouse;
+
+pub use self::ps2_mouse::*;
@@ -0,0 +1,280 @@
+//! A PS/2 mouse on the second port of the 8042, with the IntelliMouse extensions for a scroll
+//! wheel and two extra buttons. Packets are read by the IRQ handler and decoded into input events
+//! by a bottom half.
+
+use arch::interrupts::{bottom_half, disable_interrupts_and_then};
+use device::{apic, ps2_8042};
+use device::input::{self, Axis, Button, EventKind, Sources};
+use device::ps2_8042::{Ps2, SELF_TEST_PASSED, SELF_TEST_TIMEOUT};
+use device::ring::ByteRing;
+use spin::{Mutex, Once};
+
+/// The IRQ the mouse interrupts on.
+const MOUSE_IRQ: u8 = 12;
+
+/// Command bytes.
+const GET_ID: u8 = 0xF2;
+const SET_SAMPLE_RATE: u8 = 0xF3;
+const ENABLE_REPORTING: u8 = 0xF4;
+const SET_DEFAULTS: u8 = 0xF6;
+const RESET: u8 = 0xFF;
+
+/// The sample rate used once the protocol has been negotiated.
+const SAMPLE_RATE: u8 = 100;
+
+/// The buttons in the order of their bits in `Packet::buttons`.
+const BUTTONS: [Button; 5] = [
+    Button::Left,
+    Button::Right,
+    Button::Middle,
+    Button::Side,
+    Button::Extra,
+];
+
+/// The packet format the mouse has agreed to send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Protocol {
+    /// Three byte packets with three buttons.
+    Standard,
+    /// Four byte packets, the last holding the wheel.
+    Wheel,
+    /// Four byte packets, the last holding the wheel and two more buttons.
+    FiveButton,
+}
+
+impl Protocol {
+    fn packet_size(&self) -> usize {
+        match *self {
+            Protocol::Standard => 3,
+            Protocol::Wheel | Protocol::FiveButton => 4,
+        }
+    }
+}
+
+/// A decoded packet.
+struct Packet {
+    dx: i32,
+    dy: i32,
+    wheel: i32,
+    /// One bit for each of `BUTTONS`.
+    buttons: u8,
+}
+
+impl Packet {
+    fn parse(bytes: &[u8; 4], protocol: Protocol) -> Packet {
+        let flags = bytes[0];
+
+        // The movements are 9 bit two's complement, with the sign bits in the first byte. Movements
+        // which overflowed are meaningless, so they are dropped.
+        let dx = if flags & 0x40 != 0 {
+            0
+        } else {
+            bytes[1] as i32 - ((flags as i32) << 4 & 0x100)
+        };
+        let dy = if flags & 0x80 != 0 {
+            0
+        } else {
+            bytes[2] as i32 - ((flags as i32) << 3 & 0x100)
+        };
+
+        let mut buttons = flags & 0x7;
+
+        // The mouse counts the wheel and Y up towards the user, the opposite of the input axes.
+        let wheel = match protocol {
+            Protocol::Standard => 0,
+            Protocol::Wheel => -(bytes[3] as i8 as i32),
+            Protocol::FiveButton => {
+                buttons |= (bytes[3] >> 4 & 0x3) << 3;
+                // The wheel is only the low 4 bits, so it is sign extended from there.
+                -(((bytes[3] << 4) as i8 >> 4) as i32)
+            }
+        };
+
+        Packet {
+            dx: dx,
+            dy: -dy,
+            wheel: wheel,
+            buttons: buttons,
+        }
+    }
+}
+
+/// The bytes of the packet being received.
+struct PacketState {
+    protocol: Protocol,
+    bytes: [u8; 4],
+    received: usize,
+    /// The buttons held in the last packet, so that only changes are reported.
+    buttons: u8,
+}
+
+impl PacketState {
+    const fn new() -> Self {
+        PacketState {
+            protocol: Protocol::Standard,
+            bytes: [0; 4],
+            received: 0,
+            buttons: 0,
+        }
+    }
+
+    /// Add a byte, returning the packet if it is the last byte of one.
+    fn feed(&mut self, byte: u8) -> Option<Packet> {
+        // Bit 3 of the first byte is always set, so a byte without it can't start a packet and a
+        // byte has been lost. Dropping bytes until one has it gets back in step.
+        if self.received == 0 && byte & 0x08 == 0 {
+            return None;
+        }
+
+        self.bytes[self.received] = byte;
+        self.received += 1;
+
+        if self.received < self.protocol.packet_size() {
+            return None;
+        }
+
+        self.received = 0;
+        Some(Packet::parse(&self.bytes, self.protocol))
+    }
+}
+
+/// Bytes read by the interrupt handler, waiting to be decoded by the bottom half.
+static BYTES: ByteRing = ByteRing::new();
+
+/// The id of the bottom half which decodes `BYTES`.
+static BOTTOM_HALF: Once<usize> = Once::new();
+
+static STATE: Mutex<PacketState> = Mutex::new(PacketState::new());
+
+fn set_sample_rate(ps2: &mut Ps2, rate: u8) -> Result<(), &'static str> {
+    ps2.send_to_second(SET_SAMPLE_RATE)?;
+    ps2.send_to_second(rate)
+}
+
+/// Set a sequence of sample rates, which is how the IntelliMouse extensions are enabled, and
+/// return the device id the mouse reports afterwards.
+fn knock(ps2: &mut Ps2, rates: &[u8]) -> Result<u8, &'static str> {
+    for &rate in rates {
+        set_sample_rate(ps2, rate)?;
+    }
+
+    ps2.send_to_second(GET_ID)?;
+    ps2.wait_then_read_second()
+}
+
+/// Reset the mouse and negotiate the best protocol it supports. The mouse IRQ is not yet routed,
+/// so this polls for each response.
+fn reset_mouse(ps2: &mut Ps2) -> Result<Protocol, &'static str> {
+    ps2.send_to_second(RESET)?;
+
+    if ps2.wait_then_read_second_for(SELF_TEST_TIMEOUT)? != SELF_TEST_PASSED {
+        return Err("Mouse self test failed");
+    }
+
+    // The self test result is followed by the device id.
+    ps2.wait_then_read_second()?;
+    ps2.send_to_second(SET_DEFAULTS)?;
+
+    let mut protocol = Protocol::Standard;
+
+    if knock(ps2, &[200, 100, 80])? == 3 {
+        protocol = Protocol::Wheel;
+
+        if knock(ps2, &[200, 200, 80])? == 4 {
+            protocol = Protocol::FiveButton;
+        }
+    }
+
+    set_sample_rate(ps2, SAMPLE_RATE)?;
+    ps2.send_to_second(ENABLE_REPORTING)?;
+
+    Ok(protocol)
+}
+
+/// Reset the mouse, register the bottom half and route the mouse IRQ through the I/O APIC.
+pub fn init() {
+    let protocol = {
+        let mut ps2 = ps2_8042::PS2.lock();
+
+        if !ps2.second_port {
+            println!("[ dev ] No second PS/2 port, so no PS/2 mouse.");
+            return;
+        }
+
+        match reset_mouse(&mut ps2) {
+            Ok(protocol) => protocol,
+            Err(e) => {
+                println!("[ dev ] Could not initialise the PS/2 mouse: {}", e);
+                return;
+            }
+        }
+    };
+
+    println!("[ dev ] PS/2 mouse found, using the {:?} protocol", protocol);
+    STATE.lock().protocol = protocol;
+
+    BOTTOM_HALF.call_once(|| {
+        bottom_half::register(decode_pending).expect("Could not register the mouse bottom half")
+    });
+
+    match apic::route_isa_irq(MOUSE_IRQ) {
+        Ok(()) => println!("[ dev ] PS/2 mouse routed to vector {:#x}", apic::ISA_VECTOR_BASE + MOUSE_IRQ),
+        Err(e) => println!("[ dev ] Could not route the PS/2 mouse IRQ: {}", e),
+    }
+}
+
+/// Called by the mouse IRQ handler. This only reads the byte into the ring, leaving the decoding
+/// to the bottom half.
+pub fn handle_interrupt() {
+    BYTES.push(ps2_8042::read_data());
+
+    if let Some(&id) = BOTTOM_HALF.try() {
+        bottom_half::schedule(id);
+    }
+}
+
+/// Decode every byte waiting in the ring, and queue the events of each complete packet.
+fn decode_pending() {
+    while let Some(byte) = BYTES.pop() {
+        // Interrupts are disabled while the state is locked, so that nothing scheduled while it
+        // is held can wait on it forever.
+        let changes = disable_interrupts_and_then(|| {
+            let mut state = STATE.lock();
+            let packet = state.feed(byte);
+
+            packet.map(|packet| {
+                let previous = state.buttons;
+                state.buttons = packet.buttons;
+                (packet, previous)
+            })
+        });
+
+        if let Some((packet, previous)) = changes {
+            report(&packet, previous);
+        }
+    }
+}
+
+fn report(packet: &Packet, previous_buttons: u8) {
+    if packet.dx != 0 {
+        input::push(Sources::MOUSE, EventKind::Relative(Axis::X, packet.dx));
+    }
+
+    if packet.dy != 0 {
+        input::push(Sources::MOUSE, EventKind::Relative(Axis::Y, packet.dy));
+    }
+
+    if packet.wheel != 0 {
+        input::push(Sources::MOUSE, EventKind::Relative(Axis::Wheel, packet.wheel));
+    }
+
+    for (i, &button) in BUTTONS.iter().enumerate() {
+        let bit = 1 << i;
+
+        if (packet.buttons ^ previous_buttons) & bit != 0 {
+            input::push(Sources::MOUSE, EventKind::Button(button, packet.buttons & bit != 0));
+        }
+    }
+
+    input::push(Sources::MOUSE, EventKind::Sync);
+}
@@ -0,0 +1,66 @@
+//! The PCI capability list, which describes optional features of a function such as power
+//! management, MSI and MSI-X.
+
+use super::Device;
+
+pub const CAP_POWER_MANAGEMENT: u8 = 0x01;
+pub const CAP_MSI: u8 = 0x05;
+pub const CAP_VENDOR_SPECIFIC: u8 = 0x09;
+pub const CAP_PCI_EXPRESS: u8 = 0x10;
+pub const CAP_MSIX: u8 = 0x11;
+
+/// A single capability in the list.
+#[derive(Debug, Clone, Copy)]
+pub struct Capability {
+    pub id: u8,
+    /// Offset of this capability in the configuration space.
+    pub offset: u8,
+}
+
+/// An iterator over the capability list of a function.
+pub struct CapabilityIter {
+    device: Device,
+    offset: u8,
+    /// Guards against malformed lists which loop back on themselves.
+    remaining: usize,
+}
+
+impl CapabilityIter {
+    pub fn new(device: Device) -> Self {
+        // Bit 4 of the status register says whether the capability pointer at 0x34 is valid.
+        let status = unsafe { device.read(0x04) } >> 16;
+
+        let offset = if status & (1 << 4) != 0 {
+            (unsafe { device.read(0x34) } & 0xFC) as u8
+        } else {
+            0
+        };
+
+        CapabilityIter {
+            device: device,
+            offset: offset,
+            remaining: 48,
+        }
+    }
+}
+
+impl Iterator for CapabilityIter {
+    type Item = Capability;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset < 0x40 || self.remaining == 0 {
+            return None;
+        }
+
+        let header = unsafe { self.device.read(self.offset as u32) };
+        let capability = Capability {
+            id: header as u8,
+            offset: self.offset,
+        };
+
+        self.offset = ((header >> 8) & 0xFC) as u8;
+        self.remaining -= 1;
+
+        Some(capability)
+    }
+}
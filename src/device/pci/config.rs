@@ -0,0 +1,140 @@
+//! PCI configuration space access. PCI Express ECAM regions described by the MCFG table are used
+//! where available, since they reach the full 4 KiB extended configuration space of each function.
+//! Everything else falls back to the legacy 0xCF8/0xCFC port I/O mechanism.
+
+use acpi::{self, TableType};
+use alloc::Vec;
+use arch::memory;
+use core::ptr;
+use spin::Mutex;
+use super::PCI;
+
+/// The size of the configuration space of a single function under ECAM.
+pub const EXTENDED_CONFIG_SIZE: u16 = 4096;
+
+/// The size of the configuration space reachable through port I/O.
+pub const LEGACY_CONFIG_SIZE: u16 = 256;
+
+/// A memory mapped configuration region, covering a range of buses.
+#[derive(Debug, Clone, Copy)]
+pub struct EcamRegion {
+    pub base: usize,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+impl EcamRegion {
+    fn contains(&self, bus: u8) -> bool {
+        self.segment == 0 && bus >= self.start_bus && bus <= self.end_bus
+    }
+
+    /// Physical address of a register in the configuration space of a function.
+    fn address(&self, bus: u8, slot: u8, func: u8, offset: u16) -> usize {
+        self.base
+            + (((bus - self.start_bus) as usize) << 20 | (slot as usize) << 15
+                | (func as usize) << 12 | (offset & 0xFFC) as usize)
+    }
+}
+
+lazy_static! {
+    /// All the ECAM regions found in the MCFG table.
+    static ref ECAM_REGIONS: Mutex<Vec<EcamRegion>> = Mutex::new(Vec::new());
+}
+
+/// Find the ECAM regions described by the MCFG table, if there is one.
+pub fn init() {
+    let mcfg = match acpi::get_table(b"MCFG") {
+        Some(TableType::Mcfg(mcfg)) => mcfg,
+        _ => {
+            println!("[ dev ] No MCFG table, using port I/O for PCI configuration space.");
+            return;
+        }
+    };
+
+    let mut regions = ECAM_REGIONS.lock();
+
+    for entry in mcfg.entries() {
+        let region = EcamRegion {
+            base: entry.base_address as usize,
+            segment: entry.segment,
+            start_bus: entry.start_bus,
+            end_bus: entry.end_bus,
+        };
+
+        println!(
+            "[ dev ] Found ECAM region at {:#x}, segment {}, buses {}-{}",
+            region.base, region.segment, region.start_bus, region.end_bus
+        );
+
+        regions.push(region);
+    }
+}
+
+/// Return the virtual address of a register in the ECAM space of a function, mapping the
+/// function's page of configuration space if needed, and whether the page was mapped by this
+/// call. Returns `None` if no ECAM region covers the bus.
+fn ecam_address(bus: u8, slot: u8, func: u8, offset: u16) -> Option<(usize, bool)> {
+    let region = match ECAM_REGIONS.lock().iter().find(|r| r.contains(bus)) {
+        Some(region) => *region,
+        None => return None,
+    };
+
+    let address = region.address(bus, slot, func, offset);
+
+    // Every function has its own page of configuration space, so it is mapped lazily the first
+    // time it is touched rather than mapping up to 256 MiB up front.
+    let page = address & !0xFFF;
+    let newly_mapped = !memory::is_mapped(page);
+
+    if newly_mapped {
+        memory::map_mmio(page, 4096);
+    }
+
+    Some((address, newly_mapped))
+}
+
+/// Read an aligned dword from the configuration space of a function. Offsets at or above 256
+/// are only reachable through ECAM, and read as all ones otherwise, as do offsets past the end of
+/// the configuration space.
+pub unsafe fn read(bus: u8, slot: u8, func: u8, offset: u16) -> u32 {
+    if offset >= EXTENDED_CONFIG_SIZE {
+        return 0xFFFFFFFF;
+    }
+
+    if let Some((address, newly_mapped)) = ecam_address(bus, slot, func, offset) {
+        let value = ptr::read_volatile(address as *const u32);
+
+        // Functions which don't exist read as all ones. Enumeration probes every one of them, so
+        // their pages are unmapped again rather than left mapped for good.
+        if newly_mapped && value == 0xFFFFFFFF {
+            memory::unmap(address & !0xFFF, 4096);
+        }
+
+        value
+    } else if offset < LEGACY_CONFIG_SIZE {
+        PCI.lock().read_config(bus, slot, func, offset as u8)
+    } else {
+        0xFFFFFFFF
+    }
+}
+
+/// Write an aligned dword to the configuration space of a function. Writes to the extended
+/// configuration space are dropped if there is no ECAM region covering the bus, as are writes
+/// past the end of the configuration space.
+pub unsafe fn write(bus: u8, slot: u8, func: u8, offset: u16, value: u32) {
+    if offset >= EXTENDED_CONFIG_SIZE {
+        return;
+    }
+
+    if let Some((address, _)) = ecam_address(bus, slot, func, offset) {
+        ptr::write_volatile(address as *mut u32, value);
+    } else if offset < LEGACY_CONFIG_SIZE {
+        PCI.lock().write_config(bus, slot, func, offset as u8, value);
+    }
+}
+
+/// Whether the extended configuration space is reachable for the given bus.
+pub fn has_extended_config(bus: u8) -> bool {
+    ECAM_REGIONS.lock().iter().any(|r| r.contains(bus))
+}
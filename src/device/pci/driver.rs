@@ -0,0 +1,160 @@
+//! The PCI driver model. Drivers describe the functions they support with an ID table, and are
+//! bound to every matching function found during enumeration.
+
+use alloc::Vec;
+use spin::Mutex;
+use super::{Device, DeviceClass};
+
+/// An entry in a driver's ID table.
+#[derive(Debug, Clone, Copy)]
+pub enum DeviceId {
+    /// Match a specific vendor and device ID.
+    Vendor { vendor: u16, device: u16 },
+    /// Match a class and subclass, and optionally a programming interface.
+    Class {
+        class: DeviceClass,
+        subclass: u8,
+        prog_if: Option<u8>,
+    },
+}
+
+impl DeviceId {
+    /// Whether this ID matches the given function.
+    pub fn matches(&self, device: &Device) -> bool {
+        match *self {
+            DeviceId::Vendor { vendor, device: id } => {
+                device.vendor_id() == vendor && device.device_id() == id
+            }
+            DeviceId::Class {
+                class,
+                subclass,
+                prog_if,
+            } => {
+                device.class() == class && device.subclass() == subclass
+                    && prog_if.map_or(true, |p| device.prog_if() == p)
+            }
+        }
+    }
+}
+
+/// A driver for PCI functions.
+pub trait PciDriver: Sync {
+    /// A short name for the driver, used in log messages.
+    fn name(&self) -> &'static str;
+
+    /// The functions this driver supports.
+    fn id_table(&self) -> &'static [DeviceId];
+
+    /// Called for each matching function. If this fails the function is left unbound, and other
+    /// drivers may try to claim it.
+    fn probe(&self, device: &Device) -> Result<(), &'static str>;
+
+    /// Called when the driver is unregistered, for each function bound to it.
+    fn remove(&self, _device: &Device) {}
+}
+
+/// A function which has been claimed by a driver.
+struct Binding {
+    device: Device,
+    driver: &'static PciDriver,
+}
+
+lazy_static! {
+    static ref DRIVERS: Mutex<Vec<&'static PciDriver>> = Mutex::new(Vec::new());
+    static ref BINDINGS: Mutex<Vec<Binding>> = Mutex::new(Vec::new());
+}
+
+fn same_driver(a: &'static PciDriver, b: &'static PciDriver) -> bool {
+    a as *const PciDriver as *const u8 == b as *const PciDriver as *const u8
+}
+
+/// Whether a driver has already claimed the given function.
+pub fn is_bound(device: &Device) -> bool {
+    BINDINGS
+        .lock()
+        .iter()
+        .any(|b| b.device.address() == device.address())
+}
+
+/// Return the name of the driver bound to the given function, if there is one.
+pub fn bound_driver(device: &Device) -> Option<&'static str> {
+    BINDINGS
+        .lock()
+        .iter()
+        .find(|b| b.device.address() == device.address())
+        .map(|b| b.driver.name())
+}
+
+/// Try to bind a single driver to a function, returning whether it was claimed.
+fn try_bind(driver: &'static PciDriver, device: &Device) -> bool {
+    if !driver.id_table().iter().any(|id| id.matches(device)) {
+        return false;
+    }
+
+    // The locks are not held while probing, so that drivers can use the query API.
+    match driver.probe(device) {
+        Ok(()) => {
+            println!("[ dev ] {} bound to {}", driver.name(), device);
+
+            BINDINGS.lock().push(Binding {
+                device: *device,
+                driver: driver,
+            });
+
+            true
+        }
+        Err(e) => {
+            println!("[ dev ] {} failed to probe {}: {}", driver.name(), device, e);
+            false
+        }
+    }
+}
+
+/// Offer a newly discovered function to every registered driver, in registration order.
+pub fn bind_device(device: &Device) {
+    if is_bound(device) {
+        return;
+    }
+
+    let drivers = DRIVERS.lock().clone();
+
+    for driver in drivers {
+        if try_bind(driver, device) {
+            break;
+        }
+    }
+}
+
+/// Register a driver, and bind it to any matching function which has already been discovered
+/// and is not yet claimed.
+pub fn register_driver(driver: &'static PciDriver) {
+    DRIVERS.lock().push(driver);
+
+    for device in super::devices() {
+        if !is_bound(&device) {
+            try_bind(driver, &device);
+        }
+    }
+}
+
+/// Unregister a driver, calling its `remove` callback for every function bound to it.
+pub fn unregister_driver(driver: &'static PciDriver) {
+    DRIVERS.lock().retain(|&d| !same_driver(d, driver));
+
+    let removed: Vec<Device> = {
+        let mut bindings = BINDINGS.lock();
+        let removed = bindings
+            .iter()
+            .filter(|b| same_driver(b.driver, driver))
+            .map(|b| b.device)
+            .collect();
+
+        bindings.retain(|b| !same_driver(b.driver, driver));
+        removed
+    };
+
+    for device in removed {
+        driver.remove(&device);
+        println!("[ dev ] {} removed from {}", driver.name(), device);
+    }
+}
@@ -0,0 +1,419 @@
+use device::io::Port;
+use spin::Mutex;
+use alloc::Vec;
+use core::{cmp, fmt};
+// use core::num::Float;
+
+pub mod cap;
+pub mod config;
+pub mod driver;
+pub mod msi;
+
+pub use self::driver::{register_driver, unregister_driver, DeviceId, PciDriver};
+
+#[allow(dead_code)]
+const MAX_BUS: u8 = 255;
+
+#[allow(dead_code)]
+const MAX_DEVICE: u8 = 31;
+
+#[allow(dead_code)]
+const MAX_FUNCTION: u8 = 7;
+
+static PCI: Mutex<Pci> = Mutex::new(Pci {
+    cfg_address: unsafe { Port::new(0xCF8) },
+    cfg_data: unsafe { Port::new(0xCFC) },
+});
+
+lazy_static! {
+    static ref DEVICES: Mutex<Vec<Device>> = Mutex::new(Vec::new());
+}
+
+/// The legacy configuration mechanism, using the 0xCF8 and 0xCFC I/O ports.
+pub struct Pci {
+    pub cfg_address: Port<u32>,
+    pub cfg_data: Port<u32>,
+}
+
+impl Pci {
+    /// Read an aligned dword from the PCI configuration space.
+    pub unsafe fn read_config(&mut self, bus: u8, slot: u8, func: u8, offset: u8) -> u32 {
+        let address: u32 = 0x80000000 | (bus as u32) << 16 | (slot as u32) << 11
+            | (func as u32) << 8 | (offset & 0xFC) as u32;
+
+        self.cfg_address.write(address);
+        self.cfg_data.read()
+    }
+
+    /// Write an aligned dword to the PCI configuration space.
+    pub unsafe fn write_config(&mut self, bus: u8, slot: u8, func: u8, offset: u8, value: u32) {
+        let address: u32 = 0x80000000 | (bus as u32) << 16 | (slot as u32) << 11
+            | (func as u32) << 8 | (offset & 0xFC) as u32;
+
+        self.cfg_address.write(address);
+        self.cfg_data.write(value);
+    }
+}
+
+/// Read the configuration header to determine unique info about a device.
+pub unsafe fn probe(bus: u8, slot: u8, function: u8) -> Option<Device> {
+    let config_0 = config::read(bus, slot, function, 0);
+
+    if config_0 == 0xFFFFFFFF {
+        return None;
+    }
+
+    let config_4 = config::read(bus, slot, function, 0x8);
+    let config_c = config::read(bus, slot, function, 0xC);
+
+    Some(Device {
+        bus: bus,
+        function: function,
+        device: slot,
+        device_id: (config_0 >> 16) as u16,
+        vendor_id: config_0 as u16,
+        rev_id: config_4 as u8,
+        subclass: (config_4 >> 16) as u8,
+        class: DeviceClass::from_u8((config_4 >> 24) as u8),
+        prog_if: (config_4 >> 8) as u8,
+        multifunction: config_c & 0x800000 != 0,
+        bars: [0; 6],
+    })
+}
+
+/// Read an aligned dword from the configuration space of the given function.
+pub unsafe fn read_config(bus: u8, slot: u8, func: u8, offset: u16) -> u32 {
+    config::read(bus, slot, func, offset)
+}
+
+/// Write an aligned dword to the configuration space of the given function.
+pub unsafe fn write_config(bus: u8, slot: u8, func: u8, offset: u16, value: u32) {
+    config::write(bus, slot, func, offset, value)
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}: 0x{:04x} 0x{:04x} {:?} {:02x}",
+            self.bus,
+            self.device,
+            self.function,
+            self.vendor_id,
+            self.device_id,
+            self.class,
+            self.subclass
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u8)]
+#[allow(dead_code)]
+pub enum DeviceClass {
+    Legacy = 0x00,
+    MassStorage = 0x01,
+    Network = 0x02,
+    Display = 0x03,
+    Multimedia = 0x04,
+    Memory = 0x05,
+    BridgeDevice = 0x06,
+    SimpleCommunication = 0x07,
+    BaseSystemPeripheral = 0x08,
+    InputDevice = 0x09,
+    DockingStation = 0x0A,
+    Processor = 0x0B,
+    SerialBus = 0x0C,
+    Wireless = 0x0D,
+    IntelligentIO = 0x0E,
+    SatelliteCommunication = 0x0F,
+    EncryptionDecryption = 0x10,
+    DataAndSignalProcessing = 0x11,
+    Unknown,
+}
+
+impl DeviceClass {
+    /// Convert a given device code to a possible `DeviceClass` variant.
+    fn from_u8(c: u8) -> Self {
+        if c <= DeviceClass::DataAndSignalProcessing as u8 {
+            unsafe { ::core::mem::transmute(c) }
+        } else {
+            DeviceClass::Unknown
+        }
+    }
+}
+
+/// A PCI device.
+#[derive(Debug, Copy, Clone)]
+pub struct Device {
+    bus: u8,
+    function: u8,
+    device: u8,
+    device_id: u16,
+    vendor_id: u16,
+    rev_id: u8,
+    subclass: u8,
+    class: DeviceClass,
+    prog_if: u8,
+    /// Whether this device is multifunction or not.
+    multifunction: bool,
+    /// Base addresses.
+    bars: [u32; 6],
+}
+
+impl Device {
+    pub fn bus(&self) -> u8 {
+        self.bus
+    }
+
+    pub fn slot(&self) -> u8 {
+        self.device
+    }
+
+    pub fn function(&self) -> u8 {
+        self.function
+    }
+
+    /// The bus, slot and function numbers which identify this function.
+    pub fn address(&self) -> (u8, u8, u8) {
+        (self.bus, self.device, self.function)
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.vendor_id
+    }
+
+    pub fn device_id(&self) -> u16 {
+        self.device_id
+    }
+
+    pub fn rev_id(&self) -> u8 {
+        self.rev_id
+    }
+
+    pub fn class(&self) -> DeviceClass {
+        self.class
+    }
+
+    pub fn subclass(&self) -> u8 {
+        self.subclass
+    }
+
+    pub fn prog_if(&self) -> u8 {
+        self.prog_if
+    }
+
+    pub fn is_multifunction(&self) -> bool {
+        self.multifunction
+    }
+
+    /// Read a dword from this function's configuration space, including the extended space when
+    /// it is reachable through ECAM.
+    pub unsafe fn read(&self, offset: u32) -> u32 {
+        let offset = cmp::min(offset, 0xFFFF) as u16;
+        config::read(self.bus, self.device, self.function, offset)
+    }
+
+    /// Write a dword to this function's configuration space.
+    pub unsafe fn write(&self, offset: u32, value: u32) {
+        let offset = cmp::min(offset, 0xFFFF) as u16;
+        config::write(self.bus, self.device, self.function, offset, value)
+    }
+
+    /// Iterate over the PCI Express extended capabilities of this function, such as AER and
+    /// SR-IOV. This is empty if the extended configuration space is not reachable.
+    pub fn extended_capabilities(&self) -> ExtendedCapabilityIter {
+        ExtendedCapabilityIter {
+            device: *self,
+            offset: if config::has_extended_config(self.bus) { 0x100 } else { 0 },
+            remaining: 960,
+        }
+    }
+
+    /// Set a certain flag
+    pub unsafe fn set_flag(&self, offset: u32, flag: u32, toggle: bool) {
+        let mut value = self.read(offset);
+
+        if toggle {
+            value |= flag
+        } else {
+            value &= 0xFFFFFFFF - flag;
+        }
+        self.write(offset, value);
+    }
+
+    unsafe fn load_bars(&mut self) {
+        for i in 0..6 {
+            let bar = self.read(i * 4 + 0x10);
+            if bar > 0 {
+                self.bars[i as usize] = bar;
+                self.write(i * 4 + 0x10, 0xFFFFFFFF);
+                let size = (0xFFFFFFFF - (self.read(i * 4 + 0x10) & 0xFFFFFFF0)) + 1;
+                self.write(i * 4 + 0x10, bar);
+                if size > 0 {
+                    self.bars[i as usize] = size;
+                }
+            }
+        }
+    }
+
+    pub fn bar(&self, index: usize) -> u32 {
+        self.bars[index]
+    }
+
+    /// Return the base address of a memory BAR, reading both halves of 64-bit BARs. Returns
+    /// `None` for I/O BARs and unimplemented BARs.
+    pub fn bar_address(&self, index: usize) -> Option<u64> {
+        if index >= 6 {
+            return None;
+        }
+
+        let offset = 0x10 + index as u32 * 4;
+        let low = unsafe { self.read(offset) };
+
+        // Bit 0 set means this is an I/O space BAR.
+        if low & 1 != 0 {
+            return None;
+        }
+
+        let address = match (low >> 1) & 0x3 {
+            0x2 if index < 5 => {
+                (low & 0xFFFFFFF0) as u64 | (unsafe { self.read(offset + 4) } as u64) << 32
+            }
+            _ => (low & 0xFFFFFFF0) as u64,
+        };
+
+        if address == 0 {
+            None
+        } else {
+            Some(address)
+        }
+    }
+
+    /// Iterate over the capability list of this function.
+    pub fn capabilities(&self) -> cap::CapabilityIter {
+        cap::CapabilityIter::new(*self)
+    }
+
+    /// Find the first capability with the given ID.
+    pub fn find_capability(&self, id: u8) -> Option<cap::Capability> {
+        self.capabilities().find(|c| c.id == id)
+    }
+
+    /// Stop this function from asserting its legacy INTx# interrupt line.
+    pub fn disable_intx(&self) {
+        unsafe { self.set_flag(0x04, 1 << 10, true) };
+    }
+}
+
+/// Well-known PCI Express extended capability IDs.
+pub const EXT_CAP_AER: u16 = 0x0001;
+pub const EXT_CAP_SRIOV: u16 = 0x0010;
+
+/// A PCI Express extended capability header.
+#[derive(Debug, Clone, Copy)]
+pub struct ExtendedCapability {
+    pub id: u16,
+    pub version: u8,
+    /// Offset of this capability in the configuration space.
+    pub offset: u16,
+}
+
+/// An iterator over the extended capability list, which starts at offset 0x100.
+pub struct ExtendedCapabilityIter {
+    device: Device,
+    offset: u16,
+    /// Guards against malformed lists which loop back on themselves.
+    remaining: usize,
+}
+
+impl Iterator for ExtendedCapabilityIter {
+    type Item = ExtendedCapability;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset < 0x100 || self.remaining == 0 {
+            return None;
+        }
+
+        let header = unsafe { self.device.read(self.offset as u32) };
+
+        // An empty list has a header of zero, and a missing function reads as all ones.
+        if header == 0 || header == 0xFFFFFFFF {
+            return None;
+        }
+
+        let capability = ExtendedCapability {
+            id: header as u16,
+            version: ((header >> 16) & 0xF) as u8,
+            offset: self.offset,
+        };
+
+        self.offset = ((header >> 20) & 0xFFC) as u16;
+        self.remaining -= 1;
+
+        Some(capability)
+    }
+}
+
+/// Return a copy of every function found during enumeration.
+pub fn devices() -> Vec<Device> {
+    DEVICES.lock().clone()
+}
+
+/// Return every function which satisfies `predicate`.
+pub fn find_devices<F: Fn(&Device) -> bool>(predicate: F) -> Vec<Device> {
+    DEVICES.lock().iter().filter(|d| predicate(d)).cloned().collect()
+}
+
+/// Find the first function with the given vendor and device ID.
+pub fn find_by_id(vendor: u16, device: u16) -> Option<Device> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|d| d.vendor_id == vendor && d.device_id == device)
+        .cloned()
+}
+
+/// Return every function with the given class and subclass.
+pub fn find_by_class(class: DeviceClass, subclass: u8) -> Vec<Device> {
+    find_devices(|d| d.class == class && d.subclass == subclass)
+}
+
+fn init_dev(bus: u8, dev: u8) {
+    for func in 0..MAX_FUNCTION {
+        unsafe {
+            let device = probe(bus, dev, func);
+
+            match device {
+                // Device found, load bars.
+                Some(mut d) => {
+                    d.load_bars();
+                    DEVICES.lock().push(d);
+                }
+
+                None => {}
+            }
+        }
+    }
+}
+
+fn init_bus(bus: u8) {
+    for dev in 0..MAX_DEVICE {
+        init_dev(bus, dev);
+    }
+}
+
+/// Scan every bus, then offer each function found to the registered drivers.
+pub fn init() {
+    config::init();
+
+    for bus in 0..MAX_BUS {
+        init_bus(bus);
+    }
+
+    println!("[ dev ] Discovered {} PCI devices.", DEVICES.lock().len());
+
+    for device in devices() {
+        driver::bind_device(&device);
+    }
+}
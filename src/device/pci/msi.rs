@@ -0,0 +1,174 @@
+//! Message signalled interrupts. Rather than asserting a shared INTx# line, a function using MSI or
+//! MSI-X writes a message to the local APIC, which delivers it on a vector of our choosing.
+
+use alloc::Vec;
+use arch::interrupts::vectors::{self, VectorHandler};
+use arch::memory;
+use core::ptr;
+use device::apic;
+use super::Device;
+use super::cap::{CAP_MSI, CAP_MSIX};
+
+/// Base of the address range which the local APICs decode message writes in.
+const MESSAGE_ADDRESS_BASE: u32 = 0xFEE00000;
+
+/// MSI control register bits.
+const MSI_ENABLE: u16 = 1 << 0;
+const MSI_MULTIPLE_MESSAGE_ENABLE: u16 = 0x7 << 4;
+const MSI_64BIT: u16 = 1 << 7;
+const MSI_PER_VECTOR_MASK: u16 = 1 << 8;
+
+/// MSI-X control register bits.
+const MSIX_TABLE_SIZE: u16 = 0x7FF;
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_ENABLE: u16 = 1 << 15;
+
+/// The size of an entry in the MSI-X table.
+const MSIX_ENTRY_SIZE: usize = 16;
+
+/// Bit 0 of an MSI-X entry's vector control masks that entry.
+const MSIX_ENTRY_MASKED: u32 = 1 << 0;
+
+/// The message address targeting a local APIC, with fixed delivery and physical destination mode.
+fn message_address(apic_id: u8) -> u32 {
+    MESSAGE_ADDRESS_BASE | (apic_id as u32) << 12
+}
+
+/// Allocate a vector and register `handler` for it.
+fn allocate_vector(handler: VectorHandler) -> Result<u8, &'static str> {
+    let vector = vectors::allocate_vector().ok_or("No free interrupt vectors")?;
+
+    if let Err(e) = vectors::register_handler(vector, handler) {
+        vectors::free_vector(vector);
+        return Err(e);
+    }
+
+    Ok(vector)
+}
+
+impl Device {
+    /// Read the message control register of a capability, which is the upper half of its header.
+    unsafe fn message_control(&self, offset: u32) -> u16 {
+        (self.read(offset) >> 16) as u16
+    }
+
+    unsafe fn set_message_control(&self, offset: u32, control: u16) {
+        let header = self.read(offset) & 0xFFFF;
+        self.write(offset, header | (control as u32) << 16);
+    }
+
+    /// Enable MSI with a single message, delivered to the bootstrap processor. `handler` is called
+    /// every time the function signals an interrupt. Returns the vector that was allocated.
+    pub fn enable_msi(&self, handler: VectorHandler) -> Result<u8, &'static str> {
+        let cap = self.find_capability(CAP_MSI)
+            .ok_or("Device does not support MSI")?;
+        let apic_id = apic::bsp_id().ok_or("No local APIC to deliver MSIs to")?;
+        let vector = allocate_vector(handler)?;
+
+        let offset = cap.offset as u32;
+
+        unsafe {
+            let control = self.message_control(offset);
+
+            self.write(offset + 0x04, message_address(apic_id));
+
+            // The layout after the address depends on whether the function supports 64-bit
+            // message addresses.
+            let (data, mask) = if control & MSI_64BIT != 0 {
+                self.write(offset + 0x08, 0);
+                (offset + 0x0C, offset + 0x10)
+            } else {
+                (offset + 0x08, offset + 0x0C)
+            };
+
+            self.write(data, vector as u32);
+
+            if control & MSI_PER_VECTOR_MASK != 0 {
+                self.write(mask, 0);
+            }
+
+            // Ask for a single message only, since we hand out one vector.
+            self.set_message_control(offset, (control & !MSI_MULTIPLE_MESSAGE_ENABLE) | MSI_ENABLE);
+        }
+
+        self.disable_intx();
+
+        println!(
+            "[ dev ] Enabled MSI for {:02x}:{:02x}.{} on vector {:#x}",
+            self.bus, self.device, self.function, vector
+        );
+
+        Ok(vector)
+    }
+
+    /// Enable MSI-X, with one table entry per handler starting from entry 0. Any remaining
+    /// entries are left masked. Returns the vectors allocated, in the order of `handlers`.
+    pub fn enable_msix(&self, handlers: &[VectorHandler]) -> Result<Vec<u8>, &'static str> {
+        let cap = self.find_capability(CAP_MSIX)
+            .ok_or("Device does not support MSI-X")?;
+        let apic_id = apic::bsp_id().ok_or("No local APIC to deliver MSIs to")?;
+
+        let offset = cap.offset as u32;
+        let control = unsafe { self.message_control(offset) };
+        let table_size = (control & MSIX_TABLE_SIZE) as usize + 1;
+
+        if handlers.len() > table_size {
+            return Err("More handlers than MSI-X table entries");
+        }
+
+        // The table lives in one of the memory BARs, given by the low three bits.
+        let table = unsafe { self.read(offset + 0x04) };
+        let bar = self.bar_address((table & 0x7) as usize)
+            .ok_or("MSI-X table BAR is not a memory BAR")?;
+        let table_address = bar as usize + (table & !0x7) as usize;
+
+        memory::map_mmio(table_address, table_size * MSIX_ENTRY_SIZE);
+
+        let mut allocated = Vec::new();
+
+        for &handler in handlers {
+            match allocate_vector(handler) {
+                Ok(vector) => allocated.push(vector),
+                Err(e) => {
+                    for &vector in allocated.iter() {
+                        vectors::free_vector(vector);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        unsafe {
+            // Mask the whole function while the table is being programmed.
+            self.set_message_control(offset, control | MSIX_ENABLE | MSIX_FUNCTION_MASK);
+
+            for entry in 0..table_size {
+                let address = (table_address + entry * MSIX_ENTRY_SIZE) as *mut u32;
+
+                match allocated.get(entry) {
+                    Some(&vector) => {
+                        ptr::write_volatile(address, message_address(apic_id));
+                        ptr::write_volatile(address.offset(1), 0);
+                        ptr::write_volatile(address.offset(2), vector as u32);
+                        ptr::write_volatile(address.offset(3), 0);
+                    }
+                    None => {
+                        let vector_control = ptr::read_volatile(address.offset(3));
+                        ptr::write_volatile(address.offset(3), vector_control | MSIX_ENTRY_MASKED);
+                    }
+                }
+            }
+
+            self.set_message_control(offset, (control | MSIX_ENABLE) & !MSIX_FUNCTION_MASK);
+        }
+
+        self.disable_intx();
+
+        println!(
+            "[ dev ] Enabled MSI-X for {:02x}:{:02x}.{} with {} vectors",
+            self.bus, self.device, self.function, allocated.len()
+        );
+
+        Ok(allocated)
+    }
+}
@@ -1,11 +1,14 @@
 use device::Port;
 use spin::Mutex;
-use core::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT};
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
 
 /// Configuration data. Use channel 0 and mode 3, square wave generator. Use lohi operation.
 const PIT_SET: u8 = 0x36;
 static DIVISOR: u16 = 2685;
 
+/// The number of times the PIT interrupts each second.
+const FREQUENCY: u64 = 1193182 / 2685;
+
 /// Simple interface to the PIT.
 pub static PIT: Mutex<[Port<u8>; 2]> = Mutex::new(unsafe { [Port::new(0x43), Port::new(0x40)] });
 
@@ -30,3 +33,65 @@ pub fn init() {
 }
 
 pub static PIT_TICKS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Ticks since boot. Unlike `PIT_TICKS`, which the scheduler resets, this only ever counts up, so
+/// it serves as a clock.
+static UPTIME_TICKS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Count a tick. Called by the timer handler.
+pub fn tick() {
+    UPTIME_TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The time since boot in microseconds, to the resolution of one tick.
+pub fn uptime_micros() -> u64 {
+    UPTIME_TICKS.load(Ordering::Relaxed) as u64 * 1_000_000 / FREQUENCY
+}
+
+/// The frequency of the clock driving the PIT, in Hz.
+const INPUT_FREQUENCY: u64 = 1193182;
+
+/// A deadline measured by reading the count of channel 0, rather than by counting ticks, so it
+/// passes even while interrupts are disabled. It has to be checked at least once every half period
+/// of the timer, about a millisecond, which any polling loop does.
+pub struct Timeout {
+    /// The number of PIT input cycles left.
+    remaining: u64,
+    /// The count read when the timeout was last checked.
+    last: u16,
+}
+
+impl Timeout {
+    pub fn new(micros: u64) -> Timeout {
+        Timeout {
+            remaining: micros * INPUT_FREQUENCY / 1_000_000,
+            last: read_count(),
+        }
+    }
+
+    /// Whether the time has run out.
+    pub fn expired(&mut self) -> bool {
+        let now = read_count();
+
+        // In mode 3 the count drops by two each cycle and is reloaded twice a period.
+        let elapsed = if now <= self.last {
+            self.last - now
+        } else {
+            self.last + (DIVISOR - now)
+        };
+
+        self.last = now;
+        self.remaining = self.remaining.saturating_sub(elapsed as u64 / 2);
+        self.remaining == 0
+    }
+}
+
+/// Latch and read the current count of channel 0.
+fn read_count() -> u16 {
+    let mut pit = PIT.lock();
+    pit[0].write(0x00);
+    let low = pit[1].read();
+    let high = pit[1].read();
+
+    (high as u16) << 8 | low as u16
+}
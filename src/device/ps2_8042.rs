@@ -1,9 +1,33 @@
 use spin::Mutex;
 use device::io::Port;
+use device::pit::Timeout;
+
+/// How long to wait for the controller before an operation times out, in microseconds. Time is
+/// measured with `pit::Timeout`, since the controller is set up with interrupts disabled, when
+/// the uptime doesn't advance.
+const TIMEOUT: u64 = 100_000;
+
+/// The number of times a byte is resent before giving up.
+const RETRIES: usize = 3;
+
+/// Responses from a device to a byte it was sent.
+pub const ACK: u8 = 0xFA;
+pub const RESEND: u8 = 0xFE;
+
+/// Sent by a device when it passes the self test it runs after a reset.
+pub const SELF_TEST_PASSED: u8 = 0xAA;
+
+/// How long to wait for a device's self test, which takes far longer than an acknowledgement, in
+/// microseconds.
+pub const SELF_TEST_TIMEOUT: u64 = 2_000_000;
 
 pub struct Ps2 {
     pub controller: Port<u8>,
     pub device: Port<u8>,
+    /// Whether the controller translates the keyboard's scancodes to set 1.
+    pub translation: bool,
+    /// Whether the controller has a working second port, for a mouse.
+    pub second_port: bool,
 }
 
 impl Ps2 {
@@ -11,67 +35,199 @@ impl Ps2 {
         Ps2 {
             controller: Port::new(controller),
             device: Port::new(device),
+            translation: false,
+            second_port: false,
         }
     }
 
     /// Poll bit 0 of status register: "Output buffer empty/full"
-    pub fn wait_then_read(&mut self) -> u8 {
-        while self.controller.read() & 0x1 == 0 {}
-        self.device.read()
+    pub fn wait_then_read(&mut self) -> Result<u8, &'static str> {
+        self.wait_then_read_for(TIMEOUT)
+    }
+
+    /// Like `wait_then_read`, but giving up after `micros` microseconds, for responses which take
+    /// longer than most.
+    pub fn wait_then_read_for(&mut self, micros: u64) -> Result<u8, &'static str> {
+        let mut timeout = Timeout::new(micros);
+
+        while !timeout.expired() {
+            if self.controller.read() & 0x1 != 0 {
+                return Ok(self.device.read());
+            }
+        }
+
+        Err("Timed out waiting for the PS/2 controller's output buffer")
+    }
+
+    /// Like `wait_then_read_for`, but only for data from the device on the second port, which is
+    /// marked by bit 5 of the status register. Data from the first port is discarded.
+    pub fn wait_then_read_second_for(&mut self, micros: u64) -> Result<u8, &'static str> {
+        let mut timeout = Timeout::new(micros);
+
+        while !timeout.expired() {
+            let status = self.controller.read();
+
+            if status & 0x1 != 0 {
+                let data = self.device.read();
+
+                if status & 0x20 != 0 {
+                    return Ok(data);
+                }
+            }
+        }
+
+        Err("Timed out waiting for the PS/2 controller's second port")
+    }
+
+    pub fn wait_then_read_second(&mut self) -> Result<u8, &'static str> {
+        self.wait_then_read_second_for(TIMEOUT)
     }
 
     /// Poll bit 1 of status register: "Input buffer empty/full"
-    pub fn wait_then_write(&mut self, data: u8) {
-        while self.controller.read() & 0x2 == 1 {}
+    fn wait_for_input_buffer(&mut self) -> Result<(), &'static str> {
+        let mut timeout = Timeout::new(TIMEOUT);
+
+        while !timeout.expired() {
+            if self.controller.read() & 0x2 == 0 {
+                return Ok(());
+            }
+        }
+
+        Err("Timed out waiting for the PS/2 controller's input buffer")
+    }
+
+    pub fn wait_then_write(&mut self, data: u8) -> Result<(), &'static str> {
+        self.wait_for_input_buffer()?;
         self.device.write(data);
+        Ok(())
+    }
+
+    /// Send a command to the controller itself.
+    pub fn command(&mut self, command: u8) -> Result<(), &'static str> {
+        self.wait_for_input_buffer()?;
+        self.controller.write(command);
+        Ok(())
+    }
+
+    /// Send a byte to the device on the first port and wait for it to be acknowledged, resending
+    /// it when the device asks. Anything else the device sends meanwhile is discarded.
+    pub fn send_to_device(&mut self, byte: u8) -> Result<(), &'static str> {
+        self.send(false, byte)
+    }
+
+    /// Like `send_to_device`, for the device on the second port.
+    pub fn send_to_second(&mut self, byte: u8) -> Result<(), &'static str> {
+        self.send(true, byte)
+    }
+
+    fn send(&mut self, second: bool, byte: u8) -> Result<(), &'static str> {
+        for _ in 0..RETRIES {
+            if second {
+                // The next byte written to the data port goes to the second port.
+                self.command(0xD4)?;
+            }
+            self.wait_then_write(byte)?;
+
+            loop {
+                let response = if second {
+                    self.wait_then_read_second()?
+                } else {
+                    self.wait_then_read()?
+                };
+
+                match response {
+                    ACK => return Ok(()),
+                    RESEND => break,
+                    _ => continue,
+                }
+            }
+        }
+
+        Err("PS/2 device kept asking for a resend")
     }
 
-    pub fn init(&mut self) {
+    pub fn init(&mut self) -> Result<(), &'static str> {
         println!("[ dev ] Initialising PS/2 8042 controller.");
         // Disable devices.
-        self.controller.write(0xAD);
-        self.controller.write(0xA7);
+        self.command(0xAD)?;
+        self.command(0xA7)?;
 
         // Flush output buffer.
         self.device.read();
 
         // Setup Controller Config Byte.
-        self.controller.write(0x20);
-        let mut config_byte: u8 = self.wait_then_read();
+        self.command(0x20)?;
+        let mut config_byte: u8 = self.wait_then_read()?;
+
+        // The second port was just disabled, so if its clock doesn't show as disabled there is
+        // no second port.
+        let mut dual_channel = config_byte & (1 << 5) != 0;
 
         // Disable IRQs.
         config_byte &= !(1 << 0);
         config_byte &= !(1 << 1);
 
         // Write back the modified config.
-        self.controller.write(0x60);
-        self.wait_then_write(config_byte);
+        self.command(0x60)?;
+        self.wait_then_write(config_byte)?;
 
         // Controller self test.
-        self.controller.write(0xAA);
-        assert!(self.wait_then_read() == 0x55, "PS/2 self test failed");
+        self.command(0xAA)?;
+        if self.wait_then_read()? != 0x55 {
+            return Err("PS/2 self test failed");
+        }
+
+        // Confirm the second port, by checking its clock is enabled when the port is.
+        if dual_channel {
+            self.command(0xA8)?;
+            self.command(0x20)?;
+            dual_channel = self.wait_then_read()? & (1 << 5) == 0;
+            self.command(0xA7)?;
+        }
 
         // Interface tests.
-        self.controller.write(0xAB);
-        assert!(self.wait_then_read() == 0x0, "Interface tests failed",);
+        self.command(0xAB)?;
+        if self.wait_then_read()? != 0x0 {
+            return Err("Interface tests failed");
+        }
+
+        self.second_port = false;
+
+        if dual_channel {
+            self.command(0xA9)?;
+            if self.wait_then_read()? == 0x0 {
+                self.second_port = true;
+            } else {
+                println!("[ dev ] PS/2 second port failed its interface test.");
+            }
+        }
 
         // Enable devices.
-        self.controller.write(0xAE);
+        self.command(0xAE)?;
+        if self.second_port {
+            self.command(0xA8)?;
+        }
 
         // Config byte.
-        self.controller.write(0x20);
-        let mut enable: u8 = self.wait_then_read();
+        self.command(0x20)?;
+        let mut enable: u8 = self.wait_then_read()?;
 
         // Re-enable IRQs.
         enable |= 1 << 0;
+        if self.second_port {
+            enable |= 1 << 1;
+        }
 
-        self.controller.write(0x60);
-        self.wait_then_write(enable);
+        self.translation = enable & (1 << 6) != 0;
+
+        self.command(0x60)?;
+        self.wait_then_write(enable)?;
 
         // Clear output buffer.
         self.device.read();
 
         println!("[ dev ] PS/2 8042 initialised.");
+        Ok(())
     }
 
     pub fn read_char(&mut self) -> u8 {
@@ -84,3 +240,9 @@ pub static PS2: Mutex<Ps2> = Mutex::new(unsafe { Ps2::new(0x64, 0x60) });
 pub fn read_char() -> u8 {
     PS2.lock().read_char()
 }
+
+/// Read the data port without taking the `PS2` lock, for interrupt handlers, which may have
+/// interrupted code holding it.
+pub fn read_data() -> u8 {
+    unsafe { Port::<u8>::new(0x60).read() }
+}
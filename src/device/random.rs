@@ -0,0 +1,135 @@
+//! A source of random numbers, from `rdrand` where the CPU has it, and otherwise from a xorshift
+//! generator seeded by the timestamp counter.
+
+use alloc::arc::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+use fs::{self, devfs};
+use raw_cpuid::CpuId;
+use spin::Mutex;
+
+/// The number of times to retry `rdrand`, which may fail if the hardware is out of entropy.
+const RDRAND_RETRIES: usize = 10;
+
+static STATE: Mutex<u64> = Mutex::new(0);
+
+/// Whether the CPU has `rdrand`, set once by `init`.
+static HAS_RDRAND: AtomicBool = AtomicBool::new(false);
+
+fn has_rdrand() -> bool {
+    CpuId::new()
+        .get_feature_info()
+        .map_or(false, |f| f.has_rdrand())
+}
+
+unsafe fn rdrand() -> Option<u64> {
+    for _ in 0..RDRAND_RETRIES {
+        let value: u64;
+        let ok: u8;
+
+        asm!("rdrand $0; setc $1"
+             : "=r"(value), "=r"(ok)
+             :
+             : "cc"
+             : "intel", "volatile");
+
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+unsafe fn rdtsc() -> u64 {
+    let low: u32;
+    let high: u32;
+
+    asm!("rdtsc"
+         : "={eax}"(low), "={edx}"(high)
+         :
+         :
+         : "intel", "volatile");
+
+    (high as u64) << 32 | low as u64
+}
+
+/// Advance the xorshift generator and return its next value.
+fn step(state: &mut u64) -> u64 {
+    if *state == 0 {
+        // The generator must never be seeded with zero.
+        *state = unsafe { rdtsc() } | 1;
+    }
+
+    let mut x = *state;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    *state = x;
+
+    x
+}
+
+/// Return the next value of the xorshift generator.
+fn xorshift() -> u64 {
+    step(&mut STATE.lock())
+}
+
+/// Return a random number.
+pub fn next_u64() -> u64 {
+    if HAS_RDRAND.load(Ordering::Relaxed) {
+        if let Some(value) = unsafe { rdrand() } {
+            return value;
+        }
+    }
+
+    xorshift()
+}
+
+/// Fill `buffer` with random bytes.
+pub fn fill(buffer: &mut [u8]) {
+    for chunk in buffer.chunks_mut(8) {
+        let value = next_u64();
+
+        for (i, b) in chunk.iter_mut().enumerate() {
+            *b = (value >> (i * 8)) as u8;
+        }
+    }
+}
+
+/// `/dev/random`.
+struct Random;
+
+impl devfs::CharDevice for Random {
+    fn read(&self, buffer: &mut [u8]) -> fs::Result<usize> {
+        fill(buffer);
+        Ok(buffer.len())
+    }
+
+    fn write(&self, buffer: &[u8]) -> fs::Result<usize> {
+        // Mix whatever is written into the fallback generator, stirring it between words so that
+        // the data adds to the state rather than taking its place.
+        let mut state = STATE.lock();
+        for chunk in buffer.chunks(8) {
+            let value = chunk
+                .iter()
+                .enumerate()
+                .fold(0, |value, (i, &b)| value | (b as u64) << (i * 8));
+
+            step(&mut state);
+            *state ^= value;
+            step(&mut state);
+        }
+
+        Ok(buffer.len())
+    }
+}
+
+pub fn init() {
+    HAS_RDRAND.store(has_rdrand(), Ordering::Relaxed);
+
+    if !HAS_RDRAND.load(Ordering::Relaxed) {
+        println!("[ dev ] rdrand is unavailable, /dev/random will use a seeded generator");
+    }
+
+    devfs::register_char("random", Arc::new(Random)).expect("Could not register /dev/random");
+}
@@ -0,0 +1,76 @@
+//! A fixed size ring of bytes with a single producer and a single consumer, which needs no locks.
+//! Interrupt handlers push the bytes they read from a device, and the code which decodes them
+//! pops them later, so the handler never waits on the consumer.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The number of bytes a ring holds. This is a power of two, so that indices wrap with a mask.
+pub const RING_SIZE: usize = 256;
+
+pub struct ByteRing {
+    buffer: UnsafeCell<[u8; RING_SIZE]>,
+    /// The number of bytes ever pushed. Only the producer writes this.
+    head: AtomicUsize,
+    /// The number of bytes ever popped. Only the consumer writes this.
+    tail: AtomicUsize,
+    /// The number of bytes dropped because the ring was full.
+    dropped: AtomicUsize,
+}
+
+// The producer only writes the slot at `head` before publishing it, and the consumer only reads
+// slots before `head`, so the two never touch the same byte at once.
+unsafe impl Sync for ByteRing {}
+
+impl ByteRing {
+    pub const fn new() -> Self {
+        ByteRing {
+            buffer: UnsafeCell::new([0; RING_SIZE]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Add a byte, dropping it if the ring is full. Must only be called by the producer.
+    pub fn push(&self, byte: u8) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+
+        if head.wrapping_sub(tail) >= RING_SIZE {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        unsafe {
+            (*self.buffer.get())[head % RING_SIZE] = byte;
+        }
+
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    /// Remove the oldest byte. Must only be called by the consumer.
+    pub fn pop(&self) -> Option<u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+
+        if tail == head {
+            return None;
+        }
+
+        let byte = unsafe { (*self.buffer.get())[tail % RING_SIZE] };
+
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(byte)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+
+    /// The number of bytes dropped so far because the consumer fell behind.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
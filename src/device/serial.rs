@@ -1,7 +1,14 @@
+use alloc::arc::Arc;
+use arch::interrupts::bottom_half;
+use device::apic;
+use device::input::{self, Consumer, EventKind, Sources};
 use device::io::cpuio::Port;
+use device::ring::ByteRing;
+use fs::{self, devfs};
 use self::Register::*;
-use spin::Mutex;
+use spin::{Mutex, Once};
 use core::fmt::{self, Write};
+use task;
 
 #[repr(C, u8)]
 #[allow(dead_code)]
@@ -52,6 +59,11 @@ impl SerialPort {
         // Done!
     }
 
+    /// Interrupt when a byte is received.
+    fn enable_receive_interrupt(&mut self) {
+        self.port(IntEnableOrMsb).write(0x01);
+    }
+
     /// Check if it is safe to read from this port.
     fn can_read(&mut self) -> bool {
         (self.port(LineStatus).read() & 1) == 0
@@ -91,8 +103,138 @@ impl Write for SerialPort {
     }
 }
 
-pub static COM1: Mutex<SerialPort> = Mutex::new(unsafe { SerialPort::new(0x3f8) });
+/// The base I/O port of COM1.
+const COM1_BASE: u16 = 0x3f8;
+
+/// The IRQ COM1 interrupts on.
+const COM1_IRQ: u8 = 4;
+
+pub static COM1: Mutex<SerialPort> = Mutex::new(unsafe { SerialPort::new(COM1_BASE) });
+
+/// Bytes read by the interrupt handler, waiting to be turned into input events.
+static RECEIVED: ByteRing = ByteRing::new();
+
+/// The id of the bottom half which empties `RECEIVED`.
+static BOTTOM_HALF: Once<usize> = Once::new();
+
+/// The queue `/dev/ttyS0` reads from, once received bytes come through the input core.
+static SERIAL_EVENTS: Once<Consumer> = Once::new();
+
+/// `/dev/ttyS0`, backed by `COM1`.
+struct SerialDevice;
+
+impl devfs::CharDevice for SerialDevice {
+    /// Wait for at least one byte, then return whatever else has already arrived.
+    fn read(&self, buffer: &mut [u8]) -> fs::Result<usize> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        if let Some(consumer) = SERIAL_EVENTS.try() {
+            let mut count = 0;
+
+            while count == 0 {
+                if let EventKind::Char(byte) = consumer.wait().kind {
+                    buffer[0] = byte;
+                    count = 1;
+                }
+            }
+
+            while count < buffer.len() {
+                match consumer.pop() {
+                    Some(event) => if let EventKind::Char(byte) = event.kind {
+                        buffer[count] = byte;
+                        count += 1;
+                    },
+                    None => break,
+                }
+            }
+
+            return Ok(count);
+        }
+
+        // Without the receive interrupt the port is polled instead. `COM1` is only locked to look
+        // at the port, never while waiting, since `print!` takes the same lock.
+        let mut count = 0;
+
+        loop {
+            {
+                let mut port = COM1.lock();
+
+                // `can_read` is true while nothing has been received.
+                while count < buffer.len() && !port.can_read() {
+                    buffer[count] = port.read();
+                    count += 1;
+                }
+            }
+
+            if count > 0 {
+                return Ok(count);
+            }
+
+            task::wait();
+        }
+    }
+
+    fn write(&self, buffer: &[u8]) -> fs::Result<usize> {
+        let mut port = COM1.lock();
+        for &byte in buffer {
+            port.write(byte);
+        }
+
+        Ok(buffer.len())
+    }
+}
+
+/// Add the serial device nodes. This allocates, so it can't happen in `init`, which runs before
+/// the heap is set up.
+pub fn register_devices() {
+    devfs::register_char("ttyS0", Arc::new(SerialDevice)).expect("Could not register /dev/ttyS0");
+}
 
 pub fn init() {
     COM1.lock().do_init();
 }
+
+/// Make COM1 a producer for the input core: every byte received becomes a character event, which
+/// the TTYs and `/dev/ttyS0` consume.
+pub fn init_input() {
+    BOTTOM_HALF.call_once(|| {
+        bottom_half::register(produce_pending).expect("Could not register the serial bottom half")
+    });
+
+    if let Err(e) = apic::route_isa_irq(COM1_IRQ) {
+        println!("[ dev ] Could not route the COM1 IRQ: {}", e);
+        return;
+    }
+
+    SERIAL_EVENTS.call_once(|| Consumer::new(Sources::SERIAL));
+    COM1.lock().enable_receive_interrupt();
+    println!("[ dev ] COM1 routed to vector {:#x}", apic::ISA_VECTOR_BASE + COM1_IRQ);
+}
+
+/// Called by the serial IRQ handler. `COM1` may be locked by whatever was interrupted, so the
+/// registers are read directly.
+pub fn handle_interrupt() {
+    let (mut status, mut data) = unsafe {
+        (
+            Port::<u8>::new(COM1_BASE + LineStatus as u8 as u16),
+            Port::<u8>::new(COM1_BASE + DataOrBaudLsb as u8 as u16),
+        )
+    };
+
+    while status.read() & 1 != 0 {
+        RECEIVED.push(data.read());
+    }
+
+    if let Some(&id) = BOTTOM_HALF.try() {
+        bottom_half::schedule(id);
+    }
+}
+
+/// Turn every received byte into a character event.
+fn produce_pending() {
+    while let Some(byte) = RECEIVED.pop() {
+        input::push(Sources::SERIAL, EventKind::Char(byte));
+    }
+}
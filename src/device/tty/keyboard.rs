@@ -0,0 +1,178 @@
+//! Turns key events into what they type on a VT, tracking the modifiers and lock keys and applying
+//! the keyboard layout. Control with a letter types its control character, control with a
+//! function key asks for a VT switch, and Shift+PageUp and Shift+PageDown scroll the history.
+//!
+//! Characters are typed as their code page 437 byte, which is what the console shows. Characters
+//! which aren't in the code page type nothing.
+
+use alloc::Vec;
+use device::keyboard::{self, Key, KeyCode, KeyEvent, Modifiers};
+use device::keyboard::command::Leds;
+use device::keyboard::layout::{self, Symbol};
+use device::vga::cp437;
+
+/// What a key event does.
+pub enum Action {
+    /// Type these bytes on the active VT.
+    Type(Vec<u8>),
+    /// Switch to the VT with this index.
+    Switch(usize),
+    /// Scroll the active VT this many half screens back into its history, or forward if
+    /// negative.
+    Scroll(isize),
+}
+
+/// A pair of keys on the left and the right of the keyboard.
+#[derive(Debug)]
+struct KeyPair {
+    left: bool,
+    right: bool,
+}
+
+impl KeyPair {
+    const fn new() -> Self {
+        KeyPair {
+            left: false,
+            right: false,
+        }
+    }
+
+    fn is_pressed(&self) -> bool {
+        self.left || self.right
+    }
+}
+
+pub struct ModifierState {
+    shift: KeyPair,
+    control: KeyPair,
+    alt: KeyPair,
+    caps_lock: bool,
+    num_lock: bool,
+    scroll_lock: bool,
+    /// The accent of a dead key typed before this key.
+    dead: Option<char>,
+}
+
+impl ModifierState {
+    pub const fn new() -> Self {
+        ModifierState {
+            shift: KeyPair::new(),
+            control: KeyPair::new(),
+            alt: KeyPair::new(),
+            caps_lock: false,
+            num_lock: false,
+            scroll_lock: false,
+            dead: None,
+        }
+    }
+
+    /// Update the state for a key event, and return what it does, if anything.
+    pub fn handle(&mut self, event: KeyEvent) -> Option<Action> {
+        if event.is_pressed() && self.shift.is_pressed() {
+            match event.code() {
+                KeyCode::PageUp => return Some(Action::Scroll(1)),
+                KeyCode::PageDown => return Some(Action::Scroll(-1)),
+                _ => (),
+            }
+        }
+
+        match keyboard::get_key(event)? {
+            Key::Ascii(byte) => Some(Action::Type(vec![byte])),
+            Key::Meta(Modifiers::FunctionKeys(index)) => if self.control.is_pressed() {
+                Some(Action::Switch(index))
+            } else {
+                None
+            },
+            Key::Meta(modifier) => {
+                self.update(modifier);
+                None
+            }
+            Key::Layout(code) => self.apply_to(code).map(Action::Type),
+            Key::Keypad(byte) => {
+                let is_number = (byte as char).is_digit(10) || byte == b'.';
+
+                if !is_number || self.num_lock {
+                    Some(Action::Type(vec![byte]))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Apply modifiers and the current layout to a key, and return what it types.
+    fn apply_to(&mut self, code: KeyCode) -> Option<Vec<u8>> {
+        // Right alt is AltGr.
+        let symbol = layout::current().symbol(
+            code,
+            self.shift.is_pressed(),
+            self.alt.right,
+            self.caps_lock,
+        )?;
+
+        let mut bytes = Vec::new();
+
+        {
+            let mut push = |c: char| bytes.extend(cp437::encode(c));
+
+            match (self.dead.take(), symbol) {
+                // Control turns `@`, the letters and `[\]^_` into the control characters.
+                (None, Symbol::Char(c)) if self.control.is_pressed() && control_character(c).is_some() => {
+                    push(control_character(c).unwrap() as char)
+                }
+                (None, Symbol::Dead(accent)) => self.dead = Some(accent),
+                (None, Symbol::Char(c)) => push(c),
+                // A dead key typed twice, or followed by space, types the accent itself.
+                (Some(accent), Symbol::Dead(_)) => push(layout::spacing(accent)),
+                (Some(accent), Symbol::Char(' ')) => push(layout::spacing(accent)),
+                (Some(accent), Symbol::Char(c)) => match layout::compose(accent, c) {
+                    Some(composed) => push(composed),
+                    None => {
+                        push(layout::spacing(accent));
+                        push(c);
+                    }
+                },
+            }
+        }
+
+        Some(bytes)
+    }
+
+    fn leds(&self) -> Leds {
+        let mut leds = Leds::empty();
+        leds.set(Leds::CAPS_LOCK, self.caps_lock);
+        leds.set(Leds::NUM_LOCK, self.num_lock);
+        leds.set(Leds::SCROLL_LOCK, self.scroll_lock);
+        leds
+    }
+
+    /// Update modifier state.
+    fn update(&mut self, modifier: Modifiers) {
+        use device::keyboard::Modifiers::*;
+
+        match modifier {
+            AltLeft(m) => self.alt.left = m,
+            AltRight(m) => self.alt.right = m,
+            CapsLock => self.caps_lock = !self.caps_lock,
+            ControlLeft(m) => self.control.left = m,
+            ControlRight(m) => self.control.right = m,
+            NumLock => self.num_lock = !self.num_lock,
+            ScrollLock => self.scroll_lock = !self.scroll_lock,
+            ShiftLeft(m) => self.shift.left = m,
+            ShiftRight(m) => self.shift.right = m,
+            FunctionKeys(_) => (),
+        }
+
+        // Make the keyboard LEDs match the lock state.
+        keyboard::set_leds(self.leds());
+    }
+}
+
+/// The control character typed by control and `c`, if there is one.
+fn control_character(c: char) -> Option<u8> {
+    match c {
+        '@'...'_' => Some(c as u8 & 0x1F),
+        'a'...'z' => Some(c as u8 & 0x1F),
+        _ => None,
+    }
+}
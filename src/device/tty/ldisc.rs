@@ -0,0 +1,228 @@
+//! The line discipline between what is typed on a VT and what its readers see. In canonical mode
+//! input is collected into a line which can be edited with erase and kill, and is only readable
+//! once the line is finished. In raw mode every byte is readable as soon as it is typed. Either
+//! way typed bytes can be echoed, and ^C and ^Z can be turned into signals.
+//!
+//! Like the console, the line discipline takes every byte as a character of code page 437.
+
+use alloc::{Vec, VecDeque};
+use task::signal::Signals;
+
+/// Control characters.
+pub const INTERRUPT: u8 = 0x03;
+pub const END_OF_FILE: u8 = 0x04;
+pub const BACKSPACE: u8 = 0x08;
+pub const KILL: u8 = 0x15;
+pub const SUSPEND: u8 = 0x1A;
+pub const DELETE: u8 = 0x7F;
+
+/// The longest line which can be edited. Bytes typed past this are dropped.
+const MAX_LINE: usize = 1024;
+
+/// The number of bytes kept for a reader which isn't reading. Past this typed bytes are dropped.
+const MAX_READY: usize = 4096;
+
+bitflags! {
+    pub struct Mode: u8 {
+        /// Collect input into lines, with erase and kill.
+        const CANONICAL = 1 << 0;
+        /// Echo typed bytes back to the screen.
+        const ECHO = 1 << 1;
+        /// Turn ^C and ^Z into signals.
+        const SIGNALS = 1 << 2;
+        /// Turn carriage return into newline, since that is what terminals send for enter.
+        const CR_TO_NL = 1 << 3;
+    }
+}
+
+pub struct LineDiscipline {
+    mode: Mode,
+    /// The line being edited, in canonical mode.
+    line: Vec<u8>,
+    /// Bytes readers can take.
+    ready: VecDeque<u8>,
+    /// ^D was typed on an empty line, so the next read returns nothing.
+    end_of_file: bool,
+}
+
+impl LineDiscipline {
+    pub fn new() -> Self {
+        LineDiscipline {
+            mode: Mode::all(),
+            line: Vec::new(),
+            ready: VecDeque::new(),
+            end_of_file: false,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Change mode. Leaving canonical mode makes the line being edited readable.
+    pub fn set_mode(&mut self, mode: Mode) {
+        if !mode.contains(Mode::CANONICAL) {
+            self.finish_line();
+        }
+
+        self.mode = mode;
+    }
+
+    /// Handle a typed byte, adding what should be echoed to `echo`. Returns the signals to send to
+    /// the foreground process.
+    pub fn receive(&mut self, byte: u8, echo: &mut Vec<u8>) -> Signals {
+        let byte = match byte {
+            b'\r' if self.mode.contains(Mode::CR_TO_NL) => b'\n',
+            byte => byte,
+        };
+
+        if self.mode.contains(Mode::SIGNALS) {
+            let signal = match byte {
+                INTERRUPT => Signals::INTERRUPT,
+                SUSPEND => Signals::STOP,
+                _ => Signals::empty(),
+            };
+
+            if !signal.is_empty() {
+                // Whatever was typed before the signal is thrown away.
+                self.line.clear();
+                self.ready.clear();
+
+                if self.mode.contains(Mode::ECHO) {
+                    echo_control(byte, echo);
+                    echo.push(b'\n');
+                }
+                return signal;
+            }
+        }
+
+        if !self.mode.contains(Mode::CANONICAL) {
+            self.push_ready(byte);
+
+            if self.mode.contains(Mode::ECHO) {
+                echo_byte(byte, echo);
+            }
+            return Signals::empty();
+        }
+
+        let echoing = self.mode.contains(Mode::ECHO);
+
+        match byte {
+            BACKSPACE | DELETE => if let Some(width) = self.erase() {
+                if echoing {
+                    echo_erase(width, echo);
+                }
+            },
+            KILL => while let Some(width) = self.erase() {
+                if echoing {
+                    echo_erase(width, echo);
+                }
+            },
+            END_OF_FILE => {
+                if self.line.is_empty() {
+                    self.end_of_file = true;
+                }
+                self.finish_line();
+            }
+            b'\n' => {
+                self.line.push(b'\n');
+                self.finish_line();
+
+                if echoing {
+                    echo.push(b'\n');
+                }
+            }
+            byte => if self.line.len() < MAX_LINE {
+                self.line.push(byte);
+
+                if echoing {
+                    echo_byte(byte, echo);
+                }
+            },
+        }
+
+        Signals::empty()
+    }
+
+    /// Erase the last character of the line, returning the number of cells its echo took up, or
+    /// `None` if the line was empty.
+    fn erase(&mut self) -> Option<usize> {
+        self.line.pop().map(echo_width)
+    }
+
+    /// Make the line being edited readable.
+    fn finish_line(&mut self) {
+        for byte in self.line.drain(..) {
+            if self.ready.len() < MAX_READY {
+                self.ready.push_back(byte);
+            }
+        }
+    }
+
+    fn push_ready(&mut self, byte: u8) {
+        if self.ready.len() < MAX_READY {
+            self.ready.push_back(byte);
+        }
+    }
+
+    /// Take readable bytes into `buffer`. Returns `None` if there are none yet, and `Some(0)` at
+    /// end of file. In canonical mode a read stops at the end of a line.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        if self.ready.is_empty() {
+            if self.end_of_file {
+                self.end_of_file = false;
+                return Some(0);
+            }
+            return None;
+        }
+
+        let mut count = 0;
+
+        while count < buffer.len() {
+            let byte = match self.ready.pop_front() {
+                Some(byte) => byte,
+                None => break,
+            };
+
+            buffer[count] = byte;
+            count += 1;
+
+            if byte == b'\n' && self.mode.contains(Mode::CANONICAL) {
+                break;
+            }
+        }
+
+        Some(count)
+    }
+}
+
+/// Echo a byte, showing control characters other than newline and tab as `^X`.
+fn echo_byte(byte: u8, echo: &mut Vec<u8>) {
+    match byte {
+        b'\n' | b'\t' => echo.push(byte),
+        0...0x1F | DELETE => echo_control(byte, echo),
+        byte => echo.push(byte),
+    }
+}
+
+/// The number of cells `echo_byte` takes to show a character starting with `byte`. A tab is
+/// counted as one cell, as the line discipline doesn't know the column it was typed at.
+fn echo_width(byte: u8) -> usize {
+    match byte {
+        b'\n' | b'\t' => 1,
+        0...0x1F | DELETE => 2,
+        _ => 1,
+    }
+}
+
+/// Echo what takes a character's echo off the screen.
+fn echo_erase(width: usize, echo: &mut Vec<u8>) {
+    for _ in 0..width {
+        echo.extend_from_slice(b"\x08 \x08");
+    }
+}
+
+fn echo_control(byte: u8, echo: &mut Vec<u8>) {
+    echo.push(b'^');
+    echo.push(byte ^ 0x40);
+}
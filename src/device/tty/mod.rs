@@ -0,0 +1,297 @@
+//! Virtual terminals. Each VT owns its text buffer, which holds its cursor, and a line discipline
+//! holding its input. One VT is active at a time: it is the one shown on the screen, and the one
+//! keyboard and serial input goes to. Ctrl+F1 to Ctrl+F6 switch between them, and Shift+PageUp
+//! and Shift+PageDown scroll the active one through the rows which have scrolled off its screen.
+//!
+//! Input reaches the VTs through a bottom half which consumes keyboard and serial events from the
+//! input core. The VTs are locked by both that bottom half and processes writing to them, so they
+//! are always locked with interrupts disabled.
+
+pub mod keyboard;
+pub mod ldisc;
+
+use alloc::arc::Arc;
+use alloc::Vec;
+use arch::cmdline;
+use arch::interrupts::{bottom_half, disable_interrupts_and_then};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use device::input::{Consumer, EventKind, Sources};
+use device::keyboard::layout;
+use device::vga::buffer::TextBuffer;
+use fs::{self, devfs};
+use self::keyboard::{Action, ModifierState};
+use self::ldisc::{LineDiscipline, Mode};
+use spin::{Mutex, Once};
+use task::{self, ProcessId, Scheduling, SCHEDULER};
+use task::signal::{self, Signals};
+
+/// The number of VTs.
+pub const VT_COUNT: usize = 6;
+
+/// Return the index in `layout::LAYOUTS` of the keyboard layout in use.
+pub const KBD_GET_LAYOUT: usize = 0x4B01;
+/// Switch to the keyboard layout whose index in `layout::LAYOUTS` is the argument.
+pub const KBD_SET_LAYOUT: usize = 0x4B02;
+
+/// Set the keyboard's repeat rate to the low byte of the argument and its repeat delay to the
+/// next byte.
+pub const KBD_SET_TYPEMATIC: usize = 0x4B03;
+
+/// Return the line discipline mode of the VT, as the bits of `ldisc::Mode`.
+pub const TTY_GET_MODE: usize = 0x5401;
+/// Set the line discipline mode of the VT to the bits of `ldisc::Mode` in the argument.
+pub const TTY_SET_MODE: usize = 0x5402;
+/// Continue the VT's foreground process if ^Z stopped it.
+pub const TTY_CONTINUE: usize = 0x5403;
+
+/// Return the index of the active VT, counting `/dev/tty1` as 0.
+pub const VT_GET_ACTIVE: usize = 0x5601;
+/// Make the VT whose index is the argument active.
+pub const VT_ACTIVATE: usize = 0x5602;
+
+struct Vt {
+    buffer: TextBuffer,
+    discipline: LineDiscipline,
+    /// The process which last read from this VT, which is sent the signals typed on it.
+    foreground: Option<ProcessId>,
+}
+
+impl Vt {
+    fn new() -> Self {
+        Vt {
+            buffer: TextBuffer::with_scrollback(),
+            discipline: LineDiscipline::new(),
+            foreground: None,
+        }
+    }
+
+    /// Write bytes to the buffer. Every byte is a character of code page 437, which is what the
+    /// keyboard types and the font shows.
+    fn write(&mut self, bytes: &[u8]) {
+        self.buffer.write_bytes(bytes);
+    }
+
+    /// Pass typed bytes through the line discipline and echo what it says to. Returns the
+    /// signals to send to the foreground process.
+    fn receive(&mut self, bytes: &[u8]) -> Signals {
+        let mut echo = Vec::new();
+        let mut signals = Signals::empty();
+
+        for &byte in bytes {
+            signals |= self.discipline.receive(byte, &mut echo);
+        }
+
+        self.write(&echo);
+        signals
+    }
+}
+
+lazy_static! {
+    static ref VTS: Vec<Mutex<Vt>> = (0..VT_COUNT).map(|_| Mutex::new(Vt::new())).collect();
+}
+
+/// The index of the VT on the screen.
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// Keyboard state, shared by every VT. Only the bottom half uses this.
+static STATE: Mutex<ModifierState> = Mutex::new(ModifierState::new());
+
+/// The queue of keyboard and serial events the VTs consume.
+static EVENTS: Once<Consumer> = Once::new();
+
+/// The id of the bottom half which consumes `EVENTS`.
+static BOTTOM_HALF: Once<usize> = Once::new();
+
+/// Return the index of the active VT.
+pub fn active() -> usize {
+    ACTIVE.load(Ordering::SeqCst)
+}
+
+/// Make another VT active. Its buffer is drawn as it was left, and it takes over the input.
+pub fn switch(index: usize) {
+    if index >= VT_COUNT {
+        return;
+    }
+
+    disable_interrupts_and_then(|| {
+        let previous = ACTIVE.swap(index, Ordering::SeqCst);
+
+        if previous != index {
+            VTS[previous].lock().buffer.active = false;
+
+            let mut vt = VTS[index].lock();
+            vt.buffer.active = true;
+            vt.buffer.redraw();
+        }
+    });
+}
+
+/// Scroll the active VT into its history by half screens.
+fn scroll(halves: isize) {
+    disable_interrupts_and_then(|| {
+        let mut vt = VTS[active()].lock();
+        let (_, height) = vt.buffer.size();
+        vt.buffer.scroll_view(halves * (height / 2) as isize);
+    });
+}
+
+/// Write to a VT, which is drawn if it is active.
+pub fn write(index: usize, bytes: &[u8]) {
+    disable_interrupts_and_then(|| VTS[index].lock().write(bytes));
+}
+
+/// Read from a VT, blocking until its line discipline has something for the reader. The reader
+/// becomes the VT's foreground process. Returns `FsError::Interrupted` if the reader is sent ^C.
+pub fn read(index: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+    if buffer.is_empty() {
+        return Ok(0);
+    }
+
+    let pid = SCHEDULER.get_id();
+
+    loop {
+        let count = disable_interrupts_and_then(|| {
+            if !signal::take(pid, Signals::INTERRUPT).is_empty() {
+                return Err(fs::FsError::Interrupted);
+            }
+
+            let mut vt = VTS[index].lock();
+            vt.foreground = Some(pid);
+            Ok(vt.discipline.read(buffer))
+        })?;
+
+        match count {
+            Some(count) => return Ok(count),
+            None => task::wait(),
+        }
+    }
+}
+
+/// Type bytes on the active VT.
+fn type_bytes(bytes: &[u8]) {
+    let (signals, foreground) = disable_interrupts_and_then(|| {
+        let mut vt = VTS[active()].lock();
+        (vt.receive(bytes), vt.foreground)
+    });
+
+    if let Some(pid) = foreground {
+        if !signals.is_empty() {
+            signal::send(pid, signals);
+        }
+    }
+}
+
+/// Handle every event waiting in the queue.
+fn consume_pending() {
+    let events = match EVENTS.try() {
+        Some(events) => events,
+        None => return,
+    };
+
+    while let Some(event) = events.pop() {
+        let action = match event.kind {
+            EventKind::Key(key) => STATE.lock().handle(key),
+            EventKind::Char(byte) => Some(Action::Type(vec![byte])),
+            _ => None,
+        };
+
+        match action {
+            Some(Action::Type(bytes)) => type_bytes(&bytes),
+            Some(Action::Switch(index)) => switch(index),
+            Some(Action::Scroll(halves)) => scroll(halves),
+            None => (),
+        }
+    }
+}
+
+/// `/dev/tty1` to `/dev/tty6`.
+struct TtyDevice(usize);
+
+impl devfs::CharDevice for TtyDevice {
+    fn read(&self, buffer: &mut [u8]) -> fs::Result<usize> {
+        read(self.0, buffer)
+    }
+
+    fn ioctl(&self, command: usize, argument: usize) -> fs::Result<usize> {
+        match command {
+            KBD_GET_LAYOUT => Ok(layout::current_index()),
+            KBD_SET_LAYOUT => layout::set_layout_index(argument)
+                .map(|_| 0)
+                .map_err(|_| fs::FsError::InvalidArgument),
+            KBD_SET_TYPEMATIC => {
+                let (rate, delay) = (argument & 0xFF, (argument >> 8) & 0xFF);
+
+                if rate > 0x1F || delay > 0x3 {
+                    return Err(fs::FsError::InvalidArgument);
+                }
+
+                ::device::keyboard::set_typematic(rate as u8, delay as u8);
+                Ok(0)
+            }
+            TTY_GET_MODE => Ok(disable_interrupts_and_then(|| {
+                VTS[self.0].lock().discipline.mode().bits() as usize
+            })),
+            TTY_SET_MODE => {
+                if argument > 0xFF {
+                    return Err(fs::FsError::InvalidArgument);
+                }
+
+                let mode = Mode::from_bits(argument as u8).ok_or(fs::FsError::InvalidArgument)?;
+
+                disable_interrupts_and_then(|| VTS[self.0].lock().discipline.set_mode(mode));
+                Ok(0)
+            }
+            TTY_CONTINUE => {
+                let foreground = disable_interrupts_and_then(|| VTS[self.0].lock().foreground);
+
+                if let Some(pid) = foreground {
+                    signal::send(pid, Signals::CONTINUE);
+                }
+                Ok(0)
+            }
+            VT_GET_ACTIVE => Ok(active()),
+            VT_ACTIVATE => {
+                if argument >= VT_COUNT {
+                    return Err(fs::FsError::InvalidArgument);
+                }
+
+                switch(argument);
+                Ok(0)
+            }
+            _ => Err(fs::FsError::NotSupported),
+        }
+    }
+
+    fn write(&self, buffer: &[u8]) -> fs::Result<usize> {
+        write(self.0, buffer);
+        Ok(buffer.len())
+    }
+}
+
+/// Set up the VTs with the first one active, apply the keyboard layout from the command line, and
+/// start consuming input events.
+pub fn init() {
+    {
+        let mut vt = VTS[0].lock();
+        vt.buffer.active = true;
+        vt.buffer.redraw();
+    }
+
+    for index in 0..VT_COUNT {
+        let name = format!("tty{}", index + 1);
+        devfs::register_char(&name, Arc::new(TtyDevice(index))).expect("Could not register a TTY");
+    }
+
+    if let Some(name) = cmdline::option("keymap") {
+        match layout::set_layout(name) {
+            Ok(()) => println!("[ dev ] Using the {} keyboard layout", name),
+            Err(e) => println!("[ dev ] Could not use keyboard layout {}: {}", name, e),
+        }
+    }
+
+    let id = *BOTTOM_HALF.call_once(|| {
+        bottom_half::register(consume_pending).expect("Could not register the TTY bottom half")
+    });
+
+    EVENTS.call_once(|| Consumer::with_bottom_half(Sources::KEYBOARD | Sources::SERIAL, id));
+}
@@ -0,0 +1,200 @@
+//! A parser for the subset of VT100 and xterm escape sequences the console understands. Bytes are
+//! fed in one at a time, and each completed sequence comes out as an `Action` for the console to
+//! carry out.
+
+use core::cmp;
+use device::vga::vga::Color;
+
+/// The most parameters kept for a control sequence. Any more are ignored.
+pub const MAX_PARAMS: usize = 8;
+
+const ESCAPE: u8 = 0x1B;
+const CANCEL: u8 = 0x18;
+const SUBSTITUTE: u8 = 0x1A;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    /// An escape has been received.
+    Escape,
+    /// Inside a control sequence, after `ESC [`.
+    Csi,
+    /// Inside a control sequence which isn't understood, which is dropped once it ends.
+    Ignore,
+}
+
+/// A control sequence: its parameters and final byte.
+#[derive(Debug, Clone, Copy)]
+pub struct Csi {
+    params: [u16; MAX_PARAMS],
+    count: usize,
+    /// The sequence started with `?`, as the xterm private modes do.
+    pub private: bool,
+    pub final_byte: u8,
+}
+
+impl Csi {
+    /// The parameter at `index`, or `default` if it was left out or is zero.
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(&value) if value != 0 => value,
+            _ => default,
+        }
+    }
+
+    pub fn params(&self) -> &[u16] {
+        &self.params[..cmp::min(self.count, MAX_PARAMS)]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Action {
+    /// A printable byte.
+    Print(u8),
+    /// A control character, such as newline or backspace.
+    Control(u8),
+    /// An escape followed by this byte.
+    Escape(u8),
+    Csi(Csi),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Parser {
+    state: State,
+    csi: Csi,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Parser {
+            state: State::Ground,
+            csi: Csi {
+                params: [0; MAX_PARAMS],
+                count: 0,
+                private: false,
+                final_byte: 0,
+            },
+        }
+    }
+
+    /// Feed one byte, returning an action if it completes one.
+    pub fn feed(&mut self, byte: u8) -> Option<Action> {
+        // These abort a sequence wherever they appear.
+        match byte {
+            CANCEL | SUBSTITUTE => {
+                self.state = State::Ground;
+                return None;
+            }
+            ESCAPE => {
+                self.state = State::Escape;
+                return None;
+            }
+            _ => (),
+        }
+
+        match self.state {
+            State::Ground => match byte {
+                0x00...0x1F => Some(Action::Control(byte)),
+                0x7F => None,
+                _ => Some(Action::Print(byte)),
+            },
+            State::Escape => match byte {
+                b'[' => {
+                    self.state = State::Csi;
+                    self.csi = Parser::new().csi;
+                    None
+                }
+                // Control characters still act in the middle of a sequence.
+                0x00...0x1F => Some(Action::Control(byte)),
+                _ => {
+                    self.state = State::Ground;
+                    Some(Action::Escape(byte))
+                }
+            },
+            State::Csi => self.feed_csi(byte),
+            State::Ignore => {
+                if byte >= 0x40 && byte <= 0x7E {
+                    self.state = State::Ground;
+                }
+                None
+            }
+        }
+    }
+
+    fn feed_csi(&mut self, byte: u8) -> Option<Action> {
+        let csi = &mut self.csi;
+
+        match byte {
+            0x00...0x1F => Some(Action::Control(byte)),
+            b'0'...b'9' => {
+                if csi.count == 0 {
+                    csi.count = 1;
+                }
+
+                if csi.count <= MAX_PARAMS {
+                    let param = &mut csi.params[csi.count - 1];
+                    *param = param.saturating_mul(10).saturating_add((byte - b'0') as u16);
+                }
+                None
+            }
+            b';' => {
+                // A separator with nothing before it still ends an empty parameter.
+                if csi.count == 0 {
+                    csi.count = 1;
+                }
+
+                // Past the last parameter kept, the count runs one over so the rest are dropped.
+                if csi.count <= MAX_PARAMS {
+                    csi.count += 1;
+                }
+                None
+            }
+            b'?' if csi.count == 0 => {
+                csi.private = true;
+                None
+            }
+            0x40...0x7E => {
+                self.state = State::Ground;
+                csi.final_byte = byte;
+                Some(Action::Csi(*csi))
+            }
+            // Intermediate bytes, and anything else, make this a sequence we don't understand.
+            _ => {
+                self.state = State::Ignore;
+                None
+            }
+        }
+    }
+}
+
+/// The VGA colour for one of the eight ANSI colours, or its bright version.
+pub fn color(index: u16, bright: bool) -> Color {
+    let normal = [
+        Color::Black,
+        Color::Red,
+        Color::Green,
+        Color::Brown,
+        Color::Blue,
+        Color::Magenta,
+        Color::Cyan,
+        Color::LightGray,
+    ];
+    let bright_colors = [
+        Color::DarkGray,
+        Color::LightRed,
+        Color::LightGreen,
+        Color::Yellow,
+        Color::LightBlue,
+        Color::Pink,
+        Color::LightCyan,
+        Color::White,
+    ];
+
+    let index = index as usize & 0x7;
+
+    if bright {
+        bright_colors[index]
+    } else {
+        normal[index]
+    }
+}
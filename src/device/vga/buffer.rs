@@ -1,47 +1,187 @@
-use spin::Mutex;
-use device::vga::vga::{Color, ColorCode, VGA};
-use core::fmt;
-
-/// Main print interface.
-pub fn print(args: fmt::Arguments) {
-    use core::fmt::Write;
-    SCREEN.lock().write_fmt(args).unwrap();
-}
+use alloc::{Vec, VecDeque};
+use arch::memory::heap_allocator::HEAP_SIZE;
+use core::{cmp, mem};
+use device::vga::ansi::{self, Action, Csi, Parser};
+use device::vga::cp437;
+use device::vga::screen;
+use device::vga::vga::{Color, ColorCode, ScreenChar, VGA};
 
 /// The width of the VGA text buffer.
 pub const BUFFER_WIDTH: usize = 80;
 /// The height of the VGA text buffer.
 pub const BUFFER_HEIGHT: usize = 25;
 
-#[derive(Copy, Clone)]
-/// A virtual text buffer.
+/// The ANSI colours used until SGR changes them: light grey on black.
+const DEFAULT_FOREGROUND: u16 = 7;
+const DEFAULT_BACKGROUND: u16 = 0;
+
+/// The distance between tab stops.
+const TAB_WIDTH: usize = 8;
+
+/// The most heap the rows kept after they scroll off the top of a buffer may take. Every VT keeps
+/// its own, so together they stay a tenth of the heap. How many rows fit depends on the width of
+/// the screen.
+const SCROLLBACK_BYTES: usize = HEAP_SIZE / 64;
+
+/// An empty cell in the default colours.
+const BLANK: ScreenChar = ScreenChar {
+    ascii_character: b' ',
+    color_code: ColorCode::new(Color::LightGray, Color::Black),
+};
+
+/// A virtual text buffer the size of the screen, which interprets the VT100 escape sequences in
+/// `ansi`.
 pub struct TextBuffer {
-    /// Array of rows of characters.
-    pub chars: [[u8; BUFFER_WIDTH]; BUFFER_HEIGHT],
-    /// How far along a row we are.
+    /// The size of the buffer, as columns and rows.
+    width: usize,
+    height: usize,
+    /// Rows of characters, each with its own colour.
+    pub chars: Vec<Vec<ScreenChar>>,
+    /// How far along a row we are. This is `width` after the last column has been written, so
+    /// that the line only wraps once another character is printed.
     pub column_position: usize,
-    /// Represents the colour of the TTY buffer.
+    /// The row the cursor is on.
+    pub row_position: usize,
+    /// The colour characters are written in, set by SGR sequences.
     pub color_code: ColorCode,
     pub active: bool,
+    /// The escape sequence being received.
+    parser: Parser,
+    /// The ANSI colours set by SGR, with 8 added for the bright ones.
+    foreground: u16,
+    background: u16,
+    bold: bool,
+    reverse: bool,
+    /// The cursor saved by `ESC 7` or `CSI s`, as a row and column.
+    saved_cursor: (usize, usize),
+    /// The first and last rows which scroll, set by `CSI r`.
+    scroll_top: usize,
+    scroll_bottom: usize,
+    /// Rows which have scrolled off the top, oldest first. Only buffers made with
+    /// `with_scrollback` keep them.
+    history: Option<VecDeque<Vec<ScreenChar>>>,
+    /// The number of rows the history holds before the oldest are dropped.
+    history_rows: usize,
+    /// How many rows back into the history the screen shows.
+    view_offset: usize,
+    /// The rows which have changed since the buffer was last synced.
+    dirty: Vec<bool>,
 }
 
-/// Clear the VGA buffer.
+/// Clear the VGA buffer. This doesn't allocate, so it can be used before the heap is set up.
 pub fn clear_screen() {
-    for _row in 0..BUFFER_HEIGHT {
-        SCREEN.lock().new_line();
+    let mut vga = VGA.lock();
+
+    for row in 0..BUFFER_HEIGHT {
+        vga.sync_row(row, &[BLANK; BUFFER_WIDTH]);
     }
+    vga.update_cursor(0, 0);
 }
 
 impl TextBuffer {
-    /// Sync this virtual text buffer with the actual VGA buffer at 0xb8000.
-    fn sync(&self) {
-        VGA.lock().sync_buffer(&self);
-        VGA.lock()
-            .update_cursor(BUFFER_HEIGHT - 1, self.column_position);
+    /// A blank buffer the size of the screen, which isn't shown until it is made active.
+    pub fn new() -> TextBuffer {
+        let (width, height) = screen::size();
+
+        TextBuffer {
+            width: width,
+            height: height,
+            column_position: 0,
+            row_position: 0,
+            color_code: ColorCode::new(Color::LightGray, Color::Black),
+            chars: vec![vec![BLANK; width]; height],
+            active: false,
+            parser: Parser::new(),
+            foreground: DEFAULT_FOREGROUND,
+            background: DEFAULT_BACKGROUND,
+            bold: false,
+            reverse: false,
+            saved_cursor: (0, 0),
+            scroll_top: 0,
+            scroll_bottom: height - 1,
+            history: None,
+            history_rows: 0,
+            view_offset: 0,
+            dirty: vec![true; height],
+        }
+    }
+
+    /// A blank buffer which keeps as many of the last rows to scroll off the top as fit in
+    /// `SCROLLBACK_BYTES`.
+    pub fn with_scrollback() -> TextBuffer {
+        let mut buffer = TextBuffer::new();
+        let row_size =
+            buffer.width * mem::size_of::<ScreenChar>() + mem::size_of::<Vec<ScreenChar>>();
+
+        buffer.history = Some(VecDeque::new());
+        buffer.history_rows = SCROLLBACK_BYTES / row_size;
+        buffer
+    }
+
+    /// Sync this virtual text buffer with the screen. Only the rows which have changed are drawn.
+    pub fn sync(&mut self) {
+        for row in 0..self.height {
+            if self.dirty[row] {
+                screen::draw_row(row, self.visible_row(row));
+                self.dirty[row] = false;
+            }
+        }
+
+        // While the history is shown the cursor may be below the screen, where it is hidden.
+        screen::set_cursor(
+            self.row_position + self.view_offset,
+            cmp::min(self.column_position, self.width - 1),
+        );
+    }
+
+    /// Sync every row, as when the buffer has just been made active.
+    pub fn redraw(&mut self) {
+        let height = self.height;
+        self.set_dirty(0, height);
+        self.sync();
+    }
+
+    /// Mark rows `from` up to `to` as changed.
+    fn set_dirty(&mut self, from: usize, to: usize) {
+        for row in from..to {
+            self.dirty[row] = true;
+        }
+    }
+
+    /// The row shown on the screen at `row`, taking scrolling back into the history into account.
+    fn visible_row(&self, row: usize) -> &[ScreenChar] {
+        match self.history {
+            Some(ref history) if row < self.view_offset => {
+                &history[history.len() - self.view_offset + row]
+            }
+            _ => &self.chars[row - self.view_offset],
+        }
+    }
+
+    /// Scroll the view `rows` into the history, or back towards the screen if `rows` is
+    /// negative.
+    pub fn scroll_view(&mut self, rows: isize) {
+        let available = self.history.as_ref().map_or(0, |history| history.len()) as isize;
+        let offset = cmp::max(0, cmp::min(self.view_offset as isize + rows, available)) as usize;
+
+        if offset != self.view_offset {
+            let height = self.height;
+            self.view_offset = offset;
+            self.set_dirty(0, height);
+
+            if self.active {
+                self.sync();
+            }
+        }
+    }
+
+    /// Return the size of the buffer, as columns and rows.
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
     }
 
     /// Return the current character array.
-    pub fn chars(&self) -> &[[u8; BUFFER_WIDTH]; BUFFER_HEIGHT] {
+    pub fn chars(&self) -> &[Vec<ScreenChar>] {
         &self.chars
     }
 
@@ -50,29 +190,20 @@ impl TextBuffer {
         self.color_code
     }
 
-    /// Write a byte to the VGA buffer.
+    /// Write a byte to the buffer. Control characters and escape sequences are carried out
+    /// rather than shown.
     pub fn write_byte(&mut self, byte: u8) {
-        match byte {
-            // Newline character.
-            b'\n' => self.new_line(),
-            // Backspace.
-            0x8 => self.delete_byte(),
-            // Tab escape.
-            b'\t' => for _ in 0..4 {
-                self.write_byte(b' ');
-            },
-            // Catch-all pattern that just updates the character array with the given byte.
-            byte => {
-                if self.column_position >= BUFFER_WIDTH {
-                    // At end of row.
-                    self.new_line();
-                }
+        self.process(byte);
 
-                let row = BUFFER_HEIGHT - 1;
-                let col = self.column_position;
-                self.chars[row][col] = byte;
-                self.column_position += 1;
-            }
+        if self.active {
+            self.sync();
+        }
+    }
+
+    /// Write bytes to the buffer, syncing once at the end rather than after every byte.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.process(byte);
         }
 
         if self.active {
@@ -80,97 +211,413 @@ impl TextBuffer {
         }
     }
 
-    /// Delete a single byte from the buffer.
-    pub fn delete_byte(&mut self) {
-        if self.column_position == 0 {
-            //At start of row, no bytes to delete.
+    fn process(&mut self, byte: u8) {
+        let action = match self.parser.feed(byte) {
+            Some(action) => action,
+            None => return,
+        };
+
+        // Output brings the view back from the history.
+        if self.view_offset != 0 {
+            let height = self.height;
+            self.view_offset = 0;
+            self.set_dirty(0, height);
+        }
+
+        match action {
+            Action::Print(byte) => self.print(byte),
+            Action::Control(byte) => self.control(byte),
+            Action::Escape(byte) => self.escape(byte),
+            Action::Csi(csi) => self.csi(&csi),
+        }
+    }
+
+    /// An empty cell in the current colours, which erased cells are filled with.
+    fn blank(&self) -> ScreenChar {
+        ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        }
+    }
+
+    /// Put a character at the cursor and move the cursor along, wrapping at the end of the row.
+    fn print(&mut self, byte: u8) {
+        if self.column_position >= self.width {
+            self.column_position = 0;
+            self.line_feed();
+        }
+
+        let (row, col) = (self.row_position, self.column_position);
+        self.chars[row][col] = ScreenChar {
+            ascii_character: byte,
+            color_code: self.color_code,
+        };
+        self.dirty[row] = true;
+        self.column_position += 1;
+    }
+
+    fn control(&mut self, byte: u8) {
+        match byte {
+            // Output uses newline alone to end a line, so it returns the carriage too.
+            b'\n' => self.new_line(),
+            b'\r' => self.column_position = 0,
+            // Backspace only moves the cursor. Erasing is done by writing over the character.
+            0x8 => {
+                self.column_position = cmp::min(self.column_position, self.width - 1).saturating_sub(1)
+            }
+            b'\t' => {
+                let next = (self.column_position / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.column_position = cmp::min(next, self.width - 1);
+            }
+            // Vertical tab and form feed.
+            0xB | 0xC => self.line_feed(),
+            _ => (),
+        }
+    }
+
+    fn escape(&mut self, byte: u8) {
+        match byte {
+            b'7' => self.save_cursor(),
+            b'8' => self.restore_cursor(),
+            // Index, next line and reverse index.
+            b'D' => self.line_feed(),
+            b'E' => self.new_line(),
+            b'M' => self.reverse_index(),
+            // Reset to the initial state.
+            b'c' => {
+                let active = self.active;
+                let history = self.history.take();
+                *self = TextBuffer::new();
+                self.active = active;
+                self.history = history;
+            }
+            _ => (),
+        }
+    }
+
+    fn csi(&mut self, csi: &Csi) {
+        // The xterm private modes, such as showing and hiding the cursor, aren't supported.
+        if csi.private {
             return;
         }
 
-        let col = self.column_position - 1;
+        let n = csi.param(0, 1) as usize;
 
-        self.chars[BUFFER_HEIGHT - 1][col] = b' ';
-        self.column_position -= 1;
+        match csi.final_byte {
+            b'A' => self.cursor_up(n),
+            b'B' => self.cursor_down(n),
+            b'C' => self.column_position = cmp::min(self.column_position + n, self.width - 1),
+            b'D' => {
+                self.column_position = cmp::min(self.column_position, self.width - 1).saturating_sub(n)
+            }
+            b'E' => {
+                self.cursor_down(n);
+                self.column_position = 0;
+            }
+            b'F' => {
+                self.cursor_up(n);
+                self.column_position = 0;
+            }
+            b'G' => self.column_position = cmp::min(n - 1, self.width - 1),
+            b'd' => self.row_position = cmp::min(n - 1, self.height - 1),
+            b'H' | b'f' => {
+                self.row_position = cmp::min(n - 1, self.height - 1);
+                self.column_position = cmp::min(csi.param(1, 1) as usize - 1, self.width - 1);
+            }
+            b'J' => self.erase_in_display(csi.param(0, 0)),
+            b'K' => self.erase_in_line(csi.param(0, 0)),
+            b'L' => if self.in_scroll_region() {
+                let (row, bottom) = (self.row_position, self.scroll_bottom);
+                self.scroll_down(row, bottom, n);
+            },
+            b'M' => if self.in_scroll_region() {
+                let (row, bottom) = (self.row_position, self.scroll_bottom);
+                self.scroll_up(row, bottom, n);
+            },
+            b'@' => self.insert_chars(n),
+            b'P' => self.delete_chars(n),
+            b'X' => {
+                let (row, col) = (self.row_position, cmp::min(self.column_position, self.width - 1));
+                self.erase(row, col, col + n);
+            }
+            b'S' => {
+                let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+                self.scroll_up(top, bottom, n);
+            }
+            b'T' => {
+                let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+                self.scroll_down(top, bottom, n);
+            }
+            b'm' => self.select_graphic_rendition(csi.params()),
+            b'r' => {
+                let top = csi.param(0, 1) as usize - 1;
+                let bottom = cmp::min(csi.param(1, self.height as u16) as usize, self.height) - 1;
 
-        if self.active {
-            self.sync();
+                if top < bottom {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                    self.row_position = 0;
+                    self.column_position = 0;
+                }
+            }
+            b's' => self.save_cursor(),
+            b'u' => self.restore_cursor(),
+            _ => (),
         }
     }
 
+    fn save_cursor(&mut self) {
+        self.saved_cursor = (self.row_position, self.column_position);
+    }
+
+    fn restore_cursor(&mut self) {
+        let (row, col) = self.saved_cursor;
+        self.row_position = row;
+        self.column_position = col;
+    }
+
+    fn in_scroll_region(&self) -> bool {
+        self.row_position >= self.scroll_top && self.row_position <= self.scroll_bottom
+    }
+
+    /// Move the cursor up, stopping at the top of the scroll region if it starts inside it.
+    fn cursor_up(&mut self, n: usize) {
+        let top = if self.in_scroll_region() { self.scroll_top } else { 0 };
+        self.row_position = cmp::max(self.row_position.saturating_sub(n), top);
+    }
+
+    /// Move the cursor down, stopping at the bottom of the scroll region if it starts inside it.
+    fn cursor_down(&mut self, n: usize) {
+        let bottom = if self.in_scroll_region() {
+            self.scroll_bottom
+        } else {
+            self.height - 1
+        };
+        self.row_position = cmp::min(self.row_position + n, bottom);
+    }
+
     /// Newline. This method will be called when a `\n` character is written
     /// to the virtual buffer.
     pub fn new_line(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                self.chars[row - 1][col] = self.chars[row][col]
+        self.column_position = 0;
+        self.line_feed();
+    }
+
+    /// Move the cursor down a row, scrolling the scroll region if it is on its last row.
+    fn line_feed(&mut self) {
+        if self.row_position == self.scroll_bottom {
+            let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+            self.scroll_up(top, bottom, 1);
+        } else if self.row_position < self.height - 1 {
+            self.row_position += 1;
+        }
+    }
+
+    /// Move the cursor up a row, scrolling the scroll region down if it is on its first row.
+    fn reverse_index(&mut self) {
+        if self.row_position == self.scroll_top {
+            let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+            self.scroll_down(top, bottom, 1);
+        } else if self.row_position > 0 {
+            self.row_position -= 1;
+        }
+    }
+
+    /// Move rows `top` to `bottom` up by `n`, clearing the rows left at the bottom. Rows which
+    /// scroll off the top of the screen go into the history.
+    fn scroll_up(&mut self, top: usize, bottom: usize, n: usize) {
+        if top == 0 {
+            let limit = self.history_rows;
+
+            if let Some(ref mut history) = self.history {
+                for row in 0..cmp::min(n, bottom + 1) {
+                    // Reuse the oldest row once the history is full rather than allocating.
+                    let mut saved = if history.len() >= limit {
+                        history.pop_front().unwrap_or_else(Vec::new)
+                    } else {
+                        Vec::new()
+                    };
+
+                    saved.clone_from(&self.chars[row]);
+                    history.push_back(saved);
+                }
             }
         }
 
-        self.clear_row(BUFFER_HEIGHT - 1);
-        //Set position to start of row.
-        self.column_position = 0;
+        self.set_dirty(top, bottom + 1);
 
-        if self.active {
-            self.sync();
+        // Each row is swapped with the one which moves into its place, and rows left holding
+        // what has moved are either swapped again or cleared.
+        for row in top..bottom + 1 {
+            if row + n <= bottom {
+                self.chars.swap(row, row + n);
+            } else {
+                self.clear_row(row);
+            }
         }
     }
 
-    /// Clear a single row by stepping across the entire width of the current row, and writing a
-    /// blank character to each position.
-    pub fn clear_row(&mut self, row: usize) {
-        for col in 0..BUFFER_WIDTH {
-            self.chars[row][col] = b' ';
+    /// Move rows `top` to `bottom` down by `n`, clearing the rows left at the top.
+    fn scroll_down(&mut self, top: usize, bottom: usize, n: usize) {
+        self.set_dirty(top, bottom + 1);
+
+        for row in (top..bottom + 1).rev() {
+            if row >= top + n {
+                self.chars.swap(row, row - n);
+            } else {
+                self.clear_row(row);
+            }
         }
     }
-}
 
-impl ::core::fmt::Write for TextBuffer {
-    fn write_str(&mut self, s: &str) -> ::core::fmt::Result {
-        for byte in s.bytes() {
-            self.write_byte(byte)
+    /// Erase the columns from `from` up to `to` of a row.
+    fn erase(&mut self, row: usize, from: usize, to: usize) {
+        let blank = self.blank();
+
+        for col in from..cmp::min(to, self.width) {
+            self.chars[row][col] = blank;
         }
+        self.dirty[row] = true;
+    }
 
-        Ok(())
+    /// Erase from the cursor to the end of the screen (0), from the start of the screen to the
+    /// cursor (1), or the whole screen (2 or 3).
+    fn erase_in_display(&mut self, mode: u16) {
+        let row = self.row_position;
+
+        match mode {
+            0 => {
+                self.erase_in_line(0);
+                for row in row + 1..self.height {
+                    self.clear_row(row);
+                }
+            }
+            1 => {
+                self.erase_in_line(1);
+                for row in 0..row {
+                    self.clear_row(row);
+                }
+            }
+            2 | 3 => for row in 0..self.height {
+                self.clear_row(row);
+            },
+            _ => (),
+        }
     }
-}
 
-/// Global interface to the VGA text mode.
-pub static SCREEN: Mutex<TextBuffer> = Mutex::new(TextBuffer {
-    column_position: 0,
-    color_code: ColorCode::new(Color::LightGray, Color::Black),
-    chars: [[b' '; BUFFER_WIDTH]; BUFFER_HEIGHT],
-    active: true,
-});
+    /// Erase from the cursor to the end of the row (0), from the start of the row to the cursor
+    /// (1), or the whole row (2).
+    fn erase_in_line(&mut self, mode: u16) {
+        let (row, col) = (self.row_position, cmp::min(self.column_position, self.width - 1));
+
+        let width = self.width;
+
+        match mode {
+            0 => self.erase(row, col, width),
+            1 => self.erase(row, 0, col + 1),
+            2 => self.clear_row(row),
+            _ => (),
+        }
+    }
+
+    /// Insert blanks at the cursor, moving the rest of the row right.
+    fn insert_chars(&mut self, n: usize) {
+        let (row, col) = (self.row_position, cmp::min(self.column_position, self.width - 1));
+        let blank = self.blank();
+
+        for i in (col..self.width).rev() {
+            let moved = if i >= col + n {
+                self.chars[row][i - n]
+            } else {
+                blank
+            };
+            self.chars[row][i] = moved;
+        }
+        self.dirty[row] = true;
+    }
+
+    /// Delete characters at the cursor, moving the rest of the row left.
+    fn delete_chars(&mut self, n: usize) {
+        let (row, col) = (self.row_position, cmp::min(self.column_position, self.width - 1));
+        let blank = self.blank();
+
+        for i in col..self.width {
+            let moved = if i + n < self.width {
+                self.chars[row][i + n]
+            } else {
+                blank
+            };
+            self.chars[row][i] = moved;
+        }
+        self.dirty[row] = true;
+    }
+
+    /// Carry out an SGR sequence, which sets colours and attributes.
+    fn select_graphic_rendition(&mut self, params: &[u16]) {
+        // No parameters means the same as a single 0.
+        if params.is_empty() {
+            self.select_graphic_rendition(&[0]);
+            return;
+        }
 
-pub static TTYS: Mutex<Option<[TextBuffer; 6]>> = Mutex::new(None);
+        for &param in params {
+            match param {
+                0 => {
+                    self.foreground = DEFAULT_FOREGROUND;
+                    self.background = DEFAULT_BACKGROUND;
+                    self.bold = false;
+                    self.reverse = false;
+                }
+                1 => self.bold = true,
+                22 => self.bold = false,
+                7 => self.reverse = true,
+                27 => self.reverse = false,
+                30...37 => self.foreground = param - 30,
+                39 => self.foreground = DEFAULT_FOREGROUND,
+                40...47 => self.background = param - 40,
+                49 => self.background = DEFAULT_BACKGROUND,
+                90...97 => self.foreground = param - 90 + 8,
+                100...107 => self.background = param - 100 + 8,
+                _ => (),
+            }
+        }
 
-/// Switch `SCREEN` to `ttys[index]`.
-pub fn switch(index: usize) {
-    let inner = |idx: usize, list: &mut [TextBuffer; 6]| {
-        list[idx].active = true;
-        *SCREEN.lock() = list[idx];
-    };
+        // Bold is shown as the bright version of the foreground colour, as the Linux console does.
+        let mut foreground = ansi::color(self.foreground, self.foreground >= 8 || self.bold);
+        let mut background = ansi::color(self.background, self.background >= 8);
+
+        if self.reverse {
+            let swapped = foreground;
+            foreground = background;
+            background = swapped;
+        }
 
-    let mut list = *TTYS.lock();
+        self.color_code = ColorCode::new(foreground, background);
+    }
 
-    let list = match list {
-        Some(ref mut t) => t,
-        None => panic!("TTY list called before init."),
-    };
+    /// Clear a single row by stepping across the entire width of the current row, and writing a
+    /// blank character to each position.
+    pub fn clear_row(&mut self, row: usize) {
+        let blank = self.blank();
 
-    // Only gets called if `list` is Some
-    inner(index, list);
+        for col in 0..self.width {
+            self.chars[row][col] = blank;
+        }
+        self.dirty[row] = true;
+    }
 }
 
-/// Initialise all the TTYS.
-pub fn tty_init() {
-    // Create six identical TTYS.
-    let buffers: [TextBuffer; 6] = [TextBuffer {
-        column_position: 0,
-        color_code: ColorCode::new(Color::LightGray, Color::Black),
-        chars: [[b' '; BUFFER_WIDTH]; BUFFER_HEIGHT],
-        active: false,
-    }; 6];
-
-    *TTYS.lock() = Some(buffers);
+impl ::core::fmt::Write for TextBuffer {
+    /// The buffer holds code page 437, so the characters of the string are encoded into it, with
+    /// `?` for those it doesn't have.
+    fn write_str(&mut self, s: &str) -> ::core::fmt::Result {
+        for c in s.chars() {
+            self.process(cp437::encode(c).unwrap_or(b'?'));
+        }
+
+        if self.active {
+            self.sync();
+        }
+        Ok(())
+    }
 }
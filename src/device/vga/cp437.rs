@@ -0,0 +1,20 @@
+//! Code page 437, the character set of the VGA text mode font. The console stores one byte per
+//! cell, so characters outside ASCII are stored as their code in this page.
+
+/// The characters of codes 0x80 to 0xFF.
+const UPPER: &'static str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»\
+                             ░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀\
+                             αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{A0}";
+
+/// The code of `c`, if it has one. The glyphs the font shows for control characters are left
+/// out, since those codes are taken as control characters when typed.
+pub fn encode(c: char) -> Option<u8> {
+    if (c as u32) < 0x80 {
+        return Some(c as u8);
+    }
+
+    UPPER
+        .chars()
+        .position(|upper| upper == c)
+        .map(|index| 0x80 + index as u8)
+}
@@ -0,0 +1,95 @@
+//! Bitmap fonts in the PC Screen Font format, used to draw text on a framebuffer. Both versions of
+//! the format are understood, though only the glyphs are used: the Unicode table some fonts carry
+//! is ignored, and characters are looked up by their byte.
+
+/// The font built into the kernel: 8x16 glyphs for code page 437, the characters the VGA text
+/// mode shows, with a box for the control characters.
+static BUILTIN: &[u8] = include_bytes!("font.psf");
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+
+/// A PSF1 font has 512 glyphs rather than 256 if this bit of its mode is set.
+const PSF1_MODE_512: u8 = 0x01;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Font {
+    glyphs: &'static [u8],
+    glyph_count: usize,
+    bytes_per_glyph: usize,
+    /// The size of a glyph in pixels.
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Font {
+    /// Parse a PSF1 or PSF2 font.
+    pub fn parse(data: &'static [u8]) -> Result<Font, &'static str> {
+        if data.len() >= 4 && data[..2] == PSF1_MAGIC {
+            let glyph_count = if data[2] & PSF1_MODE_512 != 0 { 512 } else { 256 };
+            let height = data[3] as usize;
+
+            Font::new(&data[4..], glyph_count, height, 8, height)
+        } else if data.len() >= 32 && data[..4] == PSF2_MAGIC {
+            let field = |i: usize| {
+                let at = i * 4;
+                (data[at] as usize) | (data[at + 1] as usize) << 8 | (data[at + 2] as usize) << 16
+                    | (data[at + 3] as usize) << 24
+            };
+
+            let header_size = field(2);
+
+            if header_size > data.len() {
+                return Err("PSF2 header is larger than the font");
+            }
+
+            Font::new(&data[header_size..], field(4), field(6), field(7), field(5))
+        } else {
+            Err("Not a PSF font")
+        }
+    }
+
+    fn new(
+        glyphs: &'static [u8],
+        glyph_count: usize,
+        height: usize,
+        width: usize,
+        bytes_per_glyph: usize,
+    ) -> Result<Font, &'static str> {
+        if glyph_count == 0 || height == 0 || width == 0 {
+            return Err("PSF font has no glyphs");
+        }
+
+        // Each row of a glyph is padded to a whole number of bytes.
+        if bytes_per_glyph < (width + 7) / 8 * height {
+            return Err("PSF glyphs are too small for their size");
+        }
+
+        if glyphs.len() < glyph_count * bytes_per_glyph {
+            return Err("PSF font is truncated");
+        }
+
+        Ok(Font {
+            glyphs: glyphs,
+            glyph_count: glyph_count,
+            bytes_per_glyph: bytes_per_glyph,
+            width: width,
+            height: height,
+        })
+    }
+
+    /// The font built into the kernel.
+    pub fn builtin() -> Font {
+        Font::parse(BUILTIN).expect("The built in font is invalid")
+    }
+
+    /// Whether the pixel at `x` and `y` of the glyph for `byte` is set. Bytes without a glyph are
+    /// drawn as glyph 0.
+    pub fn pixel(&self, byte: u8, x: usize, y: usize) -> bool {
+        let index = if (byte as usize) < self.glyph_count { byte as usize } else { 0 };
+        let bytes_per_row = (self.width + 7) / 8;
+        let row = index * self.bytes_per_glyph + y * bytes_per_row;
+
+        self.glyphs[row + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+}
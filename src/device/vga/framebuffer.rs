@@ -0,0 +1,310 @@
+//! A console on the linear framebuffer the bootloader sets up if it honours the framebuffer tag in
+//! the multiboot header. Text is drawn with the built in PSF font, so the number of columns and
+//! rows depends on the resolution. Only direct colour framebuffers are used: if the bootloader
+//! gives us an indexed one, or leaves the display in text mode, text stays in the VGA buffer.
+
+use arch::memory;
+use arch::memory::paging::entry::EntryFlags;
+use arch::multiboot::{self, read_u32};
+use core::ptr;
+use device::vga::font::Font;
+use device::vga::screen::Screen;
+use device::vga::vga::ScreenChar;
+use spin::Mutex;
+
+/// The multiboot2 tag describing the framebuffer.
+const FRAMEBUFFER_TAG: u32 = 8;
+
+/// The framebuffer type for direct colour, where each pixel holds its red, green and blue.
+const TYPE_RGB: u8 = 1;
+
+/// The red, green and blue of the 16 VGA colours, in the order of `vga::Color`.
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0xAA),
+    (0x00, 0xAA, 0x00),
+    (0x00, 0xAA, 0xAA),
+    (0xAA, 0x00, 0x00),
+    (0xAA, 0x00, 0xAA),
+    (0xAA, 0x55, 0x00),
+    (0xAA, 0xAA, 0xAA),
+    (0x55, 0x55, 0x55),
+    (0x55, 0x55, 0xFF),
+    (0x55, 0xFF, 0x55),
+    (0x55, 0xFF, 0xFF),
+    (0xFF, 0x55, 0x55),
+    (0xFF, 0x55, 0xFF),
+    (0xFF, 0xFF, 0x55),
+    (0xFF, 0xFF, 0xFF),
+];
+
+/// The number of rows of pixels at the bottom of a cell the cursor covers.
+const CURSOR_HEIGHT: usize = 2;
+
+/// The framebuffer console, if the bootloader gave us a framebuffer we can draw on.
+pub static FRAMEBUFFER: Mutex<Option<Framebuffer>> = Mutex::new(None);
+
+/// Where one of red, green and blue is within a pixel.
+#[derive(Debug, Clone, Copy)]
+struct Channel {
+    /// The bit the channel starts at.
+    position: u8,
+    /// The number of bits in the channel.
+    size: u8,
+}
+
+impl Channel {
+    /// Place an 8 bit intensity in this channel.
+    fn encode(&self, value: u8) -> u32 {
+        let value = value as u32;
+
+        let scaled = if self.size >= 8 {
+            value << (self.size - 8)
+        } else {
+            value >> (8 - self.size)
+        };
+
+        scaled << self.position
+    }
+}
+
+pub struct Framebuffer {
+    /// The address the framebuffer is identity mapped at.
+    address: usize,
+    /// The number of bytes between the starts of two rows of pixels.
+    pitch: usize,
+    /// The resolution in pixels.
+    width: usize,
+    height: usize,
+    bytes_per_pixel: usize,
+    font: Font,
+    /// The pixel value of each VGA colour.
+    colors: [u32; 16],
+    /// The cell the cursor is drawn in, if it is drawn.
+    cursor: Option<(usize, usize)>,
+}
+
+impl Framebuffer {
+    fn pixel_address(&self, x: usize, y: usize) -> usize {
+        self.address + y * self.pitch + x * self.bytes_per_pixel
+    }
+
+    fn write_pixel(&mut self, x: usize, y: usize, value: u32) {
+        let address = self.pixel_address(x, y);
+
+        unsafe {
+            match self.bytes_per_pixel {
+                4 => ptr::write_volatile(address as *mut u32, value),
+                2 => ptr::write_volatile(address as *mut u16, value as u16),
+                _ => for i in 0..self.bytes_per_pixel {
+                    ptr::write_volatile((address + i) as *mut u8, (value >> (i * 8)) as u8);
+                },
+            }
+        }
+    }
+
+    fn read_pixel(&self, x: usize, y: usize) -> u32 {
+        let address = self.pixel_address(x, y);
+
+        unsafe {
+            match self.bytes_per_pixel {
+                4 => ptr::read_volatile(address as *const u32),
+                2 => ptr::read_volatile(address as *const u16) as u32,
+                _ => (0..self.bytes_per_pixel).fold(0, |value, i| {
+                    value | (ptr::read_volatile((address + i) as *const u8) as u32) << (i * 8)
+                }),
+            }
+        }
+    }
+
+    /// Fill the whole framebuffer with black.
+    fn clear(&mut self) {
+        let black = self.colors[0];
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                self.write_pixel(x, y, black);
+            }
+        }
+    }
+
+    /// Draw a character and its background in a cell.
+    fn draw_cell(&mut self, row: usize, col: usize, cell: ScreenChar) {
+        let font = self.font;
+        let foreground = self.colors[cell.color_code.foreground() as usize];
+        let background = self.colors[cell.color_code.background() as usize];
+        let (left, top) = (col * font.width, row * font.height);
+
+        for y in 0..font.height {
+            for x in 0..font.width {
+                let value = if font.pixel(cell.ascii_character, x, y) {
+                    foreground
+                } else {
+                    background
+                };
+
+                self.write_pixel(left + x, top + y, value);
+            }
+        }
+    }
+
+    /// Invert the bottom of a cell, which draws the cursor there or removes it.
+    fn invert_cursor(&mut self, row: usize, col: usize) {
+        let font = self.font;
+        let mask = if self.bytes_per_pixel >= 4 {
+            !0
+        } else {
+            (1 << (self.bytes_per_pixel * 8)) - 1
+        };
+        let left = col * font.width;
+        let bottom = (row + 1) * font.height;
+
+        for y in bottom - CURSOR_HEIGHT..bottom {
+            for x in left..left + font.width {
+                let value = self.read_pixel(x, y);
+                self.write_pixel(x, y, value ^ mask);
+            }
+        }
+    }
+}
+
+impl Screen for Framebuffer {
+    fn size(&self) -> (usize, usize) {
+        (self.width / self.font.width, self.height / self.font.height)
+    }
+
+    fn draw_row(&mut self, row: usize, cells: &[ScreenChar]) {
+        let (columns, rows) = self.size();
+
+        if row >= rows {
+            return;
+        }
+
+        for (col, &cell) in cells.iter().take(columns).enumerate() {
+            self.draw_cell(row, col, cell);
+        }
+
+        // Drawing over the cursor removes it.
+        if let Some((cursor_row, cursor_col)) = self.cursor {
+            if cursor_row == row && cursor_col < cells.len() {
+                self.cursor = None;
+            }
+        }
+    }
+
+    fn set_cursor(&mut self, row: usize, col: usize) {
+        if self.cursor == Some((row, col)) {
+            return;
+        }
+
+        if let Some((old_row, old_col)) = self.cursor.take() {
+            self.invert_cursor(old_row, old_col);
+        }
+
+        let (columns, rows) = self.size();
+
+        if row < rows && col < columns {
+            self.invert_cursor(row, col);
+            self.cursor = Some((row, col));
+        }
+    }
+}
+
+/// Look for the framebuffer tag in the multiboot information structure, and if the framebuffer is
+/// one we can draw on, map it and make it the screen. This must be called once memory is set up
+/// and before the TTYs are, since their buffers take the size of the screen.
+pub unsafe fn init(multiboot_info: usize) {
+    // The address, pitch, width and height, the bits per pixel and the type, then two reserved
+    // bytes and, for direct colour, the position and size of red, green and blue.
+    let tag = match multiboot::find(multiboot_info, FRAMEBUFFER_TAG) {
+        Some(tag) if tag.len() >= 30 => tag,
+        _ => return,
+    };
+
+    let address = read_u32(tag, 0) as usize | (read_u32(tag, 4) as usize) << 32;
+    let pitch = read_u32(tag, 8) as usize;
+    let width = read_u32(tag, 12) as usize;
+    let height = read_u32(tag, 16) as usize;
+    let bits_per_pixel = tag[20] as usize;
+    let framebuffer_type = tag[21];
+
+    if framebuffer_type != TYPE_RGB {
+        return;
+    }
+
+    if bits_per_pixel < 15 || bits_per_pixel > 32 {
+        println!(
+            "[ dev ] Framebuffer has {} bits per pixel, which isn't supported",
+            bits_per_pixel
+        );
+        return;
+    }
+
+    let bytes_per_pixel = (bits_per_pixel + 7) / 8;
+
+    // A row of pixels which doesn't fit in the pitch would be drawn over the next row, and the
+    // last row past the end of the mapping.
+    if width * bytes_per_pixel > pitch {
+        println!(
+            "[ dev ] Framebuffer rows of {} pixels don't fit in a pitch of {} bytes",
+            width, pitch
+        );
+        return;
+    }
+
+    let channel = |at: usize| Channel {
+        position: tag[at],
+        size: tag[at + 1],
+    };
+    let (red, green, blue) = (channel(24), channel(26), channel(28));
+
+    // A channel which doesn't fit in the pixel would be shifted out of the 32 bits it is encoded
+    // in.
+    let fits = |c: &Channel| c.size != 0 && c.position as usize + c.size as usize <= bits_per_pixel;
+
+    if ![red, green, blue].iter().all(fits) {
+        println!(
+            "[ dev ] Framebuffer colour channels {:?}, {:?} and {:?} don't fit in {} bits",
+            red, green, blue, bits_per_pixel
+        );
+        return;
+    }
+
+    let mut colors = [0; 16];
+    for (color, &(r, g, b)) in colors.iter_mut().zip(PALETTE.iter()) {
+        *color = red.encode(r) | green.encode(g) | blue.encode(b);
+    }
+
+    // Write through, so that what is drawn reaches the screen without the cache being flushed.
+    memory::identity_map(
+        address,
+        pitch * height,
+        EntryFlags::WRITABLE | EntryFlags::WRITE_THROUGH | EntryFlags::NO_EXECUTE,
+    );
+
+    let mut framebuffer = Framebuffer {
+        address: address,
+        pitch: pitch,
+        width: width,
+        height: height,
+        bytes_per_pixel: bytes_per_pixel,
+        font: Font::builtin(),
+        colors: colors,
+        cursor: None,
+    };
+
+    let (columns, rows) = framebuffer.size();
+
+    if columns == 0 || rows == 0 {
+        println!("[ dev ] Framebuffer is too small to draw text on");
+        return;
+    }
+
+    framebuffer.clear();
+    println!(
+        "[ dev ] Framebuffer console at {:#x}: {}x{}x{}, {} columns and {} rows",
+        address, width, height, bits_per_pixel, columns, rows
+    );
+
+    *FRAMEBUFFER.lock() = Some(framebuffer);
+}
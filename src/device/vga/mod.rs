@@ -1,6 +1,7 @@
+pub mod ansi;
 pub mod buffer;
+pub mod cp437;
+pub mod font;
+pub mod framebuffer;
+pub mod screen;
 pub mod vga;
-
-pub fn init() {
-    self::buffer::tty_init();
-}
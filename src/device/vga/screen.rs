@@ -0,0 +1,41 @@
+//! The screen text buffers are drawn on. This is the VGA text buffer, unless the bootloader set up
+//! a graphics mode, in which case text is drawn on its framebuffer.
+
+use device::vga::framebuffer::FRAMEBUFFER;
+use device::vga::vga::{ScreenChar, VGA};
+
+/// Something which shows a grid of character cells.
+pub trait Screen {
+    /// The size of the grid, as columns and rows.
+    fn size(&self) -> (usize, usize);
+
+    /// Draw one row of cells. `cells` holds a cell for every column.
+    fn draw_row(&mut self, row: usize, cells: &[ScreenChar]);
+
+    /// Show the cursor at `row` and `col`. A position off the screen hides it.
+    fn set_cursor(&mut self, row: usize, col: usize);
+}
+
+/// The size of the screen, as columns and rows.
+pub fn size() -> (usize, usize) {
+    match *FRAMEBUFFER.lock() {
+        Some(ref framebuffer) => framebuffer.size(),
+        None => VGA.lock().size(),
+    }
+}
+
+/// Draw one row of cells on the screen.
+pub fn draw_row(row: usize, cells: &[ScreenChar]) {
+    match *FRAMEBUFFER.lock() {
+        Some(ref mut framebuffer) => framebuffer.draw_row(row, cells),
+        None => VGA.lock().draw_row(row, cells),
+    }
+}
+
+/// Move the cursor on the screen.
+pub fn set_cursor(row: usize, col: usize) {
+    match *FRAMEBUFFER.lock() {
+        Some(ref mut framebuffer) => framebuffer.set_cursor(row, col),
+        None => VGA.lock().set_cursor(row, col),
+    }
+}
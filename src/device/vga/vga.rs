@@ -1,6 +1,8 @@
 //! VGA - Interface to the VGA text buffer at physical address 0xb8000.
 
-use device::vga::buffer::{TextBuffer, BUFFER_HEIGHT, BUFFER_WIDTH};
+use device::vga::buffer::{BUFFER_HEIGHT, BUFFER_WIDTH};
+use device::vga::screen::Screen;
+use core::cmp;
 use core::ptr::Unique;
 use spin::Mutex;
 use volatile::Volatile;
@@ -37,6 +39,16 @@ impl ColorCode {
     pub const fn new(foreground: Color, background: Color) -> ColorCode {
         ColorCode((background as u8) << 4 | (foreground as u8))
     }
+
+    /// The index of the foreground colour.
+    pub fn foreground(&self) -> u8 {
+        self.0 & 0xF
+    }
+
+    /// The index of the background colour.
+    pub fn background(&self) -> u8 {
+        self.0 >> 4
+    }
 }
 
 #[repr(C)]
@@ -72,20 +84,12 @@ impl Vga {
         unsafe { self.frame.as_mut() }
     }
 
-    /// Sync the virtual `buffer` with the `ScreenBuffer` pointer.
-    pub fn sync_buffer(&mut self, buffer: &TextBuffer) {
+    /// Write one row of characters to the screen.
+    pub fn sync_row(&mut self, row: usize, chars: &[ScreenChar]) {
         let frame = self.frame();
 
-        for row in 0..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                // Update using the text buffer.
-                let character = ScreenChar {
-                    ascii_character: buffer.chars()[row][col],
-                    color_code: buffer.color_code(),
-                };
-
-                frame.chars[row][col].write(character);
-            }
+        for col in 0..cmp::min(chars.len(), BUFFER_WIDTH) {
+            frame.chars[row][col].write(chars[col]);
         }
     }
 
@@ -106,3 +110,20 @@ impl Vga {
         }
     }
 }
+
+impl Screen for Vga {
+    fn size(&self) -> (usize, usize) {
+        (BUFFER_WIDTH, BUFFER_HEIGHT)
+    }
+
+    fn draw_row(&mut self, row: usize, cells: &[ScreenChar]) {
+        if row < BUFFER_HEIGHT {
+            self.sync_row(row, cells);
+        }
+    }
+
+    fn set_cursor(&mut self, row: usize, col: usize) {
+        // Positions past the end of the buffer hide the cursor, but the position must still fit.
+        self.update_cursor(cmp::min(row, BUFFER_HEIGHT), col);
+    }
+}
@@ -0,0 +1,147 @@
+//! Unpacking of newc format cpio archives, as produced by `find | cpio -o -H newc`.
+
+use alloc::arc::Arc;
+use core::str;
+use super::{FileType, FsError, Inode, Result};
+
+const HEADER_SIZE: usize = 110;
+const TRAILER: &'static str = "TRAILER!!!";
+
+/// File type bits of the mode field.
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+/// Parse one of the eight digit hexadecimal fields of a header.
+fn field(header: &[u8], index: usize) -> Result<u32> {
+    let start = 6 + index * 8;
+    let digits = str::from_utf8(&header[start..start + 8])
+        .map_err(|_| FsError::Corrupted("cpio header is not ASCII"))?;
+
+    u32::from_str_radix(digits, 16).map_err(|_| FsError::Corrupted("cpio header field is not hex"))
+}
+
+/// Find or create each directory along `path`, returning the last one.
+fn make_dirs(root: &Arc<Inode>, path: &str) -> Result<Arc<Inode>> {
+    let mut current = root.clone();
+
+    for component in path.split('/').filter(|c| !c.is_empty()) {
+        current = match current.lookup(component) {
+            Ok(inode) => inode,
+            Err(FsError::NotFound) => current.create(component, FileType::Directory)?,
+            Err(e) => return Err(e),
+        };
+    }
+
+    Ok(current)
+}
+
+/// Remove `name` from `dir` if it is there, so that an entry later in the archive replaces an
+/// earlier one with the same path, as when several archives are concatenated.
+fn remove_existing(dir: &Arc<Inode>, name: &str) -> Result<()> {
+    match dir.unlink(name) {
+        Ok(()) | Err(FsError::NotFound) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Unpack a single directory, regular file or symbolic link at `path`. Paths with `.` or `..`
+/// components are refused, so that an entry can't reach outside of `root`.
+fn unpack_entry(root: &Arc<Inode>, path: &str, mode: u32, data: &[u8]) -> Result<()> {
+    if path.split('/').any(|c| c == "." || c == "..") {
+        return Err(FsError::InvalidArgument);
+    }
+
+    let (parent, file_name) = split(path);
+    let dir = make_dirs(root, parent)?;
+
+    match mode & S_IFMT {
+        S_IFDIR => {
+            // A directory which is already there is kept along with its contents.
+            let is_dir = match dir.lookup(file_name) {
+                Ok(existing) => existing.metadata()?.file_type == FileType::Directory,
+                Err(_) => false,
+            };
+
+            if !is_dir {
+                remove_existing(&dir, file_name)?;
+                dir.create(file_name, FileType::Directory)?;
+            }
+        }
+        S_IFREG => {
+            remove_existing(&dir, file_name)?;
+            dir.create(file_name, FileType::File)?.write_at(0, data)?;
+        }
+        S_IFLNK => {
+            let target = str::from_utf8(data)
+                .map_err(|_| FsError::Corrupted("cpio symlink target is not UTF-8"))?;
+            remove_existing(&dir, file_name)?;
+            dir.symlink(file_name, target)?;
+        }
+        _ => return Err(FsError::NotSupported),
+    }
+
+    Ok(())
+}
+
+/// Split a path into its parent directory and final component.
+fn split(path: &str) -> (&str, &str) {
+    match path.rfind('/') {
+        Some(i) => (&path[..i], &path[i + 1..]),
+        None => ("", path),
+    }
+}
+
+/// Unpack every directory, regular file and symbolic link in `archive` under `root`. Entries which
+/// can't be unpacked are skipped. Returns the number of entries unpacked.
+pub fn unpack(archive: &[u8], root: &Arc<Inode>) -> Result<usize> {
+    let mut offset = 0;
+    let mut count = 0;
+
+    while offset + HEADER_SIZE <= archive.len() {
+        let header = &archive[offset..offset + HEADER_SIZE];
+
+        if &header[..6] != b"070701" && &header[..6] != b"070702" {
+            return Err(FsError::Corrupted("cpio header has an invalid magic number"));
+        }
+
+        let mode = field(header, 1)?;
+        let file_size = field(header, 6)? as usize;
+        let name_size = field(header, 11)? as usize;
+
+        let name_start = offset + HEADER_SIZE;
+        let data_start = align4(name_start + name_size);
+        let data_end = data_start + file_size;
+
+        if name_size == 0 || data_end > archive.len() {
+            return Err(FsError::Corrupted("cpio entry runs past the end of the archive"));
+        }
+
+        // The name includes a terminating NUL.
+        let name = str::from_utf8(&archive[name_start..name_start + name_size - 1])
+            .map_err(|_| FsError::Corrupted("cpio entry name is not UTF-8"))?;
+
+        if name == TRAILER {
+            break;
+        }
+
+        let path = name.trim_left_matches("./").trim_matches('/');
+        let data = &archive[data_start..data_end];
+
+        if !path.is_empty() && path != "." {
+            match unpack_entry(root, path, mode, data) {
+                Ok(()) => count += 1,
+                Err(e) => println!("[ vfs ] Skipping cpio entry {}: {:?}", path, e),
+            }
+        }
+
+        offset = align4(data_end);
+    }
+
+    Ok(count)
+}
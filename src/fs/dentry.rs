@@ -0,0 +1,159 @@
+//! Directory entries and path resolution. A dentry pairs an inode with the absolute path it was
+//! reached through, and keeps its parent alive so that `..` works across mount points.
+
+use alloc::arc::Arc;
+use alloc::String;
+use super::{mount, FileType, FsError, Inode, Result};
+
+/// The most symbolic links followed while resolving a single path.
+const MAX_SYMLINK_DEPTH: usize = 8;
+
+pub struct Dentry {
+    /// The absolute, normalised path of this entry.
+    path: String,
+    inode: Arc<Inode>,
+    parent: Option<Arc<Dentry>>,
+}
+
+impl Dentry {
+    /// The dentry for `/`, which is the root of whatever is mounted there.
+    pub fn root() -> Result<Arc<Dentry>> {
+        let fs = mount::mounted_at("/").ok_or(FsError::NotFound)?;
+
+        Ok(Arc::new(Dentry {
+            path: String::from("/"),
+            inode: fs.root(),
+            parent: None,
+        }))
+    }
+
+    /// Create the dentry for the entry `name` of `parent`. If a filesystem is mounted there, the
+    /// dentry refers to its root instead.
+    fn child(parent: &Arc<Dentry>, name: &str, inode: Arc<Inode>) -> Arc<Dentry> {
+        let path = join(&parent.path, name);
+
+        let inode = match mount::mounted_at(&path) {
+            Some(fs) => fs.root(),
+            None => inode,
+        };
+
+        Arc::new(Dentry {
+            path: path,
+            inode: inode,
+            parent: Some(parent.clone()),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn inode(&self) -> &Arc<Inode> {
+        &self.inode
+    }
+
+    /// The parent directory, or this dentry itself for the root.
+    pub fn parent(this: &Arc<Dentry>) -> Arc<Dentry> {
+        match this.parent {
+            Some(ref parent) => parent.clone(),
+            None => this.clone(),
+        }
+    }
+
+    /// The last component of the path.
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or("")
+    }
+}
+
+/// Append a component to an absolute path.
+fn join(base: &str, name: &str) -> String {
+    if base.ends_with('/') {
+        format!("{}{}", base, name)
+    } else {
+        format!("{}/{}", base, name)
+    }
+}
+
+/// Resolve `path` starting from `start`, which is only used for relative paths. Symbolic links
+/// are followed, except for the final component when `follow_last` is false.
+fn resolve(start: &Arc<Dentry>, path: &str, follow_last: bool, depth: usize) -> Result<Arc<Dentry>> {
+    if path.is_empty() {
+        return Err(FsError::InvalidPath);
+    }
+
+    let mut current = if path.starts_with('/') {
+        Dentry::root()?
+    } else {
+        start.clone()
+    };
+
+    let mut components = path.split('/').filter(|c| !c.is_empty()).peekable();
+
+    while let Some(component) = components.next() {
+        let last = components.peek().is_none();
+
+        match component {
+            "." => {}
+            ".." => current = Dentry::parent(&current),
+            name => {
+                if current.inode.metadata()?.file_type != FileType::Directory {
+                    return Err(FsError::NotADirectory);
+                }
+
+                let inode = current.inode.lookup(name)?;
+                let child = Dentry::child(&current, name, inode);
+
+                let is_symlink = child.inode.metadata()?.file_type == FileType::Symlink;
+
+                if is_symlink && (!last || follow_last) {
+                    if depth >= MAX_SYMLINK_DEPTH {
+                        return Err(FsError::TooManyLinks);
+                    }
+
+                    // Relative targets are relative to the directory containing the link.
+                    let target = child.inode.readlink()?;
+                    current = resolve(&current, &target, true, depth + 1)?;
+                } else {
+                    current = child;
+                }
+            }
+        }
+    }
+
+    Ok(current)
+}
+
+/// Resolve a path to a dentry. Relative paths start from `cwd`, or from the root if there is no
+/// working directory.
+pub fn lookup(cwd: Option<&Arc<Dentry>>, path: &str, follow_last: bool) -> Result<Arc<Dentry>> {
+    let start = match cwd {
+        Some(cwd) => cwd.clone(),
+        None => Dentry::root()?,
+    };
+
+    resolve(&start, path, follow_last, 0)
+}
+
+/// Split a path into the dentry of its parent directory and its final component.
+pub fn lookup_parent<'a>(cwd: Option<&Arc<Dentry>>, path: &'a str) -> Result<(Arc<Dentry>, &'a str)> {
+    let trimmed = path.trim_right_matches('/');
+
+    let (parent, name) = match trimmed.rfind('/') {
+        Some(0) => ("/", &trimmed[1..]),
+        Some(i) => (&trimmed[..i], &trimmed[i + 1..]),
+        None => (".", trimmed),
+    };
+
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(FsError::InvalidPath);
+    }
+
+    let parent = lookup(cwd, parent, true)?;
+
+    if parent.inode.metadata()?.file_type != FileType::Directory {
+        return Err(FsError::NotADirectory);
+    }
+
+    Ok((parent, name))
+}
@@ -0,0 +1,300 @@
+//! A filesystem of device nodes. Drivers register character devices and the block layer registers
+//! disks under a name, such as `ttyS0` or `sda1`, and the nodes appear under wherever devfs is
+//! mounted. Names containing `/` appear in subdirectories.
+
+use alloc::arc::Arc;
+use alloc::btree_map::BTreeMap;
+use alloc::{String, Vec};
+use core::cmp;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use device::block::Disk;
+use spin::RwLock;
+use super::{DirEntry, FileSystem, FileSystemType, FileType, FsError, Inode, Metadata, Result};
+
+/// A device which transfers a stream of bytes.
+pub trait CharDevice: Send + Sync {
+    /// Read into `buffer`, returning the number of bytes read.
+    fn read(&self, buffer: &mut [u8]) -> Result<usize>;
+
+    /// Write `buffer`, returning the number of bytes written.
+    fn write(&self, buffer: &[u8]) -> Result<usize>;
+
+    /// Perform a device specific operation.
+    fn ioctl(&self, _command: usize, _argument: usize) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Called as the device is opened. Devices which keep state for each open file return a new
+    /// device holding it, which the open file uses instead.
+    fn open(&self) -> Result<Option<Arc<CharDevice>>> {
+        Ok(None)
+    }
+}
+
+#[derive(Clone)]
+enum Device {
+    Char(Arc<CharDevice>),
+    Block(Arc<Disk>),
+}
+
+/// A registered device node.
+pub struct DevNode {
+    inode: u64,
+    device: Device,
+}
+
+/// Inode numbers are unique across every device node.
+static NEXT_INODE: AtomicUsize = AtomicUsize::new(2);
+
+/// The inode number of the root directory.
+const ROOT_INODE: u64 = 1;
+
+lazy_static! {
+    /// Every registered node, by name.
+    static ref NODES: RwLock<BTreeMap<String, Arc<DevNode>>> = RwLock::new(BTreeMap::new());
+}
+
+fn register(name: &str, device: Device) -> Result<()> {
+    let mut nodes = NODES.write();
+
+    if nodes.contains_key(name) {
+        return Err(FsError::AlreadyExists);
+    }
+
+    let node = Arc::new(DevNode {
+        inode: NEXT_INODE.fetch_add(1, Ordering::SeqCst) as u64,
+        device: device,
+    });
+
+    nodes.insert(String::from(name), node);
+    Ok(())
+}
+
+/// Add a character device node.
+pub fn register_char(name: &str, device: Arc<CharDevice>) -> Result<()> {
+    register(name, Device::Char(device))
+}
+
+/// Add a block device node for a disk.
+pub fn register_block(name: &str, disk: Arc<Disk>) -> Result<()> {
+    register(name, Device::Block(disk))
+}
+
+/// Remove a node. Files which already have it open keep working.
+pub fn unregister(name: &str) -> Result<()> {
+    NODES.write().remove(name).map(|_| ()).ok_or(FsError::NotFound)
+}
+
+impl Inode for DevNode {
+    fn metadata(&self) -> Result<Metadata> {
+        let metadata = match self.device {
+            Device::Char(_) => Metadata::new(self.inode, FileType::CharDevice, 0),
+            Device::Block(ref disk) => Metadata::new(
+                self.inode,
+                FileType::BlockDevice,
+                disk.sector_count() * disk.sector_size() as u64,
+            ),
+        };
+
+        Ok(metadata)
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        match self.device {
+            Device::Char(ref device) => device.read(buffer),
+            Device::Block(ref disk) => {
+                let size = disk.sector_count() * disk.sector_size() as u64;
+                if offset >= size {
+                    return Ok(0);
+                }
+
+                let length = cmp::min(buffer.len() as u64, size - offset) as usize;
+                let sector_size = disk.sector_size() as u64;
+                let first = offset / sector_size;
+                let last = (offset + length as u64 + sector_size - 1) / sector_size;
+
+                let mut data = vec![0; ((last - first) * sector_size) as usize];
+                disk.read(first, &mut data).map_err(FsError::Io)?;
+
+                let start = (offset - first * sector_size) as usize;
+                buffer[..length].copy_from_slice(&data[start..start + length]);
+                Ok(length)
+            }
+        }
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize> {
+        match self.device {
+            Device::Char(ref device) => device.write(buffer),
+            Device::Block(ref disk) => {
+                let size = disk.sector_count() * disk.sector_size() as u64;
+                if offset >= size {
+                    return Err(FsError::NoSpace);
+                }
+
+                let length = cmp::min(buffer.len() as u64, size - offset) as usize;
+                let sector_size = disk.sector_size() as u64;
+                let first = offset / sector_size;
+                let last = (offset + length as u64 + sector_size - 1) / sector_size;
+
+                // Partial sectors at either end are read first, so their other bytes survive.
+                let mut data = vec![0; ((last - first) * sector_size) as usize];
+                disk.read(first, &mut data).map_err(FsError::Io)?;
+
+                let start = (offset - first * sector_size) as usize;
+                data[start..start + length].copy_from_slice(&buffer[..length]);
+                disk.write(first, &data).map_err(FsError::Io)?;
+
+                Ok(length)
+            }
+        }
+    }
+
+    fn truncate(&self, _size: u64) -> Result<()> {
+        // Opening a device with `TRUNCATE` is harmless.
+        Ok(())
+    }
+
+    fn ioctl(&self, command: usize, argument: usize) -> Result<usize> {
+        match self.device {
+            Device::Char(ref device) => device.ioctl(command, argument),
+            Device::Block(_) => Err(FsError::NotSupported),
+        }
+    }
+
+    fn open(&self) -> Result<Option<Arc<Inode>>> {
+        let device = match self.device {
+            Device::Char(ref device) => device.open()?,
+            Device::Block(_) => None,
+        };
+
+        Ok(device.map(|device| {
+            let node: Arc<Inode> = Arc::new(DevNode {
+                inode: self.inode,
+                device: Device::Char(device),
+            });
+            node
+        }))
+    }
+}
+
+/// A directory of devfs, holding every node whose name starts with `prefix`.
+struct DevDir {
+    prefix: String,
+}
+
+impl Inode for DevDir {
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata::new(ROOT_INODE, FileType::Directory, 0))
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<Inode>> {
+        let path = format!("{}{}", self.prefix, name);
+        let nodes = NODES.read();
+
+        if let Some(node) = nodes.get(&path) {
+            return Ok(node.clone());
+        }
+
+        let prefix = format!("{}/", path);
+
+        if nodes.keys().any(|k| k.starts_with(&prefix)) {
+            Ok(Arc::new(DevDir { prefix: prefix }))
+        } else {
+            Err(FsError::NotFound)
+        }
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        let mut entries: Vec<DirEntry> = Vec::new();
+
+        for (name, node) in NODES.read().iter() {
+            if !name.starts_with(&self.prefix) {
+                continue;
+            }
+
+            let rest = &name[self.prefix.len()..];
+
+            let entry = match rest.find('/') {
+                Some(i) => DirEntry {
+                    name: String::from(&rest[..i]),
+                    inode: ROOT_INODE,
+                    file_type: FileType::Directory,
+                },
+                None => DirEntry {
+                    name: String::from(rest),
+                    inode: node.inode,
+                    file_type: node.metadata()?.file_type,
+                },
+            };
+
+            // Names are sorted, so the nodes of a subdirectory are next to each other.
+            if entries.last().map(|e| e.name == entry.name) != Some(true) {
+                entries.push(entry);
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+/// `/dev/null`, which discards writes and is always at its end.
+struct Null;
+
+impl CharDevice for Null {
+    fn read(&self, _buffer: &mut [u8]) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize> {
+        Ok(buffer.len())
+    }
+}
+
+/// `/dev/zero`, which discards writes and reads as zeroes.
+struct Zero;
+
+impl CharDevice for Zero {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        for b in buffer.iter_mut() {
+            *b = 0;
+        }
+        Ok(buffer.len())
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize> {
+        Ok(buffer.len())
+    }
+}
+
+pub struct DevFs {
+    root: Arc<DevDir>,
+}
+
+impl FileSystem for DevFs {
+    fn root(&self) -> Arc<Inode> {
+        self.root.clone()
+    }
+}
+
+pub struct DevFsType;
+
+impl FileSystemType for DevFsType {
+    fn name(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn mount(&self, _source: Option<Arc<Disk>>) -> Result<Arc<FileSystem>> {
+        Ok(Arc::new(DevFs {
+            root: Arc::new(DevDir {
+                prefix: String::new(),
+            }),
+        }))
+    }
+}
+
+/// Add the nodes which don't belong to any driver.
+pub fn init() {
+    register_char("null", Arc::new(Null)).expect("Could not register /dev/null");
+    register_char("zero", Arc::new(Zero)).expect("Could not register /dev/zero");
+}
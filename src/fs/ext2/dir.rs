@@ -0,0 +1,265 @@
+//! Directory entries, stored as variable length records packed into the blocks of a directory.
+
+use alloc::{String, Vec};
+use super::inode::{Ext2Inode, S_IFBLK, S_IFCHR, S_IFDIR, S_IFLNK, S_IFMT, S_IFREG};
+use super::{read_u16, read_u32, write_u16, write_u32, Ext2};
+use super::super::{FileType, FsError, Result};
+
+/// The size of the fixed part of an entry, before the name.
+const HEADER_SIZE: usize = 8;
+const MAX_NAME_LENGTH: usize = 255;
+
+/// Values of the file type byte.
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+const FT_CHRDEV: u8 = 3;
+const FT_BLKDEV: u8 = 4;
+const FT_SYMLINK: u8 = 7;
+
+#[derive(Debug, Clone)]
+pub struct Ext2DirEntry {
+    pub name: String,
+    pub inode: u32,
+    /// The type recorded in the entry, if the filesystem records types.
+    pub file_type: Option<FileType>,
+}
+
+/// The space a record with a name of `length` bytes needs.
+fn record_length(length: usize) -> usize {
+    (HEADER_SIZE + length + 3) & !3
+}
+
+fn file_type_byte(mode: u16) -> u8 {
+    match mode & S_IFMT {
+        S_IFREG => FT_REG_FILE,
+        S_IFDIR => FT_DIR,
+        S_IFCHR => FT_CHRDEV,
+        S_IFBLK => FT_BLKDEV,
+        S_IFLNK => FT_SYMLINK,
+        _ => 0,
+    }
+}
+
+fn file_type(byte: u8) -> Option<FileType> {
+    match byte {
+        FT_REG_FILE => Some(FileType::File),
+        FT_DIR => Some(FileType::Directory),
+        FT_CHRDEV => Some(FileType::CharDevice),
+        FT_BLKDEV => Some(FileType::BlockDevice),
+        FT_SYMLINK => Some(FileType::Symlink),
+        _ => None,
+    }
+}
+
+/// A record within a directory block.
+struct Record {
+    offset: usize,
+    inode: u32,
+    length: usize,
+    name_length: usize,
+    file_type: u8,
+}
+
+impl Ext2 {
+    /// Parse the records of one directory block.
+    fn records(&self, block: &[u8]) -> Result<Vec<Record>> {
+        let mut records = Vec::new();
+        let mut offset = 0;
+
+        while offset + HEADER_SIZE <= block.len() {
+            let length = read_u16(block, offset + 4) as usize;
+
+            // Without the file type feature, the name length is 16 bits.
+            let (name_length, file_type) = if self.has_file_type {
+                (block[offset + 6] as usize, block[offset + 7])
+            } else {
+                (read_u16(block, offset + 6) as usize, 0)
+            };
+
+            if length < HEADER_SIZE || length % 4 != 0 || offset + length > block.len()
+                || HEADER_SIZE + name_length > length
+            {
+                return Err(FsError::Corrupted("ext2 directory entry has an invalid length"));
+            }
+
+            records.push(Record {
+                offset: offset,
+                inode: read_u32(block, offset),
+                length: length,
+                name_length: name_length,
+                file_type: file_type,
+            });
+
+            offset += length;
+        }
+
+        Ok(records)
+    }
+
+    fn directory_blocks(&self, dir: &Ext2Inode) -> u64 {
+        dir.size() / self.block_size as u64
+    }
+
+    /// List the entries of a directory, not including `.` and `..`.
+    pub fn entries(&self, dir: &Ext2Inode) -> Result<Vec<Ext2DirEntry>> {
+        let mut entries = Vec::new();
+        let mut block = vec![0; self.block_size as usize];
+
+        for index in 0..self.directory_blocks(dir) {
+            dir.read_dir_block(index, &mut block)?;
+
+            for record in self.records(&block)?.into_iter().filter(|r| r.inode != 0) {
+                let start = record.offset + HEADER_SIZE;
+                let name = String::from_utf8_lossy(&block[start..start + record.name_length])
+                    .into_owned();
+
+                if name == "." || name == ".." {
+                    continue;
+                }
+
+                entries.push(Ext2DirEntry {
+                    name: name,
+                    inode: record.inode,
+                    file_type: file_type(record.file_type),
+                });
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Find an entry of a directory by name.
+    pub fn find(&self, dir: &Ext2Inode, name: &str) -> Result<Ext2DirEntry> {
+        self.entries(dir)?
+            .into_iter()
+            .find(|e| e.name == name)
+            .ok_or(FsError::NotFound)
+    }
+
+    /// Write a record header and name at `offset` in a directory block.
+    fn write_record(
+        &self,
+        block: &mut [u8],
+        offset: usize,
+        inode: u32,
+        length: usize,
+        name: &str,
+        mode: u16,
+    ) {
+        write_u32(block, offset, inode);
+        write_u16(block, offset + 4, length as u16);
+
+        if self.has_file_type {
+            block[offset + 6] = name.len() as u8;
+            block[offset + 7] = file_type_byte(mode);
+        } else {
+            write_u16(block, offset + 6, name.len() as u16);
+        }
+
+        block[offset + HEADER_SIZE..offset + HEADER_SIZE + name.len()]
+            .copy_from_slice(name.as_bytes());
+    }
+
+    /// Link `inode` into a directory as `name`, splitting an existing record if one has room,
+    /// or adding a block to the directory otherwise.
+    pub fn add_entry(&self, dir: &Ext2Inode, name: &str, inode: u32, mode: u16) -> Result<()> {
+        if name.is_empty() || name == "." || name == ".." || name.len() > MAX_NAME_LENGTH
+            || name.contains('/') || name.contains('\0')
+        {
+            return Err(FsError::InvalidPath);
+        }
+
+        let needed = record_length(name.len());
+        let mut block = vec![0; self.block_size as usize];
+        let count = self.directory_blocks(dir);
+
+        for index in 0..count {
+            dir.read_dir_block(index, &mut block)?;
+
+            for record in self.records(&block)? {
+                // An unused record can be taken whole, otherwise split off its slack.
+                let used = if record.inode == 0 { 0 } else { record_length(record.name_length) };
+
+                if record.length - used < needed {
+                    continue;
+                }
+
+                if used == 0 {
+                    self.write_record(&mut block, record.offset, inode, record.length, name, mode);
+                } else {
+                    write_u16(&mut block, record.offset + 4, used as u16);
+                    self.write_record(
+                        &mut block,
+                        record.offset + used,
+                        inode,
+                        record.length - used,
+                        name,
+                        mode,
+                    );
+                }
+
+                return dir.write_dir_block(index, &block);
+            }
+        }
+
+        for b in block.iter_mut() {
+            *b = 0;
+        }
+
+        let length = self.block_size as usize;
+        self.write_record(&mut block, 0, inode, length, name, mode);
+        dir.write_dir_block(count, &block)
+    }
+
+    /// Remove the entry `name` from a directory, merging its space into the previous record.
+    pub fn remove_entry(&self, dir: &Ext2Inode, name: &str) -> Result<()> {
+        let mut block = vec![0; self.block_size as usize];
+
+        for index in 0..self.directory_blocks(dir) {
+            dir.read_dir_block(index, &mut block)?;
+            let records = self.records(&block)?;
+
+            for (i, record) in records.iter().enumerate() {
+                let start = record.offset + HEADER_SIZE;
+
+                if record.inode == 0 || &block[start..start + record.name_length] != name.as_bytes() {
+                    continue;
+                }
+
+                if i == 0 {
+                    // The first record of a block has nothing before it, so it is marked unused.
+                    write_u32(&mut block, record.offset, 0);
+                } else {
+                    let previous = &records[i - 1];
+                    write_u16(
+                        &mut block,
+                        previous.offset + 4,
+                        (previous.length + record.length) as u16,
+                    );
+                }
+
+                return dir.write_dir_block(index, &block);
+            }
+        }
+
+        Err(FsError::NotFound)
+    }
+}
+
+/// Write the `.` and `..` entries of a new directory.
+pub fn init_directory(fs: &Ext2, dir: &Ext2Inode, parent: u32) -> Result<()> {
+    let mut block = vec![0; fs.block_size as usize];
+    let dot_length = record_length(1);
+
+    fs.write_record(&mut block, 0, dir.number(), dot_length, ".", S_IFDIR);
+    fs.write_record(
+        &mut block,
+        dot_length,
+        parent,
+        fs.block_size as usize - dot_length,
+        "..",
+        S_IFDIR,
+    );
+
+    dir.write_dir_block(0, &block)
+}
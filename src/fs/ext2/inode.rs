@@ -0,0 +1,750 @@
+//! Inodes, and the mapping of file blocks through direct and indirect block pointers.
+
+use alloc::arc::Arc;
+use alloc::{String, Vec};
+use core::cmp;
+use spin::RwLock;
+use super::dir;
+use super::{read_u16, read_u32, write_u16, write_u32, Ext2, FEATURE_RO_COMPAT_LARGE_FILE};
+use super::super::{DirEntry, FileType, FsError, Inode, Metadata, Result};
+
+pub const S_IFMT: u16 = 0xF000;
+pub const S_IFLNK: u16 = 0xA000;
+pub const S_IFREG: u16 = 0x8000;
+pub const S_IFBLK: u16 = 0x6000;
+pub const S_IFDIR: u16 = 0x4000;
+pub const S_IFCHR: u16 = 0x2000;
+
+/// The number of block pointers in an inode: twelve direct, then single, double and triple
+/// indirect.
+const DIRECT_BLOCKS: usize = 12;
+const SINGLE_INDIRECT: usize = 12;
+const DOUBLE_INDIRECT: usize = 13;
+const TRIPLE_INDIRECT: usize = 14;
+
+/// Symbolic links shorter than this are stored in the block pointers themselves.
+const FAST_SYMLINK_LENGTH: usize = 60;
+
+/// The first 128 bytes of an on disk inode, which every revision shares.
+#[derive(Clone)]
+pub struct DiskInode {
+    pub raw: [u8; 128],
+}
+
+impl DiskInode {
+    pub fn empty() -> Self {
+        DiskInode { raw: [0; 128] }
+    }
+
+    pub fn mode(&self) -> u16 {
+        read_u16(&self.raw, 0)
+    }
+
+    pub fn set_mode(&mut self, mode: u16) {
+        write_u16(&mut self.raw, 0, mode)
+    }
+
+    pub fn uid(&self) -> u32 {
+        read_u16(&self.raw, 2) as u32 | (read_u16(&self.raw, 120) as u32) << 16
+    }
+
+    pub fn gid(&self) -> u32 {
+        read_u16(&self.raw, 24) as u32 | (read_u16(&self.raw, 122) as u32) << 16
+    }
+
+    pub fn file_type(&self) -> FileType {
+        match self.mode() & S_IFMT {
+            S_IFDIR => FileType::Directory,
+            S_IFLNK => FileType::Symlink,
+            S_IFCHR => FileType::CharDevice,
+            S_IFBLK => FileType::BlockDevice,
+            _ => FileType::File,
+        }
+    }
+
+    /// The size in bytes. The upper half is only used by regular files.
+    pub fn size(&self) -> u64 {
+        let high = if self.mode() & S_IFMT == S_IFREG { read_u32(&self.raw, 108) } else { 0 };
+        read_u32(&self.raw, 4) as u64 | (high as u64) << 32
+    }
+
+    pub fn set_size(&mut self, size: u64) {
+        write_u32(&mut self.raw, 4, size as u32);
+
+        if self.mode() & S_IFMT == S_IFREG {
+            write_u32(&mut self.raw, 108, (size >> 32) as u32);
+        }
+    }
+
+    pub fn links(&self) -> u16 {
+        read_u16(&self.raw, 26)
+    }
+
+    pub fn set_links(&mut self, links: u16) {
+        write_u16(&mut self.raw, 26, links)
+    }
+
+    /// The number of 512 byte sectors allocated, including indirect blocks.
+    pub fn sectors(&self) -> u32 {
+        read_u32(&self.raw, 28)
+    }
+
+    fn set_sectors(&mut self, sectors: u32) {
+        write_u32(&mut self.raw, 28, sectors)
+    }
+
+    fn file_acl(&self) -> u32 {
+        read_u32(&self.raw, 104)
+    }
+
+    pub fn set_dtime(&mut self, dtime: u32) {
+        write_u32(&mut self.raw, 20, dtime)
+    }
+
+    pub fn block(&self, index: usize) -> u32 {
+        read_u32(&self.raw, 40 + index * 4)
+    }
+
+    pub fn set_block(&mut self, index: usize, block: u32) {
+        write_u32(&mut self.raw, 40 + index * 4, block)
+    }
+
+    /// Whether this is a symbolic link stored in the block pointers.
+    fn is_fast_symlink(&self, block_size: u32) -> bool {
+        let acl_sectors = if self.file_acl() != 0 { block_size / 512 } else { 0 };
+        self.mode() & S_IFMT == S_IFLNK && self.sectors() == acl_sectors
+    }
+
+    /// A new inode with the given mode and one link.
+    pub fn new(mode: u16) -> Self {
+        let mut inode = DiskInode::empty();
+        inode.set_mode(mode);
+        inode.set_links(1);
+        inode
+    }
+}
+
+struct State {
+    disk: DiskInode,
+    /// Set once the last link has been removed.
+    unlinked: bool,
+    /// Set while the inode has no links left but its blocks and number are still held, because it
+    /// is in use. They are released when it is dropped.
+    orphaned: bool,
+}
+
+pub struct Ext2Inode {
+    fs: Arc<Ext2>,
+    number: u32,
+    state: RwLock<State>,
+}
+
+impl Ext2Inode {
+    pub fn new(fs: Arc<Ext2>, number: u32, disk: DiskInode) -> Self {
+        Ext2Inode {
+            fs: fs,
+            number: number,
+            state: RwLock::new(State {
+                disk: disk,
+                unlinked: false,
+                orphaned: false,
+            }),
+        }
+    }
+
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    fn file_type(&self) -> FileType {
+        self.state.read().disk.file_type()
+    }
+
+    /// Check that this is a directory which still exists.
+    fn check_directory(&self) -> Result<()> {
+        if self.file_type() != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+
+        if self.state.read().unlinked {
+            return Err(FsError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Add `delta` to the link count.
+    pub fn adjust_links(&self, delta: i16) -> Result<()> {
+        let mut state = self.state.write();
+        let links = (state.disk.links() as i16 + delta) as u16;
+        state.disk.set_links(links);
+        self.fs.write_inode(self.number, &state.disk)
+    }
+
+    /// Split a file block index into the pointer slot in the inode and the indices within each
+    /// level of indirect blocks.
+    fn block_path(&self, index: u64) -> Result<(usize, Vec<u32>)> {
+        let per_block = self.fs.pointers_per_block() as u64;
+        let mut index = index;
+
+        if index < DIRECT_BLOCKS as u64 {
+            return Ok((index as usize, Vec::new()));
+        }
+        index -= DIRECT_BLOCKS as u64;
+
+        if index < per_block {
+            return Ok((SINGLE_INDIRECT, vec![index as u32]));
+        }
+        index -= per_block;
+
+        if index < per_block * per_block {
+            return Ok((
+                DOUBLE_INDIRECT,
+                vec![(index / per_block) as u32, (index % per_block) as u32],
+            ));
+        }
+        index -= per_block * per_block;
+
+        if index < per_block * per_block * per_block {
+            return Ok((
+                TRIPLE_INDIRECT,
+                vec![
+                    (index / (per_block * per_block)) as u32,
+                    (index / per_block % per_block) as u32,
+                    (index % per_block) as u32,
+                ],
+            ));
+        }
+
+        Err(FsError::NoSpace)
+    }
+
+    /// The block holding file block `index`, or 0 for a hole. If `allocate` is set, holes are
+    /// filled, along with any missing indirect blocks.
+    fn map_block(&self, disk: &mut DiskInode, index: u64, allocate: bool) -> Result<u32> {
+        let (slot, path) = self.block_path(index)?;
+        let sectors_per_block = self.fs.block_size / 512;
+        let mut block = disk.block(slot);
+
+        if block == 0 {
+            if !allocate {
+                return Ok(0);
+            }
+
+            // Keep data near the inode table of the inode's group.
+            let group = (self.number - 1) / self.fs.inodes_per_group;
+            let near = self.fs.first_data_block + group * self.fs.blocks_per_group;
+
+            block = self.fs.allocate_block(near)?;
+            disk.set_block(slot, block);
+            let sectors = disk.sectors() + sectors_per_block;
+            disk.set_sectors(sectors);
+        }
+
+        let mut table = vec![0; self.fs.block_size as usize];
+
+        for &entry in path.iter() {
+            let entry = entry as usize * 4;
+            self.fs.read_block(block, &mut table)?;
+            let next = read_u32(&table, entry);
+
+            if next != 0 {
+                block = next;
+                continue;
+            }
+
+            if !allocate {
+                return Ok(0);
+            }
+
+            let next = self.fs.allocate_block(block)?;
+            write_u32(&mut table, entry, next);
+            self.fs.write_block(block, &table)?;
+
+            let sectors = disk.sectors() + sectors_per_block;
+            disk.set_sectors(sectors);
+            block = next;
+        }
+
+        Ok(block)
+    }
+
+    /// Free the blocks of the tree rooted at `block` beyond the first `keep` data blocks.
+    /// `level` is 0 for a data block. Returns the number of blocks freed, and whether `block`
+    /// itself was freed.
+    fn truncate_tree(&self, block: u32, level: u32, keep: u64) -> Result<(u32, bool)> {
+        if level == 0 {
+            if keep == 0 {
+                self.fs.free_block(block)?;
+                return Ok((1, true));
+            }
+            return Ok((0, false));
+        }
+
+        let per_block = self.fs.pointers_per_block() as u64;
+        let span = per_block.pow(level - 1);
+        let mut table = vec![0; self.fs.block_size as usize];
+        self.fs.read_block(block, &mut table)?;
+
+        let mut freed = 0;
+        let mut modified = false;
+
+        for i in 0..per_block {
+            let child = read_u32(&table, i as usize * 4);
+            let child_keep = keep.saturating_sub(i * span);
+
+            if child == 0 || child_keep >= span {
+                continue;
+            }
+
+            let (count, gone) = self.truncate_tree(child, level - 1, child_keep)?;
+            freed += count;
+
+            if gone {
+                write_u32(&mut table, i as usize * 4, 0);
+                modified = true;
+            }
+        }
+
+        if keep == 0 {
+            self.fs.free_block(block)?;
+            return Ok((freed + 1, true));
+        }
+
+        if modified {
+            self.fs.write_block(block, &table)?;
+        }
+
+        Ok((freed, false))
+    }
+
+    /// Free every block past `size` bytes.
+    fn free_blocks(&self, disk: &mut DiskInode, size: u64) -> Result<()> {
+        let block_size = self.fs.block_size as u64;
+        let per_block = self.fs.pointers_per_block() as u64;
+        let keep = (size + block_size - 1) / block_size;
+        let mut freed = 0;
+
+        for slot in 0..DIRECT_BLOCKS {
+            let block = disk.block(slot);
+
+            if block != 0 && slot as u64 >= keep {
+                self.fs.free_block(block)?;
+                disk.set_block(slot, 0);
+                freed += 1;
+            }
+        }
+
+        let mut first = DIRECT_BLOCKS as u64;
+
+        for (level, &slot) in [SINGLE_INDIRECT, DOUBLE_INDIRECT, TRIPLE_INDIRECT].iter().enumerate() {
+            let level = level as u32 + 1;
+            let span = per_block.pow(level);
+            let block = disk.block(slot);
+
+            if block != 0 && keep < first + span {
+                let (count, gone) = self.truncate_tree(block, level, keep.saturating_sub(first))?;
+                freed += count;
+
+                if gone {
+                    disk.set_block(slot, 0);
+                }
+            }
+
+            first += span;
+        }
+
+        let sectors = disk.sectors() - freed * (self.fs.block_size / 512);
+        disk.set_sectors(sectors);
+
+        Ok(())
+    }
+
+    fn read_locked(&self, disk: &mut DiskInode, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        let size = disk.size();
+
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let block_size = self.fs.block_size as u64;
+        let total = cmp::min(buffer.len() as u64, size - offset) as usize;
+        let mut read = 0;
+
+        while read < total {
+            let position = offset + read as u64;
+            let within = (position % block_size) as usize;
+            let length = cmp::min(total - read, block_size as usize - within);
+            let block = self.map_block(disk, position / block_size, false)?;
+            let chunk = &mut buffer[read..read + length];
+
+            if block == 0 {
+                for b in chunk.iter_mut() {
+                    *b = 0;
+                }
+            } else {
+                super::read_bytes(
+                    &self.fs.disk,
+                    block as u64 * block_size + within as u64,
+                    chunk,
+                )?;
+            }
+
+            read += length;
+        }
+
+        Ok(read)
+    }
+
+    /// Write `buffer` at `offset`, allocating blocks as needed. The caller stores the inode.
+    fn write_locked(&self, disk: &mut DiskInode, offset: u64, buffer: &[u8]) -> Result<usize> {
+        let block_size = self.fs.block_size as u64;
+        let mut written = 0;
+
+        while written < buffer.len() {
+            let position = offset + written as u64;
+            let within = (position % block_size) as usize;
+            let length = cmp::min(buffer.len() - written, block_size as usize - within);
+            let block = self.map_block(disk, position / block_size, true)?;
+
+            super::write_bytes(
+                &self.fs.disk,
+                block as u64 * block_size + within as u64,
+                &buffer[written..written + length],
+            )?;
+
+            written += length;
+
+            if position + length as u64 > disk.size() {
+                disk.set_size(position + length as u64);
+            }
+        }
+
+        if disk.size() > i32::max_value() as u64 {
+            self.fs.require_feature(FEATURE_RO_COMPAT_LARGE_FILE);
+        }
+
+        Ok(written)
+    }
+
+    /// Release the blocks and inode number of an inode with no links left.
+    pub fn release(&self) -> Result<()> {
+        let mut state = self.state.write();
+        let directory = state.disk.file_type() == FileType::Directory;
+
+        if !state.disk.is_fast_symlink(self.fs.block_size) {
+            self.free_blocks(&mut state.disk, 0)?;
+        }
+
+        state.disk.set_size(0);
+        state.disk.set_links(0);
+        // There is no clock, but a non-zero deletion time marks the inode as deleted.
+        state.disk.set_dtime(1);
+        state.unlinked = true;
+        state.orphaned = false;
+
+        self.fs.write_inode(self.number, &state.disk)?;
+        self.fs.free_inode(self.number, directory)
+    }
+
+    /// Remove the last link to this inode. Its blocks stay allocated, so that it can still be read
+    /// through files which have it open, until it is dropped.
+    fn orphan(&self) -> Result<()> {
+        let mut state = self.state.write();
+
+        state.disk.set_links(0);
+        state.unlinked = true;
+        state.orphaned = true;
+
+        self.fs.write_inode(self.number, &state.disk)
+    }
+
+    /// Create a new inode of `mode` and link it into this directory as `name`.
+    fn create_child(&self, name: &str, mode: u16) -> Result<Arc<Ext2Inode>> {
+        self.fs.check_writable()?;
+        self.check_directory()?;
+        let _guard = self.fs.dir_lock.lock();
+
+        if self.fs.find(self, name).is_ok() {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let directory = mode & S_IFMT == S_IFDIR;
+        let number = self.fs.allocate_inode(self.number, directory)?;
+
+        let mut disk = DiskInode::new(mode);
+        if directory {
+            // One link from the parent, and one from its own `.` entry.
+            disk.set_links(2);
+        }
+        self.fs.init_inode(number, &disk)?;
+
+        let child = Ext2::inode(&self.fs, number)?;
+
+        let result = if directory {
+            dir::init_directory(&self.fs, &child, self.number)
+                .and_then(|_| self.fs.add_entry(self, name, number, mode))
+                .and_then(|_| self.adjust_links(1))
+        } else {
+            self.fs.add_entry(self, name, number, mode)
+        };
+
+        if let Err(e) = result {
+            child.release()?;
+            return Err(e);
+        }
+
+        Ok(child)
+    }
+
+    /// Read or write the contents of a directory, used by the directory code.
+    pub fn read_dir_block(&self, index: u64, buffer: &mut [u8]) -> Result<()> {
+        let mut state = self.state.write();
+        let offset = index * self.fs.block_size as u64;
+        self.read_locked(&mut state.disk, offset, buffer).map(|_| ())
+    }
+
+    pub fn write_dir_block(&self, index: u64, buffer: &[u8]) -> Result<()> {
+        let mut state = self.state.write();
+        let offset = index * self.fs.block_size as u64;
+        self.write_locked(&mut state.disk, offset, buffer)?;
+        self.fs.write_inode(self.number, &state.disk)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.state.read().disk.size()
+    }
+}
+
+impl Inode for Ext2Inode {
+    fn metadata(&self) -> Result<Metadata> {
+        let state = self.state.read();
+        let disk = &state.disk;
+
+        let mut metadata = Metadata::new(self.number as u64, disk.file_type(), disk.size());
+        metadata.mode = disk.mode() & !S_IFMT;
+        metadata.nlink = disk.links() as u32;
+        metadata.uid = disk.uid();
+        metadata.gid = disk.gid();
+        metadata.blocks = disk.sectors() as u64;
+        metadata.atime = read_u32(&disk.raw, 8) as u64;
+        metadata.ctime = read_u32(&disk.raw, 12) as u64;
+        metadata.mtime = read_u32(&disk.raw, 16) as u64;
+
+        Ok(metadata)
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        match self.file_type() {
+            FileType::Directory => return Err(FsError::IsADirectory),
+            FileType::File => (),
+            _ => return Err(FsError::InvalidArgument),
+        }
+
+        // Mapping never allocates when reading, but takes the inode mutably to share the code.
+        let mut state = self.state.write();
+        self.read_locked(&mut state.disk, offset, buffer)
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize> {
+        match self.file_type() {
+            FileType::Directory => return Err(FsError::IsADirectory),
+            FileType::File => (),
+            _ => return Err(FsError::InvalidArgument),
+        }
+
+        self.fs.check_writable()?;
+        let mut state = self.state.write();
+
+        if state.unlinked {
+            return Err(FsError::NotFound);
+        }
+
+        let result = self.write_locked(&mut state.disk, offset, buffer);
+
+        // Record whatever was allocated, even if the write failed part way.
+        self.fs.write_inode(self.number, &state.disk)?;
+        result
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        match self.file_type() {
+            FileType::Directory => return Err(FsError::IsADirectory),
+            FileType::File => (),
+            _ => return Err(FsError::InvalidArgument),
+        }
+
+        self.fs.check_writable()?;
+        let mut state = self.state.write();
+
+        if state.unlinked {
+            return Err(FsError::NotFound);
+        }
+
+        if size < state.disk.size() {
+            self.free_blocks(&mut state.disk, size)?;
+
+            // Zero the rest of the last block, so that extending the file later reads zeroes.
+            let block_size = self.fs.block_size as u64;
+            let within = (size % block_size) as usize;
+
+            if within != 0 {
+                let block = self.map_block(&mut state.disk, size / block_size, false)?;
+
+                if block != 0 {
+                    let zeroes = vec![0; block_size as usize - within];
+                    super::write_bytes(
+                        &self.fs.disk,
+                        block as u64 * block_size + within as u64,
+                        &zeroes,
+                    )?;
+                }
+            }
+        }
+
+        // Growing a file leaves a hole, which reads as zeroes.
+        state.disk.set_size(size);
+        if size > i32::max_value() as u64 {
+            self.fs.require_feature(FEATURE_RO_COMPAT_LARGE_FILE);
+        }
+
+        self.fs.write_inode(self.number, &state.disk)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<Inode>> {
+        self.check_directory()?;
+        let number = self.fs.find(self, name)?.inode;
+
+        Ok(Ext2::inode(&self.fs, number)?)
+    }
+
+    fn create(&self, name: &str, file_type: FileType) -> Result<Arc<Inode>> {
+        let mode = match file_type {
+            FileType::File => S_IFREG | 0o644,
+            FileType::Directory => S_IFDIR | 0o755,
+            _ => return Err(FsError::NotSupported),
+        };
+
+        Ok(self.create_child(name, mode)?)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<Inode>> {
+        if target.is_empty() || target.len() >= self.fs.block_size as usize {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let child = self.create_child(name, S_IFLNK | 0o777)?;
+
+        {
+            let mut state = child.state.write();
+
+            if target.len() < FAST_SYMLINK_LENGTH {
+                state.disk.raw[40..40 + target.len()].copy_from_slice(target.as_bytes());
+                state.disk.set_size(target.len() as u64);
+            } else {
+                child.write_locked(&mut state.disk, 0, target.as_bytes())?;
+            }
+
+            self.fs.write_inode(child.number, &state.disk)?;
+        }
+
+        Ok(child)
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        self.fs.check_writable()?;
+        self.check_directory()?;
+        let _guard = self.fs.dir_lock.lock();
+
+        let entry = self.fs.find(self, name)?;
+        let child = Ext2::inode(&self.fs, entry.inode)?;
+        let directory = child.file_type() == FileType::Directory;
+
+        if directory && !self.fs.entries(&child)?.is_empty() {
+            return Err(FsError::DirectoryNotEmpty);
+        }
+
+        self.fs.remove_entry(self, name)?;
+
+        if directory {
+            // The child's `..` entry no longer refers to this directory.
+            self.adjust_links(-1)?;
+            child.orphan()
+        } else if child.state.read().disk.links() <= 1 {
+            child.orphan()
+        } else {
+            child.adjust_links(-1)
+        }
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        self.check_directory()?;
+
+        self.fs
+            .entries(self)?
+            .into_iter()
+            .map(|entry| {
+                let file_type = match entry.file_type {
+                    Some(file_type) => file_type,
+                    None => Ext2::inode(&self.fs, entry.inode)?.file_type(),
+                };
+
+                Ok(DirEntry {
+                    name: entry.name,
+                    inode: entry.inode as u64,
+                    file_type: file_type,
+                })
+            })
+            .collect()
+    }
+
+    fn readlink(&self) -> Result<String> {
+        let mut state = self.state.write();
+
+        if state.disk.file_type() != FileType::Symlink {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let size = state.disk.size() as usize;
+
+        let target = if state.disk.is_fast_symlink(self.fs.block_size) {
+            if size >= FAST_SYMLINK_LENGTH {
+                return Err(FsError::Corrupted("ext2 fast symlink is too long"));
+            }
+            state.disk.raw[40..40 + size].to_vec()
+        } else {
+            // Slow symlinks are kept in a single block.
+            if size > self.fs.block_size as usize {
+                return Err(FsError::Corrupted("ext2 symlink is too long"));
+            }
+
+            let mut target = vec![0; size];
+            self.read_locked(&mut state.disk, 0, &mut target)?;
+            target
+        };
+
+        String::from_utf8(target).map_err(|_| FsError::Corrupted("ext2 symlink is not UTF-8"))
+    }
+
+    fn chmod(&self, mode: u16) -> Result<()> {
+        self.fs.check_writable()?;
+        let mut state = self.state.write();
+
+        let mode = (state.disk.mode() & S_IFMT) | (mode & !S_IFMT);
+        state.disk.set_mode(mode);
+
+        self.fs.write_inode(self.number, &state.disk)
+    }
+}
+
+impl Drop for Ext2Inode {
+    fn drop(&mut self) {
+        let orphaned = self.state.read().orphaned;
+
+        if orphaned {
+            if let Err(e) = self.release() {
+                println!("[ ext2 ] Could not free removed inode {}: {:?}", self.number, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,588 @@
+//! The second extended filesystem.
+
+use alloc::arc::{Arc, Weak};
+use alloc::btree_map::BTreeMap;
+use alloc::Vec;
+use device::block::Disk;
+use spin::Mutex;
+use super::{FileSystem, FileSystemType, FsError, Inode, Result};
+
+mod dir;
+mod inode;
+
+use self::inode::{DiskInode, Ext2Inode};
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const MAGIC: u16 = 0xEF53;
+
+const ROOT_INODE: u32 = 2;
+/// The first non-reserved inode on revision 0 filesystems.
+const GOOD_OLD_FIRST_INODE: u32 = 11;
+const GOOD_OLD_INODE_SIZE: u32 = 128;
+
+/// Directory entries carry a file type byte.
+const FEATURE_INCOMPAT_FILETYPE: u32 = 0x0002;
+/// Backup superblocks are only kept in some groups.
+const FEATURE_RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+/// Files may be larger than 2 GiB.
+const FEATURE_RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+
+const SUPPORTED_INCOMPAT: u32 = FEATURE_INCOMPAT_FILETYPE;
+const SUPPORTED_RO_COMPAT: u32 = FEATURE_RO_COMPAT_SPARSE_SUPER | FEATURE_RO_COMPAT_LARGE_FILE;
+
+const GROUP_DESCRIPTOR_SIZE: usize = 32;
+/// The largest group descriptor table read, enough for 2048 groups, which is 16 GiB of 1 KiB
+/// blocks or 256 GiB of 4 KiB blocks.
+const MAX_DESCRIPTOR_TABLE: u64 = 64 * 1024;
+
+fn read_u16(bytes: &[u8], i: usize) -> u16 {
+    bytes[i] as u16 | (bytes[i + 1] as u16) << 8
+}
+
+fn read_u32(bytes: &[u8], i: usize) -> u32 {
+    read_u16(bytes, i) as u32 | (read_u16(bytes, i + 2) as u32) << 16
+}
+
+fn write_u16(bytes: &mut [u8], i: usize, value: u16) {
+    bytes[i] = value as u8;
+    bytes[i + 1] = (value >> 8) as u8;
+}
+
+fn write_u32(bytes: &mut [u8], i: usize, value: u32) {
+    write_u16(bytes, i, value as u16);
+    write_u16(bytes, i + 2, (value >> 16) as u16);
+}
+
+/// A block group descriptor.
+#[derive(Debug, Clone, Copy)]
+struct GroupDescriptor {
+    block_bitmap: u32,
+    inode_bitmap: u32,
+    inode_table: u32,
+    free_blocks: u16,
+    free_inodes: u16,
+    used_dirs: u16,
+}
+
+impl GroupDescriptor {
+    fn parse(bytes: &[u8]) -> Self {
+        GroupDescriptor {
+            block_bitmap: read_u32(bytes, 0),
+            inode_bitmap: read_u32(bytes, 4),
+            inode_table: read_u32(bytes, 8),
+            free_blocks: read_u16(bytes, 12),
+            free_inodes: read_u16(bytes, 14),
+            used_dirs: read_u16(bytes, 16),
+        }
+    }
+
+    fn store(&self, bytes: &mut [u8]) {
+        write_u16(bytes, 12, self.free_blocks);
+        write_u16(bytes, 14, self.free_inodes);
+        write_u16(bytes, 16, self.used_dirs);
+    }
+}
+
+/// The allocation state, guarded by a single lock.
+struct Groups {
+    /// The raw superblock, written back on sync.
+    superblock: [u8; SUPERBLOCK_SIZE],
+    descriptors: Vec<GroupDescriptor>,
+    dirty: bool,
+}
+
+/// A mounted ext2 filesystem.
+pub struct Ext2 {
+    disk: Arc<Disk>,
+    block_size: u32,
+    blocks_count: u32,
+    inodes_count: u32,
+    first_data_block: u32,
+    blocks_per_group: u32,
+    inodes_per_group: u32,
+    inode_size: u32,
+    first_inode: u32,
+    /// Directory entries carry a file type.
+    has_file_type: bool,
+    /// The filesystem uses features which this driver can read but not safely modify.
+    read_only: bool,
+    groups: Mutex<Groups>,
+    /// Serialises changes to directories.
+    dir_lock: Mutex<()>,
+    /// Live inodes, so that every lookup of a file shares the same state.
+    inodes: Mutex<BTreeMap<u32, Weak<Ext2Inode>>>,
+}
+
+impl Ext2 {
+    /// Parse the superblock and block group descriptors.
+    fn new(disk: Arc<Disk>) -> Result<Arc<Ext2>> {
+        let mut superblock = [0; SUPERBLOCK_SIZE];
+        read_bytes(&disk, SUPERBLOCK_OFFSET, &mut superblock)?;
+
+        if read_u16(&superblock, 56) != MAGIC {
+            return Err(FsError::Corrupted("ext2 superblock has an invalid magic number"));
+        }
+
+        let log_block_size = read_u32(&superblock, 24);
+        // Directory record lengths are 16 bits, so blocks of 64 KiB would need special casing.
+        if log_block_size > 5 {
+            return Err(FsError::NotSupported);
+        }
+
+        let block_size = 1024 << log_block_size;
+        let blocks_count = read_u32(&superblock, 4);
+        let inodes_count = read_u32(&superblock, 0);
+        let first_data_block = read_u32(&superblock, 20);
+        let blocks_per_group = read_u32(&superblock, 32);
+        let inodes_per_group = read_u32(&superblock, 40);
+        let revision = read_u32(&superblock, 76);
+
+        // Each group's block and inode bitmaps are a single block.
+        if blocks_per_group == 0 || inodes_per_group == 0 || blocks_count <= first_data_block
+            || blocks_per_group > block_size * 8 || inodes_per_group > block_size * 8
+        {
+            return Err(FsError::Corrupted("ext2 superblock is invalid"));
+        }
+
+        let (first_inode, inode_size, incompat, ro_compat) = if revision == 0 {
+            (GOOD_OLD_FIRST_INODE, GOOD_OLD_INODE_SIZE, 0, 0)
+        } else {
+            (
+                read_u32(&superblock, 84),
+                read_u16(&superblock, 88) as u32,
+                read_u32(&superblock, 96),
+                read_u32(&superblock, 100),
+            )
+        };
+
+        if first_inode <= ROOT_INODE || first_inode > inodes_per_group {
+            return Err(FsError::Corrupted("ext2 first inode is invalid"));
+        }
+
+        if inode_size < GOOD_OLD_INODE_SIZE || inode_size > block_size
+            || !inode_size.is_power_of_two()
+        {
+            return Err(FsError::Corrupted("ext2 inode size is invalid"));
+        }
+
+        if incompat & !SUPPORTED_INCOMPAT != 0 {
+            println!("[ ext2 ] Unsupported incompatible features {:#x}", incompat);
+            return Err(FsError::NotSupported);
+        }
+
+        let read_only = ro_compat & !SUPPORTED_RO_COMPAT != 0;
+        if read_only {
+            println!("[ ext2 ] Mounting read only, unsupported features {:#x}", ro_compat);
+        }
+
+        let group_count = ((blocks_count - first_data_block) as u64 + blocks_per_group as u64 - 1)
+            / blocks_per_group as u64;
+        let table_size = group_count * GROUP_DESCRIPTOR_SIZE as u64;
+
+        // The descriptor table has to fit in the first group, after the superblock.
+        if table_size > (blocks_per_group as u64 - 1) * block_size as u64 {
+            return Err(FsError::Corrupted("ext2 group descriptor table is too large"));
+        }
+
+        if table_size > MAX_DESCRIPTOR_TABLE {
+            println!("[ ext2 ] Too many block groups: {}", group_count);
+            return Err(FsError::NotSupported);
+        }
+
+        // Every group but the last is full of inodes.
+        if inodes_count as u64 > group_count * inodes_per_group as u64
+            || inodes_count as u64 <= (group_count - 1) * inodes_per_group as u64
+        {
+            return Err(FsError::Corrupted("ext2 inode count doesn't match the groups"));
+        }
+
+        // The descriptor table starts in the block after the superblock.
+        let table_offset = (first_data_block as u64 + 1) * block_size as u64;
+        let mut table = vec![0; table_size as usize];
+        read_bytes(&disk, table_offset, &mut table)?;
+
+        let descriptors = table
+            .chunks(GROUP_DESCRIPTOR_SIZE)
+            .map(GroupDescriptor::parse)
+            .collect();
+
+        println!(
+            "[ ext2 ] Mounted volume on {}, {} blocks of {} bytes in {} groups",
+            disk.name(),
+            blocks_count,
+            block_size,
+            group_count
+        );
+
+        Ok(Arc::new(Ext2 {
+            disk: disk,
+            block_size: block_size,
+            blocks_count: blocks_count,
+            inodes_count: inodes_count,
+            first_data_block: first_data_block,
+            blocks_per_group: blocks_per_group,
+            inodes_per_group: inodes_per_group,
+            inode_size: inode_size,
+            first_inode: first_inode,
+            has_file_type: incompat & FEATURE_INCOMPAT_FILETYPE != 0,
+            read_only: read_only,
+            groups: Mutex::new(Groups {
+                superblock: superblock,
+                descriptors: descriptors,
+                dirty: false,
+            }),
+            dir_lock: Mutex::new(()),
+            inodes: Mutex::new(BTreeMap::new()),
+        }))
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            Err(FsError::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The number of block numbers which fit in an indirect block.
+    fn pointers_per_block(&self) -> u32 {
+        self.block_size / 4
+    }
+
+    fn read_block(&self, block: u32, buffer: &mut [u8]) -> Result<()> {
+        read_bytes(&self.disk, block as u64 * self.block_size as u64, buffer)
+    }
+
+    fn write_block(&self, block: u32, buffer: &[u8]) -> Result<()> {
+        write_bytes(&self.disk, block as u64 * self.block_size as u64, buffer)
+    }
+
+    /// Write the descriptor of group `index`.
+    fn store_descriptor(&self, groups: &Groups, index: usize) -> Result<()> {
+        let offset = (self.first_data_block as u64 + 1) * self.block_size as u64
+            + (index * GROUP_DESCRIPTOR_SIZE) as u64;
+
+        let mut bytes = [0; GROUP_DESCRIPTOR_SIZE];
+        read_bytes(&self.disk, offset, &mut bytes)?;
+        groups.descriptors[index].store(&mut bytes);
+        write_bytes(&self.disk, offset, &bytes)
+    }
+
+    /// Find and set a clear bit between `start` and `limit` in a bitmap block.
+    fn allocate_bit(&self, bitmap: u32, start: u32, limit: u32) -> Result<Option<u32>> {
+        let mut bytes = vec![0; self.block_size as usize];
+        self.read_block(bitmap, &mut bytes)?;
+
+        for bit in start..limit {
+            let (byte, mask) = ((bit / 8) as usize, 1 << (bit % 8));
+
+            if bytes[byte] & mask == 0 {
+                bytes[byte] |= mask;
+                self.write_block(bitmap, &bytes)?;
+                return Ok(Some(bit));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Clear a bit in a bitmap, failing if it was already clear.
+    fn free_bit(&self, bitmap: u32, bit: u32) -> Result<()> {
+        let mut bytes = vec![0; self.block_size as usize];
+        self.read_block(bitmap, &mut bytes)?;
+
+        let (byte, mask) = ((bit / 8) as usize, 1 << (bit % 8));
+
+        if bytes[byte] & mask == 0 {
+            return Err(FsError::Corrupted("ext2 freed an unallocated block or inode"));
+        }
+
+        bytes[byte] &= !mask;
+        self.write_block(bitmap, &bytes)
+    }
+
+    /// Adjust one of the free counts in the superblock.
+    fn adjust_superblock(groups: &mut Groups, offset: usize, delta: i32) {
+        let count = read_u32(&groups.superblock, offset);
+        let count = if delta < 0 {
+            count.saturating_sub(-delta as u32)
+        } else {
+            count.saturating_add(delta as u32)
+        };
+        write_u32(&mut groups.superblock, offset, count);
+        groups.dirty = true;
+    }
+
+    /// Allocate a zeroed block, preferring the group `near` is in.
+    fn allocate_block(&self, near: u32) -> Result<u32> {
+        self.check_writable()?;
+
+        let block = {
+            let mut groups = self.groups.lock();
+            let count = groups.descriptors.len();
+            let start = (near.saturating_sub(self.first_data_block) / self.blocks_per_group) as usize;
+            let mut found = None;
+
+            for i in 0..count {
+                let group = (start + i) % count;
+
+                if groups.descriptors[group].free_blocks == 0 {
+                    continue;
+                }
+
+                let first = self.first_data_block + group as u32 * self.blocks_per_group;
+                let limit = (self.blocks_count - first).min(self.blocks_per_group);
+
+                if let Some(bit) = self.allocate_bit(groups.descriptors[group].block_bitmap, 0, limit)? {
+                    groups.descriptors[group].free_blocks -= 1;
+                    self.store_descriptor(&groups, group)?;
+                    Ext2::adjust_superblock(&mut groups, 12, -1);
+
+                    found = Some(first + bit);
+                    break;
+                }
+            }
+
+            found.ok_or(FsError::NoSpace)?
+        };
+
+        let zeroes = vec![0; self.block_size as usize];
+        self.write_block(block, &zeroes)?;
+
+        Ok(block)
+    }
+
+    fn free_block(&self, block: u32) -> Result<()> {
+        if block < self.first_data_block || block >= self.blocks_count {
+            return Err(FsError::Corrupted("ext2 block number is outside the volume"));
+        }
+
+        let mut groups = self.groups.lock();
+        let group = ((block - self.first_data_block) / self.blocks_per_group) as usize;
+        let bit = (block - self.first_data_block) % self.blocks_per_group;
+
+        self.free_bit(groups.descriptors[group].block_bitmap, bit)?;
+        groups.descriptors[group].free_blocks =
+            groups.descriptors[group].free_blocks.saturating_add(1);
+        self.store_descriptor(&groups, group)?;
+        Ext2::adjust_superblock(&mut groups, 12, 1);
+
+        Ok(())
+    }
+
+    /// Allocate an inode number, preferring the group of `parent`.
+    fn allocate_inode(&self, parent: u32, directory: bool) -> Result<u32> {
+        self.check_writable()?;
+
+        let mut groups = self.groups.lock();
+        let count = groups.descriptors.len();
+        let start = ((parent - 1) / self.inodes_per_group) as usize;
+
+        for i in 0..count {
+            let group = (start + i) % count;
+
+            if groups.descriptors[group].free_inodes == 0 {
+                continue;
+            }
+
+            let limit = (self.inodes_count - group as u32 * self.inodes_per_group)
+                .min(self.inodes_per_group);
+            let bitmap = groups.descriptors[group].inode_bitmap;
+
+            // Never hand out the reserved inodes at the start of the first group.
+            let first = if group == 0 { self.first_inode - 1 } else { 0 };
+
+            if let Some(bit) = self.allocate_bit(bitmap, first, limit)? {
+                let number = group as u32 * self.inodes_per_group + bit + 1;
+
+                groups.descriptors[group].free_inodes -= 1;
+                if directory {
+                    groups.descriptors[group].used_dirs =
+                        groups.descriptors[group].used_dirs.saturating_add(1);
+                }
+                self.store_descriptor(&groups, group)?;
+                Ext2::adjust_superblock(&mut groups, 16, -1);
+
+                return Ok(number);
+            }
+        }
+
+        Err(FsError::NoSpace)
+    }
+
+    fn free_inode(&self, number: u32, directory: bool) -> Result<()> {
+        let mut groups = self.groups.lock();
+        let group = ((number - 1) / self.inodes_per_group) as usize;
+        let bit = (number - 1) % self.inodes_per_group;
+
+        self.free_bit(groups.descriptors[group].inode_bitmap, bit)?;
+        groups.descriptors[group].free_inodes =
+            groups.descriptors[group].free_inodes.saturating_add(1);
+        if directory {
+            groups.descriptors[group].used_dirs =
+                groups.descriptors[group].used_dirs.saturating_sub(1);
+        }
+        self.store_descriptor(&groups, group)?;
+        Ext2::adjust_superblock(&mut groups, 16, 1);
+
+        Ok(())
+    }
+
+    /// The byte offset of an inode within the inode table.
+    fn inode_offset(&self, number: u32) -> Result<u64> {
+        if number == 0 || number > self.inodes_count {
+            return Err(FsError::Corrupted("ext2 inode number is out of range"));
+        }
+
+        let group = ((number - 1) / self.inodes_per_group) as usize;
+        let index = (number - 1) % self.inodes_per_group;
+        let table = self.groups.lock().descriptors[group].inode_table;
+
+        Ok(table as u64 * self.block_size as u64 + index as u64 * self.inode_size as u64)
+    }
+
+    fn read_inode(&self, number: u32) -> Result<DiskInode> {
+        let mut inode = DiskInode::empty();
+        read_bytes(&self.disk, self.inode_offset(number)?, &mut inode.raw)?;
+        Ok(inode)
+    }
+
+    /// Write the fields this driver knows about, leaving any extra space of larger inodes alone.
+    fn write_inode(&self, number: u32, inode: &DiskInode) -> Result<()> {
+        write_bytes(&self.disk, self.inode_offset(number)?, &inode.raw)
+    }
+
+    /// Write a newly allocated inode. The extra space of larger inodes is zeroed, so that nothing
+    /// left there by the last inode with this number is taken for extended fields.
+    fn init_inode(&self, number: u32, inode: &DiskInode) -> Result<()> {
+        let mut record = vec![0; self.inode_size as usize];
+        record[..inode.raw.len()].copy_from_slice(&inode.raw);
+        write_bytes(&self.disk, self.inode_offset(number)?, &record)
+    }
+
+    /// Return the inode numbered `number`, reusing a live one if there is one.
+    fn inode(fs: &Arc<Ext2>, number: u32) -> Result<Arc<Ext2Inode>> {
+        let mut inodes = fs.inodes.lock();
+
+        if let Some(inode) = inodes.get(&number).and_then(|w| w.upgrade()) {
+            return Ok(inode);
+        }
+
+        let inode = Arc::new(Ext2Inode::new(fs.clone(), number, fs.read_inode(number)?));
+        inodes.insert(number, Arc::downgrade(&inode));
+
+        // Drop the entries of inodes which no longer exist.
+        let dead: Vec<u32> = inodes
+            .iter()
+            .filter(|&(_, w)| w.upgrade().is_none())
+            .map(|(&k, _)| k)
+            .collect();
+        for key in dead {
+            inodes.remove(&key);
+        }
+
+        Ok(inode)
+    }
+
+    /// Set a read-only compatible feature flag in the superblock, once the filesystem uses it.
+    fn require_feature(&self, feature: u32) {
+        let mut groups = self.groups.lock();
+        let features = read_u32(&groups.superblock, 100);
+
+        if features & feature == 0 {
+            write_u32(&mut groups.superblock, 100, features | feature);
+            groups.dirty = true;
+        }
+    }
+
+    /// Write the free counts in the superblock back to the disk.
+    fn store_superblock(&self) -> Result<()> {
+        let mut groups = self.groups.lock();
+
+        if groups.dirty {
+            write_bytes(&self.disk, SUPERBLOCK_OFFSET, &groups.superblock)?;
+            groups.dirty = false;
+        }
+
+        Ok(())
+    }
+}
+
+/// Read bytes from anywhere on a disk.
+fn read_bytes(disk: &Disk, offset: u64, buffer: &mut [u8]) -> Result<()> {
+    if buffer.is_empty() {
+        return Ok(());
+    }
+
+    let sector_size = disk.sector_size() as u64;
+    let first = offset / sector_size;
+    let last = (offset + buffer.len() as u64 - 1) / sector_size;
+
+    let mut data = vec![0; ((last - first + 1) * sector_size) as usize];
+    disk.read(first, &mut data).map_err(FsError::Io)?;
+
+    let start = (offset - first * sector_size) as usize;
+    buffer.copy_from_slice(&data[start..start + buffer.len()]);
+
+    Ok(())
+}
+
+/// Write bytes anywhere on a disk, preserving the rest of the sectors touched.
+fn write_bytes(disk: &Disk, offset: u64, buffer: &[u8]) -> Result<()> {
+    if buffer.is_empty() {
+        return Ok(());
+    }
+
+    let sector_size = disk.sector_size() as u64;
+    let first = offset / sector_size;
+    let last = (offset + buffer.len() as u64 - 1) / sector_size;
+    let start = (offset - first * sector_size) as usize;
+
+    let mut data = vec![0; ((last - first + 1) * sector_size) as usize];
+
+    // Whole sectors don't need to be read first.
+    if start != 0 || buffer.len() != data.len() {
+        disk.read(first, &mut data).map_err(FsError::Io)?;
+    }
+
+    data[start..start + buffer.len()].copy_from_slice(buffer);
+    disk.write(first, &data).map_err(FsError::Io)?;
+
+    Ok(())
+}
+
+/// The `FileSystem` for a mounted ext2 volume.
+pub struct Ext2Fs {
+    fs: Arc<Ext2>,
+    root: Arc<Ext2Inode>,
+}
+
+impl FileSystem for Ext2Fs {
+    fn root(&self) -> Arc<Inode> {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.fs.store_superblock()?;
+        self.fs.disk.flush().map_err(FsError::Io)
+    }
+}
+
+pub struct Ext2FsType;
+
+impl FileSystemType for Ext2FsType {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn mount(&self, source: Option<Arc<Disk>>) -> Result<Arc<FileSystem>> {
+        let fs = Ext2::new(source.ok_or(FsError::InvalidArgument)?)?;
+        let root = Ext2::inode(&fs, ROOT_INODE)?;
+
+        Ok(Arc::new(Ext2Fs {
+            fs: fs,
+            root: root,
+        }))
+    }
+}
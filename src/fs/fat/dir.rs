@@ -0,0 +1,529 @@
+//! Directory entries, including the long file name entries which precede a short entry.
+
+use alloc::{String, Vec};
+use core::char;
+use super::{read_u16, read_u32, write_u16, DirLocation, Fat};
+use super::super::{FsError, Result};
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+pub const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+pub const ENTRY_SIZE: usize = 32;
+
+/// The first byte of a deleted entry.
+const DELETED: u8 = 0xE5;
+/// Set in the sequence number of the last long name entry, which is stored first.
+const LAST_LONG_ENTRY: u8 = 0x40;
+/// The number of UCS-2 characters in each long name entry.
+const CHARS_PER_LONG_ENTRY: usize = 13;
+/// Where those characters are within a long name entry.
+const LONG_NAME_OFFSETS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+const MAX_NAME_LENGTH: usize = 255;
+
+/// The modification date written to new entries, 1980-01-01, as there is no clock to read.
+const DEFAULT_DATE: u16 = 0x0021;
+
+/// A parsed short directory entry, with the long name assembled from the entries before it.
+#[derive(Debug, Clone)]
+pub struct FatDirEntry {
+    pub name: String,
+    pub short_name: [u8; 11],
+    pub attributes: u8,
+    pub first_cluster: u32,
+    pub size: u32,
+    /// The byte offset of the short entry on the volume.
+    pub offset: u64,
+    /// The index of the short entry within its directory.
+    pub slot: usize,
+    /// The number of long name entries before the short entry.
+    pub long_slots: usize,
+}
+
+impl FatDirEntry {
+    pub fn is_directory(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+}
+
+/// The checksum of a short name, stored in each of its long name entries.
+fn checksum(short_name: &[u8; 11]) -> u8 {
+    short_name.iter().fold(0u8, |sum, &c| {
+        ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(c)
+    })
+}
+
+/// Format a short name as `NAME.EXT`, honouring the lower case flags set by Windows NT.
+fn display_short_name(short_name: &[u8; 11], case_flags: u8) -> String {
+    let mut name = String::new();
+
+    let base_lower = case_flags & 0x08 != 0;
+    let ext_lower = case_flags & 0x10 != 0;
+
+    for (i, &c) in short_name[..8].iter().enumerate() {
+        // 0x05 stands in for a leading 0xE5, which would otherwise mark the entry deleted.
+        let c = if i == 0 && c == 0x05 { DELETED } else { c };
+        name.push(if base_lower { c.to_ascii_lowercase() } else { c } as char);
+    }
+
+    let trimmed = name.trim_right_matches(' ').len();
+    name.truncate(trimmed);
+
+    let extension: String = short_name[8..]
+        .iter()
+        .map(|&c| if ext_lower { c.to_ascii_lowercase() } else { c } as char)
+        .collect();
+    let extension = extension.trim_right_matches(' ');
+
+    if !extension.is_empty() {
+        name.push('.');
+        name.push_str(extension);
+    }
+
+    name
+}
+
+fn is_short_char(c: char) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || "$%'-_@~`!(){}^#&".contains(c)
+}
+
+/// The short name for `name` if it can be stored without a long name.
+fn exact_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, extension) = match name.rfind('.') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, ""),
+    };
+
+    if base.is_empty() || base.len() > 8 || extension.len() > 3
+        || !base.chars().chain(extension.chars()).all(is_short_char)
+    {
+        return None;
+    }
+
+    let mut short_name = [b' '; 11];
+    short_name[..base.len()].copy_from_slice(base.as_bytes());
+    short_name[8..8 + extension.len()].copy_from_slice(extension.as_bytes());
+
+    Some(short_name)
+}
+
+/// Generate a unique `BASIS~N` short name for a long name.
+fn generate_short_name(name: &str, existing: &[FatDirEntry]) -> Result<[u8; 11]> {
+    let convert = |s: &str| -> Vec<u8> {
+        s.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| {
+                let c = c.to_ascii_uppercase();
+                if is_short_char(c) { c as u8 } else { b'_' }
+            })
+            .collect()
+    };
+
+    let trimmed = name.trim_left_matches('.');
+    let (base, extension) = match trimmed.rfind('.') {
+        Some(i) => (convert(&trimmed[..i]), convert(&trimmed[i + 1..])),
+        None => (convert(trimmed), Vec::new()),
+    };
+
+    let mut short_name = [b' '; 11];
+    let extension_length = extension.len().min(3);
+    short_name[8..8 + extension_length].copy_from_slice(&extension[..extension_length]);
+
+    for n in 1..1000000u32 {
+        let tail = format!("~{}", n);
+        let base_length = base.len().min(8 - tail.len());
+
+        for c in short_name[..8].iter_mut() {
+            *c = b' ';
+        }
+        short_name[..base_length].copy_from_slice(&base[..base_length]);
+        short_name[base_length..base_length + tail.len()].copy_from_slice(tail.as_bytes());
+
+        if !existing.iter().any(|e| e.short_name == short_name) {
+            return Ok(short_name);
+        }
+    }
+
+    Err(FsError::NoSpace)
+}
+
+/// Check that `name` can be stored in a directory.
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.encode_utf16().count() > MAX_NAME_LENGTH
+        || name.chars().any(|c| (c as u32) < 0x20 || "\"*/:<>?\\|".contains(c))
+    {
+        return Err(FsError::InvalidPath);
+    }
+
+    Ok(())
+}
+
+/// Build a short directory entry.
+pub fn short_entry(short_name: &[u8; 11], attributes: u8, first_cluster: u32, size: u32) -> [u8; 32] {
+    let mut entry = [0; ENTRY_SIZE];
+
+    entry[..11].copy_from_slice(short_name);
+    entry[11] = attributes;
+    write_u16(&mut entry, 20, (first_cluster >> 16) as u16);
+    write_u16(&mut entry, 24, DEFAULT_DATE);
+    write_u16(&mut entry, 26, first_cluster as u16);
+    super::write_u32(&mut entry, 28, size);
+
+    entry
+}
+
+/// Build the long name entries for `name`, in the order they are stored.
+fn long_entries(name: &str, short_name: &[u8; 11]) -> Vec<[u8; 32]> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let count = (units.len() + CHARS_PER_LONG_ENTRY - 1) / CHARS_PER_LONG_ENTRY;
+    let sum = checksum(short_name);
+
+    (0..count)
+        .rev()
+        .map(|i| {
+            let mut entry = [0; ENTRY_SIZE];
+
+            entry[0] = (i + 1) as u8 | if i == count - 1 { LAST_LONG_ENTRY } else { 0 };
+            entry[11] = ATTR_LONG_NAME;
+            entry[13] = sum;
+
+            // The name is terminated by a NUL if it doesn't fill the entry, then padded with
+            // 0xFFFF.
+            for (j, &offset) in LONG_NAME_OFFSETS.iter().enumerate() {
+                let index = i * CHARS_PER_LONG_ENTRY + j;
+                let unit = if index < units.len() {
+                    units[index]
+                } else if index == units.len() {
+                    0
+                } else {
+                    0xFFFF
+                };
+
+                write_u16(&mut entry, offset, unit);
+            }
+
+            entry
+        })
+        .collect()
+}
+
+impl Fat {
+    /// The number of entries a directory can hold without being extended.
+    fn capacity(&self, location: DirLocation) -> Result<usize> {
+        match location {
+            DirLocation::FixedRoot => Ok(self.root_entry_count as usize),
+            DirLocation::Chain(first) => {
+                Ok(self.chain(first)?.len() * self.cluster_size() as usize / ENTRY_SIZE)
+            }
+        }
+    }
+
+    /// The byte offset on the volume of entry `slot` of a directory, or `None` if the directory
+    /// isn't that large.
+    fn slot_offset(&self, location: DirLocation, slot: usize) -> Result<Option<u64>> {
+        let byte = (slot * ENTRY_SIZE) as u64;
+
+        match location {
+            DirLocation::FixedRoot => {
+                if slot >= self.root_entry_count as usize {
+                    return Ok(None);
+                }
+
+                Ok(Some(self.root_dir_sector as u64 * self.bytes_per_sector as u64 + byte))
+            }
+            DirLocation::Chain(first) => {
+                let cluster_size = self.cluster_size() as u64;
+                let clusters = self.chain(first)?;
+
+                Ok(clusters
+                    .get((byte / cluster_size) as usize)
+                    .map(|&c| self.cluster_offset(c) + byte % cluster_size))
+            }
+        }
+    }
+
+    /// Read every entry of a directory.
+    fn read_dir(&self, location: DirLocation) -> Result<Vec<u8>> {
+        match location {
+            DirLocation::FixedRoot => {
+                let mut data = vec![0; self.root_entry_count as usize * ENTRY_SIZE];
+                let offset = self.root_dir_sector as u64 * self.bytes_per_sector as u64;
+                self.read_bytes(offset, &mut data)?;
+                Ok(data)
+            }
+            DirLocation::Chain(first) => {
+                let cluster_size = self.cluster_size() as usize;
+                let clusters = self.chain(first)?;
+                let mut data = vec![0; clusters.len() * cluster_size];
+
+                for (i, &cluster) in clusters.iter().enumerate() {
+                    let chunk = &mut data[i * cluster_size..(i + 1) * cluster_size];
+                    self.read_bytes(self.cluster_offset(cluster), chunk)?;
+                }
+
+                Ok(data)
+            }
+        }
+    }
+
+    /// Parse the entries of a directory, skipping `.`, `..`, volume labels and deleted entries.
+    pub fn entries(&self, location: DirLocation) -> Result<Vec<FatDirEntry>> {
+        let data = self.read_dir(location)?;
+        let mut entries = Vec::new();
+
+        // The long name entries seen since the last short entry, indexed by sequence number.
+        let mut long_name: Vec<Option<Vec<u16>>> = Vec::new();
+        let mut long_checksum = 0;
+        let mut long_slots = 0;
+
+        for (slot, raw) in data.chunks(ENTRY_SIZE).enumerate() {
+            if raw[0] == 0 {
+                break;
+            }
+
+            if raw[0] == DELETED {
+                long_name.clear();
+                long_slots = 0;
+                continue;
+            }
+
+            if raw[11] & 0x3F == ATTR_LONG_NAME {
+                let sequence = (raw[0] & 0x1F) as usize;
+
+                if raw[0] & LAST_LONG_ENTRY != 0 {
+                    long_name.clear();
+                    long_name.resize(sequence, None);
+                    long_checksum = raw[13];
+                    long_slots = 0;
+                }
+
+                if sequence == 0 || sequence > long_name.len() || raw[13] != long_checksum {
+                    long_name.clear();
+                    long_slots = 0;
+                    continue;
+                }
+
+                let units = LONG_NAME_OFFSETS
+                    .iter()
+                    .map(|&offset| read_u16(raw, offset))
+                    .take_while(|&unit| unit != 0 && unit != 0xFFFF)
+                    .collect();
+
+                long_name[sequence - 1] = Some(units);
+                long_slots += 1;
+                continue;
+            }
+
+            let mut short_name = [0; 11];
+            short_name.copy_from_slice(&raw[..11]);
+
+            let attributes = raw[11];
+            let pieces = long_name.len();
+            let have_long_name = pieces > 0 && long_slots == pieces
+                && long_checksum == checksum(&short_name)
+                && long_name.iter().all(|p| p.is_some());
+
+            let name = if have_long_name {
+                let units: Vec<u16> = long_name
+                    .iter()
+                    .flat_map(|p| p.as_ref().unwrap().iter().cloned())
+                    .collect();
+
+                char::decode_utf16(units.iter().cloned())
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect()
+            } else {
+                display_short_name(&short_name, raw[12])
+            };
+
+            let slots_before = if have_long_name { long_slots } else { 0 };
+            long_name.clear();
+            long_slots = 0;
+
+            if attributes & ATTR_VOLUME_ID != 0 || name == "." || name == ".." {
+                continue;
+            }
+
+            let offset = self.slot_offset(location, slot)?
+                .ok_or(FsError::Corrupted("FAT directory entry is outside its directory"))?;
+
+            entries.push(FatDirEntry {
+                name: name,
+                short_name: short_name,
+                attributes: attributes,
+                first_cluster: (read_u16(raw, 20) as u32) << 16 | read_u16(raw, 26) as u32,
+                size: read_u32(raw, 28),
+                offset: offset,
+                slot: slot,
+                long_slots: slots_before,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// Find an entry of a directory by name. Names are compared ignoring case, as on other
+    /// systems.
+    pub fn find(&self, location: DirLocation, name: &str) -> Result<FatDirEntry> {
+        self.entries(location)?
+            .into_iter()
+            .find(|e| {
+                e.name.eq_ignore_ascii_case(name)
+                    || display_short_name(&e.short_name, 0).eq_ignore_ascii_case(name)
+            })
+            .ok_or(FsError::NotFound)
+    }
+
+    /// Write raw entries to consecutive slots of a directory.
+    fn write_slots(&self, location: DirLocation, start: usize, slots: &[[u8; 32]]) -> Result<()> {
+        for (i, slot) in slots.iter().enumerate() {
+            let offset = self.slot_offset(location, start + i)?
+                .ok_or(FsError::Corrupted("FAT directory entry is outside its directory"))?;
+            self.write_bytes(offset, slot)?;
+        }
+
+        Ok(())
+    }
+
+    /// Find `count` consecutive free slots in a directory, extending it if it is a cluster chain.
+    fn free_slots(&self, location: DirLocation, count: usize) -> Result<usize> {
+        let data = self.read_dir(location)?;
+        let mut run_start = 0;
+        let mut run_length = 0;
+
+        for (slot, raw) in data.chunks(ENTRY_SIZE).enumerate() {
+            if raw[0] == 0 {
+                // Every slot from the end marker on is free.
+                if run_length == 0 {
+                    run_start = slot;
+                }
+                run_length += data.len() / ENTRY_SIZE - slot;
+                break;
+            } else if raw[0] == DELETED {
+                if run_length == 0 {
+                    run_start = slot;
+                }
+                run_length += 1;
+            } else {
+                run_length = 0;
+            }
+
+            if run_length >= count {
+                return Ok(run_start);
+            }
+        }
+
+        if run_length == 0 {
+            run_start = data.len() / ENTRY_SIZE;
+        }
+
+        let first = match location {
+            DirLocation::FixedRoot if run_start + count <= self.capacity(location)? => {
+                return Ok(run_start);
+            }
+            DirLocation::FixedRoot => return Err(FsError::NoSpace),
+            DirLocation::Chain(first) => first,
+        };
+
+        let mut last = *self.chain(first)?.last().ok_or(FsError::Corrupted("FAT directory has no clusters"))?;
+
+        while self.capacity(location)? < run_start + count {
+            last = self.allocate_cluster(Some(last))?;
+        }
+
+        Ok(run_start)
+    }
+
+    /// Add an entry named `name` to a directory, with long name entries if it needs them.
+    pub fn add_entry(
+        &self,
+        location: DirLocation,
+        name: &str,
+        attributes: u8,
+        first_cluster: u32,
+    ) -> Result<FatDirEntry> {
+        validate_name(name)?;
+
+        let existing = self.entries(location)?;
+
+        if existing.iter().any(|e| e.name.eq_ignore_ascii_case(name)) {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let (short_name, mut slots) = match exact_short_name(name) {
+            Some(short_name) if !existing.iter().any(|e| e.short_name == short_name) => {
+                (short_name, Vec::new())
+            }
+            _ => {
+                let short_name = generate_short_name(name, &existing)?;
+                (short_name, long_entries(name, &short_name))
+            }
+        };
+
+        let long_slots = slots.len();
+        slots.push(short_entry(&short_name, attributes, first_cluster, 0));
+
+        let start = self.free_slots(location, slots.len())?;
+        self.write_slots(location, start, &slots)?;
+
+        let slot = start + long_slots;
+        let offset = self.slot_offset(location, slot)?
+            .ok_or(FsError::Corrupted("FAT directory entry is outside its directory"))?;
+
+        Ok(FatDirEntry {
+            name: String::from(name),
+            short_name: short_name,
+            attributes: attributes,
+            first_cluster: first_cluster,
+            size: 0,
+            offset: offset,
+            slot: slot,
+            long_slots: long_slots,
+        })
+    }
+
+    /// Mark an entry and its long name entries as deleted.
+    pub fn remove_entry(&self, location: DirLocation, entry: &FatDirEntry) -> Result<()> {
+        for slot in entry.slot - entry.long_slots..entry.slot + 1 {
+            let offset = self.slot_offset(location, slot)?
+                .ok_or(FsError::Corrupted("FAT directory entry is outside its directory"))?;
+            self.write_bytes(offset, &[DELETED])?;
+        }
+
+        Ok(())
+    }
+
+    /// Update the first cluster and size of the entry at `offset`. The entry is read and written
+    /// in one go, so that entries sharing its sector which are written meanwhile are kept, and it
+    /// is left alone if it has been deleted meanwhile.
+    pub fn update_entry(&self, offset: u64, first_cluster: u32, size: u32) -> Result<()> {
+        self.modify_bytes(offset, ENTRY_SIZE, |entry| {
+            if entry[0] == 0 || entry[0] == DELETED {
+                return;
+            }
+
+            write_u16(entry, 20, (first_cluster >> 16) as u16);
+            write_u16(entry, 26, first_cluster as u16);
+            super::write_u32(entry, 28, size);
+        })
+    }
+
+    /// Write the `.` and `..` entries of a new directory.
+    pub fn init_directory(&self, cluster: u32, parent: DirLocation) -> Result<()> {
+        // `..` refers to the root directory as cluster 0, even on FAT32.
+        let parent_cluster = match parent {
+            DirLocation::Chain(c) if c != self.root_cluster => c,
+            _ => 0,
+        };
+
+        let dot = short_entry(b".          ", ATTR_DIRECTORY, cluster, 0);
+        let dot_dot = short_entry(b"..         ", ATTR_DIRECTORY, parent_cluster, 0);
+
+        self.write_slots(DirLocation::Chain(cluster), 0, &[dot, dot_dot])
+    }
+}
@@ -0,0 +1,406 @@
+//! Files and directories of a FAT volume.
+
+use alloc::arc::Arc;
+use alloc::Vec;
+use core::cmp;
+use spin::{Mutex, RwLock};
+use super::dir::{FatDirEntry, ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_READ_ONLY};
+use super::{DirLocation, Fat};
+use super::super::{DirEntry, FileType, FsError, Inode, Metadata, Result};
+
+/// The inode number of the root directory, which has no directory entry.
+const ROOT_INODE: u64 = 1;
+
+struct State {
+    first_cluster: u32,
+    size: u32,
+    /// Set once the entry has been removed from its directory. The clusters are freed when the
+    /// inode is dropped, so that files which are still open can be read until they are closed.
+    unlinked: bool,
+}
+
+pub struct FatInode {
+    fat: Arc<Fat>,
+    /// The byte offset of the directory entry, or `None` for the root directory.
+    entry_offset: Option<u64>,
+    attributes: u8,
+    state: RwLock<State>,
+    /// The clusters of the file, read from the FAT on first use and kept up to date as it grows
+    /// and shrinks. Locked after `state`.
+    chain: Mutex<Option<Vec<u32>>>,
+}
+
+impl FatInode {
+    pub fn root(fat: Arc<Fat>) -> Self {
+        let root_cluster = fat.root_cluster;
+
+        FatInode {
+            fat: fat,
+            entry_offset: None,
+            attributes: ATTR_DIRECTORY,
+            state: RwLock::new(State {
+                first_cluster: root_cluster,
+                size: 0,
+                unlinked: false,
+            }),
+            chain: Mutex::new(None),
+        }
+    }
+
+    pub fn new(fat: Arc<Fat>, entry: &FatDirEntry) -> Self {
+        FatInode {
+            fat: fat,
+            entry_offset: Some(entry.offset),
+            attributes: entry.attributes,
+            state: RwLock::new(State {
+                first_cluster: entry.first_cluster,
+                size: if entry.is_directory() { 0 } else { entry.size },
+                unlinked: false,
+            }),
+            chain: Mutex::new(None),
+        }
+    }
+
+    fn is_directory(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    /// Where the entries of this directory are stored.
+    fn location(&self) -> Result<DirLocation> {
+        if !self.is_directory() {
+            return Err(FsError::NotADirectory);
+        }
+
+        let state = self.state.read();
+
+        if state.unlinked {
+            Err(FsError::NotFound)
+        } else if self.entry_offset.is_none() {
+            Ok(self.fat.root_location())
+        } else {
+            Ok(DirLocation::Chain(state.first_cluster))
+        }
+    }
+
+    /// Write the first cluster and size back to the directory entry.
+    fn store(&self, state: &State) -> Result<()> {
+        match self.entry_offset {
+            Some(offset) if !state.unlinked => {
+                self.fat.update_entry(offset, state.first_cluster, state.size)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Run `f` on the clusters of the file starting at `first_cluster`, walking the FAT for them
+    /// only the first time.
+    fn with_chain<T, F>(&self, first_cluster: u32, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Vec<u32>) -> Result<T>,
+    {
+        let mut chain = self.chain.lock();
+
+        if chain.is_none() {
+            *chain = Some(self.fat.chain(first_cluster)?);
+        }
+
+        f(chain.as_mut().unwrap())
+    }
+
+    /// Write `buffer` at `offset`, allocating clusters as needed.
+    fn write_locked(&self, state: &mut State, offset: u64, buffer: &[u8]) -> Result<usize> {
+        let end = offset + buffer.len() as u64;
+
+        if end > u32::max_value() as u64 {
+            return Err(FsError::NoSpace);
+        }
+
+        let cluster_size = self.fat.cluster_size() as u64;
+        let needed = ((end + cluster_size - 1) / cluster_size) as usize;
+        let mut first_cluster = state.first_cluster;
+
+        let result = self.with_chain(state.first_cluster, |clusters| {
+            while clusters.len() < needed {
+                let cluster = self.fat.allocate_cluster(clusters.last().cloned())?;
+
+                if clusters.is_empty() {
+                    first_cluster = cluster;
+                }
+
+                clusters.push(cluster);
+            }
+
+            let mut written = 0;
+
+            while written < buffer.len() {
+                let position = offset + written as u64;
+                let within = position % cluster_size;
+                let length = cmp::min(buffer.len() - written, (cluster_size - within) as usize);
+                let cluster = clusters[(position / cluster_size) as usize];
+
+                self.fat.write_bytes(
+                    self.fat.cluster_offset(cluster) + within,
+                    &buffer[written..written + length],
+                )?;
+                written += length;
+            }
+
+            Ok(written)
+        });
+
+        state.first_cluster = first_cluster;
+        let written = result?;
+
+        if end > state.size as u64 {
+            state.size = end as u32;
+        }
+
+        Ok(written)
+    }
+
+    /// Zero the bytes between the end of the file and `end`, which may hold stale data from the
+    /// end of the last cluster.
+    fn zero_fill(&self, state: &mut State, end: u64) -> Result<()> {
+        let chunk = vec![0; self.fat.cluster_size() as usize];
+
+        while (state.size as u64) < end {
+            let start = state.size as u64;
+            let length = cmp::min(chunk.len() as u64, end - start) as usize;
+            self.write_locked(state, start, &chunk[..length])?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Inode for FatInode {
+    fn metadata(&self) -> Result<Metadata> {
+        let state = self.state.read();
+        let inode = self.entry_offset.unwrap_or(ROOT_INODE);
+
+        let mut metadata = if self.is_directory() {
+            Metadata::new(inode, FileType::Directory, 0)
+        } else {
+            Metadata::new(inode, FileType::File, state.size as u64)
+        };
+
+        if self.attributes & ATTR_READ_ONLY != 0 {
+            metadata.mode &= 0o555;
+        }
+
+        Ok(metadata)
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        if self.is_directory() {
+            return Err(FsError::IsADirectory);
+        }
+
+        let state = self.state.read();
+
+        if offset >= state.size as u64 {
+            return Ok(0);
+        }
+
+        let cluster_size = self.fat.cluster_size() as u64;
+        let total = cmp::min(buffer.len() as u64, state.size as u64 - offset) as usize;
+
+        self.with_chain(state.first_cluster, |clusters| {
+            let mut read = 0;
+
+            while read < total {
+                let position = offset + read as u64;
+                let within = position % cluster_size;
+                let length = cmp::min(total - read, (cluster_size - within) as usize);
+                let cluster = *clusters
+                    .get((position / cluster_size) as usize)
+                    .ok_or(FsError::Corrupted("FAT cluster chain is shorter than the file"))?;
+
+                self.fat.read_bytes(
+                    self.fat.cluster_offset(cluster) + within,
+                    &mut buffer[read..read + length],
+                )?;
+                read += length;
+            }
+
+            Ok(read)
+        })
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize> {
+        if self.is_directory() {
+            return Err(FsError::IsADirectory);
+        }
+
+        if self.attributes & ATTR_READ_ONLY != 0 {
+            return Err(FsError::PermissionDenied);
+        }
+
+        let mut state = self.state.write();
+
+        if state.unlinked {
+            return Err(FsError::NotFound);
+        }
+
+        if offset > state.size as u64 {
+            self.zero_fill(&mut state, offset)?;
+        }
+
+        let result = self.write_locked(&mut state, offset, buffer);
+
+        // Record whatever was allocated, even if the write failed part way.
+        self.store(&state)?;
+        result
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        if self.is_directory() {
+            return Err(FsError::IsADirectory);
+        }
+
+        if size > u32::max_value() as u64 {
+            return Err(FsError::NoSpace);
+        }
+
+        let mut state = self.state.write();
+
+        if state.unlinked {
+            return Err(FsError::NotFound);
+        }
+
+        if size > state.size as u64 {
+            let result = self.zero_fill(&mut state, size);
+            self.store(&state)?;
+            return result;
+        }
+
+        let cluster_size = self.fat.cluster_size() as u64;
+        let needed = ((size + cluster_size - 1) / cluster_size) as usize;
+        let first_cluster = state.first_cluster;
+
+        self.with_chain(first_cluster, |clusters| {
+            if needed == 0 {
+                if !clusters.is_empty() {
+                    self.fat.free_chain(first_cluster)?;
+                }
+                clusters.clear();
+            } else if clusters.len() > needed {
+                let rest = clusters[needed];
+
+                self.fat.set_fat_entry(clusters[needed - 1], self.fat.end_of_chain())?;
+                clusters.truncate(needed);
+                self.fat.free_chain(rest)?;
+            }
+
+            Ok(())
+        })?;
+
+        if needed == 0 {
+            state.first_cluster = 0;
+        }
+
+        state.size = size as u32;
+        self.store(&state)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<Inode>> {
+        let location = self.location()?;
+        let entry = self.fat.find(location, name)?;
+
+        Ok(Fat::inode(&self.fat, &entry))
+    }
+
+    fn create(&self, name: &str, file_type: FileType) -> Result<Arc<Inode>> {
+        let location = self.location()?;
+        let _guard = self.fat.dir_lock.lock();
+
+        let entry = match file_type {
+            FileType::File => self.fat.add_entry(location, name, ATTR_ARCHIVE, 0)?,
+            FileType::Directory => {
+                let cluster = self.fat.allocate_cluster(None)?;
+
+                let result = self.fat
+                    .init_directory(cluster, location)
+                    .and_then(|_| self.fat.add_entry(location, name, ATTR_DIRECTORY, cluster));
+
+                match result {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        self.fat.free_chain(cluster)?;
+                        return Err(e);
+                    }
+                }
+            }
+            _ => return Err(FsError::NotSupported),
+        };
+
+        Ok(Fat::inode(&self.fat, &entry))
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        let location = self.location()?;
+        let _guard = self.fat.dir_lock.lock();
+
+        let entry = self.fat.find(location, name)?;
+
+        if entry.is_directory() {
+            let children = self.fat.entries(DirLocation::Chain(entry.first_cluster))?;
+
+            if !children.is_empty() {
+                return Err(FsError::DirectoryNotEmpty);
+            }
+        }
+
+        // An inode which is in use frees the clusters itself once the last user drops it.
+        match self.fat.forget_inode(entry.offset) {
+            Some(inode) => {
+                inode.state.write().unlinked = true;
+
+                if let Err(e) = self.fat.remove_entry(location, &entry) {
+                    inode.state.write().unlinked = false;
+                    self.fat.remember_inode(entry.offset, &inode);
+                    return Err(e);
+                }
+            }
+            None => {
+                self.fat.remove_entry(location, &entry)?;
+
+                if entry.first_cluster != 0 {
+                    self.fat.free_chain(entry.first_cluster)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        let location = self.location()?;
+
+        Ok(self.fat
+            .entries(location)?
+            .into_iter()
+            .map(|entry| DirEntry {
+                inode: entry.offset,
+                file_type: if entry.is_directory() {
+                    FileType::Directory
+                } else {
+                    FileType::File
+                },
+                name: entry.name,
+            })
+            .collect())
+    }
+}
+
+impl Drop for FatInode {
+    fn drop(&mut self) {
+        let state = self.state.read();
+
+        if state.unlinked && state.first_cluster != 0 {
+            if let Err(e) = self.fat.free_chain(state.first_cluster) {
+                println!("[ fat ] Could not free the clusters of a removed file: {:?}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,611 @@
+//! The FAT12, FAT16 and FAT32 filesystems, with long file names.
+
+use alloc::arc::{Arc, Weak};
+use alloc::btree_map::BTreeMap;
+use alloc::Vec;
+use device::block::Disk;
+use spin::Mutex;
+use super::{FileSystem, FileSystemType, FsError, Inode, Result};
+
+mod dir;
+mod inode;
+
+use self::inode::FatInode;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// Where a directory's entries are stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DirLocation {
+    /// The fixed size root directory of FAT12 and FAT16.
+    FixedRoot,
+    /// A cluster chain, starting at the given cluster.
+    Chain(u32),
+}
+
+const FSINFO_LEAD_SIGNATURE: u32 = 0x41615252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x61417272;
+
+/// The first cluster number which refers to the data region.
+const FIRST_CLUSTER: u32 = 2;
+
+/// The largest sector and cluster sizes allowed by the specification. Whole clusters are read
+/// into memory, so larger ones from a corrupted boot sector would run the kernel heap out.
+const MAX_SECTOR_SIZE: u32 = 4096;
+const MAX_CLUSTER_SIZE: u32 = 32 * 1024;
+
+/// The most entries we accept in the fixed root directory of FAT12 and FAT16, which is read into
+/// memory whole. Formatting tools use 512 at most.
+const MAX_ROOT_ENTRIES: u32 = 1024;
+
+fn read_u16(bytes: &[u8], i: usize) -> u16 {
+    bytes[i] as u16 | (bytes[i + 1] as u16) << 8
+}
+
+fn read_u32(bytes: &[u8], i: usize) -> u32 {
+    read_u16(bytes, i) as u32 | (read_u16(bytes, i + 2) as u32) << 16
+}
+
+fn write_u16(bytes: &mut [u8], i: usize, value: u16) {
+    bytes[i] = value as u8;
+    bytes[i + 1] = (value >> 8) as u8;
+}
+
+fn write_u32(bytes: &mut [u8], i: usize, value: u32) {
+    write_u16(bytes, i, value as u16);
+    write_u16(bytes, i + 2, (value >> 16) as u16);
+}
+
+/// The free cluster bookkeeping which FAT32 keeps in the FSInfo sector.
+struct AllocState {
+    /// The number of free clusters, or `None` if unknown.
+    free_count: Option<u32>,
+    /// Where to start looking for a free cluster.
+    next_free: u32,
+    /// Whether the FSInfo sector needs to be written back.
+    dirty: bool,
+}
+
+/// A mounted FAT filesystem.
+pub struct Fat {
+    disk: Arc<Disk>,
+    fat_type: FatType,
+    bytes_per_sector: u32,
+    sectors_per_cluster: u32,
+    reserved_sectors: u32,
+    num_fats: u32,
+    fat_size: u32,
+    root_entry_count: u32,
+    root_dir_sector: u32,
+    first_data_sector: u32,
+    /// The number of clusters in the data region.
+    cluster_count: u32,
+    root_cluster: u32,
+    /// The sector of the FSInfo structure, for FAT32.
+    fsinfo_sector: Option<u32>,
+    alloc: Mutex<AllocState>,
+    /// Serialises changes to directories.
+    dir_lock: Mutex<()>,
+    /// Held across every read-modify-write of the sectors of the volume, so that two writers
+    /// sharing a sector, such as neighbouring directory entries or FAT12 entries, don't undo each
+    /// other's changes. Nothing else is locked while it is held.
+    write_lock: Mutex<()>,
+    /// Live inodes, keyed by the byte offset of their directory entry, so that every lookup of a
+    /// file shares the same state.
+    inodes: Mutex<BTreeMap<u64, Weak<FatInode>>>,
+}
+
+impl Fat {
+    /// Parse the BIOS parameter block and FSInfo sector of a FAT volume.
+    fn new(disk: Arc<Disk>) -> Result<Arc<Fat>> {
+        let mut boot = vec![0; disk.sector_size()];
+        disk.read(0, &mut boot).map_err(FsError::Io)?;
+
+        if boot.len() < 512 || boot[510] != 0x55 || boot[511] != 0xAA {
+            return Err(FsError::Corrupted("FAT boot sector has an invalid signature"));
+        }
+
+        let bytes_per_sector = read_u16(&boot, 0x0B) as u32;
+        let sectors_per_cluster = boot[0x0D] as u32;
+        let reserved_sectors = read_u16(&boot, 0x0E) as u32;
+        let num_fats = boot[0x10] as u32;
+        let root_entry_count = read_u16(&boot, 0x11) as u32;
+
+        if bytes_per_sector < 512 || bytes_per_sector > MAX_SECTOR_SIZE
+            || !bytes_per_sector.is_power_of_two() || sectors_per_cluster == 0
+            || !sectors_per_cluster.is_power_of_two()
+            || bytes_per_sector * sectors_per_cluster > MAX_CLUSTER_SIZE
+            || reserved_sectors == 0 || num_fats == 0
+        {
+            return Err(FsError::Corrupted("FAT BIOS parameter block is invalid"));
+        }
+
+        if root_entry_count > MAX_ROOT_ENTRIES {
+            return Err(FsError::Corrupted("FAT root directory is too large"));
+        }
+
+        let total_sectors = match read_u16(&boot, 0x13) {
+            0 => read_u32(&boot, 0x20),
+            n => n as u32,
+        };
+
+        let fat_size = match read_u16(&boot, 0x16) {
+            0 => read_u32(&boot, 0x24),
+            n => n as u32,
+        };
+
+        if fat_size == 0 {
+            return Err(FsError::Corrupted("FAT has no sectors"));
+        }
+
+        let root_dir_sectors = (root_entry_count * 32 + bytes_per_sector - 1) / bytes_per_sector;
+        let root_dir_sector = num_fats
+            .checked_mul(fat_size)
+            .and_then(|sectors| sectors.checked_add(reserved_sectors));
+        let first_data_sector =
+            root_dir_sector.and_then(|sector| sector.checked_add(root_dir_sectors));
+
+        let (root_dir_sector, first_data_sector) = match (root_dir_sector, first_data_sector) {
+            (Some(root), Some(data)) if data < total_sectors => (root, data),
+            _ => return Err(FsError::Corrupted("FAT volume has no data region")),
+        };
+
+        let cluster_count = (total_sectors - first_data_sector) / sectors_per_cluster;
+
+        if cluster_count == 0 {
+            return Err(FsError::Corrupted("FAT volume has no data region"));
+        }
+
+        // The type is determined by the number of clusters alone.
+        let fat_type = if cluster_count < 4085 {
+            FatType::Fat12
+        } else if cluster_count < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+
+        let (root_cluster, fsinfo_sector) = match fat_type {
+            FatType::Fat32 => (read_u32(&boot, 0x2C), Some(read_u16(&boot, 0x30) as u32)),
+            _ => (0, None),
+        };
+
+        if fat_type == FatType::Fat32
+            && (root_cluster < FIRST_CLUSTER || root_cluster >= cluster_count + FIRST_CLUSTER)
+        {
+            return Err(FsError::Corrupted("FAT32 root directory cluster is out of range"));
+        }
+
+        // Every cluster needs an entry in the FAT, as do the two reserved ones before them.
+        let entries = cluster_count as u64 + FIRST_CLUSTER as u64;
+        let needed = match fat_type {
+            FatType::Fat12 => (entries * 3 + 1) / 2,
+            FatType::Fat16 => entries * 2,
+            FatType::Fat32 => entries * 4,
+        };
+
+        if (fat_size as u64) * (bytes_per_sector as u64) < needed {
+            return Err(FsError::Corrupted("FAT is too small for the number of clusters"));
+        }
+
+        let mut fat = Fat {
+            disk: disk,
+            fat_type: fat_type,
+            bytes_per_sector: bytes_per_sector,
+            sectors_per_cluster: sectors_per_cluster,
+            reserved_sectors: reserved_sectors,
+            num_fats: num_fats,
+            fat_size: fat_size,
+            root_entry_count: root_entry_count,
+            root_dir_sector: root_dir_sector,
+            first_data_sector: first_data_sector,
+            cluster_count: cluster_count,
+            root_cluster: root_cluster,
+            fsinfo_sector: None,
+            alloc: Mutex::new(AllocState {
+                free_count: None,
+                next_free: FIRST_CLUSTER,
+                dirty: false,
+            }),
+            dir_lock: Mutex::new(()),
+            write_lock: Mutex::new(()),
+            inodes: Mutex::new(BTreeMap::new()),
+        };
+
+        match fsinfo_sector {
+            Some(sector) if sector != 0 && sector != 0xFFFF => fat.load_fsinfo(sector)?,
+            _ => (),
+        }
+
+        println!(
+            "[ fat ] Mounted {:?} volume on {}, {} clusters of {} bytes",
+            fat.fat_type,
+            fat.disk.name(),
+            fat.cluster_count,
+            fat.cluster_size()
+        );
+
+        Ok(Arc::new(fat))
+    }
+
+    /// Read the free cluster hints from the FSInfo sector, if it is valid.
+    fn load_fsinfo(&mut self, sector: u32) -> Result<()> {
+        let mut info = [0; 512];
+        self.read_bytes(sector as u64 * self.bytes_per_sector as u64, &mut info)?;
+
+        if read_u32(&info, 0) != FSINFO_LEAD_SIGNATURE
+            || read_u32(&info, 484) != FSINFO_STRUCT_SIGNATURE
+        {
+            println!("[ fat ] Ignoring FSInfo sector with invalid signatures");
+            return Ok(());
+        }
+
+        let free_count = read_u32(&info, 488);
+        let next_free = read_u32(&info, 492);
+
+        {
+            let mut alloc = self.alloc.lock();
+
+            if free_count <= self.cluster_count {
+                alloc.free_count = Some(free_count);
+            }
+
+            if next_free >= FIRST_CLUSTER && next_free < self.cluster_count + FIRST_CLUSTER {
+                alloc.next_free = next_free;
+            }
+        }
+
+        self.fsinfo_sector = Some(sector);
+
+        Ok(())
+    }
+
+    /// Write the free cluster hints back to the FSInfo sector.
+    fn store_fsinfo(&self) -> Result<()> {
+        let sector = match self.fsinfo_sector {
+            Some(sector) => sector,
+            None => return Ok(()),
+        };
+
+        let mut alloc = self.alloc.lock();
+        if !alloc.dirty {
+            return Ok(());
+        }
+
+        let offset = sector as u64 * self.bytes_per_sector as u64;
+        let (free_count, next_free) = (alloc.free_count.unwrap_or(0xFFFFFFFF), alloc.next_free);
+
+        self.modify_bytes(offset, 512, |info| {
+            write_u32(info, 488, free_count);
+            write_u32(info, 492, next_free);
+        })?;
+
+        alloc.dirty = false;
+        Ok(())
+    }
+
+    pub fn cluster_size(&self) -> u32 {
+        self.bytes_per_sector * self.sectors_per_cluster
+    }
+
+    /// Read bytes from anywhere on the volume.
+    fn read_bytes(&self, offset: u64, buffer: &mut [u8]) -> Result<()> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+
+        let sector_size = self.disk.sector_size() as u64;
+        let first = offset / sector_size;
+        let last = (offset + buffer.len() as u64 - 1) / sector_size;
+
+        let mut data = vec![0; ((last - first + 1) * sector_size) as usize];
+        self.disk.read(first, &mut data).map_err(FsError::Io)?;
+
+        let start = (offset - first * sector_size) as usize;
+        buffer.copy_from_slice(&data[start..start + buffer.len()]);
+
+        Ok(())
+    }
+
+    /// Write bytes anywhere on the volume, preserving the rest of the sectors touched.
+    fn write_bytes(&self, offset: u64, buffer: &[u8]) -> Result<()> {
+        self.modify_bytes(offset, buffer.len(), |bytes| bytes.copy_from_slice(buffer))
+    }
+
+    /// Read `length` bytes from anywhere on the volume, change them with `modify`, and write them
+    /// back, all under `write_lock`.
+    fn modify_bytes<F>(&self, offset: u64, length: usize, modify: F) -> Result<()>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        if length == 0 {
+            return Ok(());
+        }
+
+        let sector_size = self.disk.sector_size() as u64;
+        let first = offset / sector_size;
+        let last = (offset + length as u64 - 1) / sector_size;
+
+        let mut data = vec![0; ((last - first + 1) * sector_size) as usize];
+        let start = (offset - first * sector_size) as usize;
+
+        let _guard = self.write_lock.lock();
+
+        self.disk.read(first, &mut data).map_err(FsError::Io)?;
+        modify(&mut data[start..start + length]);
+        self.disk.write(first, &data).map_err(FsError::Io)?;
+
+        Ok(())
+    }
+
+    /// The byte offset of the start of a cluster.
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        let sector = self.first_data_sector + (cluster - FIRST_CLUSTER) * self.sectors_per_cluster;
+        sector as u64 * self.bytes_per_sector as u64
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= FIRST_CLUSTER && cluster < self.cluster_count + FIRST_CLUSTER
+    }
+
+    /// The value marking the end of a cluster chain.
+    fn end_of_chain(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => 0xFFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFFFFFF,
+        }
+    }
+
+    /// The byte offset of a cluster's entry within the first FAT.
+    fn fat_entry_offset(&self, cluster: u32) -> u64 {
+        let offset = match self.fat_type {
+            FatType::Fat12 => cluster + cluster / 2,
+            FatType::Fat16 => cluster * 2,
+            FatType::Fat32 => cluster * 4,
+        };
+
+        self.reserved_sectors as u64 * self.bytes_per_sector as u64 + offset as u64
+    }
+
+    /// Read the FAT entry of a cluster.
+    fn fat_entry(&self, cluster: u32) -> Result<u32> {
+        let offset = self.fat_entry_offset(cluster);
+
+        match self.fat_type {
+            FatType::Fat12 => {
+                let mut bytes = [0; 2];
+                self.read_bytes(offset, &mut bytes)?;
+                let value = read_u16(&bytes, 0) as u32;
+
+                Ok(if cluster & 1 == 1 { value >> 4 } else { value & 0xFFF })
+            }
+            FatType::Fat16 => {
+                let mut bytes = [0; 2];
+                self.read_bytes(offset, &mut bytes)?;
+                Ok(read_u16(&bytes, 0) as u32)
+            }
+            FatType::Fat32 => {
+                let mut bytes = [0; 4];
+                self.read_bytes(offset, &mut bytes)?;
+                Ok(read_u32(&bytes, 0) & 0x0FFFFFFF)
+            }
+        }
+    }
+
+    /// Set the FAT entry of a cluster in every copy of the FAT.
+    fn set_fat_entry(&self, cluster: u32, value: u32) -> Result<()> {
+        let fat_bytes = self.fat_size as u64 * self.bytes_per_sector as u64;
+
+        for copy in 0..self.num_fats as u64 {
+            let offset = self.fat_entry_offset(cluster) + copy * fat_bytes;
+
+            match self.fat_type {
+                FatType::Fat12 => self.modify_bytes(offset, 2, |bytes| {
+                    let old = read_u16(bytes, 0);
+
+                    // Two entries share three bytes, so keep the other entry's nibble.
+                    let new = if cluster & 1 == 1 {
+                        (old & 0x000F) | ((value as u16) << 4)
+                    } else {
+                        (old & 0xF000) | (value as u16 & 0x0FFF)
+                    };
+
+                    write_u16(bytes, 0, new);
+                })?,
+                FatType::Fat16 => {
+                    let mut bytes = [0; 2];
+                    write_u16(&mut bytes, 0, value as u16);
+                    self.write_bytes(offset, &bytes)?;
+                }
+                FatType::Fat32 => self.modify_bytes(offset, 4, |bytes| {
+                    // The top four bits are reserved and must be preserved.
+                    let old = read_u32(bytes, 0);
+                    write_u32(bytes, 0, (old & 0xF0000000) | (value & 0x0FFFFFFF));
+                })?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The cluster after `cluster` in its chain, or `None` at the end of the chain.
+    fn next_cluster(&self, cluster: u32) -> Result<Option<u32>> {
+        let next = self.fat_entry(cluster)?;
+
+        if next >= self.end_of_chain() - 7 {
+            Ok(None)
+        } else if self.is_valid_cluster(next) {
+            Ok(Some(next))
+        } else {
+            Err(FsError::Corrupted("FAT cluster chain points outside the volume"))
+        }
+    }
+
+    /// Every cluster in the chain starting at `first`.
+    fn chain(&self, first: u32) -> Result<Vec<u32>> {
+        let mut clusters = Vec::new();
+        let mut cluster = first;
+
+        if !self.is_valid_cluster(first) {
+            return Ok(clusters);
+        }
+
+        loop {
+            clusters.push(cluster);
+
+            if clusters.len() > self.cluster_count as usize {
+                return Err(FsError::Corrupted("FAT cluster chain loops"));
+            }
+
+            match self.next_cluster(cluster)? {
+                Some(next) => cluster = next,
+                None => return Ok(clusters),
+            }
+        }
+    }
+
+    /// Allocate a zeroed cluster, appending it to the chain ending at `previous` if given.
+    fn allocate_cluster(&self, previous: Option<u32>) -> Result<u32> {
+        let cluster = {
+            let mut alloc = self.alloc.lock();
+            let start = alloc.next_free;
+            let mut found = None;
+
+            for i in 0..self.cluster_count {
+                let cluster = FIRST_CLUSTER + (start - FIRST_CLUSTER + i) % self.cluster_count;
+
+                if self.fat_entry(cluster)? == 0 {
+                    found = Some(cluster);
+                    break;
+                }
+            }
+
+            let cluster = found.ok_or(FsError::NoSpace)?;
+            self.set_fat_entry(cluster, self.end_of_chain())?;
+
+            alloc.next_free = if cluster + 1 < self.cluster_count + FIRST_CLUSTER {
+                cluster + 1
+            } else {
+                FIRST_CLUSTER
+            };
+            alloc.free_count = alloc.free_count.map(|n| n.saturating_sub(1));
+            alloc.dirty = true;
+
+            cluster
+        };
+
+        let zeroes = vec![0; self.cluster_size() as usize];
+        self.write_bytes(self.cluster_offset(cluster), &zeroes)?;
+
+        if let Some(previous) = previous {
+            let _alloc = self.alloc.lock();
+            self.set_fat_entry(previous, cluster)?;
+        }
+
+        Ok(cluster)
+    }
+
+    /// Free every cluster in the chain starting at `first`.
+    fn free_chain(&self, first: u32) -> Result<()> {
+        let clusters = self.chain(first)?;
+        let mut alloc = self.alloc.lock();
+
+        for &cluster in clusters.iter() {
+            self.set_fat_entry(cluster, 0)?;
+        }
+
+        alloc.free_count = alloc.free_count.map(|n| n + clusters.len() as u32);
+        alloc.dirty = true;
+
+        Ok(())
+    }
+
+    /// Return the inode for a directory entry, reusing a live one if there is one.
+    fn inode(fat: &Arc<Fat>, entry: &dir::FatDirEntry) -> Arc<FatInode> {
+        let mut inodes = fat.inodes.lock();
+
+        if let Some(inode) = inodes.get(&entry.offset).and_then(|w| w.upgrade()) {
+            return inode;
+        }
+
+        let inode = Arc::new(FatInode::new(fat.clone(), entry));
+        inodes.insert(entry.offset, Arc::downgrade(&inode));
+
+        // Drop the entries of inodes which no longer exist.
+        let dead: Vec<u64> = inodes
+            .iter()
+            .filter(|&(_, w)| w.upgrade().is_none())
+            .map(|(&k, _)| k)
+            .collect();
+        for key in dead {
+            inodes.remove(&key);
+        }
+
+        inode
+    }
+
+    /// Put back an inode forgotten by `forget_inode`, when its entry couldn't be removed after
+    /// all.
+    fn remember_inode(&self, entry_offset: u64, inode: &Arc<FatInode>) {
+        self.inodes
+            .lock()
+            .insert(entry_offset, Arc::downgrade(inode));
+    }
+
+    /// Forget the inode of a directory entry which has been removed, returning it if it is still
+    /// in use.
+    fn forget_inode(&self, entry_offset: u64) -> Option<Arc<FatInode>> {
+        self.inodes
+            .lock()
+            .remove(&entry_offset)
+            .and_then(|w| w.upgrade())
+    }
+
+    fn root_location(&self) -> DirLocation {
+        match self.fat_type {
+            FatType::Fat32 => DirLocation::Chain(self.root_cluster),
+            _ => DirLocation::FixedRoot,
+        }
+    }
+}
+
+/// The `FileSystem` for a mounted FAT volume.
+pub struct FatFs {
+    fat: Arc<Fat>,
+    root: Arc<FatInode>,
+}
+
+impl FileSystem for FatFs {
+    fn root(&self) -> Arc<Inode> {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.fat.store_fsinfo()?;
+        self.fat.disk.flush().map_err(FsError::Io)
+    }
+}
+
+pub struct FatFsType;
+
+impl FileSystemType for FatFsType {
+    fn name(&self) -> &'static str {
+        "fat"
+    }
+
+    fn mount(&self, source: Option<Arc<Disk>>) -> Result<Arc<FileSystem>> {
+        let fat = Fat::new(source.ok_or(FsError::InvalidArgument)?)?;
+        let root = Arc::new(FatInode::root(fat.clone()));
+
+        Ok(Arc::new(FatFs {
+            fat: fat,
+            root: root,
+        }))
+    }
+}
@@ -0,0 +1,230 @@
+//! Open files and per-process file descriptor tables.
+
+use alloc::arc::Arc;
+use alloc::Vec;
+use core::fmt;
+use spin::Mutex;
+use super::{Dentry, DirEntry, FileType, FsError, Inode, Metadata, Result};
+
+/// The most file descriptors a process can have open.
+pub const MAX_FILES: usize = 256;
+
+bitflags! {
+    pub struct OpenFlags: u32 {
+        const READ =      1 << 0;
+        const WRITE =     1 << 1;
+        /// Create the file if it does not exist.
+        const CREATE =    1 << 2;
+        /// With `CREATE`, fail if the file already exists.
+        const EXCLUSIVE = 1 << 3;
+        /// Truncate the file to zero length.
+        const TRUNCATE =  1 << 4;
+        /// Every write goes to the end of the file.
+        const APPEND =    1 << 5;
+        /// Fail unless the path is a directory.
+        const DIRECTORY = 1 << 6;
+        /// Open a symbolic link itself rather than what it points to.
+        const NO_FOLLOW = 1 << 7;
+    }
+}
+
+/// Where `lseek` measures its offset from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Whence {
+    Start,
+    Current,
+    End,
+}
+
+/// An open file, shared by every descriptor which refers to it.
+pub struct File {
+    dentry: Arc<Dentry>,
+    /// The inode reads and writes go to, which is the dentry's unless opening it gave another.
+    inode: Arc<Inode>,
+    flags: OpenFlags,
+    /// The byte offset for files, or the index of the next entry for directories.
+    offset: Mutex<u64>,
+    /// The entries of a directory, listed when reading it starts from the first entry, so that
+    /// each `readdir` doesn't list it again.
+    listing: Mutex<Option<Vec<DirEntry>>>,
+}
+
+impl File {
+    pub fn new(dentry: Arc<Dentry>, flags: OpenFlags) -> Result<Self> {
+        let inode = match dentry.inode().open()? {
+            Some(inode) => inode,
+            None => dentry.inode().clone(),
+        };
+
+        Ok(File {
+            dentry: dentry,
+            inode: inode,
+            flags: flags,
+            offset: Mutex::new(0),
+            listing: Mutex::new(None),
+        })
+    }
+
+    pub fn dentry(&self) -> &Arc<Dentry> {
+        &self.dentry
+    }
+
+    pub fn flags(&self) -> OpenFlags {
+        self.flags
+    }
+
+    pub fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        if !self.flags.contains(OpenFlags::READ) {
+            return Err(FsError::BadFileDescriptor);
+        }
+
+        let mut offset = self.offset.lock();
+        let read = self.inode.read_at(*offset, buffer)?;
+        *offset += read as u64;
+
+        Ok(read)
+    }
+
+    pub fn write(&self, buffer: &[u8]) -> Result<usize> {
+        if !self.flags.contains(OpenFlags::WRITE) {
+            return Err(FsError::BadFileDescriptor);
+        }
+
+        let inode = &self.inode;
+        let mut offset = self.offset.lock();
+
+        if self.flags.contains(OpenFlags::APPEND) {
+            *offset = inode.metadata()?.size;
+        }
+
+        let written = inode.write_at(*offset, buffer)?;
+        *offset += written as u64;
+
+        Ok(written)
+    }
+
+    pub fn seek(&self, offset: i64, whence: Whence) -> Result<u64> {
+        let mut current = self.offset.lock();
+
+        let base = match whence {
+            Whence::Start => 0,
+            Whence::Current => *current as i64,
+            Whence::End => self.inode.metadata()?.size as i64,
+        };
+
+        let new = base.checked_add(offset).ok_or(FsError::InvalidArgument)?;
+        if new < 0 {
+            return Err(FsError::InvalidArgument);
+        }
+
+        *current = new as u64;
+        Ok(*current)
+    }
+
+    pub fn metadata(&self) -> Result<Metadata> {
+        self.inode.metadata()
+    }
+
+    /// Return the next entry of a directory, or `None` once every entry has been returned. The
+    /// listing starts with `.` and `..`. The directory is listed once when reading starts from
+    /// the first entry, including after seeking back to it, and entries are returned from that.
+    pub fn readdir(&self) -> Result<Option<DirEntry>> {
+        let mut offset = self.offset.lock();
+        let mut listing = self.listing.lock();
+
+        if *offset == 0 || listing.is_none() {
+            *listing = Some(self.list()?);
+        }
+
+        let entry = listing
+            .as_ref()
+            .and_then(|entries| entries.get(*offset as usize))
+            .cloned();
+
+        if entry.is_some() {
+            *offset += 1;
+        }
+
+        Ok(entry)
+    }
+
+    /// List every entry of the directory, starting with `.` and `..`.
+    fn list(&self) -> Result<Vec<DirEntry>> {
+        let inode = self.dentry.inode();
+        let metadata = inode.metadata()?;
+
+        if metadata.file_type != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+
+        let mut entries = vec![
+            DirEntry {
+                name: ".".into(),
+                inode: metadata.inode,
+                file_type: FileType::Directory,
+            },
+            DirEntry {
+                name: "..".into(),
+                inode: Dentry::parent(&self.dentry).inode().metadata()?.inode,
+                file_type: FileType::Directory,
+            },
+        ];
+
+        entries.extend(inode.readdir()?);
+        Ok(entries)
+    }
+}
+
+/// The open files of a process, indexed by file descriptor.
+#[derive(Clone)]
+pub struct FileTable {
+    files: Vec<Option<Arc<File>>>,
+}
+
+impl FileTable {
+    pub fn new() -> Self {
+        FileTable { files: Vec::new() }
+    }
+
+    /// Add a file at the lowest free descriptor.
+    pub fn insert(&mut self, file: Arc<File>) -> Result<usize> {
+        if let Some(fd) = self.files.iter().position(|f| f.is_none()) {
+            self.files[fd] = Some(file);
+            return Ok(fd);
+        }
+
+        if self.files.len() >= MAX_FILES {
+            return Err(FsError::TooManyOpenFiles);
+        }
+
+        self.files.push(Some(file));
+        Ok(self.files.len() - 1)
+    }
+
+    pub fn get(&self, fd: usize) -> Result<Arc<File>> {
+        match self.files.get(fd) {
+            Some(&Some(ref file)) => Ok(file.clone()),
+            _ => Err(FsError::BadFileDescriptor),
+        }
+    }
+
+    pub fn remove(&mut self, fd: usize) -> Result<Arc<File>> {
+        match self.files.get_mut(fd) {
+            Some(slot) => slot.take().ok_or(FsError::BadFileDescriptor),
+            None => Err(FsError::BadFileDescriptor),
+        }
+    }
+}
+
+impl fmt::Debug for FileTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let open: Vec<usize> = self.files
+            .iter()
+            .enumerate()
+            .filter(|&(_, file)| file.is_some())
+            .map(|(fd, _)| fd)
+            .collect();
+
+        write!(f, "FileTable {{ open: {:?} }}", open)
+    }
+}
@@ -0,0 +1,262 @@
+//! The virtual filesystem. Filesystems implement `FileSystem` and `Inode`, and are mounted into a
+//! single tree which paths are resolved against. Processes refer to open files through their file
+//! descriptor table.
+
+use alloc::arc::Arc;
+use alloc::{String, Vec};
+use device::block;
+use spin::RwLock;
+
+pub mod cpio;
+pub mod dentry;
+pub mod devfs;
+pub mod ext2;
+pub mod fat;
+pub mod file;
+pub mod mount;
+pub mod procfs;
+pub mod tmpfs;
+
+pub use self::dentry::{lookup, Dentry};
+pub use self::file::{File, FileTable, OpenFlags, Whence};
+pub use self::mount::{mount, mount_device, umount};
+
+/// Errors returned by filesystem operations.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FsError {
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    InvalidPath,
+    InvalidArgument,
+    BadFileDescriptor,
+    TooManyOpenFiles,
+    /// Too many symbolic links were followed while resolving a path.
+    TooManyLinks,
+    PermissionDenied,
+    ReadOnly,
+    NoSpace,
+    Busy,
+    NotSupported,
+    /// A blocking call was interrupted by a signal.
+    Interrupted,
+    /// The filesystem is damaged.
+    Corrupted(&'static str),
+    /// The underlying device failed.
+    Io(&'static str),
+}
+
+pub type Result<T> = ::core::result::Result<T, FsError>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileType {
+    File,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+}
+
+/// The attributes of an inode, as returned by `stat`.
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    /// The inode number, unique within its filesystem.
+    pub inode: u64,
+    pub file_type: FileType,
+    /// Permission bits.
+    pub mode: u16,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// The size in bytes.
+    pub size: u64,
+    /// The number of 512 byte blocks allocated.
+    pub blocks: u64,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+}
+
+impl Metadata {
+    /// Metadata for an inode with nothing interesting to report beyond its type and size.
+    pub fn new(inode: u64, file_type: FileType, size: u64) -> Self {
+        Metadata {
+            inode: inode,
+            file_type: file_type,
+            mode: if file_type == FileType::Directory { 0o755 } else { 0o644 },
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            size: size,
+            blocks: (size + 511) / 512,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+        }
+    }
+}
+
+/// A single entry returned by `readdir`.
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub inode: u64,
+    pub file_type: FileType,
+}
+
+/// A file, directory, symlink or device node in a filesystem. Operations which don't make sense
+/// for a kind of inode are left as the defaults, which fail.
+pub trait Inode: Send + Sync {
+    fn metadata(&self) -> Result<Metadata>;
+
+    /// Read from a file at `offset`, returning the number of bytes read, or 0 at the end.
+    fn read_at(&self, _offset: u64, _buffer: &mut [u8]) -> Result<usize> {
+        Err(FsError::IsADirectory)
+    }
+
+    /// Write to a file at `offset`, extending it if needed.
+    fn write_at(&self, _offset: u64, _buffer: &[u8]) -> Result<usize> {
+        Err(FsError::IsADirectory)
+    }
+
+    /// Change the size of a file.
+    fn truncate(&self, _size: u64) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Find an entry of a directory by name.
+    fn lookup(&self, _name: &str) -> Result<Arc<Inode>> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Create a new file or directory in a directory.
+    fn create(&self, _name: &str, _file_type: FileType) -> Result<Arc<Inode>> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Create a symbolic link in a directory.
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<Inode>> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Remove an entry from a directory. Directories must be empty.
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// List the entries of a directory, not including `.` and `..`.
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Return the target of a symbolic link.
+    fn readlink(&self) -> Result<String> {
+        Err(FsError::InvalidArgument)
+    }
+
+    /// Change the permission bits.
+    fn chmod(&self, _mode: u16) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Perform a device specific operation.
+    fn ioctl(&self, _command: usize, _argument: usize) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Called as the inode is opened. Inodes which keep state for each open file, such as some
+    /// devices, return a new inode holding it, which the open file reads and writes instead.
+    fn open(&self) -> Result<Option<Arc<Inode>>> {
+        Ok(None)
+    }
+}
+
+/// A mounted instance of a filesystem.
+pub trait FileSystem: Send + Sync {
+    /// The root directory.
+    fn root(&self) -> Arc<Inode>;
+
+    /// Write any cached data back to the underlying device.
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A kind of filesystem which can be mounted by name.
+pub trait FileSystemType: Sync {
+    /// The name used to select this filesystem when mounting, such as `fat` or `ext2`.
+    fn name(&self) -> &'static str;
+
+    /// Create a filesystem instance, reading from `source` if the filesystem is backed by a disk.
+    fn mount(&self, source: Option<Arc<block::Disk>>) -> Result<Arc<FileSystem>>;
+}
+
+lazy_static! {
+    /// The filesystem types which can be mounted.
+    static ref FILESYSTEM_TYPES: RwLock<Vec<&'static FileSystemType>> = RwLock::new(Vec::new());
+}
+
+/// Make a kind of filesystem available to `mount_device`.
+pub fn register_filesystem(fs_type: &'static FileSystemType) {
+    FILESYSTEM_TYPES.write().push(fs_type);
+}
+
+/// Find a filesystem type by name.
+pub fn filesystem_type(name: &str) -> Option<&'static FileSystemType> {
+    FILESYSTEM_TYPES
+        .read()
+        .iter()
+        .find(|t| t.name() == name)
+        .cloned()
+}
+
+/// Register the built in filesystem types, and mount a tmpfs as `/` with devfs on `/dev` and
+/// procfs on `/proc`. If the bootloader loaded an initramfs, it is unpacked into the root
+/// filesystem.
+pub fn init(initramfs: Option<&[u8]>) {
+    static TMPFS: tmpfs::TmpFsType = tmpfs::TmpFsType;
+    static FAT: fat::FatFsType = fat::FatFsType;
+    static EXT2: ext2::Ext2FsType = ext2::Ext2FsType;
+    static DEVFS: devfs::DevFsType = devfs::DevFsType;
+    static PROCFS: procfs::ProcFsType = procfs::ProcFsType;
+    register_filesystem(&TMPFS);
+    register_filesystem(&DEVFS);
+    register_filesystem(&PROCFS);
+    register_filesystem(&FAT);
+    register_filesystem(&EXT2);
+
+    let root = tmpfs::TmpFs::new();
+
+    if let Some(archive) = initramfs {
+        match cpio::unpack(archive, &root.root()) {
+            Ok(count) => println!("[ vfs ] Unpacked {} entries from the initramfs", count),
+            Err(e) => println!("[ vfs ] Failed to unpack the initramfs: {:?}", e),
+        }
+    }
+
+    mount("/", root.clone()).expect("Could not mount the root filesystem");
+
+    devfs::init();
+
+    mount_pseudo(&root.root(), "dev", "devfs");
+    mount_pseudo(&root.root(), "proc", "procfs");
+}
+
+/// Mount a filesystem which has no backing device on a directory of the root, creating the
+/// directory if the initramfs didn't.
+fn mount_pseudo(root: &Arc<Inode>, name: &str, fs_type: &str) {
+    let path = format!("/{}", name);
+
+    let directory = match root.lookup(name) {
+        Ok(directory) => Ok(directory),
+        Err(FsError::NotFound) => root.create(name, FileType::Directory),
+        Err(e) => Err(e),
+    };
+
+    match directory.and_then(|_| mount_device(None, &path, fs_type)) {
+        Ok(()) => println!("[ vfs ] Mounted {} on {}", fs_type, path),
+        Err(e) => println!("[ vfs ] Failed to mount {} on {}: {:?}", fs_type, path, e),
+    }
+}
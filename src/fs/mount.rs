@@ -0,0 +1,103 @@
+//! The mount table. Each mount attaches the root of a filesystem to an absolute path.
+
+use alloc::arc::Arc;
+use alloc::{String, Vec};
+use device::block;
+use spin::RwLock;
+use super::{dentry, FileSystem, FileType, FsError, Result};
+
+struct Mount {
+    /// The absolute path of the mount point, as reached by path resolution.
+    path: String,
+    fs: Arc<FileSystem>,
+}
+
+lazy_static! {
+    static ref MOUNTS: RwLock<Vec<Mount>> = RwLock::new(Vec::new());
+}
+
+/// The filesystem mounted at exactly `path`, if any. Later mounts hide earlier ones.
+pub fn mounted_at(path: &str) -> Option<Arc<FileSystem>> {
+    MOUNTS
+        .read()
+        .iter()
+        .rev()
+        .find(|m| m.path == path)
+        .map(|m| m.fs.clone())
+}
+
+/// Mount `fs` at `path`. Anything but the first mount, which must be `/`, needs an existing
+/// directory to mount on.
+pub fn mount(path: &str, fs: Arc<FileSystem>) -> Result<()> {
+    let path = if MOUNTS.read().is_empty() {
+        if path != "/" {
+            return Err(FsError::InvalidPath);
+        }
+
+        String::from("/")
+    } else {
+        let target = dentry::lookup(None, path, true)?;
+
+        if target.inode().metadata()?.file_type != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+
+        String::from(target.path())
+    };
+
+    println!("[ vfs ] Mounted filesystem at {}", path);
+
+    MOUNTS.write().push(Mount { path: path, fs: fs });
+
+    Ok(())
+}
+
+/// Mount the block device `source`, or nothing for virtual filesystems, at `path` using the
+/// filesystem type `fs_type`.
+pub fn mount_device(source: Option<&str>, path: &str, fs_type: &str) -> Result<()> {
+    let fs_type = super::filesystem_type(fs_type).ok_or(FsError::NotSupported)?;
+
+    let disk = match source {
+        Some(name) => Some(block::get(name).ok_or(FsError::NotFound)?),
+        None => None,
+    };
+
+    let fs = fs_type.mount(disk)?;
+    mount(path, fs)
+}
+
+/// Sync and unmount the filesystem at `path`. This fails if anything is mounted below it.
+pub fn umount(path: &str) -> Result<()> {
+    let target = dentry::lookup(None, path, true)?;
+    let path = String::from(target.path());
+
+    let mut mounts = MOUNTS.write();
+
+    let index = mounts
+        .iter()
+        .rposition(|m| m.path == path)
+        .ok_or(FsError::InvalidArgument)?;
+
+    let prefix = if path == "/" { String::from("/") } else { format!("{}/", path) };
+    if mounts[index + 1..].iter().any(|m| m.path.starts_with(&prefix)) {
+        return Err(FsError::Busy);
+    }
+
+    mounts[index].fs.sync()?;
+    mounts.remove(index);
+
+    println!("[ vfs ] Unmounted filesystem at {}", path);
+
+    Ok(())
+}
+
+/// Sync every mounted filesystem.
+pub fn sync_all() {
+    let filesystems: Vec<Arc<FileSystem>> = MOUNTS.read().iter().map(|m| m.fs.clone()).collect();
+
+    for fs in filesystems {
+        if let Err(e) = fs.sync() {
+            println!("[ vfs ] Failed to sync filesystem: {:?}", e);
+        }
+    }
+}
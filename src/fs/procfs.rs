@@ -0,0 +1,362 @@
+//! A filesystem of generated files describing the running kernel: processes, memory, PCI devices,
+//! ACPI tables and interrupt counts. Every read regenerates the file, so its contents are always
+//! current.
+
+use acpi;
+use alloc::arc::Arc;
+use alloc::{String, Vec};
+use arch::interrupts::counts;
+use arch::memory::{self, PAGE_SIZE};
+use core::cmp;
+use core::fmt::Write;
+use device::{apic, block::Disk, pci};
+use task::{ProcessId, SCHEDULER};
+use super::{DirEntry, FileSystem, FileSystemType, FileType, FsError, Inode, Metadata, Result};
+
+/// The inode number of the root directory.
+const ROOT_INODE: u64 = 1;
+/// The inode numbers of the fixed files and directories.
+const ACPI_INODE: u64 = 2;
+const FIRST_FILE_INODE: u64 = 16;
+/// Each process directory and its files get a range of inode numbers starting here.
+const FIRST_PROCESS_INODE: u64 = 1 << 16;
+
+/// A generated file.
+#[derive(Clone, Copy)]
+enum Content {
+    MemInfo,
+    MemMap,
+    Pci,
+    Interrupts,
+    AcpiTables,
+    Madt,
+    Status(ProcessId),
+}
+
+/// The files in the root directory, and those in `acpi`.
+const ROOT_FILES: [(&'static str, Content); 4] = [
+    ("meminfo", Content::MemInfo),
+    ("memmap", Content::MemMap),
+    ("pci", Content::Pci),
+    ("interrupts", Content::Interrupts),
+];
+const ACPI_FILES: [(&'static str, Content); 2] = [
+    ("tables", Content::AcpiTables),
+    ("madt", Content::Madt),
+];
+
+fn process_inode(pid: ProcessId) -> u64 {
+    FIRST_PROCESS_INODE + pid.inner() as u64 * 2
+}
+
+fn meminfo() -> String {
+    let mut out = String::new();
+
+    let total: usize = memory::memory_areas().iter().map(|&(_, size)| size).sum();
+    let free = memory::free_frames();
+
+    let _ = writeln!(out, "total:       {:>10} KiB", total / 1024);
+    let _ = writeln!(out, "free:        {:>10} KiB", free * PAGE_SIZE / 1024);
+    let _ = writeln!(out, "free frames: {:>10}", free);
+
+    out
+}
+
+fn memmap() -> String {
+    let mut out = String::new();
+
+    for (start, size) in memory::memory_areas().into_iter().filter(|&(_, size)| size != 0) {
+        let _ = writeln!(out, "{:#016x}-{:#016x} usable", start, start + size - 1);
+    }
+
+    out
+}
+
+fn pci() -> String {
+    let mut out = String::new();
+
+    for device in pci::devices() {
+        let _ = writeln!(
+            out,
+            "{} rev {:#04x} prog-if {:#04x} driver {}",
+            device,
+            device.rev_id(),
+            device.prog_if(),
+            pci::driver::bound_driver(&device).unwrap_or("none")
+        );
+    }
+
+    out
+}
+
+fn interrupts() -> String {
+    let mut out = String::new();
+
+    for (vector, count) in counts::counts() {
+        let _ = writeln!(out, "{:#04x}: {}", vector, count);
+    }
+
+    out
+}
+
+fn acpi_tables() -> String {
+    let mut out = String::new();
+
+    for (signature, tables) in acpi::SDT_TABLES.lock().iter() {
+        for table in tables.iter() {
+            let (length, revision, oem_id) = (table.length, table.revision, table.oem_id);
+
+            let _ = writeln!(
+                out,
+                "{} at {:#x}, length {}, revision {}, OEM {}",
+                String::from_utf8_lossy(signature),
+                *table as *const _ as usize,
+                length,
+                revision,
+                String::from_utf8_lossy(&oem_id).trim_right()
+            );
+        }
+    }
+
+    out
+}
+
+fn madt() -> String {
+    let mut out = String::new();
+
+    let manager = apic::APIC_MANAGER.lock();
+    let manager = match *manager {
+        Some(ref manager) => manager,
+        None => return String::from("No MADT was found\n"),
+    };
+
+    let _ = writeln!(out, "local APIC base: {:#x}", manager.lapic_base);
+
+    // The entries are packed, so fields are copied out before being formatted.
+    for lapic in manager.local_apics.iter() {
+        let (id, processor, flags) = (lapic.id, lapic.processor_id, lapic.flags);
+        let _ = writeln!(
+            out,
+            "lapic id {} processor {} {}",
+            id,
+            processor,
+            if flags & 1 == 1 { "enabled" } else { "disabled" }
+        );
+    }
+
+    for ioapic in manager.io_apics.iter() {
+        let (id, address, gsib) = (ioapic.id, ioapic.address, ioapic.gsib);
+        let _ = writeln!(out, "ioapic id {} address {:#x} gsi base {}", id, address, gsib);
+    }
+
+    for iso in manager.isos.iter() {
+        let (bus, irq, gsi, flags) = (iso.bus_source, iso.irq_source, iso.gsi, iso.flags);
+        let _ = writeln!(out, "iso bus {} irq {} gsi {} flags {:#x}", bus, irq, gsi, flags);
+    }
+
+    for nmi in manager.nmis.iter() {
+        let (processor, flags, lint) = (nmi.processor_id, nmi.flags, nmi.lint_no);
+        let _ = writeln!(out, "nmi processor {} lint {} flags {:#x}", processor, lint, flags);
+    }
+
+    out
+}
+
+fn status(pid: ProcessId) -> Result<String> {
+    let process = SCHEDULER
+        .processes()
+        .into_iter()
+        .find(|p| p.read().pid == pid)
+        .ok_or(FsError::NotFound)?;
+    let process = process.read();
+
+    let mut out = String::new();
+    let _ = writeln!(out, "pid:      {}", process.pid.inner());
+    let _ = writeln!(out, "name:     {}", process.name);
+    let _ = writeln!(out, "state:    {:?}", process.state);
+    let _ = writeln!(out, "priority: {}", process.priority.0);
+
+    match process.stack_usage() {
+        Some((used, size)) => {
+            let _ = writeln!(out, "stack:    {} of {} bytes", used, size);
+        }
+        None => {
+            let _ = writeln!(out, "stack:    none");
+        }
+    }
+
+    Ok(out)
+}
+
+impl Content {
+    fn generate(&self) -> Result<String> {
+        Ok(match *self {
+            Content::MemInfo => meminfo(),
+            Content::MemMap => memmap(),
+            Content::Pci => pci(),
+            Content::Interrupts => interrupts(),
+            Content::AcpiTables => acpi_tables(),
+            Content::Madt => madt(),
+            Content::Status(pid) => status(pid)?,
+        })
+    }
+}
+
+struct ProcFile {
+    inode: u64,
+    content: Content,
+}
+
+impl Inode for ProcFile {
+    fn metadata(&self) -> Result<Metadata> {
+        let mut metadata = Metadata::new(self.inode, FileType::File, 0);
+        metadata.mode = 0o444;
+        Ok(metadata)
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        let text = self.content.generate()?;
+        let bytes = text.as_bytes();
+
+        let offset = offset as usize;
+        if offset >= bytes.len() {
+            return Ok(0);
+        }
+
+        let length = cmp::min(buffer.len(), bytes.len() - offset);
+        buffer[..length].copy_from_slice(&bytes[offset..offset + length]);
+        Ok(length)
+    }
+
+    fn write_at(&self, _offset: u64, _buffer: &[u8]) -> Result<usize> {
+        Err(FsError::PermissionDenied)
+    }
+}
+
+/// The directories of procfs.
+#[derive(Clone, Copy)]
+enum Directory {
+    Root,
+    Acpi,
+    Process(ProcessId),
+}
+
+struct ProcDir {
+    directory: Directory,
+}
+
+impl ProcDir {
+    fn inode(&self) -> u64 {
+        match self.directory {
+            Directory::Root => ROOT_INODE,
+            Directory::Acpi => ACPI_INODE,
+            Directory::Process(pid) => process_inode(pid),
+        }
+    }
+
+    /// The files in this directory, with their inode numbers.
+    fn files(&self) -> Vec<(&'static str, u64, Content)> {
+        match self.directory {
+            Directory::Root => ROOT_FILES
+                .iter()
+                .enumerate()
+                .map(|(i, &(name, content))| (name, FIRST_FILE_INODE + i as u64, content))
+                .collect(),
+            Directory::Acpi => ACPI_FILES
+                .iter()
+                .enumerate()
+                .map(|(i, &(name, content))| {
+                    (name, FIRST_FILE_INODE + (ROOT_FILES.len() + i) as u64, content)
+                })
+                .collect(),
+            Directory::Process(pid) => vec![("status", process_inode(pid) + 1, Content::Status(pid))],
+        }
+    }
+
+    /// The subdirectories of this directory, by name.
+    fn directories(&self) -> Vec<(String, Directory)> {
+        match self.directory {
+            Directory::Root => {
+                let mut directories = vec![(String::from("acpi"), Directory::Acpi)];
+
+                for process in SCHEDULER.processes() {
+                    let pid = process.read().pid;
+                    directories.push((format!("{}", pid.inner()), Directory::Process(pid)));
+                }
+
+                directories
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Inode for ProcDir {
+    fn metadata(&self) -> Result<Metadata> {
+        let mut metadata = Metadata::new(self.inode(), FileType::Directory, 0);
+        metadata.mode = 0o555;
+        Ok(metadata)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<Inode>> {
+        if let Some((_, inode, content)) = self.files().into_iter().find(|f| f.0 == name) {
+            return Ok(Arc::new(ProcFile {
+                inode: inode,
+                content: content,
+            }));
+        }
+
+        self.directories()
+            .into_iter()
+            .find(|d| d.0 == name)
+            .map(|(_, directory)| Arc::new(ProcDir { directory: directory }) as Arc<Inode>)
+            .ok_or(FsError::NotFound)
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        let mut entries: Vec<DirEntry> = self.files()
+            .into_iter()
+            .map(|(name, inode, _)| DirEntry {
+                name: String::from(name),
+                inode: inode,
+                file_type: FileType::File,
+            })
+            .collect();
+
+        for (name, directory) in self.directories() {
+            entries.push(DirEntry {
+                name: name,
+                inode: ProcDir { directory: directory }.inode(),
+                file_type: FileType::Directory,
+            });
+        }
+
+        Ok(entries)
+    }
+}
+
+pub struct ProcFs {
+    root: Arc<ProcDir>,
+}
+
+impl FileSystem for ProcFs {
+    fn root(&self) -> Arc<Inode> {
+        self.root.clone()
+    }
+}
+
+pub struct ProcFsType;
+
+impl FileSystemType for ProcFsType {
+    fn name(&self) -> &'static str {
+        "procfs"
+    }
+
+    fn mount(&self, _source: Option<Arc<Disk>>) -> Result<Arc<FileSystem>> {
+        Ok(Arc::new(ProcFs {
+            root: Arc::new(ProcDir {
+                directory: Directory::Root,
+            }),
+        }))
+    }
+}
@@ -0,0 +1,252 @@
+//! A filesystem held entirely in kernel memory.
+
+use alloc::arc::Arc;
+use alloc::btree_map::BTreeMap;
+use alloc::{String, Vec};
+use arch::memory::heap_allocator::HEAP_SIZE;
+use core::cmp;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use device::block::Disk;
+use spin::RwLock;
+use super::{DirEntry, FileSystem, FileSystemType, FileType, FsError, Inode, Metadata, Result};
+
+/// Inode numbers are unique across every tmpfs instance.
+static NEXT_INODE: AtomicUsize = AtomicUsize::new(1);
+
+/// The most bytes of file data every tmpfs instance together can hold. File data lives on the
+/// kernel heap, and running the heap out panics, so growing a file past this fails instead.
+const MAX_DATA: usize = HEAP_SIZE / 4;
+
+/// The bytes of file data held by every tmpfs instance.
+static DATA_USED: AtomicUsize = AtomicUsize::new(0);
+
+/// Grow or shrink the data of a file to `size` bytes, zero filling, within `MAX_DATA`.
+fn resize(data: &mut Vec<u8>, size: usize) -> Result<()> {
+    let length = data.len();
+
+    if size > length {
+        let grow = size - length;
+
+        if DATA_USED.fetch_add(grow, Ordering::SeqCst) + grow > MAX_DATA {
+            DATA_USED.fetch_sub(grow, Ordering::SeqCst);
+            return Err(FsError::NoSpace);
+        }
+
+        data.reserve_exact(grow);
+    } else {
+        DATA_USED.fetch_sub(length - size, Ordering::SeqCst);
+    }
+
+    data.resize(size, 0);
+    Ok(())
+}
+
+enum Node {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, Arc<TmpInode>>),
+    Symlink(String),
+}
+
+pub struct TmpInode {
+    inode: u64,
+    node: RwLock<Node>,
+}
+
+impl TmpInode {
+    fn new(node: Node) -> Arc<Self> {
+        Arc::new(TmpInode {
+            inode: NEXT_INODE.fetch_add(1, Ordering::SeqCst) as u64,
+            node: RwLock::new(node),
+        })
+    }
+
+    /// Add a new child to this directory.
+    fn insert(&self, name: &str, child: Arc<TmpInode>) -> Result<Arc<Inode>> {
+        match *self.node.write() {
+            Node::Directory(ref mut entries) => {
+                if entries.contains_key(name) {
+                    return Err(FsError::AlreadyExists);
+                }
+
+                entries.insert(String::from(name), child.clone());
+                Ok(child)
+            }
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+}
+
+impl Drop for TmpInode {
+    fn drop(&mut self) {
+        if let Node::File(ref data) = *self.node.read() {
+            DATA_USED.fetch_sub(data.len(), Ordering::SeqCst);
+        }
+    }
+}
+
+impl Inode for TmpInode {
+    fn metadata(&self) -> Result<Metadata> {
+        let metadata = match *self.node.read() {
+            Node::File(ref data) => Metadata::new(self.inode, FileType::File, data.len() as u64),
+            Node::Directory(ref entries) => {
+                let mut metadata = Metadata::new(self.inode, FileType::Directory, 0);
+                metadata.nlink = 2 + entries
+                    .values()
+                    .filter(|e| match *e.node.read() {
+                        Node::Directory(_) => true,
+                        _ => false,
+                    })
+                    .count() as u32;
+                metadata
+            }
+            Node::Symlink(ref target) => {
+                let mut metadata =
+                    Metadata::new(self.inode, FileType::Symlink, target.len() as u64);
+                metadata.mode = 0o777;
+                metadata
+            }
+        };
+
+        Ok(metadata)
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        match *self.node.read() {
+            Node::File(ref data) => {
+                let offset = offset as usize;
+                if offset >= data.len() {
+                    return Ok(0);
+                }
+
+                let length = cmp::min(buffer.len(), data.len() - offset);
+                buffer[..length].copy_from_slice(&data[offset..offset + length]);
+                Ok(length)
+            }
+            Node::Directory(_) => Err(FsError::IsADirectory),
+            Node::Symlink(_) => Err(FsError::InvalidArgument),
+        }
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize> {
+        match *self.node.write() {
+            Node::File(ref mut data) => {
+                let offset = offset as usize;
+                let end = offset.checked_add(buffer.len()).ok_or(FsError::NoSpace)?;
+
+                if end > data.len() {
+                    resize(data, end)?;
+                }
+
+                data[offset..end].copy_from_slice(buffer);
+                Ok(buffer.len())
+            }
+            Node::Directory(_) => Err(FsError::IsADirectory),
+            Node::Symlink(_) => Err(FsError::InvalidArgument),
+        }
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        match *self.node.write() {
+            Node::File(ref mut data) => resize(data, size as usize),
+            Node::Directory(_) => Err(FsError::IsADirectory),
+            Node::Symlink(_) => Err(FsError::InvalidArgument),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<Inode>> {
+        match *self.node.read() {
+            Node::Directory(ref entries) => match entries.get(name) {
+                Some(entry) => Ok(entry.clone()),
+                None => Err(FsError::NotFound),
+            },
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn create(&self, name: &str, file_type: FileType) -> Result<Arc<Inode>> {
+        let node = match file_type {
+            FileType::File => Node::File(Vec::new()),
+            FileType::Directory => Node::Directory(BTreeMap::new()),
+            _ => return Err(FsError::NotSupported),
+        };
+
+        self.insert(name, TmpInode::new(node))
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<Inode>> {
+        self.insert(name, TmpInode::new(Node::Symlink(String::from(target))))
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        match *self.node.write() {
+            Node::Directory(ref mut entries) => {
+                {
+                    let entry = entries.get(name).ok_or(FsError::NotFound)?;
+
+                    if let Node::Directory(ref children) = *entry.node.read() {
+                        if !children.is_empty() {
+                            return Err(FsError::DirectoryNotEmpty);
+                        }
+                    }
+                }
+
+                entries.remove(name);
+                Ok(())
+            }
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>> {
+        match *self.node.read() {
+            Node::Directory(ref entries) => entries
+                .iter()
+                .map(|(name, inode)| {
+                    Ok(DirEntry {
+                        name: name.clone(),
+                        inode: inode.inode,
+                        file_type: inode.metadata()?.file_type,
+                    })
+                })
+                .collect(),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn readlink(&self) -> Result<String> {
+        match *self.node.read() {
+            Node::Symlink(ref target) => Ok(target.clone()),
+            _ => Err(FsError::InvalidArgument),
+        }
+    }
+}
+
+pub struct TmpFs {
+    root: Arc<TmpInode>,
+}
+
+impl TmpFs {
+    pub fn new() -> Arc<Self> {
+        Arc::new(TmpFs {
+            root: TmpInode::new(Node::Directory(BTreeMap::new())),
+        })
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn root(&self) -> Arc<Inode> {
+        self.root.clone()
+    }
+}
+
+pub struct TmpFsType;
+
+impl FileSystemType for TmpFsType {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn mount(&self, _source: Option<Arc<Disk>>) -> Result<Arc<FileSystem>> {
+        Ok(TmpFs::new())
+    }
+}
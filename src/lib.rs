@@ -21,7 +21,6 @@ extern crate bitflags;
 #[macro_use]
 extern crate lazy_static;
 extern crate linked_list_allocator;
-extern crate multiboot2;
 #[macro_use]
 extern crate once;
 extern crate raw_cpuid;
@@ -38,6 +37,7 @@ pub mod task;
 pub mod syscall;
 pub mod arch;
 pub mod acpi;
+pub mod fs;
 mod runtime_glue;
 
 pub use runtime_glue::*;
@@ -46,7 +46,11 @@ pub use runtime_glue::*;
 pub extern "C" fn kmain(multiboot_information_address: usize) {
     unsafe { arch::init(multiboot_information_address) };
 
-    loop {}
+    // The idle loop, which runs the work interrupt handlers defer.
+    loop {
+        arch::interrupts::bottom_half::run_pending();
+        arch::interrupts::wait_for_interrupt_unless(arch::interrupts::bottom_half::is_pending);
+    }
 }
 
 // TODO: Move this to the memory module once some bugs with Rust get figured out.
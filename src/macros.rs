@@ -24,7 +24,7 @@ macro_rules! format {
 
 macro_rules! tty_switch {
     ($x:expr) => ({
-        use device::vga::buffer::switch;
+        use device::tty::switch;
 
         switch($x);
     });
@@ -0,0 +1,138 @@
+use alloc::arc::Arc;
+use alloc::String;
+use fs::{self, dentry, Dentry, DirEntry, File, FileType, FsError, Metadata, OpenFlags, Whence};
+use task::{Process, SCHEDULER};
+use spin::RwLock;
+
+/// The current process. Every file syscall acts on its descriptor table.
+fn current() -> Arc<RwLock<Process>> {
+    SCHEDULER.current().expect("No current process")
+}
+
+/// The working directory of the current process.
+fn cwd() -> fs::Result<Arc<Dentry>> {
+    let path = current().read().cwd.clone();
+    fs::lookup(None, &path, true)
+}
+
+fn get_file(fd: usize) -> fs::Result<Arc<File>> {
+    current().read().files.get(fd)
+}
+
+/// Open the file at `path`, returning a new file descriptor.
+pub fn open(path: &str, flags: OpenFlags) -> fs::Result<usize> {
+    let cwd = cwd()?;
+    let follow = !flags.contains(OpenFlags::NO_FOLLOW);
+
+    let dentry = match fs::lookup(Some(&cwd), path, follow) {
+        Ok(_) if flags.contains(OpenFlags::CREATE | OpenFlags::EXCLUSIVE) => {
+            return Err(FsError::AlreadyExists)
+        }
+        Ok(dentry) => dentry,
+        Err(FsError::NotFound) if flags.contains(OpenFlags::CREATE) => {
+            let (parent, name) = dentry::lookup_parent(Some(&cwd), path)?;
+            parent.inode().create(name, FileType::File)?;
+            fs::lookup(Some(&parent), name, follow)?
+        }
+        Err(e) => return Err(e),
+    };
+
+    let metadata = dentry.inode().metadata()?;
+
+    if flags.contains(OpenFlags::DIRECTORY) && metadata.file_type != FileType::Directory {
+        return Err(FsError::NotADirectory);
+    }
+
+    if metadata.file_type == FileType::Directory && flags.contains(OpenFlags::WRITE) {
+        return Err(FsError::IsADirectory);
+    }
+
+    if flags.contains(OpenFlags::TRUNCATE) && flags.contains(OpenFlags::WRITE) {
+        dentry.inode().truncate(0)?;
+    }
+
+    let file = Arc::new(File::new(dentry, flags)?);
+    current().write().files.insert(file)
+}
+
+/// Read from a file descriptor into `buffer`.
+pub fn read(fd: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+    get_file(fd)?.read(buffer)
+}
+
+/// Write `buffer` to a file descriptor.
+pub fn write(fd: usize, buffer: &[u8]) -> fs::Result<usize> {
+    get_file(fd)?.write(buffer)
+}
+
+/// Close a file descriptor.
+pub fn close(fd: usize) -> fs::Result<()> {
+    current().write().files.remove(fd).map(|_| ())
+}
+
+/// Move the offset of a file descriptor, returning the new offset.
+pub fn lseek(fd: usize, offset: i64, whence: Whence) -> fs::Result<u64> {
+    get_file(fd)?.seek(offset, whence)
+}
+
+/// Return the metadata of the file at `path`, following symbolic links.
+pub fn stat(path: &str) -> fs::Result<Metadata> {
+    let cwd = cwd()?;
+    fs::lookup(Some(&cwd), path, true)?.inode().metadata()
+}
+
+/// Return the metadata of the file at `path`, without following a final symbolic link.
+pub fn lstat(path: &str) -> fs::Result<Metadata> {
+    let cwd = cwd()?;
+    fs::lookup(Some(&cwd), path, false)?.inode().metadata()
+}
+
+/// Return the metadata of an open file.
+pub fn fstat(fd: usize) -> fs::Result<Metadata> {
+    get_file(fd)?.metadata()
+}
+
+/// Return the next entry of an open directory, or `None` at the end.
+pub fn readdir(fd: usize) -> fs::Result<Option<DirEntry>> {
+    get_file(fd)?.readdir()
+}
+
+/// Create a directory.
+pub fn mkdir(path: &str) -> fs::Result<()> {
+    let cwd = cwd()?;
+    let (parent, name) = dentry::lookup_parent(Some(&cwd), path)?;
+    parent.inode().create(name, FileType::Directory).map(|_| ())
+}
+
+/// Remove a file or empty directory.
+pub fn unlink(path: &str) -> fs::Result<()> {
+    let cwd = cwd()?;
+    let (parent, name) = dentry::lookup_parent(Some(&cwd), path)?;
+    parent.inode().unlink(name)
+}
+
+/// Create a symbolic link at `path` pointing to `target`.
+pub fn symlink(target: &str, path: &str) -> fs::Result<()> {
+    let cwd = cwd()?;
+    let (parent, name) = dentry::lookup_parent(Some(&cwd), path)?;
+    parent.inode().symlink(name, target).map(|_| ())
+}
+
+/// Change the permission bits of the file at `path`.
+pub fn chmod(path: &str, mode: u16) -> fs::Result<()> {
+    let cwd = cwd()?;
+    fs::lookup(Some(&cwd), path, true)?.inode().chmod(mode)
+}
+
+/// Change the working directory of the current process.
+pub fn chdir(path: &str) -> fs::Result<()> {
+    let cwd = cwd()?;
+    let dentry = fs::lookup(Some(&cwd), path, true)?;
+
+    if dentry.inode().metadata()?.file_type != FileType::Directory {
+        return Err(FsError::NotADirectory);
+    }
+
+    current().write().cwd = String::from(dentry.path());
+    Ok(())
+}
@@ -1,3 +1,5 @@
+pub mod fs;
 pub mod process;
 
+pub use self::fs::*;
 pub use self::process::*;
@@ -1,11 +1,14 @@
 use alloc::VecDeque;
+use alloc::arc::Arc;
 use alloc::vec::Vec;
 use alloc::String;
 use core::mem;
 use core::ops::DerefMut;
 use core::sync::atomic::{AtomicUsize, Ordering};
+use arch::interrupts::bottom_half;
 use task::{Process, ProcessId, ProcessList, Scheduling, State, INITIAL_STACK};
 use task::process;
+use task::signal::{self, Signals};
 use spin::RwLock;
 
 /// Global kernel scheduler type.
@@ -128,12 +131,27 @@ impl Scheduling for CoopScheduler {
                 .expect("Could not find old process")
                 .write();
 
+            // A process which has been stopped is left off the ready list until it is continued.
             if prev.state == State::Current {
-                prev.set_state(State::Ready);
-                ready_list_lock.push_back(curr_id);
+                if signal::take(curr_id, Signals::STOP).is_empty() {
+                    prev.set_state(State::Ready);
+                    ready_list_lock.push_back(curr_id);
+                } else {
+                    prev.set_state(State::Stopped);
+                }
             }
 
-            if let Some(next_id) = ready_list_lock.pop_front() {
+            // The idle process runs the bottom halves, so it goes first while any are pending.
+            let idle = if bottom_half::is_pending() {
+                let position = ready_list_lock
+                    .iter()
+                    .position(|&id| id == ProcessId::NULL_PROC);
+                position.and_then(|i| ready_list_lock.remove(i))
+            } else {
+                None
+            };
+
+            if let Some(next_id) = idle.or_else(|| ready_list_lock.pop_front()) {
                 if next_id != self.get_id() {
                     let mut next = task_table_lock
                         .get(next_id)
@@ -175,4 +193,32 @@ impl CoopScheduler {
             ready_list: RwLock::new(VecDeque::<ProcessId>::new()),
         }
     }
+
+    /// Returns the current process.
+    pub fn current(&self) -> Option<Arc<RwLock<Process>>> {
+        self.task_table.read().get(self.get_id()).cloned()
+    }
+
+    /// Put a process stopped by a signal back on the ready list. Returns whether it was stopped.
+    pub fn resume(&self, id: ProcessId) -> bool {
+        let task_table_lock = self.task_table.read();
+
+        let mut process = match task_table_lock.get(id) {
+            Some(process) => process.write(),
+            None => return false,
+        };
+
+        if process.state != State::Stopped {
+            return false;
+        }
+
+        process.set_state(State::Ready);
+        self.ready_list.write().push_back(id);
+        true
+    }
+
+    /// Returns every process in the task table.
+    pub fn processes(&self) -> Vec<Arc<RwLock<Process>>> {
+        self.task_table.read().iter().map(|(_, p)| p.clone()).collect()
+    }
 }
@@ -2,6 +2,7 @@ pub mod context;
 pub mod process;
 pub mod proc_list;
 pub mod coop_sched;
+pub mod signal;
 
 use self::coop_sched as scheduler;
 
@@ -30,3 +31,16 @@ lazy_static! {
     /// Global kernel scheduler.
     pub static ref SCHEDULER: Scheduler = Scheduler::new();
 }
+
+/// Block the current process until something may have changed: let anything else run, then
+/// sleep until the next interrupt and run the bottom halves it scheduled. Callers waiting on a
+/// condition check it again after this, and must not hold any lock while calling it.
+pub fn wait() {
+    use arch::interrupts::{bottom_half, disable_interrupts_and_then, wait_for_interrupt_unless};
+
+    unsafe {
+        disable_interrupts_and_then(|| SCHEDULER.resched());
+    }
+    wait_for_interrupt_unless(bottom_half::is_pending);
+    bottom_half::run_pending();
+}
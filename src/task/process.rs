@@ -1,5 +1,7 @@
 use alloc::string::String;
 use alloc::vec::Vec;
+use core::mem;
+use fs::FileTable;
 use task::context::Context;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -11,6 +13,8 @@ pub enum State {
     Current,
     /// Process has been stopped.
     Suspended,
+    /// Process was stopped by a signal, and stays off the ready list until it is continued.
+    Stopped,
     /// Process is ready to be ran by the scheduler.
     Ready,
 }
@@ -42,6 +46,10 @@ pub struct Process {
     pub priority: Priority,
     pub ctx: Context,
     pub stack: Option<Vec<usize>>,
+    /// Open file descriptors.
+    pub files: FileTable,
+    /// The absolute path of the working directory.
+    pub cwd: String,
 }
 
 impl Process {
@@ -53,6 +61,8 @@ impl Process {
             priority: Priority(0),
             ctx: Context::new(),
             stack: None,
+            files: FileTable::new(),
+            cwd: String::from("/"),
         }
     }
 
@@ -70,6 +80,17 @@ impl Process {
     pub fn set_stack(&mut self, addr: usize) {
         self.ctx.set_stack(addr);
     }
+
+    /// Return the most stack this process has used and the size of its stack, in bytes. Stacks
+    /// start zeroed and grow down, so everything below the lowest non-zero word is untouched.
+    pub fn stack_usage(&self) -> Option<(usize, usize)> {
+        self.stack.as_ref().map(|stack| {
+            let untouched = stack.iter().take_while(|&&word| word == 0).count();
+            let word = mem::size_of::<usize>();
+
+            ((stack.len() - untouched) * word, stack.len() * word)
+        })
+    }
 }
 
 ///A returned process pops an instruction pointer off the stack then jumps to it.
@@ -0,0 +1,72 @@
+//! Signals sent to processes, such as those the TTYs send for ^C and ^Z. A signal is only
+//! recorded as pending here; it takes effect where the process next checks for it. Blocking TTY
+//! reads give up when interrupted, and the scheduler suspends a stopped process at its next
+//! reschedule, leaving it off the ready list until it is sent `CONTINUE`.
+
+use alloc::Vec;
+use arch::interrupts::disable_interrupts_and_then;
+use spin::Mutex;
+use task::{ProcessId, SCHEDULER};
+
+bitflags! {
+    pub struct Signals: u8 {
+        /// Interrupt what the process is doing, sent by ^C.
+        const INTERRUPT = 1 << 0;
+        /// Stop the process, sent by ^Z.
+        const STOP = 1 << 1;
+        /// Continue a stopped process, sent by the `TTY_CONTINUE` ioctl. This takes effect when
+        /// it is sent rather than being left pending.
+        const CONTINUE = 1 << 2;
+    }
+}
+
+lazy_static! {
+    /// The signals sent to each process which it hasn't yet taken.
+    static ref PENDING: Mutex<Vec<(ProcessId, Signals)>> = Mutex::new(Vec::new());
+}
+
+/// Send signals to a process. Bottom halves send signals, so the lock is taken with interrupts
+/// disabled.
+pub fn send(pid: ProcessId, signals: Signals) {
+    disable_interrupts_and_then(|| {
+        let mut signals = signals;
+
+        // Continuing a process cancels a stop it hasn't taken yet, and readies it if it has.
+        if signals.contains(Signals::CONTINUE) {
+            signals.remove(Signals::CONTINUE | Signals::STOP);
+            take(pid, Signals::STOP);
+            SCHEDULER.resume(pid);
+        }
+
+        if signals.is_empty() {
+            return;
+        }
+
+        let mut pending = PENDING.lock();
+
+        match pending.iter().position(|&(id, _)| id == pid) {
+            Some(i) => pending[i].1.insert(signals),
+            None => pending.push((pid, signals)),
+        }
+    });
+}
+
+/// Take those of `signals` which are pending for a process, leaving the rest pending. This must
+/// be called with interrupts disabled.
+pub fn take(pid: ProcessId, signals: Signals) -> Signals {
+    let mut pending = PENDING.lock();
+
+    let i = match pending.iter().position(|&(id, _)| id == pid) {
+        Some(i) => i,
+        None => return Signals::empty(),
+    };
+
+    let taken = pending[i].1 & signals;
+    pending[i].1.remove(signals);
+
+    if pending[i].1.is_empty() {
+        pending.swap_remove(i);
+    }
+
+    taken
+}